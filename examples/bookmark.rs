@@ -12,7 +12,35 @@ fn main() {
     let _ = doc.get_page(page2).add_layer("Layer 3");
     doc.add_bookmark("This is another bookmark", page2);
 
-    // If this is successful, you should see a PDF with two blank A4 pages and 2 bookmarks
+    // nested outline items with different destinations
+    let chapter = doc.add_outline_item(None, OutlineItem::new("Chapter 1", Destination::Fit { page: page1 })
+        .with_open(true)
+        .with_bold(true));
+    doc.add_outline_item(Some(chapter), OutlineItem::new("Section 1.1", Destination::XYZ {
+        page: page1, left: Some(Mm(0.0)), top: Some(Mm(150.0)), zoom: Some(2.0)
+    }));
+    doc.add_outline_item(Some(chapter), OutlineItem::new("Section 1.2", Destination::FitH { page: page2, top: None })
+        .with_italic(true)
+        .with_color(Rgb::new(0.8, 0.0, 0.0, None)));
+
+    // links to a website, to another page and to a named destination
+    doc.add_named_destination("second-page", Destination::page(page2));
+    let first_page = doc.get_page(page1);
+    first_page.add_link_annotation(LinkAnnotation::new(
+        Rect::new(Mm(10.0), Mm(270.0), Mm(60.0), Mm(10.0)),
+        LinkAction::Uri("https://github.com/fschutt/printpdf".into())
+    ).with_border_width(1.0));
+    first_page.add_link_annotation(LinkAnnotation::new(
+        Rect::new(Mm(10.0), Mm(250.0), Mm(60.0), Mm(10.0)),
+        LinkAction::GoTo(Destination::page(page2))
+    ).with_border_width(1.0));
+    first_page.add_link_annotation(LinkAnnotation::new(
+        Rect::new(Mm(10.0), Mm(230.0), Mm(60.0), Mm(10.0)),
+        LinkAction::Named("second-page".into())
+    ).with_border_width(1.0));
+
+    // If this is successful, you should see a PDF with two blank A4 pages, 2 bookmarks,
+    // a chapter with two sections and three (bordered) links on the first page
     doc.save(&mut BufWriter::new(
         File::create("test_bookmark.pdf").unwrap(),
     ))
@@ -0,0 +1,71 @@
+extern crate printpdf;
+
+use printpdf::*;
+use std::fs::File;
+use std::io::BufWriter;
+
+fn rectangle(x: f64, y: f64, width: f64, height: f64) -> Line {
+    Line {
+        points: vec![
+            (Point::new(Mm(x), Mm(y)), false),
+            (Point::new(Mm(x + width), Mm(y)), false),
+            (Point::new(Mm(x + width), Mm(y + height)), false),
+            (Point::new(Mm(x), Mm(y + height)), false),
+        ],
+        is_closed: true,
+        has_fill: true,
+        has_stroke: false,
+        is_clipping_path: false,
+    }
+}
+
+fn main() {
+    let (doc, page1, layer1) = PdfDocument::new("printpdf pattern test", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page1);
+    let current_layer = page.get_layer(layer1);
+
+    // linear gradient with three colors
+    let gradient = page.add_shading_pattern(ShadingPattern::new(
+        ShadingType::Axial { start: Point::new(Mm(20.0), Mm(0.0)), end: Point::new(Mm(190.0), Mm(0.0)) },
+        vec![
+            ColorStop::new(0.0, Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None))),
+            ColorStop::new(0.5, Color::Rgb(Rgb::new(1.0, 1.0, 0.0, None))),
+            ColorStop::new(1.0, Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None))),
+        ],
+    ));
+
+    // radial gradient in CMYK
+    let center = Point::new(Mm(105.0), Mm(150.0));
+    let radial = page.add_shading_pattern(ShadingPattern::new(
+        ShadingType::Radial { start: center, start_radius: Pt(0.0), end: center, end_radius: Mm(50.0).into() },
+        vec![
+            ColorStop::new(0.0, Color::Cmyk(Cmyk::new(0.0, 0.0, 0.0, 0.0, None))),
+            ColorStop::new(1.0, Color::Cmyk(Cmyk::new(1.0, 0.0, 0.0, 0.2, None))),
+        ],
+    ));
+
+    // uncolored hatch, the color is given when the pattern is used
+    let cell = Rect::new(Mm(0.0), Mm(0.0), Mm(3.0), Mm(3.0));
+    let hatch = page.add_tiling_pattern(TilingPattern::new(cell, Mm(3.0).into(), Mm(3.0).into()).with_uncolored(), |cell| {
+        cell.set_outline_thickness(0.5);
+        cell.add_shape(Line {
+            points: vec![(Point::new(Mm(0.0), Mm(0.0)), false), (Point::new(Mm(3.0), Mm(3.0)), false)],
+            is_closed: false,
+            has_fill: false,
+            has_stroke: true,
+            is_clipping_path: false,
+        });
+    });
+
+    current_layer.set_fill_pattern(&gradient, None);
+    current_layer.add_shape(rectangle(20.0, 220.0, 170.0, 50.0));
+
+    current_layer.set_fill_pattern(&radial, None);
+    current_layer.add_shape(rectangle(55.0, 100.0, 100.0, 100.0));
+
+    current_layer.set_fill_pattern(&hatch, Some(Color::Rgb(Rgb::new(0.0, 0.4, 0.0, None))));
+    current_layer.add_shape(rectangle(20.0, 20.0, 170.0, 60.0));
+
+    // If this is successful, you should see a gradient, a radial gradient and a hatched rectangle
+    doc.save(&mut BufWriter::new(File::create("test_pattern.pdf").unwrap())).unwrap();
+}
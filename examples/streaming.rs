@@ -0,0 +1,28 @@
+extern crate printpdf;
+
+use printpdf::*;
+use std::fs::File;
+use std::io::BufWriter;
+
+fn main() {
+    let (doc, page1, layer1) = PdfDocument::new("printpdf streaming test", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+
+    // the header is written immediately, every finished page is written by write_page
+    let file = BufWriter::new(File::create("test_streaming.pdf").unwrap());
+    let mut writer = PdfStreamWriter::new(&doc, file, PdfSaveOptions::default()).unwrap();
+
+    doc.get_page(page1).get_layer(layer1).use_text("Page 1", 48.0, Mm(10.0), Mm(270.0), &font);
+    writer.write_page(&doc, page1).unwrap();
+
+    for i in 2..=500 {
+        let (page, layer) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+        doc.get_page(page).get_layer(layer).use_text(format!("Page {}", i), 48.0, Mm(10.0), Mm(270.0), &font);
+        doc.add_bookmark(format!("Page {}", i), page);
+        // the contents of the page are dropped after writing it
+        writer.write_page(&doc, page).unwrap();
+    }
+
+    // writes the fonts, the bookmarks, the page tree and the cross-reference table
+    writer.finish(doc).unwrap();
+}
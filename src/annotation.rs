@@ -0,0 +1,151 @@
+//! Link annotations: clickable areas on a page
+
+use lopdf;
+use lopdf::{Dictionary as LoDictionary, ObjectId};
+use std::iter::FromIterator;
+use {Destination, Rect};
+
+/// What happens when a link is clicked
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkAction {
+    /// Open an external URI, such as `https://example.com`
+    Uri(String),
+    /// Jump to a destination in this document
+    GoTo(Destination),
+    /// Jump to a named destination that was registered with
+    /// `PdfDocumentReference::add_named_destination`
+    Named(String),
+}
+
+impl LinkAction {
+
+    /// Converts the action into an action dictionary, see page 652 in the PDF 1.7 reference
+    pub(crate) fn into_pdf_dictionary(self, page_ids: &[ObjectId]) -> LoDictionary {
+        use lopdf::Object::*;
+        use lopdf::StringFormat::Literal;
+
+        match self {
+            LinkAction::Uri(uri) => LoDictionary::from_iter(vec![
+                ("S", Name("URI".into())),
+                ("URI", String(uri.into_bytes(), Literal)),
+            ]),
+            LinkAction::GoTo(destination) => LoDictionary::from_iter(vec![
+                ("S", Name("GoTo".into())),
+                ("D", destination.into_pdf_array(page_ids)),
+            ]),
+            LinkAction::Named(name) => LoDictionary::from_iter(vec![
+                ("S", Name("GoTo".into())),
+                ("D", String(name.into_bytes(), Literal)),
+            ]),
+        }
+    }
+}
+
+/// A clickable rectangle on a page, see page 615 in the PDF 1.7 reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkAnnotation {
+    /// Clickable area on the page
+    pub rect: Rect,
+    /// Action that is performed when clicking the link
+    pub action: LinkAction,
+    /// Width of the border around the link in points. Default: 0 (no border)
+    pub border_width: f64,
+}
+
+impl LinkAnnotation {
+
+    /// Creates a new link annotation without a border
+    #[inline]
+    pub fn new(rect: Rect, action: LinkAction)
+    -> Self
+    {
+        Self {
+            rect,
+            action,
+            border_width: 0.0,
+        }
+    }
+
+    /// Sets the width of the border (in points) that is drawn around the link
+    #[inline]
+    pub fn with_border_width(mut self, border_width: f64)
+    -> Self
+    {
+        self.border_width = border_width;
+        self
+    }
+
+    /// Adds the annotation to the document, `page_id` is the page the annotation is on
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, page_id: ObjectId, page_ids: &[ObjectId])
+    -> ObjectId
+    {
+        use lopdf::Object::*;
+
+        let rect = self.rect;
+
+        doc.add_object(LoDictionary::from_iter(vec![
+            ("Type", Name("Annot".into())),
+            ("Subtype", Name("Link".into())),
+            ("Rect", Array(vec![rect.ll.x.into(), rect.ll.y.into(), rect.ur.x.into(), rect.ur.y.into()])),
+            ("Border", Array(vec![Integer(0), Integer(0), Real(self.border_width)])),
+            ("P", Reference(page_id)),
+            // print flag, required by PDF/A
+            ("F", Integer(4)),
+            ("A", Dictionary(self.action.into_pdf_dictionary(page_ids))),
+        ]))
+    }
+}
+
+#[test]
+fn test_link_annotations() {
+    use lopdf::Object;
+    use {Mm, PdfDocument};
+
+    let (doc, page1, _) = PdfDocument::new("Links", Mm(100.0), Mm(100.0), "Layer 1");
+    let (page2, _) = doc.add_page(Mm(100.0), Mm(100.0), "Layer 1");
+    let rect = Rect::new(Mm(10.0), Mm(10.0), Mm(30.0), Mm(10.0));
+    doc.add_named_destination("chapter", Destination::FitH { page: page2, top: Some(Mm(50.0)) });
+
+    let page = doc.get_page(page1);
+    page.add_link_annotation(LinkAnnotation::new(rect, LinkAction::Uri("https://example.com".into())).with_border_width(1.0));
+    page.add_link_annotation(LinkAnnotation::new(rect, LinkAction::GoTo(Destination::Fit { page: page2 })));
+    page.add_link_annotation(LinkAnnotation::new(rect, LinkAction::Named("chapter".into())));
+
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let pages = saved.get_pages();
+    let (page1_id, page2_id) = (pages[&1], pages[&2]);
+    let name = |object: &Object| object.as_name_str().unwrap().to_string();
+
+    let annotations: Vec<&lopdf::Dictionary> = saved.get_dictionary(page1_id).unwrap()
+        .get(b"Annots").and_then(Object::as_array).unwrap()
+        .iter().map(|annotation| saved.get_dictionary(annotation.as_reference().unwrap()).unwrap())
+        .collect();
+    assert_eq!(annotations.len(), 3);
+    assert!(annotations.iter().all(|annotation| name(annotation.get(b"Subtype").unwrap()) == "Link"));
+    assert!(annotations.iter().all(|annotation| annotation.get(b"P").and_then(Object::as_reference).unwrap() == page1_id));
+    let action = |index: usize| annotations[index].get(b"A").and_then(Object::as_dict).unwrap();
+
+    assert_eq!(name(action(0).get(b"S").unwrap()), "URI");
+    assert_eq!(action(0).get(b"URI").and_then(Object::as_str).unwrap(), b"https://example.com");
+    assert_eq!(annotations[0].get(b"Border").and_then(Object::as_array).unwrap()[2].as_float().unwrap(), 1.0);
+
+    assert_eq!(name(action(1).get(b"S").unwrap()), "GoTo");
+    let destination = action(1).get(b"D").and_then(Object::as_array).unwrap();
+    assert_eq!(destination[0].as_reference().unwrap(), page2_id);
+    assert_eq!(name(&destination[1]), "Fit");
+
+    assert_eq!(name(action(2).get(b"S").unwrap()), "GoTo");
+    assert_eq!(action(2).get(b"D").and_then(Object::as_str).unwrap(), b"chapter");
+
+    // the name tree of the named destinations
+    let names = saved.catalog().unwrap().get(b"Names").unwrap();
+    let names = saved.dereference(names).unwrap().1.as_dict().unwrap();
+    let dests = saved.dereference(names.get(b"Dests").unwrap()).unwrap().1.as_dict().unwrap();
+    let dests = dests.get(b"Names").and_then(Object::as_array).unwrap();
+    assert_eq!(dests.len(), 2);
+    assert_eq!(dests[0].as_str().unwrap(), b"chapter");
+    let destination = saved.dereference(&dests[1]).unwrap().1.as_array().unwrap();
+    assert_eq!(destination[0].as_reference().unwrap(), page2_id);
+    assert_eq!(name(&destination[1]), "FitH");
+    assert!((destination[2].as_float().unwrap() - 141.73).abs() < 0.01);
+}
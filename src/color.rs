@@ -2,7 +2,9 @@
 
 #[cfg(feature = "embedded_images")]
 use image_crate;
+use lopdf;
 use lopdf::content::Operation;
+use std::collections::BTreeMap;
 
 use glob_defines::{
     OP_COLOR_SET_FILL_CS_DEVICERGB, OP_COLOR_SET_FILL_CS_DEVICECMYK, OP_COLOR_SET_FILL_CS_DEVICEGRAY,
@@ -24,27 +26,29 @@ impl Into<Operation> for PdfColor {
     {
         use lopdf::Object::*;
 
-        // todo: incorporate ICC profile instead of just setting the default device cmyk color space
+        // the ICC profiles and CIE-based color spaces need the resources of the page,
+        // `PdfLayerReference::set_fill_color` sets them. Here, the colors are approximated
+        // in the device color spaces
         let (color_identifier, color_vec) = {
             use self::PdfColor::*;
             match self {
                 FillColor(fill) => {
                     let ci = match fill {
-                        Color::Rgb(_) => { OP_COLOR_SET_FILL_CS_DEVICERGB }
+                        Color::Rgb(_) | Color::CalRgb(_) | Color::Lab(_) => { OP_COLOR_SET_FILL_CS_DEVICERGB }
                         Color::Cmyk(_) | Color::SpotColor(_) => { OP_COLOR_SET_FILL_CS_DEVICECMYK }
-                        Color::Greyscale(_) => { OP_COLOR_SET_FILL_CS_DEVICEGRAY }
+                        Color::Greyscale(_) | Color::CalGray(_) => { OP_COLOR_SET_FILL_CS_DEVICEGRAY }
                     };
-                    let cvec = fill.into_vec().into_iter().map(Real).collect();
+                    let cvec = fill.into_device_vec().into_iter().map(Real).collect();
                     (ci, cvec)
                 },
                 OutlineColor(outline) => {
                     let ci = match outline {
-                        Color::Rgb(_) => { OP_COLOR_SET_STROKE_CS_DEVICERGB }
+                        Color::Rgb(_) | Color::CalRgb(_) | Color::Lab(_) => { OP_COLOR_SET_STROKE_CS_DEVICERGB }
                         Color::Cmyk(_) | Color::SpotColor(_) => { OP_COLOR_SET_STROKE_CS_DEVICECMYK }
-                        Color::Greyscale(_) => { OP_COLOR_SET_STROKE_CS_DEVICEGRAY }
+                        Color::Greyscale(_) | Color::CalGray(_) => { OP_COLOR_SET_STROKE_CS_DEVICEGRAY }
                     };
 
-                    let cvec = outline.into_vec().into_iter().map(Real).collect();
+                    let cvec = outline.into_device_vec().into_iter().map(Real).collect();
                     (ci, cvec)
                 }
             }
@@ -55,7 +59,7 @@ impl Into<Operation> for PdfColor {
 }
 
 /// Color space (enum for marking the number of bits a color has)
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ColorSpace {
     Rgb,
     Rgba,
@@ -100,6 +104,8 @@ impl Into<&'static str> for ColorSpace {
 #[derive(Debug, Copy, Clone)]
 pub enum ColorBits {
     Bit1,
+    Bit2,
+    Bit4,
     Bit8,
     Bit16,
 }
@@ -127,6 +133,8 @@ impl Into<i64> for ColorBits {
     {
         match self {
             ColorBits::Bit1 => 1,
+            ColorBits::Bit2 => 2,
+            ColorBits::Bit4 => 4,
             ColorBits::Bit8 => 8,
             ColorBits::Bit16 => 16,
         }
@@ -139,7 +147,13 @@ pub enum Color {
     Rgb(Rgb),
     Cmyk(Cmyk),
     Greyscale(Greyscale),
-    SpotColor(SpotColor)
+    SpotColor(SpotColor),
+    /// Device independent CIE L*a*b* color
+    Lab(Lab),
+    /// Calibrated RGB color, boxed because it is much larger than the other colors
+    CalRgb(Box<CalRgb>),
+    /// Calibrated grey
+    CalGray(CalGray),
 }
 
 impl Color {
@@ -153,6 +167,19 @@ impl Color {
             Color::Cmyk(cmyk) => { vec![cmyk.c, cmyk.m, cmyk.y, cmyk.k ]},
             Color::Greyscale(gs) => { vec![gs.percent]},
             Color::SpotColor(spot) => { vec![spot.c, spot.m, spot.y, spot.k ]},
+            Color::Lab(lab) => { vec![lab.l, lab.a, lab.b ]},
+            Color::CalRgb(rgb) => { vec![rgb.r, rgb.g, rgb.b ]},
+            Color::CalGray(gs) => { vec![gs.percent]},
+        }
+    }
+
+    /// Like `into_vec`, but Lab colors are converted to sRGB
+    pub(crate) fn into_device_vec(self)
+    -> Vec<f64>
+    {
+        match self {
+            Color::Lab(lab) => lab.to_rgb().to_vec(),
+            other => other.into_vec(),
         }
     }
 
@@ -164,11 +191,71 @@ impl Color {
             Color::Rgb(ref rgb) => Some(&rgb.icc_profile),
             Color::Cmyk(ref cmyk) => Some(&cmyk.icc_profile),
             Color::Greyscale(ref gs) => Some(&gs.icc_profile),
-            Color::SpotColor(_) => None,
+            Color::SpotColor(_) | Color::Lab(_) | Color::CalRgb(_) | Color::CalGray(_) => None,
         }
     }
+
+    /// Returns the key of the color space of a color with an ICC profile or of
+    /// a Lab, CalRGB or CalGray color, `None` for device colors
+    pub fn get_color_space_key(&self)
+    -> Option<ColorSpaceKey>
+    {
+        match *self {
+            Color::Lab(ref lab) => Some(ColorSpaceKey::Lab(lab.white_point)),
+            Color::CalRgb(ref rgb) => Some(ColorSpaceKey::CalRgb(rgb.white_point, rgb.gamma, rgb.matrix)),
+            Color::CalGray(ref gs) => Some(ColorSpaceKey::CalGray(gs.white_point, gs.gamma)),
+            _ => match self.get_icc_profile() {
+                Some(&Some(ref profile)) => Some(ColorSpaceKey::IccProfile(profile.index)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns the CIE-based color space of Lab, CalRGB and CalGray colors
+    /// (for example `[/Lab << /WhitePoint [...] >>]`), `None` for device colors
+    pub fn get_cie_color_space(&self)
+    -> Option<lopdf::Object>
+    {
+        use lopdf::Object::*;
+        use std::iter::FromIterator;
+
+        let (name, white_point, mut entries) = match *self {
+            Color::Lab(ref lab) => {
+                let range = LAB_RANGE.iter().map(|value| Real(*value)).collect();
+                ("Lab", lab.white_point, vec![("Range", Array(range))])
+            },
+            Color::CalRgb(ref rgb) => {
+                let gamma = rgb.gamma.iter().map(|value| Real(*value)).collect();
+                let matrix = rgb.matrix.iter().map(|value| Real(*value)).collect();
+                ("CalRGB", rgb.white_point, vec![("Gamma", Array(gamma)), ("Matrix", Array(matrix))])
+            },
+            Color::CalGray(ref gs) => ("CalGray", gs.white_point, vec![("Gamma", Real(gs.gamma))]),
+            _ => return None,
+        };
+
+        entries.insert(0, ("WhitePoint", Array(vec![Real(white_point.x), Real(white_point.y), Real(white_point.z)])));
+        Some(Array(vec![Name(name.into()), Dictionary(lopdf::Dictionary::from_iter(entries))]))
+    }
 }
 
+/// Identifies a color space in a `ColorSpaceList`, equal keys are the same color space
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorSpaceKey {
+    /// ICC-based color space, with the index of the profile in the document
+    IccProfile(usize),
+    /// Lab color space with the white point
+    Lab(WhitePoint),
+    /// CalRGB color space with the white point, gamma and matrix
+    CalRgb(WhitePoint, [f64; 3], [f64; 9]),
+    /// CalGray color space with the white point and gamma
+    CalGray(WhitePoint, f64),
+    /// `[/Pattern base]` color space of uncolored patterns
+    Pattern(Box<ColorSpaceKey>),
+}
+
+/// Range of the a* and b* components of Lab colors
+const LAB_RANGE: [f64; 4] = [-128.0, 127.0, -128.0, 127.0];
+
 /// RGB color
 #[derive(Debug, Clone, PartialEq)]
 pub struct Rgb {
@@ -223,6 +310,188 @@ impl Greyscale {
 }
 
 
+/// CIE XYZ coordinates of the white of a CIE-based color space (the diffuse white point)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WhitePoint {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl WhitePoint {
+    /// Daylight at 5000 K, the white point of printing (and of ICC profiles)
+    pub const D50: WhitePoint = WhitePoint { x: 0.9642, y: 1.0, z: 0.8249 };
+    /// Daylight at 6500 K, the white point of sRGB and most monitors
+    pub const D65: WhitePoint = WhitePoint { x: 0.9505, y: 1.0, z: 1.0890 };
+
+    /// Adapts XYZ coordinates relative to this white point to D50 (Bradford transform)
+    fn adapt_to_d50(self, xyz: [f64; 3])
+    -> [f64; 3]
+    {
+        const BRADFORD: [[f64; 3]; 3] = [
+            [0.8951, 0.2664, -0.1614],
+            [-0.7502, 1.7135, 0.0367],
+            [0.0389, -0.0685, 1.0296],
+        ];
+        const BRADFORD_INVERSE: [[f64; 3]; 3] = [
+            [0.986_992_9, -0.147_054_3, 0.159_962_7],
+            [0.432_305_3, 0.518_360_3, 0.049_291_2],
+            [-0.008_528_7, 0.040_042_8, 0.968_486_7],
+        ];
+        let multiply = |matrix: &[[f64; 3]; 3], vector: [f64; 3]| {
+            let row = |i: usize| matrix[i][0] * vector[0] + matrix[i][1] * vector[1] + matrix[i][2] * vector[2];
+            [row(0), row(1), row(2)]
+        };
+
+        // scale the cone responses from this white point to the one of D50
+        let source = multiply(&BRADFORD, [self.x, self.y, self.z]);
+        let target = multiply(&BRADFORD, [WhitePoint::D50.x, WhitePoint::D50.y, WhitePoint::D50.z]);
+        let cone = multiply(&BRADFORD, xyz);
+        multiply(&BRADFORD_INVERSE, [cone[0] * target[0] / source[0],
+                                     cone[1] * target[1] / source[1],
+                                     cone[2] * target[2] / source[2]])
+    }
+}
+
+/// CIE L*a*b* color: lightness from 0 to 100, a* (green to red) and b* (blue to yellow)
+/// from -128 to 127, relative to the white point. Lab colors look the same on every
+/// device (as far as the device can show them), for example for brand colors.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+    pub white_point: WhitePoint,
+}
+
+impl Lab {
+    /// Creates a new Lab color
+    pub fn new(l: f64, a: f64, b: f64, white_point: WhitePoint)
+    -> Self
+    {
+        Self { l, a, b, white_point }
+    }
+
+    /// Converts the color to sRGB (for the places that only support device colors)
+    pub(crate) fn to_rgb(self)
+    -> [f64; 3]
+    {
+        let f_inverse = |t: f64| if t > 6.0 / 29.0 { t.powi(3) } else { 108.0 / 841.0 * (t - 4.0 / 29.0) };
+        let fy = (self.l + 16.0) / 116.0;
+        let (fx, fz) = (fy + self.a / 500.0, fy - self.b / 200.0);
+
+        // XYZ relative to the white point of the color, adapted to D50
+        let white_point = self.white_point;
+        let xyz = [f_inverse(fx) * white_point.x, f_inverse(fy) * white_point.y, f_inverse(fz) * white_point.z];
+        let [x, y, z] = white_point.adapt_to_d50(xyz);
+
+        // XYZ (D50) to sRGB (Bradford)
+        let r = 3.133_856_1 * x - 1.616_866_7 * y - 0.490_614_6 * z;
+        let g = -0.978_768_4 * x + 1.916_141_5 * y + 0.033_454_0 * z;
+        let b = 0.071_945_3 * x - 0.228_991_4 * y + 1.405_242_7 * z;
+
+        let gamma = |value: f64| {
+            let value = value.clamp(0.0, 1.0);
+            if value <= 0.003_130_8 { 12.92 * value } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+        };
+        [gamma(r), gamma(g), gamma(b)]
+    }
+}
+
+/// Calibrated RGB color (`CalRGB` color space): RGB with a gamma and the
+/// XYZ coordinates of the red, green and blue primaries
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CalRgb {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub white_point: WhitePoint,
+    /// Gamma of the red, green and blue components
+    pub gamma: [f64; 3],
+    /// XYZ coordinates of red, green and blue at full intensity (`[XR YR ZR XG YG ZG XB YB ZB]`)
+    pub matrix: [f64; 9],
+}
+
+impl CalRgb {
+    /// Creates a new calibrated RGB color with the primaries and the white point
+    /// of sRGB and a gamma of 2.2, which is close to sRGB
+    pub fn new(r: f64, g: f64, b: f64)
+    -> Self
+    {
+        Self {
+            r, g, b,
+            white_point: WhitePoint::D65,
+            gamma: [2.2; 3],
+            matrix: [0.4124, 0.2126, 0.0193, 0.3576, 0.7152, 0.1192, 0.1805, 0.0722, 0.9505],
+        }
+    }
+}
+
+/// Calibrated grey (`CalGray` color space)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CalGray {
+    pub percent: f64,
+    pub white_point: WhitePoint,
+    pub gamma: f64,
+}
+
+impl CalGray {
+    /// Creates a new calibrated grey with the D65 white point and a gamma of 2.2
+    pub fn new(percent: f64)
+    -> Self
+    {
+        Self { percent, white_point: WhitePoint::D65, gamma: 2.2 }
+    }
+}
+
+/// Color spaces in the resources of a page (ICC-based color spaces and the CIE-based
+/// color spaces of Lab, CalRGB and CalGray colors), see `PdfLayerReference::set_fill_color`
+#[derive(Default, Debug, Clone)]
+pub struct ColorSpaceList {
+    pub(crate) color_spaces: BTreeMap<String, lopdf::Object>,
+    /// Key of each color space, to add equal color spaces only once
+    keys: Vec<(ColorSpaceKey, String)>,
+}
+
+impl ColorSpaceList {
+    /// Creates a new color space list
+    pub fn new()
+    -> Self
+    {
+        Self::default()
+    }
+
+    /// Adds the color space, if the list doesn't contain a color space with
+    /// the same key yet, and returns its name
+    pub fn add_color_space(&mut self, key: ColorSpaceKey, color_space: lopdf::Object)
+    -> String
+    {
+        if let Some(&(_, ref name)) = self.keys.iter().find(|&&(ref existing, _)| *existing == key) {
+            return name.clone();
+        }
+
+        let name = format!("CS{}", self.color_spaces.len());
+        self.color_spaces.insert(name.clone(), color_space);
+        self.keys.push((key, name.clone()));
+        name
+    }
+
+    /// Returns the color spaces, to adjust the references in them
+    pub(crate) fn color_spaces_mut(&mut self)
+    -> impl Iterator<Item = &mut lopdf::Object>
+    {
+        self.color_spaces.values_mut()
+    }
+}
+
+impl From<ColorSpaceList> for lopdf::Dictionary {
+    fn from(list: ColorSpaceList)
+    -> Self
+    {
+        list.color_spaces.into_iter().collect()
+    }
+}
+
 /// Spot color
 /// Spot colors are like Cmyk, but without color space
 /// They are essentially "named" colors from specific vendors
@@ -242,3 +511,42 @@ impl SpotColor {
         Self { c, m, y, k }
     }
 }
+
+#[test]
+fn test_lab_to_rgb_uses_the_white_point() {
+    let assert_close = |rgb: [f64; 3], expected: [f64; 3]| {
+        for (value, expected) in rgb.iter().zip(expected.iter()) {
+            assert!((value - expected).abs() < 0.01, "{:?} is not {:?}", rgb, expected);
+        }
+    };
+
+    // the white point of the color is white, whatever the white point is
+    assert_close(Lab::new(100.0, 0.0, 0.0, WhitePoint::D50).to_rgb(), [1.0, 1.0, 1.0]);
+    assert_close(Lab::new(100.0, 0.0, 0.0, WhitePoint::D65).to_rgb(), [1.0, 1.0, 1.0]);
+    assert_close(Lab::new(50.0, 0.0, 0.0, WhitePoint::D65).to_rgb(), [0.466, 0.466, 0.466]);
+
+    // sRGB red, in Lab relative to D65 and relative to D50
+    assert_close(Lab::new(53.24, 80.09, 67.20, WhitePoint::D65).to_rgb(), [1.0, 0.0, 0.0]);
+    assert_close(Lab::new(54.29, 80.80, 69.89, WhitePoint::D50).to_rgb(), [1.0, 0.0, 0.0]);
+}
+
+#[test]
+fn test_color_space_list_compares_keys() {
+    let mut list = ColorSpaceList::new();
+    let lab_space = |white_point| Color::Lab(Lab::new(50.0, 0.0, 0.0, white_point));
+    let add = |list: &mut ColorSpaceList, color: Color| {
+        list.add_color_space(color.get_color_space_key().unwrap(), color.get_cie_color_space().unwrap())
+    };
+
+    assert_eq!(add(&mut list, lab_space(WhitePoint::D50)), "CS0");
+    assert_eq!(add(&mut list, lab_space(WhitePoint::D65)), "CS1");
+    assert_eq!(add(&mut list, lab_space(WhitePoint::D50)), "CS0");
+    assert_eq!(add(&mut list, Color::CalGray(CalGray::new(0.5))), "CS2");
+    assert_eq!(add(&mut list, Color::CalGray(CalGray { gamma: 1.8, .. CalGray::new(0.2) })), "CS3");
+    assert_eq!(add(&mut list, Color::CalGray(CalGray::new(0.2))), "CS2");
+
+    let icc_based = lopdf::Object::Array(vec![lopdf::Object::Name("ICCBased".into()), lopdf::Object::Reference((10, 0))]);
+    assert_eq!(list.add_color_space(ColorSpaceKey::IccProfile(0), icc_based.clone()), "CS4");
+    assert_eq!(list.add_color_space(ColorSpaceKey::Pattern(Box::new(ColorSpaceKey::IccProfile(0))), icc_based), "CS5");
+    assert_eq!(list.color_spaces.len(), 6);
+}
@@ -0,0 +1,89 @@
+//! Destinations: a page plus the view of the page that a viewer should show,
+//! used by outline items and links
+
+use lopdf;
+use {Mm, PdfPageIndex, Pt, Rect};
+
+/// Target of an outline item or a link, see page 582 in the PDF 1.7 reference
+///
+/// Positions are measured from the bottom left corner of the page. `None` means
+/// that the viewer should keep the current value.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Destination {
+    /// Show the page with the given coordinates at the upper left corner of the window,
+    /// magnified by `zoom` (1.0 = 100%)
+    XYZ { page: PdfPageIndex, left: Option<Mm>, top: Option<Mm>, zoom: Option<f64> },
+    /// Fit the entire page into the window
+    Fit { page: PdfPageIndex },
+    /// Fit the width of the page into the window, with `top` at the top edge of the window
+    FitH { page: PdfPageIndex, top: Option<Mm> },
+    /// Fit the rectangle into the window
+    FitR { page: PdfPageIndex, rect: Rect },
+}
+
+impl Destination {
+
+    /// Shows the top of the page, without changing the zoom
+    #[inline]
+    pub fn page(page: PdfPageIndex) -> Self {
+        Destination::XYZ { page, left: None, top: None, zoom: None }
+    }
+
+    /// Returns the page this destination points to
+    #[inline]
+    pub fn get_page(&self) -> PdfPageIndex {
+        match *self {
+            Destination::XYZ { page, .. } |
+            Destination::Fit { page } |
+            Destination::FitH { page, .. } |
+            Destination::FitR { page, .. } => page,
+        }
+    }
+
+    /// Changes the page this destination points to (when pages are moved)
+    #[inline]
+    pub(crate) fn set_page(&mut self, new_page: PdfPageIndex) {
+        match *self {
+            Destination::XYZ { ref mut page, .. } |
+            Destination::Fit { ref mut page } |
+            Destination::FitH { ref mut page, .. } |
+            Destination::FitR { ref mut page, .. } => *page = new_page,
+        }
+    }
+
+    /// Converts the destination into a PDF array, given the object IDs of all pages.
+    /// Returns `Null` if the page doesn't exist.
+    pub(crate) fn into_pdf_array(self, page_ids: &[lopdf::ObjectId]) -> lopdf::Object {
+        use lopdf::Object::*;
+
+        let page_id = match page_ids.get(self.get_page().0) {
+            Some(id) => *id,
+            None => return Null,
+        };
+
+        let position = |p: Option<Mm>| p.map(|mm| Pt::from(mm).into()).unwrap_or(Null);
+
+        let mut array = vec![Reference(page_id)];
+        match self {
+            Destination::XYZ { left, top, zoom, .. } => {
+                array.push(Name("XYZ".into()));
+                array.push(position(left));
+                array.push(position(top));
+                array.push(zoom.map(Real).unwrap_or(Null));
+            },
+            Destination::Fit { .. } => {
+                array.push(Name("Fit".into()));
+            },
+            Destination::FitH { top, .. } => {
+                array.push(Name("FitH".into()));
+                array.push(position(top));
+            },
+            Destination::FitR { rect, .. } => {
+                array.push(Name("FitR".into()));
+                array.extend(vec![rect.ll.x.into(), rect.ll.y.into(), rect.ur.x.into(), rect.ur.y.into()]);
+            },
+        }
+
+        Array(array)
+    }
+}
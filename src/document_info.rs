@@ -77,22 +77,45 @@ impl DocumentInfo {
         let info_mod_date = to_pdf_time_stamp_metadata(&m.modification_date);
         let info_create_date = to_pdf_time_stamp_metadata(&m.creation_date);
 
-        Dictionary(LoDictionary::from_iter(vec![
-            ("Trapped", trapping.into()),
+        let mut info = LoDictionary::from_iter(vec![
             ("CreationDate", String(info_create_date.into_bytes(), Literal)),
             ("ModDate", String(info_mod_date.into_bytes(), Literal)),
-            ("GTS_PDFXVersion", String(gts_pdfx_version.into(), Literal)),
-            ("Title", String(m.document_title.to_string().as_bytes().to_vec(), Literal)),
-            ("Author", String(m.author.as_bytes().to_vec(), Literal)),
-            ("Creator", String(m.creator.as_bytes().to_vec(), Literal)),
-            ("Producer", String(m.producer.as_bytes().to_vec(), Literal)),
-            ("Subject", String(m.subject.as_bytes().to_vec(), Literal)),
-            ("Identifier", String(m.identifier.as_bytes().to_vec(), Literal)),
-            ("Keywords", String(m.keywords.join(",").as_bytes().to_vec(), Literal))
-        ]))
+            ("Title", to_pdf_text_string(&m.document_title)),
+            ("Author", to_pdf_text_string(&m.author)),
+            ("Creator", to_pdf_text_string(&m.creator)),
+            ("Producer", to_pdf_text_string(&m.producer)),
+            ("Subject", to_pdf_text_string(&m.subject)),
+            ("Identifier", to_pdf_text_string(&m.identifier)),
+            ("Keywords", to_pdf_text_string(&m.keywords.join(",")))
+        ]);
+
+        // PDF/A requires every entry to be the same as in the XMP metadata, which
+        // has no trapping and no PDF/X version for PDF/A files
+        if !m.conformance.is_pdf_a() {
+            info.set("Trapped", trapping);
+            info.set("GTS_PDFXVersion", String(gts_pdfx_version.into(), Literal));
+        }
+
+        Dictionary(info)
     }
 }
 
+/// Encodes a text string: ASCII text as it is, other text as UTF-16 with a byte order mark
+pub(crate) fn to_pdf_text_string(text: &str)
+-> lopdf::Object
+{
+    let bytes = if text.is_ascii() {
+        text.as_bytes().to_vec()
+    } else {
+        let mut bytes = vec![0xFE, 0xFF];
+        for unit in text.encode_utf16() {
+            bytes.extend_from_slice(&unit.to_be_bytes());
+        }
+        bytes
+    };
+    lopdf::Object::String(bytes, lopdf::StringFormat::Literal)
+}
+
 // D:20170505150224+02'00'
 fn to_pdf_time_stamp_metadata(date: &OffsetDateTime)
 -> String
@@ -3,7 +3,9 @@
 use std::error::Error as IError;
 use std::io::Error as IoError;
 use owned_ttf_parser::FaceParsingError;
+use lopdf::Error as LopdfError;
 use std::fmt;
+use ConformanceReport;
 
 /// error_chain and failure are certainly nice, but completely overengineered
 /// for this use-case. For example, neither of them allow error localization.
@@ -30,8 +32,12 @@ pub enum Error {
     Io(IoError),
     /// External: owned_ttf_parser::FaceParsingError
     FaceParsing(FaceParsingError),
+    /// External: lopdf::Error, when a PDF file can't be parsed
+    Lopdf(LopdfError),
     /// PDF error
     Pdf(PdfError),
+    /// The document violates the rules of its conformance (see `check_for_errors`)
+    Conformance(ConformanceReport),
     /// Indexing error (please report if this happens, shouldn't happen)
     Index(IndexError),
 }
@@ -39,11 +45,22 @@ pub enum Error {
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum PdfError {
     FontFaceError,
+    /// Image file that can't be embedded, with the reason
+    InvalidImage(&'static str),
+    /// PDF file that can't be loaded, with the reason
+    InvalidPdf(&'static str),
+    /// The document can't be saved while another thread still uses it
+    DocumentInUse,
 }
 
 impl fmt::Display for PdfError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Invalid or corrupt font face")
+        match *self {
+            PdfError::FontFaceError => write!(f, "Invalid or corrupt font face"),
+            PdfError::InvalidImage(reason) => write!(f, "Invalid or unsupported image: {}", reason),
+            PdfError::InvalidPdf(reason) => write!(f, "Invalid or unsupported PDF file: {}", reason),
+            PdfError::DocumentInUse => write!(f, "The document is still in use by another thread"),
+        }
     }
 }
 
@@ -71,7 +88,9 @@ impl IError for IndexError {}
 
 impl_from!(IoError, Error::Io);
 impl_from!(FaceParsingError, Error::FaceParsing);
+impl_from!(LopdfError, Error::Lopdf);
 impl_from!(PdfError, Error::Pdf);
+impl_from!(ConformanceReport, Error::Conformance);
 impl_from!(IndexError, Error::Index);
 
 impl fmt::Display for Error {
@@ -80,7 +99,9 @@ impl fmt::Display for Error {
         match *self {
             Io(ref e) => write!(f, "{}", e),
             FaceParsing(ref e) => write!(f, "{}", e),
+            Lopdf(ref e) => write!(f, "{}", e),
             Pdf(ref e) => write!(f, "{}", e),
+            Conformance(ref e) => write!(f, "{}", e),
             Index(ref e) => write!(f, "{}", e),
         }
     }
@@ -39,8 +39,9 @@ use lopdf::content::Operation;
 use lopdf::Object::*;
 use std::string::String;
 use indices::FontIndex;
+use {Color, XObjectRef};
 use std::collections::HashSet;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 // identifiers for tracking the changed fields
 pub (crate) const LINE_WIDTH: &'static str = "line_width";
@@ -77,7 +78,7 @@ pub struct ExtendedGraphicsStateList {
     pub(crate) latest_graphics_state: (usize, ExtendedGraphicsState),
     /// All graphics states needed for this layer, collected together with a name for each one
     /// The name should be: "GS[index of the graphics state]", so `/GS0` for the first graphics state.
-    pub(crate) all_graphics_states: HashMap<String, (usize, ExtendedGraphicsState)>,
+    pub(crate) all_graphics_states: BTreeMap<String, (usize, ExtendedGraphicsState)>,
 }
 
 impl Default for ExtendedGraphicsStateList {
@@ -86,7 +87,7 @@ impl Default for ExtendedGraphicsStateList {
     {
         Self {
             latest_graphics_state: (0, ExtendedGraphicsState::default()),
-            all_graphics_states: HashMap::new(),
+            all_graphics_states: BTreeMap::new(),
         }
     }
 }
@@ -110,16 +111,18 @@ impl ExtendedGraphicsStateList {
     }
 }
 
-impl Into<lopdf::Dictionary> for ExtendedGraphicsStateList {
+impl ExtendedGraphicsStateList {
 
+    /// Same as `Into<lopdf::Dictionary>`, but soft masks can refer to their transparency
+    /// groups. `xobjects` is the (already serialized) XObject dictionary of the page
     #[cfg_attr(feature = "cargo-clippy", allow(needless_return))]
-    fn into(self)
+    pub(crate) fn into_with_xobjects(self, xobjects: &lopdf::Dictionary)
     -> lopdf::Dictionary
     {
         let mut ext_g_state_resources = lopdf::Dictionary::new();
 
         for (name, (_, graphics_state)) in self.all_graphics_states {
-            let gs: lopdf::Object = graphics_state.into();
+            let gs: lopdf::Object = graphics_state.into_with_xobjects(xobjects);
             ext_g_state_resources.set(name.to_string(), gs);
         }
 
@@ -127,6 +130,15 @@ impl Into<lopdf::Dictionary> for ExtendedGraphicsStateList {
     }
 }
 
+impl Into<lopdf::Dictionary> for ExtendedGraphicsStateList {
+
+    fn into(self)
+    -> lopdf::Dictionary
+    {
+        self.into_with_xobjects(&lopdf::Dictionary::new())
+    }
+}
+
 /// `ExtGState` dictionary
 #[derive(Debug, PartialEq, Clone)]
 pub struct ExtendedGraphicsState {
@@ -620,10 +632,53 @@ impl Into<lopdf::Object> for ExtendedGraphicsState {
     /// Compares the current graphics state with the previous one and returns an
     /// "optimized" graphics state, meaning only the fields that have changed in
     /// comparison to the previous one are returned.
+    ///
+    /// __NOTE__: Soft masks can't be serialized without the XObjects of the page
+    /// and are ignored, see `ExtendedGraphicsStateList::into_with_xobjects`
+    fn into(self)
+    -> lopdf::Object
+    {
+        self.into_with_xobjects(&lopdf::Dictionary::new())
+    }
+}
+
+impl ExtendedGraphicsState {
+
+    /// Returns which kind of transparency the graphics state uses, if any
+    /// (for checking the conformance of a document)
+    pub(crate) fn transparency(&self)
+    -> Option<&'static str>
+    {
+        let changed = |field| self.changed_fields.contains(field);
+
+        if changed(CURRENT_FILL_ALPHA) && self.current_fill_alpha < 1.0 ||
+           changed(CURRENT_STROKE_ALPHA) && self.current_stroke_alpha < 1.0 {
+            Some("constant alpha")
+        } else if changed(SOFT_MASK) && self.soft_mask.is_some() {
+            Some("soft mask")
+        } else if changed(BLEND_MODE) && self.blend_mode != BlendMode::Seperable(SeperableBlendMode::Normal) {
+            Some("blend mode")
+        } else {
+            None
+        }
+    }
+
+    /// Removes the transparency of the graphics state: the content is painted
+    /// opaque, with the normal blend mode and without a soft mask
+    pub(crate) fn remove_transparency(&mut self)
+    {
+        self.current_fill_alpha = 1.0;
+        self.current_stroke_alpha = 1.0;
+        self.soft_mask = None;
+        self.blend_mode = BlendMode::Seperable(SeperableBlendMode::Normal);
+    }
+
+    /// See `Into<lopdf::Object>`, `xobjects` is the XObject dictionary of the page,
+    /// which is needed to look up the transparency group of a soft mask
     #[cfg_attr(feature = "cargo-clippy", allow(needless_return))]
     #[cfg_attr(feature = "cargo-clippy", allow(cyclomatic_complexity))]
     #[cfg_attr(feature = "cargo-clippy", allow(string_lit_as_bytes))]
-    fn into(self)
+    pub(crate) fn into_with_xobjects(self, xobjects: &lopdf::Dictionary)
     -> lopdf::Object
     {
         use std::iter::FromIterator;
@@ -671,11 +726,11 @@ impl Into<lopdf::Object> for ExtendedGraphicsState {
         }
 
         if self.changed_fields.contains(CURRENT_FILL_ALPHA) {
-            gs_operations.push(("CA".to_string(), self.current_fill_alpha.into()));
+            gs_operations.push(("ca".to_string(), self.current_fill_alpha.into()));
         }
 
         if self.changed_fields.contains(CURRENT_STROKE_ALPHA) {
-            gs_operations.push(("ca".to_string(), self.current_stroke_alpha.into()));
+            gs_operations.push(("CA".to_string(), self.current_stroke_alpha.into()));
         }
 
         if self.changed_fields.contains(BLEND_MODE) {
@@ -754,9 +809,12 @@ impl Into<lopdf::Object> for ExtendedGraphicsState {
 
         if self.changed_fields.contains(SOFT_MASK) {
             if let Some(ref soft_mask) = self.soft_mask {
-
+                // without its group, the mask can't be written (see the docs of `SoftMask`)
+                if let Some(soft_mask) = soft_mask.to_pdf_dictionary(xobjects) {
+                    gs_operations.push(("SMask".to_string(), Dictionary(soft_mask)));
+                }
             } else {
-                gs_operations.push(("SM".to_string(), Name("None".as_bytes().to_vec())));
+                gs_operations.push(("SMask".to_string(), Name("None".as_bytes().to_vec())));
             }
         }
 
@@ -1356,18 +1414,69 @@ impl Into<lopdf::Object> for RenderingIntent {
     }
 }
 
-/// A soft mask is used for transparent images such as PNG with an alpha component
-/// The bytes range from 0xFF (opaque) to 0x00 (transparent). The alpha channel of a
-/// PNG image have to be sorted out.
-/// Can also be used for Vignettes, etc.
+/// A soft mask makes the following content partially transparent, depending on
+/// the contents of a transparency group. Can be used for fades, vignettes, etc.
+/// The group is added with `PdfPageReference::add_transparency_group` and must be
+/// on the same page as the graphics state that uses the mask.
+///
+/// __NOTE__: A soft mask whose group can't be found in the XObjects of the page
+/// is left out when the page is written, the content is then drawn without a mask.
 /// Beware of color spaces!
 /// __See PDF Reference Page 545__ - Soft masks
 #[derive(Debug, PartialEq, Clone)]
 pub struct SoftMask {
-    /// The data to be used as a soft mask
-    data: Vec<u8>,
-    /// Bits per component (1 for black / white, 8 for greyscale, up to 16)
-    bits_per_component: u8,
+    /// How the mask values are computed from the group
+    pub function: SoftMaskFunction,
+    /// The transparency group that defines the mask
+    pub group: XObjectRef,
+    /// Color of the backdrop that the group is composited onto (only for
+    /// luminosity masks), must be in the color space of the group.
+    /// Default: None (black, meaning everything outside of the group is transparent)
+    pub backdrop: Option<Color>,
+}
+
+impl SoftMask {
+
+    /// Creates a new soft mask from a transparency group
+    #[inline]
+    pub fn new(function: SoftMaskFunction, group: XObjectRef)
+    -> Self
+    {
+        Self {
+            function,
+            group,
+            backdrop: None,
+        }
+    }
+
+    /// Sets the backdrop color of a luminosity mask
+    #[inline]
+    pub fn with_backdrop(mut self, backdrop: Color)
+    -> Self
+    {
+        self.backdrop = Some(backdrop);
+        self
+    }
+
+    /// Returns the soft mask dictionary or `None` if the group isn't in `xobjects`
+    fn to_pdf_dictionary(&self, xobjects: &lopdf::Dictionary)
+    -> Option<lopdf::Dictionary>
+    {
+        use std::iter::FromIterator;
+
+        let group = xobjects.get(self.group.name.as_bytes()).ok()?.clone();
+        let mut dict = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("Mask".as_bytes().to_vec())),
+            ("S", self.function.into()),
+            ("G", group),
+        ]);
+
+        if let Some(ref backdrop) = self.backdrop {
+            dict.set("BC", Array(backdrop.clone().into_vec().into_iter().map(Real).collect()));
+        }
+
+        Some(dict)
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -1376,10 +1485,22 @@ pub enum SoftMaskFunction {
     /// In this function, the old (backdrop) color does not contribute to the result.
     /// This is the easies function, but may look bad at edges.
     GroupAlpha,
-    //
+    /// The mask values are the luminosity of the group (white = opaque, black = transparent).
+    /// The group must have a color space
     GroupLuminosity,
 
 }
+
+impl Into<lopdf::Object> for SoftMaskFunction {
+    fn into(self)
+    -> lopdf::Object
+    {
+        match self {
+            SoftMaskFunction::GroupAlpha => Name("Alpha".as_bytes().to_vec()),
+            SoftMaskFunction::GroupLuminosity => Name("Luminosity".as_bytes().to_vec()),
+        }
+    }
+}
 /// __See PDF Reference Page 216__ - Line join style
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum LineJoinStyle {
@@ -5,9 +5,11 @@ use lopdf;
 use lopdf::{Stream as LoStream, Dictionary as LoDictionary};
 use lopdf::StringFormat;
 use owned_ttf_parser::{AsFaceRef as _, Face, OwnedFace};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::iter::FromIterator;
-use {Error, PdfError};
+use font_subset::{subset_truetype, subset_tag};
+use builtin_font_metrics::*;
+use {Error, PdfError, Pt};
 
 /// The font
 #[derive(Debug, Clone, PartialEq)]
@@ -59,6 +61,65 @@ impl Into<&'static str> for BuiltinFont {
     }
 }
 
+impl BuiltinFont {
+
+    /// Returns the metrics of the font from the bundled AFM data,
+    /// in units of 1/1000 of the font size
+    ///
+    /// The line gap is the difference between the height of the font bounding box
+    /// and the ascent - descent, since AFM files have no line gap.
+    pub fn font_metrics(&self) -> FontMetrics {
+        use BuiltinFont::*;
+        // Ascender, Descender and FontBBox (bottom, top) from the AFM files. Symbol and
+        // ZapfDingbats have no Ascender / Descender, the font bounding box is used instead
+        let (ascent, descent, bbox_bottom, bbox_top) = match self {
+            TimesRoman              => (683, -217, -218, 898),
+            TimesBold               => (683, -217, -218, 935),
+            TimesItalic             => (683, -217, -217, 883),
+            TimesBoldItalic         => (683, -217, -218, 921),
+            Helvetica               => (718, -207, -225, 931),
+            HelveticaBold           => (718, -207, -228, 962),
+            HelveticaOblique        => (718, -207, -225, 931),
+            HelveticaBoldOblique    => (718, -207, -228, 962),
+            Courier                 => (629, -157, -250, 805),
+            CourierOblique          => (629, -157, -250, 805),
+            CourierBold             => (629, -157, -250, 801),
+            CourierBoldOblique      => (629, -157, -250, 801),
+            Symbol                  => (1010, -293, -293, 1010),
+            ZapfDingbats            => (820, -143, -143, 820),
+        };
+
+        FontMetrics {
+            ascent,
+            descent,
+            line_gap: (bbox_top - bbox_bottom) - (ascent - descent),
+            units_per_em: 1000,
+        }
+    }
+
+    /// Returns the widths of the 256 character codes of the font
+    /// (in the encoding used by `write_text`), in 1/1000 of the font size
+    pub(crate) fn glyph_widths(&self) -> &'static [u16; 256] {
+        use BuiltinFont::*;
+        match self {
+            TimesRoman              => &TIMES_ROMAN_WIDTHS,
+            TimesBold               => &TIMES_BOLD_WIDTHS,
+            TimesItalic             => &TIMES_ITALIC_WIDTHS,
+            TimesBoldItalic         => &TIMES_BOLD_ITALIC_WIDTHS,
+            Helvetica               => &HELVETICA_WIDTHS,
+            HelveticaBold           => &HELVETICA_BOLD_WIDTHS,
+            HelveticaOblique        => &HELVETICA_OBLIQUE_WIDTHS,
+            HelveticaBoldOblique    => &HELVETICA_BOLD_OBLIQUE_WIDTHS,
+            Courier                 => &COURIER_WIDTHS,
+            CourierOblique          => &COURIER_OBLIQUE_WIDTHS,
+            CourierBold             => &COURIER_BOLD_WIDTHS,
+            CourierBoldOblique      => &COURIER_BOLD_OBLIQUE_WIDTHS,
+            Symbol                  => &SYMBOL_WIDTHS,
+            ZapfDingbats            => &ZAPF_DINGBATS_WIDTHS,
+        }
+    }
+}
+
 impl Into<LoDictionary> for BuiltinFont {
     fn into(self) -> LoDictionary {
         use lopdf::Object;
@@ -89,6 +150,9 @@ pub struct ExternalFont {
     pub(crate) face_name: String,
     /// Is the font written vertically? Default: false
     pub(crate) vertical_writing: bool,
+    /// Glyphs that were written to the document, mapped to the characters
+    /// they represent. Used for subsetting the font when saving the document
+    pub(crate) used_glyphs: BTreeMap<u16, Vec<char>>,
 }
 
 /// The text rendering mode determines how a text is drawn
@@ -156,6 +220,16 @@ impl ExternalFont {
             font_data,
             face_name,
             vertical_writing: false,
+            used_glyphs: BTreeMap::new(),
+        }
+    }
+
+    /// Marks a glyph as used, so that it is included when the font is subset.
+    /// `text` is the text the glyph represents (may be empty if unknown)
+    pub(crate) fn mark_glyph_used(&mut self, glyph_id: u16, text: &[char]) {
+        let entry = self.used_glyphs.entry(glyph_id).or_default();
+        if entry.is_empty() {
+            entry.extend_from_slice(text);
         }
     }
 
@@ -166,17 +240,40 @@ impl ExternalFont {
         use lopdf::Object;
         use lopdf::Object::*;
 
-        let face_name = self.face_name.clone();
+        // Glyphs that should end up in the embedded font. If no glyphs were
+        // tracked (for example because only raw operations were used), the
+        // whole font has to be embedded
+        let subset_glyphs: Option<BTreeSet<u16>> = if self.used_glyphs.is_empty() {
+            None
+        } else {
+            Some(Some(0).into_iter().chain(self.used_glyphs.keys().cloned()).collect())
+        };
+
+        // Only TrueType outlines can be subset, CFF fonts are embedded as a whole
+        let subset = subset_glyphs.as_ref().and_then(|glyphs| subset_truetype(&self.font_bytes, glyphs));
+
+        // Subset fonts have to be named "ABCDEF+FontName", see page 442 in the PDF 1.7 reference
+        let face_name = match (&subset, &subset_glyphs) {
+            (Some(_), Some(glyphs)) => format!("{}+{}", subset_tag(glyphs), self.face_name),
+            _ => self.face_name.clone(),
+        };
 
         // Extract basic font information
         let face_metrics = self.font_data.font_metrics();
+        let descriptor_metrics = font_descriptor_metrics(&self.font_bytes, &face_metrics);
+
+        // the glyphs of a subset font are listed in the CIDSet (required by PDF/A-1)
+        let (font_bytes, cid_set) = match subset {
+            Some((font_bytes, glyphs)) => (font_bytes, Some(cid_set(&glyphs))),
+            None => (self.font_bytes, None),
+        };
 
         let font_stream = LoStream::new(
             LoDictionary::from_iter(vec![
-                ("Length1", Integer(self.font_bytes.len() as i64)),
+                ("Length1", Integer(font_bytes.len() as i64)),
                 ]),
-            self.font_bytes)
-        .with_compression(false); /* important! font stream must not be compressed! */
+            font_bytes)
+        .with_compression(false); /* compressed only with `PdfSaveOptions::compress_fonts` */
 
         // Begin setting required font attributes
         let mut font_vec: Vec<(::std::string::String, Object)> = vec![
@@ -191,37 +288,43 @@ impl ExternalFont {
         let mut font_descriptor_vec: Vec<(::std::string::String, Object)> = vec![
             ("Type".into(), Name("FontDescriptor".into())),
             ("FontName".into(), Name(face_name.clone().into_bytes())),
-            ("Ascent".into(), Integer(i64::from(face_metrics.ascent))),
-            ("Descent".into(), Integer(i64::from(face_metrics.descent))),
-            ("CapHeight".into(), Integer(i64::from(face_metrics.ascent))),
-            ("ItalicAngle".into(), Integer(0)),
-            ("Flags".into(), Integer(32)),
-            ("StemV".into(), Integer(80)),
         ];
+        font_descriptor_vec.extend(descriptor_metrics);
 
         // End setting required font arguments
 
-        // Maximum height of a single character in the font
-        let mut max_height = 0;
-        // Total width of all characters
-        let mut total_width = 0;
         // Widths (or heights, depends on self.vertical_writing)
         // of the individual characters, indexed by glyph id
         let mut widths = Vec::<(u32, u32)>::new();
 
-        // Glyph IDs - (Unicode IDs - character width, character height)
-        let mut cmap = BTreeMap::<u32, (u32, u32, u32)>::new();
-        cmap.insert(0, (0, 1000, 1000));
+        // Glyph IDs - (Unicode text - character width, character height)
+        let mut cmap = BTreeMap::<u32, (Vec<char>, u32, u32)>::new();
+        cmap.insert(0, (Vec::new(), 1000, 1000));
+
+        // Only the glyphs that are used in the document end up in the
+        // W array and the ToUnicode map if the font is subset
+        let glyph_ids: Vec<(u16, Vec<char>)> = match subset_glyphs {
+            Some(_) => {
+                let font_data = &self.font_data;
+                let mut reverse_cmap = None;
+                self.used_glyphs.iter().map(|(glyph_id, text)| {
+                    let text = if text.is_empty() {
+                        // glyph was written without any known text, look it up in the font
+                        let reverse_cmap = reverse_cmap.get_or_insert_with(|| font_data.glyph_ids());
+                        reverse_cmap.get(glyph_id).map(|c| vec![*c]).unwrap_or_default()
+                    } else {
+                        text.clone()
+                    };
+                    (*glyph_id, text)
+                }).collect()
+            },
+            None => self.font_data.glyph_ids().into_iter().map(|(glyph_id, c)| (glyph_id, vec![c])).collect(),
+        };
 
-        for (glyph_id, c) in self.font_data.glyph_ids() {
+        for (glyph_id, text) in glyph_ids {
             if let Some(glyph_metrics) = self.font_data.glyph_metrics(glyph_id) {
-                if glyph_metrics.height > max_height {
-                    max_height = glyph_metrics.height;
-                }
-
-                total_width += glyph_metrics.width;
                 cmap.insert(glyph_id as u32,
-                            (c as u32, glyph_metrics.width as u32, glyph_metrics.height as u32));
+                            (text, glyph_metrics.width as u32, glyph_metrics.height as u32));
             }
         }
 
@@ -251,8 +354,10 @@ impl ExternalFont {
                     cur_first_bit = (*glyph_id >> 8) as u16;
                 }
 
-                let (unicode, width, _) = *unicode_width_tuple;
-                current_cmap_block.push((*glyph_id, unicode));
+                let (ref text, width, _) = *unicode_width_tuple;
+                if !text.is_empty() {
+                    current_cmap_block.push((*glyph_id, text.clone()));
+                }
                 widths.push((*glyph_id, width));
             };
 
@@ -315,14 +420,15 @@ impl ExternalFont {
                     ("Ordering", String("Identity".into(), StringFormat::Literal)),
                     ("Supplement", Integer(0)),
             ]))),
+            // CIDs are the glyph IDs of the embedded font
+            ("CIDToGIDMap", Name("Identity".into())),
             w, dw,
         ]);
 
-        let font_bbox = vec![ Integer(0), Integer(max_height as i64), Integer(total_width as i64), Integer(max_height as i64) ];
         font_descriptor_vec.push(("FontFile2".into(), Reference(doc.add_object(font_stream))));
-
-        // although the following entry is technically not needed, Adobe Reader needs it
-        font_descriptor_vec.push(("FontBBox".into(), Array(font_bbox)));
+        if let Some(cid_set) = cid_set {
+            font_descriptor_vec.push(("CIDSet".into(), Reference(doc.add_object(LoStream::new(LoDictionary::new(), cid_set)))));
+        }
 
         let font_descriptor_vec_id = doc.add_object(LoDictionary::from_iter(font_descriptor_vec));
 
@@ -335,9 +441,70 @@ impl ExternalFont {
     }
 }
 
+// flags of the font descriptor, see page 458 in the PDF 1.7 reference
+const FONT_FLAG_FIXED_PITCH: i64 = 1 << 0;
+const FONT_FLAG_SYMBOLIC: i64 = 1 << 2;
+const FONT_FLAG_ITALIC: i64 = 1 << 6;
+
+/// Entries of the font descriptor that describe the font program (`Ascent`, `Descent`,
+/// `CapHeight`, `ItalicAngle`, `Flags`, `StemV` and `FontBBox`). Metrics are scaled to
+/// the glyph space of PDF (1000 units per em), the rest is read from the `OS/2`, `post`
+/// and `head` tables of the font.
+fn font_descriptor_metrics(font_bytes: &[u8], face_metrics: &FontMetrics)
+-> Vec<(::std::string::String, lopdf::Object)>
+{
+    use lopdf::Object::*;
+
+    let units_per_em = f64::from(face_metrics.units_per_em.max(1));
+    let scale = |value: i16| Integer((f64::from(value) * 1000.0 / units_per_em).round() as i64);
+
+    let face = Face::from_slice(font_bytes, 0).ok();
+    let face = face.as_ref();
+
+    let cap_height = face.and_then(|face| face.capital_height()).unwrap_or(face_metrics.ascent);
+    let italic_angle = face.and_then(|face| face.italic_angle()).unwrap_or(0.0);
+    let font_bbox = match face.map(|face| face.global_bounding_box()) {
+        Some(bbox) => vec![scale(bbox.x_min), scale(bbox.y_min), scale(bbox.x_max), scale(bbox.y_max)],
+        None => vec![Integer(0), scale(face_metrics.descent), Integer(1000), scale(face_metrics.ascent)],
+    };
+
+    // the glyphs are selected by their ID and not with a standard Latin encoding
+    let mut flags = FONT_FLAG_SYMBOLIC;
+    if matches!(face, Some(face) if face.is_monospaced()) {
+        flags |= FONT_FLAG_FIXED_PITCH;
+    }
+    if italic_angle != 0.0 || matches!(face, Some(face) if face.is_italic()) {
+        flags |= FONT_FLAG_ITALIC;
+    }
+
+    // PDF has no exact definition of the stem width, this is the usual estimate from the weight class
+    let weight = face.map(|face| face.weight().to_number()).unwrap_or(400).max(50);
+    let stem_v = 10 + 220 * (i64::from(weight) - 50) / 900;
+
+    vec![
+        ("Ascent".into(), scale(face_metrics.ascent)),
+        ("Descent".into(), scale(face_metrics.descent)),
+        ("CapHeight".into(), scale(cap_height)),
+        ("ItalicAngle".into(), Real(f64::from(italic_angle))),
+        ("Flags".into(), Integer(flags)),
+        ("StemV".into(), Integer(stem_v)),
+        ("FontBBox".into(), Array(font_bbox)),
+    ]
+}
+
+/// Creates the content of a `CIDSet` stream: one bit per CID, the highest bit of the first byte is CID 0
+fn cid_set(glyphs: &BTreeSet<u16>)
+-> Vec<u8>
+{
+    let mut bits = vec![0_u8; glyphs.iter().next_back().map(|gid| usize::from(*gid) / 8 + 1).unwrap_or(0)];
+    for gid in glyphs {
+        bits[usize::from(*gid) / 8] |= 0x80 >> (gid % 8);
+    }
+    bits
+}
+
 type GlyphId = u32;
-type UnicodeCodePoint = u32;
-type CmapBlock = Vec<(GlyphId, UnicodeCodePoint)>;
+type CmapBlock = Vec<(GlyphId, Vec<char>)>;
 
 /// Generates a CMAP (character map) from valid cmap blocks
 fn generate_cid_to_unicode_map(face_name: String, all_cmap_blocks: Vec<CmapBlock>) -> String {
@@ -346,8 +513,14 @@ fn generate_cid_to_unicode_map(face_name: String, all_cmap_blocks: Vec<CmapBlock
 
     for cmap_block in all_cmap_blocks.into_iter().filter(|block| !block.is_empty() || block.len() < 100) {
         cid_to_unicode_map.push_str(format!("{} beginbfchar\r\n", cmap_block.len()).as_str());
-        for (glyph_id, unicode) in cmap_block {
-            cid_to_unicode_map.push_str(format!("<{:04x}> <{:04x}>\n", glyph_id, unicode).as_str());
+        for (glyph_id, text) in cmap_block {
+            // the destination is UTF-16BE, so that ligatures (one glyph, multiple
+            // characters) and characters outside of the BMP are mapped correctly
+            let mut buf = [0_u16; 2];
+            let unicode: String = text.iter()
+                .flat_map(|c| c.encode_utf16(&mut buf).iter().map(|u| format!("{:04x}", u)).collect::<Vec<_>>())
+                .collect();
+            cid_to_unicode_map.push_str(format!("<{:04x}> <{}>\n", glyph_id, unicode).as_str());
         }
         cid_to_unicode_map.push_str("endbfchar\r\n");
     }
@@ -356,6 +529,80 @@ fn generate_cid_to_unicode_map(face_name: String, all_cmap_blocks: Vec<CmapBlock
     cid_to_unicode_map
 }
 
+impl Font {
+
+    /// Measures a string, as it would be written by `PdfLayerReference::write_text`
+    /// in this font with the given font size (in points)
+    ///
+    /// No kerning is applied. For text written by `write_shaped_text`, use
+    /// `ExternalFont::shape_text` and `ShapedText::advance_width` instead.
+    pub fn measure_text(&self, text: &str, font_size: f64) -> TextMetrics {
+        let (metrics, width) = match self {
+            Font::BuiltinFont(f) => {
+                // same encoding as in write_text
+                let widths = f.glyph_widths();
+                let width: u64 = lopdf::Document::encode_text(Some("WinAnsiEncoding"), text)
+                    .into_iter()
+                    .map(|b| u64::from(widths[b as usize]))
+                    .sum();
+                (f.font_metrics(), width)
+            },
+            Font::ExternalFont(f) => {
+                let width: u64 = text.chars()
+                    .filter_map(|ch| f.font_data.glyph_id(ch))
+                    .filter_map(|gid| f.font_data.glyph_metrics(gid))
+                    .map(|m| u64::from(m.width))
+                    .sum();
+                (f.font_data.font_metrics(), width)
+            },
+        };
+
+        let scale = font_size / f64::from(metrics.units_per_em);
+
+        TextMetrics {
+            width: Pt(width as f64 * scale),
+            ascent: Pt(f64::from(metrics.ascent) * scale),
+            descent: Pt(f64::from(metrics.descent) * scale),
+            line_gap: Pt(f64::from(metrics.line_gap) * scale),
+        }
+    }
+
+    /// Returns if both fonts are the same font (same builtin font or same font file),
+    /// regardless of their name in the document
+    pub(crate) fn is_same_font(&self, other: &Font) -> bool {
+        match (self, other) {
+            (Font::BuiltinFont(a), Font::BuiltinFont(b)) => a == b,
+            (Font::ExternalFont(a), Font::ExternalFont(b)) => {
+                a.vertical_writing == b.vertical_writing && a.font_bytes == b.font_bytes
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Measurements of a string written in a certain font and font size.
+/// Use `.into()` to convert the values to `Mm`.
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct TextMetrics {
+    /// Advance width of the text
+    pub width: Pt,
+    /// Ascent of the font (distance from the baseline to the top, positive)
+    pub ascent: Pt,
+    /// Descent of the font (distance from the baseline to the bottom, usually negative)
+    pub descent: Pt,
+    /// Additional space the font recommends between two lines
+    pub line_gap: Pt,
+}
+
+impl TextMetrics {
+
+    /// Returns the recommended distance between two baselines,
+    /// i.e. `ascent - descent + line_gap`
+    pub fn line_height(&self) -> Pt {
+        Pt(self.ascent.0 - self.descent.0 + self.line_gap.0)
+    }
+}
+
 impl PartialEq for ExternalFont {
     /// Two fonts are equal if their names are equal, the contents aren't checked
     fn eq(&self, other: &ExternalFont) -> bool {
@@ -415,6 +662,22 @@ impl FontList {
         font_ref
     }
 
+    /// Returns the font data without cloning the font
+    #[inline]
+    pub(crate) fn get_font_data(&self, font: &IndirectFontRef)
+    -> Option<&Font>
+    {
+        self.fonts.get(font).map(|f| &f.data)
+    }
+
+    /// Returns a mutable reference to the font, for tracking the glyphs that are used
+    #[inline]
+    pub(crate) fn get_font_mut(&mut self, font: &IndirectFontRef)
+    -> Option<&mut DirectFontRef>
+    {
+        self.fonts.get_mut(font)
+    }
+
     /// Turns an indirect font reference into a direct one
     /// (Warning): clones the direct font reference
     #[inline]
@@ -445,6 +708,65 @@ impl FontList {
         self.fonts.is_empty()
     }
 
+    /// Adds the fonts of another document (when documents are merged). Fonts that are
+    /// already in this list are shared, the others get a new name if their name is taken.
+    /// Returns the old and new name of each font that was renamed
+    pub(crate) fn merge<F>(&mut self, other: FontList, mut new_object_id: F)
+    -> BTreeMap<String, String> where F: FnMut() -> lopdf::ObjectId
+    {
+        let mut renamed = BTreeMap::new();
+
+        for (indirect_ref, direct_ref) in other.fonts {
+            let existing = self.fonts.iter_mut().find(|(_, existing)| existing.data.is_same_font(&direct_ref.data));
+            if let Some((existing_ref, existing)) = existing {
+                // the glyphs of both documents are needed in the font subset
+                if let (&mut Font::ExternalFont(ref mut font), Font::ExternalFont(other_font)) = (&mut existing.data, direct_ref.data) {
+                    for (glyph, chars) in other_font.used_glyphs {
+                        let used_chars = font.used_glyphs.entry(glyph).or_insert_with(Vec::new);
+                        for c in chars {
+                            if !used_chars.contains(&c) {
+                                used_chars.push(c);
+                            }
+                        }
+                    }
+                }
+                if existing_ref.name != indirect_ref.name {
+                    renamed.insert(indirect_ref.name, existing_ref.name.clone());
+                }
+                continue;
+            }
+
+            let mut name = indirect_ref.name.clone();
+            let mut index = self.fonts.len();
+            while self.fonts.contains_key(&IndirectFontRef::new(name.as_str())) {
+                name = format!("F{}", index);
+                index += 1;
+            }
+
+            // only the name in the resources changes, the font keeps its /BaseFont
+            if name != indirect_ref.name {
+                renamed.insert(indirect_ref.name, name.clone());
+            }
+
+            self.fonts.insert(IndirectFontRef::new(name), DirectFontRef { inner_obj: new_object_id(), data: direct_ref.data });
+        }
+
+        renamed
+    }
+
+    /// Iterates over the fonts and their names
+    pub(crate) fn iter(&self)
+    -> impl Iterator<Item = (&IndirectFontRef, &DirectFontRef)>
+    {
+        self.fonts.iter()
+    }
+
+    /// Removes the fonts whose name is not in `used_names`
+    pub(crate) fn retain_used(&mut self, used_names: &BTreeSet<String>)
+    {
+        self.fonts.retain(|font_ref, _| used_names.contains(&font_ref.name));
+    }
+
     /// Converts the fonts into a dictionary
     pub(crate) fn into_with_document(self, doc: &mut lopdf::Document)
     ->lopdf::Dictionary
@@ -467,16 +789,44 @@ impl FontList {
 
 /// The unscaled base metrics for a font provided by a [`FontData`](trait.FontData.html)
 /// implementation.
+///
+/// Use `FontMetrics::new` (or `..Default::default()`) instead of a struct literal with
+/// all fields, so that fields which are added later don't break the implementation.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct FontMetrics {
     /// The ascent of the font.
     pub ascent: i16,
     /// The descent of the font.
     pub descent: i16,
+    /// The recommended gap between two lines of text.
+    pub line_gap: i16,
     /// The units per em square for this font.
     pub units_per_em: u16,
 }
 
+impl FontMetrics {
+    /// Creates new font metrics without a line gap
+    pub fn new(ascent: i16, descent: i16, units_per_em: u16)
+    -> Self
+    {
+        Self {
+            ascent,
+            descent,
+            line_gap: 0,
+            units_per_em,
+        }
+    }
+
+    /// Sets the recommended gap between two lines of text
+    #[inline]
+    pub fn with_line_gap(mut self, line_gap: i16)
+    -> Self
+    {
+        self.line_gap = line_gap;
+        self
+    }
+}
+
 /// The metrics for a glyph provided by a [`FontData`](trait.FontData.html) implementation.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct GlyphMetrics {
@@ -489,10 +839,12 @@ pub struct GlyphMetrics {
 /// Provides access to font metrics.
 ///
 /// Per default, printpdf uses [`owned_ttf_parser`][] to extract the font data.  You can implement
-/// this trait for other types if you want to use a different font backend.
+/// this trait for other types if you want to use a different font backend. Implementations
+/// have to be `Send + Sync`, because documents can be shared between threads (wrap
+/// non-thread-safe backends in a `Mutex`, or use `Arc` instead of `Rc`).
 ///
 /// [`owned_ttf_parser`]: https://docs.rs/owned_ttf_parser/latest/owned_ttf_parser/
-pub trait FontData: FontDataClone + std::fmt::Debug {
+pub trait FontData: FontDataClone + std::fmt::Debug + Send + Sync {
     /// Returns the unscaled metrics for this font.
     fn font_metrics(&self) -> FontMetrics;
 
@@ -537,6 +889,7 @@ impl FontData for TtfFace {
         FontMetrics {
             ascent: self.face().ascender(),
             descent: self.face().descender(),
+            line_gap: self.face().line_gap(),
             units_per_em: self.units_per_em,
         }
     }
@@ -598,3 +951,12 @@ impl Clone for Box<dyn FontData> {
         self.clone_font_data()
     }
 }
+
+#[test]
+fn test_measure_builtin_font() {
+    let metrics = Font::BuiltinFont(BuiltinFont::Helvetica).measure_text("Hello", 10.0);
+    // H = 722, e = 556, l = 222, o = 556
+    assert!((metrics.width.0 - 22.78).abs() < 0.0001);
+    assert!((metrics.ascent.0 - 7.18).abs() < 0.0001);
+    assert!((metrics.descent.0 + 2.07).abs() < 0.0001);
+}
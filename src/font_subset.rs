@@ -0,0 +1,311 @@
+//! Subsetting of TrueType fonts, so that only the glyphs that are
+//! actually used on the pages end up in the embedded `FontFile2`
+//!
+//! Glyph IDs are kept stable: unused glyphs are emptied out instead of
+//! being removed, so that the content streams (which reference glyphs
+//! by their ID via `Identity-H`) do not need to be rewritten.
+
+use std::collections::BTreeSet;
+
+/// Tables that are copied into the subset font. Everything else
+/// (`post`, `GSUB`, `GPOS`, `kern`, ...) is not needed by a PDF viewer
+/// for a `CIDFontType2` font and is dropped.
+const KEEP_TABLES: [&[u8; 4]; 11] = [
+    b"OS/2", b"cvt ", b"fpgm", b"glyf", b"head", b"hhea",
+    b"hmtx", b"loca", b"maxp", b"name", b"prep",
+];
+
+// flags for composite glyph components, see the `glyf` table specification
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+#[derive(Debug, Copy, Clone)]
+struct TableRecord<'a> {
+    tag: [u8; 4],
+    data: &'a [u8],
+}
+
+/// Creates a subset of a TrueType font, containing only the outlines of the
+/// glyphs in `used_glyphs` (plus `.notdef` and all components of composite glyphs).
+/// Returns the subset font and the glyphs it contains.
+///
+/// Returns `None` if the font can't be subset (CFF-based OpenType fonts,
+/// font collections or corrupt fonts), in which case the full font should be embedded.
+pub(crate) fn subset_truetype(font_bytes: &[u8], used_glyphs: &BTreeSet<u16>) -> Option<(Vec<u8>, BTreeSet<u16>)> {
+
+    let tables = read_table_directory(font_bytes)?;
+    let find = |tag: &[u8; 4]| tables.iter().find(|t| &t.tag == tag).map(|t| t.data);
+
+    let head = find(b"head")?;
+    let maxp = find(b"maxp")?;
+    let loca = find(b"loca")?;
+    let glyf = find(b"glyf")?;
+
+    let long_loca = read_u16(head, 50)? != 0;
+    let num_glyphs = read_u16(maxp, 4)?;
+
+    let glyph_range = |gid: u16| -> Option<(usize, usize)> {
+        let (start, end) = if long_loca {
+            (read_u32(loca, gid as usize * 4)? as usize, read_u32(loca, gid as usize * 4 + 4)? as usize)
+        } else {
+            (read_u16(loca, gid as usize * 2)? as usize * 2, read_u16(loca, gid as usize * 2 + 2)? as usize * 2)
+        };
+        if start > end || end > glyf.len() { None } else { Some((start, end)) }
+    };
+
+    // collect all glyphs, including the components of composite glyphs
+    let mut glyphs = BTreeSet::new();
+    let mut stack: Vec<u16> = Some(0).into_iter().chain(used_glyphs.iter().cloned()).collect();
+
+    while let Some(gid) = stack.pop() {
+        if gid >= num_glyphs || !glyphs.insert(gid) {
+            continue;
+        }
+        let (start, end) = glyph_range(gid)?;
+        stack.extend(composite_components(&glyf[start..end])?);
+    }
+
+    // build the new glyf and loca tables, glyph IDs stay the same
+    let mut new_glyf = Vec::new();
+    let mut new_loca = Vec::with_capacity((num_glyphs as usize + 1) * 4);
+
+    for gid in 0..num_glyphs {
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        if glyphs.contains(&gid) {
+            let (start, end) = glyph_range(gid)?;
+            new_glyf.extend_from_slice(&glyf[start..end]);
+            while new_glyf.len() % 4 != 0 {
+                new_glyf.push(0);
+            }
+        }
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+
+    // loca is now always in the long format, checkSumAdjustment is recalculated later
+    let mut new_head = head.to_vec();
+    new_head[8..12].copy_from_slice(&[0, 0, 0, 0]);
+    new_head[50..52].copy_from_slice(&1_u16.to_be_bytes());
+
+    let mut new_tables = Vec::new();
+    for table in &tables {
+        if !KEEP_TABLES.iter().any(|t| **t == table.tag) {
+            continue;
+        }
+        let data = match &table.tag {
+            b"glyf" => new_glyf.clone(),
+            b"loca" => new_loca.clone(),
+            b"head" => new_head.clone(),
+            _ => table.data.to_vec(),
+        };
+        new_tables.push((table.tag, data));
+    }
+
+    Some((write_font(new_tables), glyphs))
+}
+
+/// Generates the six-letter subset tag (`ABCDEF+`) that has to prefix the
+/// `BaseFont` name of a subset font. The tag is derived from the glyph set,
+/// so that the same subset always gets the same tag.
+pub(crate) fn subset_tag(used_glyphs: &BTreeSet<u16>) -> String {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for gid in used_glyphs {
+        for byte in &gid.to_be_bytes() {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    (0..6).map(|_| {
+        let ch = (b'A' + (hash % 26) as u8) as char;
+        hash /= 26;
+        ch
+    }).collect()
+}
+
+/// Parses the table directory of a TrueType font (not a collection, not CFF)
+fn read_table_directory(font_bytes: &[u8]) -> Option<Vec<TableRecord<'_>>> {
+    let version = read_u32(font_bytes, 0)?;
+    if version != 0x0001_0000 && version != u32::from_be_bytes(*b"true") {
+        return None;
+    }
+
+    let num_tables = read_u16(font_bytes, 4)? as usize;
+    let mut tables = Vec::with_capacity(num_tables);
+
+    for i in 0..num_tables {
+        let record = 12 + i * 16;
+        let tag = font_bytes.get(record..record + 4)?;
+        let offset = read_u32(font_bytes, record + 8)? as usize;
+        let length = read_u32(font_bytes, record + 12)? as usize;
+        tables.push(TableRecord {
+            tag: [tag[0], tag[1], tag[2], tag[3]],
+            data: font_bytes.get(offset..offset.checked_add(length)?)?,
+        });
+    }
+
+    Some(tables)
+}
+
+/// Returns the glyph IDs referenced by a composite glyph
+/// (or an empty list if the glyph is a simple glyph)
+fn composite_components(glyph: &[u8]) -> Option<Vec<u16>> {
+    let mut components = Vec::new();
+
+    if glyph.is_empty() || (read_u16(glyph, 0)? as i16) >= 0 {
+        return Some(components);
+    }
+
+    let mut offset = 10;
+    loop {
+        let flags = read_u16(glyph, offset)?;
+        components.push(read_u16(glyph, offset + 2)?);
+        offset += 4;
+        offset += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            offset += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            offset += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            offset += 8;
+        }
+        if flags & MORE_COMPONENTS == 0 {
+            break;
+        }
+    }
+
+    Some(components)
+}
+
+/// Writes a TrueType font from a list of (sorted) tables, including the
+/// table directory, table checksums and the `head.checkSumAdjustment`
+fn write_font(tables: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.max(1).leading_zeros() as u16;
+    let search_range = (1_u16 << entry_selector) * 16;
+    let range_shift = num_tables * 16 - search_range;
+
+    let mut font = Vec::new();
+    font.extend_from_slice(&0x0001_0000_u32.to_be_bytes());
+    font.extend_from_slice(&num_tables.to_be_bytes());
+    font.extend_from_slice(&search_range.to_be_bytes());
+    font.extend_from_slice(&entry_selector.to_be_bytes());
+    font.extend_from_slice(&range_shift.to_be_bytes());
+
+    let mut offset = 12 + tables.len() * 16;
+    let mut head_offset = None;
+
+    for (tag, data) in &tables {
+        if tag == b"head" {
+            head_offset = Some(offset);
+        }
+        font.extend_from_slice(tag);
+        font.extend_from_slice(&checksum(data).to_be_bytes());
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        offset += (data.len() + 3) & !3;
+    }
+
+    for (_, data) in &tables {
+        font.extend_from_slice(data);
+        while font.len() % 4 != 0 {
+            font.push(0);
+        }
+    }
+
+    if let Some(head_offset) = head_offset {
+        let adjustment = 0xB1B0_AFBA_u32.wrapping_sub(checksum(&font));
+        font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+
+    font
+}
+
+/// TrueType table checksum: sum of all big-endian u32, padded with zeroes
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0_u32, |sum, chunk| {
+        let mut word = [0_u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+#[inline]
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+#[inline]
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[test]
+fn test_font_checksum() {
+    assert_eq!(checksum(&[0, 0, 0, 1, 0, 0, 0, 2]), 3);
+    assert_eq!(checksum(&[0, 0, 1]), 256);
+}
+
+#[test]
+fn test_subset_tag_is_stable() {
+    let glyphs: BTreeSet<u16> = vec![3, 4, 5].into_iter().collect();
+    let tag = subset_tag(&glyphs);
+    assert_eq!(tag.len(), 6);
+    assert!(tag.chars().all(|c| c.is_ascii_uppercase()));
+    assert_eq!(tag, subset_tag(&glyphs));
+}
+
+#[test]
+fn test_subset_font() {
+    use std::io::Cursor;
+    use lopdf::Object;
+    use owned_ttf_parser::{Face, GlyphId};
+    use {Mm, PdfDocument};
+
+    let font_bytes = include_bytes!("../assets/fonts/RobotoMedium.ttf");
+    let original = Face::from_slice(font_bytes, 0).unwrap();
+    let used: BTreeSet<u16> = "Hi".chars().map(|ch| original.glyph_index(ch).unwrap().0).collect();
+    let (subset, glyphs) = subset_truetype(font_bytes, &used).unwrap();
+    assert!(subset.len() < font_bytes.len() / 2);
+    assert!(glyphs.contains(&0) && used.is_subset(&glyphs));
+
+    // glyph IDs stay the same, the outlines of unused glyphs are removed
+    let face = Face::from_slice(&subset, 0).unwrap();
+    assert_eq!(face.number_of_glyphs(), original.number_of_glyphs());
+    for gid in &used {
+        assert_eq!(face.glyph_bounding_box(GlyphId(*gid)), original.glyph_bounding_box(GlyphId(*gid)));
+    }
+    let unused = original.glyph_index('Z').unwrap();
+    assert!(original.glyph_bounding_box(unused).is_some());
+    assert!(face.glyph_bounding_box(unused).is_none());
+
+    // loca is in the long format and points into glyf
+    let tables = read_table_directory(&subset).unwrap();
+    let find = |tag: &[u8; 4]| tables.iter().find(|table| &table.tag == tag).unwrap().data;
+    let (loca, glyf) = (find(b"loca"), find(b"glyf"));
+    let offsets: Vec<usize> = loca.chunks(4).map(|offset| u32::from_be_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize).collect();
+    assert_eq!(offsets.len(), usize::from(face.number_of_glyphs()) + 1);
+    assert!(offsets.windows(2).all(|offsets| offsets[0] <= offsets[1]));
+    assert_eq!(*offsets.last().unwrap(), glyf.len());
+    for gid in 0..face.number_of_glyphs() {
+        let has_outline = offsets[usize::from(gid)] < offsets[usize::from(gid) + 1];
+        assert!(!has_outline || glyphs.contains(&gid));
+    }
+
+    // the CIDSet of the embedded font lists the glyphs of the subset
+    let (doc, page, layer) = PdfDocument::new("Subset", Mm(100.0), Mm(100.0), "Layer 1");
+    let font = doc.add_external_font(Cursor::new(font_bytes.to_vec())).unwrap();
+    doc.get_page(page).get_layer(layer).use_text("Hi", 12.0, Mm(10.0), Mm(10.0), &font);
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let descriptor = saved.objects.values()
+        .filter_map(|object| object.as_dict().ok())
+        .find(|dict| dict.get(b"Type").and_then(Object::as_name_str).ok() == Some("FontDescriptor"))
+        .unwrap();
+    let cid_set = saved.get_object(descriptor.get(b"CIDSet").and_then(Object::as_reference).unwrap()).unwrap().as_stream().unwrap();
+    let cid_set = cid_set.decompressed_content().unwrap_or_else(|_| cid_set.content.clone());
+    let cids: BTreeSet<u16> = (0..cid_set.len() as u16 * 8).filter(|cid| cid_set[usize::from(cid / 8)] & (0x80 >> (cid % 8)) != 0).collect();
+    assert_eq!(cids, glyphs);
+}
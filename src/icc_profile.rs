@@ -55,6 +55,26 @@ impl IccProfile {
         self
     }
 
+    /// Returns the color space of the profile
+    #[inline]
+    pub fn get_type(&self)
+    -> IccProfileType
+    {
+        self.icc_type
+    }
+
+    /// Returns the color space in the header of the profile, `None` if the profile
+    /// is too short or has another color space (Lab, ...)
+    pub fn get_header_type(&self)
+    -> Option<IccProfileType>
+    {
+        match self.icc.get(16..20)? {
+            b"CMYK" => Some(IccProfileType::Cmyk),
+            b"RGB " => Some(IccProfileType::Rgb),
+            b"GRAY" => Some(IccProfileType::Greyscale),
+            _ => None,
+        }
+    }
 }
 
 impl Into<lopdf::Stream> for IccProfile {
@@ -100,6 +120,8 @@ impl Into<lopdf::Stream> for IccProfile {
 #[derive(Debug, Clone, PartialEq)]
 pub struct IccProfileRef {
     pub(crate) name: String,
+    /// Index in the `IccProfileList` of the document
+    pub(crate) index: usize,
 }
 
 impl IccProfileRef {
@@ -108,14 +130,24 @@ impl IccProfileRef {
     -> Self
     {
         Self {
-            name: format!("/ICC{}", index)
+            name: format!("/ICC{}", index),
+            index,
         }
     }
 }
 
+/// ICC profiles of a document, see `PdfDocumentReference::add_icc_profile`
 #[derive(Default, Clone, Debug, PartialEq)]
 pub struct IccProfileList {
-    profiles: Vec<IccProfile>,
+    profiles: Vec<IccProfileEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum IccProfileEntry {
+    /// Profile that is embedded the first time a color uses it
+    Profile(IccProfile),
+    /// Color space of the profile and the ID of its stream
+    Embedded(IccProfileType, lopdf::ObjectId),
 }
 
 impl IccProfileList {
@@ -129,9 +161,294 @@ impl IccProfileList {
     /// Adds an ICC profile
     pub fn add_profile(&mut self, profile: IccProfile)
     -> IccProfileRef
+    {
+        self.push(IccProfileEntry::Profile(profile))
+    }
+
+    /// Adds an ICC profile, which is already added to the document as the stream `object_id`
+    pub(crate) fn add_embedded_profile(&mut self, icc_type: IccProfileType, object_id: lopdf::ObjectId)
+    -> IccProfileRef
+    {
+        self.push(IccProfileEntry::Embedded(icc_type, object_id))
+    }
+
+    fn push(&mut self, entry: IccProfileEntry)
+    -> IccProfileRef
     {
         let cur_len = self.profiles.len();
-        self.profiles.push(profile);
+        self.profiles.push(entry);
         IccProfileRef::new(cur_len)
     }
+
+    /// Returns the `[/ICCBased ...]` color space of the profile, if the
+    /// profile exists and is in the given color space
+    pub(crate) fn get_color_space(&mut self, profile: &IccProfileRef, icc_type: IccProfileType, doc: &mut lopdf::Document)
+    -> Option<lopdf::Object>
+    {
+        use lopdf::Object::*;
+
+        let entry = self.profiles.get_mut(profile.index)?;
+        if let IccProfileEntry::Profile(ref profile) = *entry {
+            let stream: lopdf::Stream = profile.clone().into();
+            *entry = IccProfileEntry::Embedded(profile.get_type(), doc.add_object(stream));
+        }
+
+        match *entry {
+            IccProfileEntry::Embedded(profile_type, object_id) if profile_type == icc_type => {
+                Some(Array(vec![Name("ICCBased".into()), Reference(object_id)]))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Converts RGB colors (assumed to be sRGB) to CMYK. If the profile is a CMYK profile
+/// with a Lab lookup table (the usual printing profiles, such as the default FOGRA39
+/// profile), the colors are converted through the relative colorimetric table of the
+/// profile, so that they print like the RGB colors look on screen. Otherwise the
+/// conversion falls back to the naive `k = 1 - max(r, g, b)`.
+#[derive(Debug, Clone)]
+pub(crate) struct RgbToCmyk {
+    lut: Option<Lut>,
+}
+
+impl RgbToCmyk {
+
+    /// Creates the conversion for the output intent profile
+    pub(crate) fn new(profile: Option<&IccProfile>)
+    -> Self
+    {
+        let lut = profile
+            .filter(|profile| profile.icc_type == IccProfileType::Cmyk)
+            .and_then(|profile| Lut::from_profile(&profile.icc));
+        Self { lut }
+    }
+
+    /// Converts a color with components from 0.0 to 1.0
+    pub(crate) fn convert(&self, r: f64, g: f64, b: f64)
+    -> [f64; 4]
+    {
+        let (r, g, b) = (r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0));
+
+        let lut = match self.lut {
+            Some(ref lut) => lut,
+            None => {
+                let k = 1.0 - r.max(g).max(b);
+                if k >= 1.0 {
+                    return [0.0, 0.0, 0.0, 1.0];
+                }
+                return [(1.0 - r - k) / (1.0 - k), (1.0 - g - k) / (1.0 - k), (1.0 - b - k) / (1.0 - k), k];
+            },
+        };
+
+        let (l, a, b) = srgb_to_lab(r, g, b);
+        let cmyk = lut.apply([l, a, b]);
+        [cmyk[0], cmyk[1], cmyk[2], cmyk[3]]
+    }
+}
+
+/// Lookup table of a `B2A` tag (`lut8Type` or `lut16Type`), which converts Lab
+/// colors to the colors of the device. Values are stored from 0.0 to 1.0
+#[derive(Debug, Clone)]
+struct Lut {
+    grid_points: usize,
+    input_tables: Vec<Vec<f64>>,
+    clut: Vec<f64>,
+    output_tables: Vec<Vec<f64>>,
+    /// The 16-bit tables encode L* 100.0 as 0xFF00 instead of 0xFFFF
+    is_16_bit: bool,
+}
+
+impl Lut {
+
+    /// Reads the relative colorimetric (or else the perceptual) table of a
+    /// CMYK profile with a Lab profile connection space
+    fn from_profile(icc: &[u8])
+    -> Option<Self>
+    {
+        if icc.get(16..20)? != b"CMYK" || icc.get(20..24)? != b"Lab " {
+            return None;
+        }
+
+        let read_u32 = |offset: usize| -> Option<usize> {
+            let bytes = icc.get(offset..offset + 4)?;
+            Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        };
+
+        let tag_count = read_u32(128)?;
+        let tag = |signature: &[u8]| -> Option<&[u8]> {
+            (0..tag_count).find_map(|index| {
+                let entry = 132 + index * 12;
+                if icc.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let (offset, size) = (read_u32(entry + 4)?, read_u32(entry + 8)?);
+                icc.get(offset..offset.checked_add(size)?)
+            })
+        };
+
+        tag(b"B2A1").or_else(|| tag(b"B2A0")).and_then(Self::parse)
+    }
+
+    fn parse(tag: &[u8])
+    -> Option<Self>
+    {
+        let is_16_bit = match tag.get(0..4)? {
+            b"mft1" => false,
+            b"mft2" => true,
+            _ => return None,
+        };
+
+        let channels = tag.get(8..11)?;
+        let (input_channels, output_channels, grid_points) = (usize::from(channels[0]), usize::from(channels[1]), usize::from(channels[2]));
+        if input_channels != 3 || output_channels != 4 || grid_points < 2 {
+            return None;
+        }
+
+        let (input_entries, output_entries, mut offset): (usize, usize, usize) = if is_16_bit {
+            let entries = |offset: usize| -> Option<usize> {
+                let bytes = tag.get(offset..offset + 2)?;
+                Some(usize::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+            };
+            (entries(48)?, entries(50)?, 52)
+        } else {
+            (256, 256, 48)
+        };
+
+        let mut read_values = |count: usize| -> Option<Vec<f64>> {
+            let size = count.checked_mul(if is_16_bit { 2 } else { 1 })?;
+            let bytes = tag.get(offset..offset.checked_add(size)?)?;
+            offset += size;
+            Some(if is_16_bit {
+                bytes.chunks_exact(2).map(|value| f64::from(u16::from_be_bytes([value[0], value[1]])) / 65535.0).collect()
+            } else {
+                bytes.iter().map(|value| f64::from(*value) / 255.0).collect()
+            })
+        };
+
+        let input_tables = (0..input_channels).map(|_| read_values(input_entries)).collect::<Option<Vec<_>>>()?;
+        let clut = read_values(grid_points.checked_pow(3)?.checked_mul(output_channels)?)?;
+        let output_tables = (0..output_channels).map(|_| read_values(output_entries)).collect::<Option<Vec<_>>>()?;
+
+        if input_tables.iter().chain(output_tables.iter()).any(|table| table.len() < 2) {
+            return None;
+        }
+
+        Some(Self { grid_points, input_tables, clut, output_tables, is_16_bit })
+    }
+
+    /// Converts a Lab color to CMYK (components from 0.0 to 1.0)
+    fn apply(&self, lab: [f64; 3])
+    -> Vec<f64>
+    {
+        let encoded = if self.is_16_bit {
+            [lab[0] * 652.8 / 65535.0, (lab[1] + 128.0) * 256.0 / 65535.0, (lab[2] + 128.0) * 256.0 / 65535.0]
+        } else {
+            [lab[0] / 100.0, (lab[1] + 128.0) / 255.0, (lab[2] + 128.0) / 255.0]
+        };
+
+        let mut input = [0.0; 3];
+        for (channel, table) in self.input_tables.iter().enumerate() {
+            input[channel] = interpolate(table, encoded[channel]);
+        }
+
+        // trilinear interpolation between the 8 grid points around the color
+        let last = (self.grid_points - 1) as f64;
+        let mut lower = [0; 3];
+        let mut fraction = [0.0; 3];
+        for channel in 0..3 {
+            let position = input[channel].clamp(0.0, 1.0) * last;
+            lower[channel] = (position.floor() as usize).min(self.grid_points - 2);
+            fraction[channel] = position - lower[channel] as f64;
+        }
+
+        let outputs = self.output_tables.len();
+        let mut output = vec![0.0; outputs];
+        for corner in 0..8 {
+            let mut index = 0;
+            let mut weight = 1.0;
+            for channel in 0..3 {
+                let upper = (corner >> (2 - channel)) & 1;
+                index = index * self.grid_points + lower[channel] + upper;
+                weight *= if upper == 1 { fraction[channel] } else { 1.0 - fraction[channel] };
+            }
+            for (value, grid_value) in output.iter_mut().zip(&self.clut[index * outputs..(index + 1) * outputs]) {
+                *value += weight * grid_value;
+            }
+        }
+
+        output.iter().zip(self.output_tables.iter()).map(|(value, table)| interpolate(table, *value)).collect()
+    }
+}
+
+/// Looks up a value from 0.0 to 1.0 in a table of evenly spaced values
+fn interpolate(table: &[f64], value: f64)
+-> f64
+{
+    let position = value.clamp(0.0, 1.0) * (table.len() - 1) as f64;
+    let index = (position.floor() as usize).min(table.len() - 2);
+    let fraction = position - index as f64;
+    table[index] * (1.0 - fraction) + table[index + 1] * fraction
+}
+
+/// Converts an sRGB color to Lab (D50 white point, like the profile connection space)
+fn srgb_to_lab(r: f64, g: f64, b: f64)
+-> (f64, f64, f64)
+{
+    let linear = |value: f64| if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+
+    // sRGB to XYZ, adapted to D50 (Bradford)
+    let x = 0.436_074_7 * r + 0.385_064_9 * g + 0.143_080_4 * b;
+    let y = 0.222_504_5 * r + 0.716_878_6 * g + 0.060_616_9 * b;
+    let z = 0.013_932_2 * r + 0.097_104_5 * g + 0.714_173_3 * b;
+
+    let f = |t: f64| if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 };
+    let (fx, fy, fz) = (f(x / 0.9642), f(y), f(z / 0.8249));
+
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+#[test]
+fn test_rgb_to_cmyk_with_profile() {
+    let profile = IccProfile::new(include_bytes!("../assets/CoatedFOGRA39.icc").to_vec(), IccProfileType::Cmyk);
+    let lut = Lut::from_profile(&profile.icc).unwrap();
+    let conversion = RgbToCmyk::new(Some(&profile));
+    let white = conversion.convert(1.0, 1.0, 1.0);
+    assert!(white.iter().all(|component| *component < 0.01));
+    let red = conversion.convert(1.0, 0.0, 0.0);
+    assert!(red[0] < 0.1 && red[1] > 0.8 && red[2] > 0.8);
+
+    // truncated or corrupt tables are rejected instead of read out of bounds
+    let tag = |grid_points: u8, length: usize| {
+        let mut tag = b"mft2".to_vec();
+        tag.resize(52, 0);
+        tag[8..11].copy_from_slice(&[3, 4, grid_points]);
+        tag[48..52].copy_from_slice(&[0, 2, 0, 2]);
+        tag.resize(length, 0);
+        tag
+    };
+    assert!(Lut::parse(b"mft2").is_none());
+    assert!(Lut::parse(&tag(2, 10)).is_none());
+    assert!(Lut::parse(&tag(255, 4096)).is_none());
+    // 3 input tables with 2 entries, 2^3 grid points with 4 outputs, 4 output tables with 2 entries
+    assert!(Lut::parse(&tag(2, 52 + (3 * 2 + 8 * 4 + 4 * 2) * 2)).is_some());
+    assert_eq!(lut.output_tables.len(), 4);
+}
+
+#[test]
+fn test_icc_profile_list_embeds_added_profiles_once() {
+    use glob_defines::ICC_PROFILE_ECI_V2;
+
+    let mut doc = lopdf::Document::with_version("1.4");
+    let mut list = IccProfileList::new();
+    let profile = list.add_profile(IccProfile::new(ICC_PROFILE_ECI_V2.to_vec(), IccProfileType::Cmyk));
+
+    assert!(list.get_color_space(&profile, IccProfileType::Rgb, &mut doc).is_none());
+    let color_space = list.get_color_space(&profile, IccProfileType::Cmyk, &mut doc).unwrap();
+    assert_eq!(doc.objects.len(), 1);
+    let object_id = color_space.as_array().unwrap()[1].as_reference().unwrap();
+    assert!(doc.get_object(object_id).unwrap().as_stream().is_ok());
+    list.get_color_space(&profile, IccProfileType::Cmyk, &mut doc).unwrap();
+    assert_eq!(doc.objects.len(), 1);
 }
@@ -3,7 +3,7 @@
 
 #[cfg(feature = "embedded_images")]
 use image_crate::{self, ImageDecoder, DynamicImage};
-use crate::{Mm, Px, ImageXObject, PdfLayerReference};
+use crate::{Mm, Px, ImageXObject, PdfLayerReference, Error};
 
 /// Image - wrapper around an `ImageXObject` to allow for more control
 /// within the library
@@ -24,6 +24,28 @@ impl From<ImageXObject> for Image {
 
 }
 
+impl Image {
+    /// Embeds a JPEG file without decoding it, see `ImageXObject::from_jpeg`
+    pub fn from_jpeg(data: Vec<u8>)
+    -> Result<Self, Error>
+    {
+        let image = ImageXObject::from_jpeg(data)?;
+        Ok(Self {
+            image,
+        })
+    }
+
+    /// Embeds a PNG file without decoding it, see `ImageXObject::from_png`
+    pub fn from_png(data: &[u8])
+    -> Result<Self, Error>
+    {
+        let image = ImageXObject::from_png(data)?;
+        Ok(Self {
+            image,
+        })
+    }
+}
+
 #[cfg(feature = "embedded_images")]
 impl<'a> Image {
     pub fn try_from<T: ImageDecoder<'a>>(image: T)
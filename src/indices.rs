@@ -8,6 +8,10 @@ pub struct PdfPageIndex(pub(crate) usize);
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct PdfLayerIndex(pub(crate) usize);
 
+/// Index of an item in the document outline
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct OutlineItemIndex(pub(crate) usize);
+
 /// Index of the arbitrary content data
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct PdfContentIndex(pub(crate) usize);
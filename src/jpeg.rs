@@ -0,0 +1,117 @@
+//! Reads the header of a JPEG file, so that the file can be embedded
+//! without decoding it (the PDF viewer decodes it with `DCTDecode`)
+
+use PdfError;
+
+/// Information from the header of a JPEG file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct JpegInfo {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// 1 (greyscale), 3 (YCbCr / RGB) or 4 (CMYK / YCCK)
+    pub(crate) components: u8,
+    /// Bits per component, always 8 for `DCTDecode`
+    pub(crate) bits_per_component: u8,
+    /// The file has an Adobe APP14 marker. Adobe applications write CMYK JPEGs
+    /// with inverted components, which have to be inverted again with a `/Decode` array
+    pub(crate) adobe: bool,
+}
+
+/// Start of frame markers that `DCTDecode` can decode (baseline, extended sequential and progressive)
+const SOF_SUPPORTED: [u8; 3] = [0xC0, 0xC1, 0xC2];
+/// Other start of frame markers (lossless, hierarchical or arithmetic coding)
+const SOF_UNSUPPORTED: [u8; 10] = [0xC3, 0xC5, 0xC6, 0xC7, 0xC9, 0xCA, 0xCB, 0xCD, 0xCE, 0xCF];
+
+/// Reads the segments of the JPEG until the start of frame segment is found
+pub(crate) fn read_jpeg_info(data: &[u8])
+-> Result<JpegInfo, PdfError>
+{
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(PdfError::InvalidImage("not a JPEG file"));
+    }
+
+    let mut adobe = false;
+    let mut pos = 2;
+
+    loop {
+        // markers may be preceded by any number of 0xFF fill bytes
+        while data.get(pos) == Some(&0xFF) && data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+
+        let marker = match (data.get(pos), data.get(pos + 1)) {
+            (Some(0xFF), Some(marker)) => *marker,
+            _ => return Err(PdfError::InvalidImage("corrupt JPEG segment")),
+        };
+
+        // markers without a length field
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+
+        if marker == 0xD9 || marker == 0xDA {
+            return Err(PdfError::InvalidImage("JPEG file has no frame header"));
+        }
+
+        let length = match (data.get(pos + 2), data.get(pos + 3)) {
+            (Some(high), Some(low)) => usize::from(u16::from_be_bytes([*high, *low])),
+            _ => return Err(PdfError::InvalidImage("corrupt JPEG segment")),
+        };
+        let segment = data.get(pos + 4..pos + 2 + length)
+            .ok_or(PdfError::InvalidImage("corrupt JPEG segment"))?;
+
+        if marker == 0xEE && segment.starts_with(b"Adobe") {
+            adobe = true;
+        }
+
+        if SOF_UNSUPPORTED.contains(&marker) {
+            return Err(PdfError::InvalidImage("unsupported JPEG compression (lossless, hierarchical or arithmetic)"));
+        }
+
+        if SOF_SUPPORTED.contains(&marker) {
+            if segment.len() < 6 {
+                return Err(PdfError::InvalidImage("corrupt JPEG frame header"));
+            }
+
+            let info = JpegInfo {
+                bits_per_component: segment[0],
+                height: usize::from(u16::from_be_bytes([segment[1], segment[2]])),
+                width: usize::from(u16::from_be_bytes([segment[3], segment[4]])),
+                components: segment[5],
+                adobe,
+            };
+
+            if info.bits_per_component != 8 {
+                return Err(PdfError::InvalidImage("only 8-bit JPEGs are supported"));
+            }
+            if info.width == 0 || info.height == 0 {
+                return Err(PdfError::InvalidImage("JPEG has no size in the frame header"));
+            }
+            if info.components != 1 && info.components != 3 && info.components != 4 {
+                return Err(PdfError::InvalidImage("JPEG must have 1, 3 or 4 components"));
+            }
+
+            return Ok(info);
+        }
+
+        pos += 2 + length;
+    }
+}
+
+#[test]
+fn test_read_jpeg_info() {
+    let mut jpeg = vec![0xFF, 0xD8];
+    // APP14 "Adobe" marker
+    jpeg.extend_from_slice(&[0xFF, 0xEE, 0x00, 0x0E]);
+    jpeg.extend_from_slice(b"Adobe\x00\x64\x00\x00\x00\x00\x02");
+    // SOF2 (progressive): 8 bits, 200 x 300 pixels, 4 components
+    jpeg.extend_from_slice(&[0xFF, 0xC2, 0x00, 0x14, 0x08, 0x01, 0x2C, 0x00, 0xC8, 0x04]);
+    jpeg.extend_from_slice(&[0x01, 0x11, 0x00, 0x02, 0x11, 0x00, 0x03, 0x11, 0x00, 0x04, 0x11, 0x00]);
+
+    let info = read_jpeg_info(&jpeg).unwrap();
+    assert_eq!(info, JpegInfo { width: 200, height: 300, components: 4, bits_per_component: 8, adobe: true });
+
+    assert!(read_jpeg_info(b"GIF89a").is_err());
+    assert!(read_jpeg_info(&jpeg[..20]).is_err());
+}
@@ -9,7 +9,7 @@
 //!
 //! # Features
 //!
-//! Currently, printpdf can only write documents, not read them.
+//! Existing documents can be loaded to add content to their pages, but their content can't be edited.
 //!
 //! - Page generation
 //! - Layers (Illustrator like layers)
@@ -17,8 +17,18 @@
 //! - Images (currently BMP/PNG/JPG only or generate your own images)
 //! - Embedded fonts (TTF and OTF) with Unicode support
 //! - Advanced graphics - overprint control, blending modes, etc.
+//! - Device independent colors: ICC-based colors (`add_icc_profile`), CIE L\*a\*b\*, CalRGB and CalGray
 //! - Advanced typography - character scaling, character spacing, superscript, subscript, outlining, etc.
 //! - PDF layers (you should be able to open the PDF in Illustrator and have the layers appear)
+//! - Streaming output for large documents (`PdfStreamWriter` writes every finished page immediately)
+//! - Thread-safe document handles (`PdfDocumentReference` is `Send + Sync`, pages can be drawn in parallel)
+//! - Reproducible output (`PdfSaveOptions::with_reproducible_ids` and `with_fixed_date`), for snapshot tests
+//! - Loading existing PDF files (`PdfDocument::load`) to stamp or annotate their pages
+//! - Importing pages of other PDF files (`import_page`), to place them scaled or rotated on any page
+//! - Merging, splitting and reordering documents (`append_document`, `move_page`, `delete_page`, `duplicate_page`, `extract_pages`)
+//! - Conformance checks (`check_conformance`), listing the builtin fonts, RGB colors, transparency and layers that a PDF/A or PDF/X standard doesn't allow
+//! - Configurable output intents (`with_output_intent`), e.g. GRACoL or SWOP instead of the default FOGRA39, with embedded or referenced ICC profiles
+//! - Automatic repair for a standard (`repair_errors`): RGB to CMYK conversion with the output intent profile, removal of transparency and layers
 //!
 //! # Getting started
 //!
@@ -34,7 +44,7 @@
 //! let (doc, page1, layer1) = PdfDocument::new("PDF_Document_title", Mm(247.0), Mm(210.0), "Layer 1");
 //! let (page2, layer1) = doc.add_page(Mm(10.0), Mm(250.0),"Page 2, Layer 1");
 //!
-//! doc.save(&mut BufWriter::new(File::create("test_working.pdf").unwrap())).unwrap();
+//! doc.save(&mut BufWriter::new(File::create(std::env::temp_dir().join("test_working.pdf")).unwrap())).unwrap();
 //! ```
 //!
 //! ### Adding graphical shapes
@@ -110,14 +120,17 @@
 //!
 //! ### Adding images
 //!
-//! Note: Images only get compressed in release mode. You might get huge PDFs (6 or more MB) in
-//! debug mode. In release mode, the compression makes these files much smaller (~ 100 - 200 KB).
+//! Note: Images are compressed when the document is saved. Use `save_with_options` and
+//! `PdfSaveOptions` to change the compression level or to turn off compression.
 //!
 //! To make this process faster, use `BufReader` instead of directly reading from the file.
 //! Images are currently not a top priority.
 //!
 //! Scaling of images is implicitly done to fit one pixel = one dot at 300 dpi.
 //!
+//! JPEG and PNG files can be embedded without decoding them with `Image::from_jpeg`
+//! and `Image::from_png`, this also works without the `embedded_images` feature.
+//!
 //! ```rust
 //! // Compile with --feature="embedded_images"
 //! extern crate printpdf;
@@ -156,6 +169,11 @@
 //!         image_data: Vec::new(),
 //!         image_filter: None, /* does not work yet */
 //!         clipping_bbox: None, /* doesn't work either, untested */
+//!         alpha_handling: AlphaHandling::default(), /* only for images with an alpha channel */
+//!         decode: None,
+//!         palette: None,
+//!         color_key_mask: None,
+//!         image_mask: false,
 //!     };
 //!
 //!     let image2 = Image::from(image_file_2);
@@ -242,10 +260,10 @@
 //! font correctly, so that's why you currently have to use `freetype`
 //!
 //! Please report issues if you have any, especially if you see `BorrowMut`
-//! errors (they should not happen). Kerning is currently not done, because
-//! neither `freetype` nor `rusttype` can reliably read kerning data.
-//! However, "correct" kerning / placement requires a full font shaping
-//! engine, etc. This would be a completely different project.
+//! errors (they should not happen). `write_text` does not do any kerning,
+//! it maps each character to a glyph one at a time. For kerning, ligatures
+//! and complex scripts, enable the `text_shaping` feature and use
+//! `PdfLayerReference::write_shaped_text`, which shapes the text with `rustybuzz`.
 //!
 //! For learning how a PDF is actually made, please read the
 //! [wiki](https://github.com/fschutt/printpdf/wiki) (currently not
@@ -259,23 +277,20 @@
 //! The goal of printpdf is to be a general-use PDF library, such as
 //! libharu or similar. PDFs generated by printpdf should always adhere
 //! to a PDF standard, except if you turn it off. Currently, only the
-//! standard `PDF/X-3:2002` is covered (i.e. valid PDF according to Adobe
-//! Acrobat). Over time, there will be more standards supported. Checking a
-//! PDF for errors is currently only a stub.
+//! standard `PDF/X-3:2002` and the archiving standards `PDF/A-1b`, `PDF/A-2b`
+//! and `PDF/A-3b` (use `repair_errors` to convert the colors and remove what
+//! they don't allow) are covered. Over time, there will be more standards supported. A document
+//! can be checked against the rules of its standard with `check_conformance`.
 //!
 //! ## Planned features / Not done yet
 //!
 //! The following features aren't implemented yet, most
 //! - Clipping
-//! - Aligning / layouting text
 //! - Open Prepress Interface
-//! - Halftoning images, Gradients, Patterns
+//! - Halftoning images
 //! - SVG / instantiated content
-//! - Forms, annotations
-//! - Bookmarks / Table of contents
-//! - Conformance / error checking for various PDF standards
+//! - Forms
 //! - Embedded Javascript
-//! - Reading PDF
 //! - Completion of printpdf wiki
 //!
 //! # Testing
@@ -334,36 +349,59 @@ pub extern crate log;
 #[cfg(feature = "embedded_images")]
 pub extern crate image as image_crate;
 pub extern crate lopdf;
+extern crate flate2;
 extern crate owned_ttf_parser;
+#[cfg(feature = "text_shaping")]
+extern crate rustybuzz;
 #[cfg(feature = "svg")]
 extern crate pdf_writer;
 extern crate time;
+extern crate unicode_linebreak;
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 extern crate js_sys;
 
+pub mod annotation;
 pub mod color;
 pub mod ctm;
 pub mod date;
+pub mod destination;
 pub mod document_info;
 pub mod errors;
 pub mod extgstate;
+pub(crate) mod builtin_font_metrics;
 pub mod font;
+pub(crate) mod font_subset;
 pub mod icc_profile;
 pub mod image;
 pub mod indices;
+pub(crate) mod jpeg;
 pub mod line;
+pub(crate) mod object_streams;
 pub mod ocg;
+pub mod outline;
+pub mod output_intent;
 pub mod pattern;
+pub(crate) mod pdf_catalog;
 pub mod pdf_conformance;
 pub mod pdf_document;
+pub mod pdf_import;
 pub mod pdf_layer;
+pub(crate) mod pdf_loader;
+pub(crate) mod pdf_merge;
 pub mod pdf_metadata;
 pub mod pdf_page;
 pub mod pdf_resources;
+pub mod pdf_save_options;
+pub mod pdf_stream_writer;
+pub mod pdf_validation;
+pub(crate) mod png;
 pub mod point;
+pub mod rect;
 pub mod scale;
+pub mod shaping;
 #[cfg(feature = "svg")]
 pub mod svg;
+pub mod text_layout;
 pub mod utils;
 pub mod xmp_metadata;
 pub mod xobject;
@@ -467,6 +505,8 @@ pub(crate) mod glob_defines {
     pub const OP_PATH_PAINT_END: &str                            = "n";
 }
 
+#[doc(inline)]
+pub use crate::annotation::*;
 #[doc(inline)]
 pub use crate::color::*;
 #[doc(inline)]
@@ -474,6 +514,8 @@ pub use crate::ctm::*;
 #[doc(inline)]
 pub use crate::date::*;
 #[doc(inline)]
+pub use crate::destination::*;
+#[doc(inline)]
 pub use crate::document_info::*;
 #[doc(inline)]
 pub use crate::errors::*;
@@ -494,12 +536,18 @@ pub use crate::line::*;
 #[doc(inline)]
 pub use crate::ocg::*;
 #[doc(inline)]
+pub use crate::outline::*;
+#[doc(inline)]
+pub use crate::output_intent::*;
+#[doc(inline)]
 pub use crate::pattern::*;
 #[doc(inline)]
 pub use crate::pdf_conformance::*;
 #[doc(inline)]
 pub use crate::pdf_document::*;
 #[doc(inline)]
+pub use crate::pdf_import::*;
+#[doc(inline)]
 pub use crate::pdf_layer::*;
 #[doc(inline)]
 pub use crate::pdf_metadata::*;
@@ -508,13 +556,25 @@ pub use crate::pdf_page::*;
 #[doc(inline)]
 pub use crate::pdf_resources::*;
 #[doc(inline)]
+pub use crate::pdf_save_options::*;
+#[doc(inline)]
+pub use crate::pdf_stream_writer::*;
+#[doc(inline)]
+pub use crate::pdf_validation::*;
+#[doc(inline)]
 pub use crate::point::*;
 #[doc(inline)]
+pub use crate::rect::*;
+#[doc(inline)]
 pub use crate::scale::*;
+#[doc(inline)]
+pub use crate::shaping::*;
 #[cfg(feature = "svg")]
 #[doc(inline)]
 pub use crate::svg::*;
 #[doc(inline)]
+pub use crate::text_layout::*;
+#[doc(inline)]
 pub use crate::utils::*;
 #[doc(inline)]
 pub use crate::xmp_metadata::*;
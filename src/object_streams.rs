@@ -0,0 +1,382 @@
+//! Writes a document with object streams and a cross-reference stream (PDF 1.5).
+//! lopdf can only write a classic cross-reference table, with every object
+//! at the top level of the file, so the objects are serialized here. Documents
+//! with a classic cross-reference table are written here as well, because lopdf
+//! leaves out the binary comment after the header (which PDF/A requires).
+
+use lopdf::{self, Object, StringFormat};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::iter::FromIterator;
+use pdf_save_options::compress_stream;
+use utils::row_bytes;
+
+/// Maximum number of objects in one object stream (viewers have to
+/// decompress the whole stream to read one of the objects)
+const OBJECTS_PER_STREAM: usize = 100;
+
+/// Location of an object in the file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum XrefEntry {
+    /// Top-level object, written at the given offset
+    Normal { offset: usize, generation: u16 },
+    /// Object at the given index in an object stream
+    Compressed { stream_id: u32, index: usize },
+}
+
+/// Writes the document: streams are written at the top level, all other objects
+/// are packed into object streams. The object streams and the cross-reference
+/// stream are compressed with `compression_level` (not compressed if 0).
+pub(crate) fn save_with_object_streams(doc: &lopdf::Document, compression_level: u32)
+-> io::Result<Vec<u8>>
+{
+    let mut output = Vec::new();
+    write_header(&mut output, doc)?;
+
+    let mut xref = BTreeMap::new();
+    let mut next_id = doc.max_id + 1;
+    let mut packed_objects = Vec::new();
+
+    for (&(id, generation), object) in &doc.objects {
+        // the object streams and cross-reference streams of a loaded file are outdated
+        let type_name = object.type_name().ok();
+        if type_name == Some("ObjStm") || type_name == Some("XRef") {
+            continue;
+        }
+        let is_stream = matches!(*object, Object::Stream(_));
+        // objects in object streams have generation 0
+        if is_stream || generation != 0 {
+            xref.insert(id, XrefEntry::Normal { offset: output.len(), generation });
+            write_indirect_object(&mut output, (id, generation), object)?;
+        } else {
+            packed_objects.push((id, object));
+        }
+    }
+
+    for objects in packed_objects.chunks(OBJECTS_PER_STREAM) {
+        let stream_id = next_id;
+        next_id += 1;
+
+        // "id offset" pairs, followed by the objects
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+
+        for (index, &(id, object)) in objects.iter().enumerate() {
+            write!(header, "{} {} ", id, body.len())?;
+            write_object(&mut body, object)?;
+            body.push(b'\n');
+            xref.insert(id, XrefEntry::Compressed { stream_id, index });
+        }
+
+        let first = header.len();
+        header.append(&mut body);
+
+        let mut stream = lopdf::Stream::new(lopdf::Dictionary::from_iter(vec![
+            ("Type", Object::Name("ObjStm".as_bytes().to_vec())),
+            ("N", Object::Integer(objects.len() as i64)),
+            ("First", Object::Integer(first as i64)),
+        ]), header);
+
+        if compression_level > 0 {
+            compress_stream(&mut stream, compression_level);
+        }
+
+        xref.insert(stream_id, XrefEntry::Normal { offset: output.len(), generation: 0 });
+        write_indirect_object(&mut output, (stream_id, 0), &Object::Stream(stream))?;
+    }
+
+    // the cross-reference stream contains an entry for itself
+    let xref_id = next_id;
+    let xref_offset = output.len();
+    xref.insert(xref_id, XrefEntry::Normal { offset: xref_offset, generation: 0 });
+    let size = xref_id + 1;
+
+    // field widths: type (1 byte), offset or object stream ID, generation or index (2 bytes)
+    let largest_value = xref_offset.max(size as usize) as u64;
+    let width = row_bytes(1, 64 - largest_value.leading_zeros() as usize).max(1);
+
+    let mut entries = Vec::with_capacity(size as usize * (width + 3));
+    for id in 0..size {
+        let (entry_type, field2, field3) = match xref.get(&id) {
+            Some(&XrefEntry::Normal { offset, generation }) => (1, offset as u64, generation),
+            Some(&XrefEntry::Compressed { stream_id, index }) => (2, u64::from(stream_id), index as u16),
+            None => {
+                let (next_free, generation) = free_entry(id, size, |id| xref.contains_key(&id));
+                (0, u64::from(next_free), generation)
+            },
+        };
+        entries.push(entry_type);
+        entries.extend_from_slice(&field2.to_be_bytes()[8 - width..]);
+        entries.extend_from_slice(&field3.to_be_bytes());
+    }
+
+    let mut xref_dict = lopdf::Dictionary::from_iter(vec![
+        ("Type", Object::Name("XRef".as_bytes().to_vec())),
+        ("Size", Object::Integer(i64::from(size))),
+        ("W", Object::Array(vec![Object::Integer(1), Object::Integer(width as i64), Object::Integer(2)])),
+    ]);
+
+    // the trailer entries (Root, Info, ID) are part of the cross-reference stream
+    for (key, value) in doc.trailer.iter() {
+        if !xref_dict.has(key) && key.as_slice() != b"Prev" && key.as_slice() != b"XRefStm" {
+            xref_dict.set(key.clone(), value.clone());
+        }
+    }
+
+    let mut xref_stream = lopdf::Stream::new(xref_dict, entries);
+    if compression_level > 0 {
+        compress_stream(&mut xref_stream, compression_level);
+    }
+
+    write_indirect_object(&mut output, (xref_id, 0), &Object::Stream(xref_stream))?;
+    write!(output, "startxref\n{}\n%%EOF", xref_offset)?;
+
+    Ok(output)
+}
+
+/// Writes the document with a classic cross-reference table, every object at the top level
+pub(crate) fn save_with_xref_table(doc: &lopdf::Document)
+-> io::Result<Vec<u8>>
+{
+    let mut output = Vec::new();
+    write_header(&mut output, doc)?;
+
+    let mut offsets = BTreeMap::new();
+    for (&(id, generation), object) in &doc.objects {
+        // the object streams and cross-reference streams of a loaded file are outdated
+        let type_name = object.type_name().ok();
+        if type_name == Some("ObjStm") || type_name == Some("XRef") {
+            continue;
+        }
+        offsets.insert(id, (output.len(), generation));
+        write_indirect_object(&mut output, (id, generation), object)?;
+    }
+
+    let size = doc.max_id + 1;
+    let xref_offset = output.len();
+    write_xref_table(&mut output, &offsets, size)?;
+
+    let mut trailer = doc.trailer.clone();
+    trailer.set("Size", i64::from(size));
+    output.extend_from_slice(b"trailer\n");
+    write_dictionary(&mut output, &trailer)?;
+    write!(output, "\nstartxref\n{}\n%%EOF", xref_offset)?;
+
+    Ok(output)
+}
+
+/// Writes the `xref` section for the objects `0..size`, given the offset and generation
+/// of each object that was written
+pub(crate) fn write_xref_table<W: Write>(output: &mut W, offsets: &BTreeMap<u32, (usize, u16)>, size: u32)
+-> io::Result<()>
+{
+    writeln!(output, "xref\n0 {}", size)?;
+    for id in 0..size {
+        match offsets.get(&id) {
+            Some(&(offset, generation)) => writeln!(output, "{:010} {:05} n ", offset, generation)?,
+            None => {
+                let (next_free, generation) = free_entry(id, size, |id| offsets.contains_key(&id));
+                writeln!(output, "{:010} {:05} f ", next_free, generation)?
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Returns the next free object number and the generation of a free entry. The free
+/// entries form a linked list that starts at object 0 and links back to it at the end.
+fn free_entry<F: Fn(u32) -> bool>(id: u32, size: u32, is_used: F)
+-> (u32, u16)
+{
+    let next_free = (id + 1..size).find(|&next| !is_used(next)).unwrap_or(0);
+    (next_free, if id == 0 { 65535 } else { 0 })
+}
+
+fn write_header(output: &mut Vec<u8>, doc: &lopdf::Document)
+-> io::Result<()>
+{
+    writeln!(output, "%PDF-{}", doc.version)?;
+    // binary comment, so that tools detect the file as binary
+    output.extend_from_slice(b"%\xE2\xE3\xCF\xD3\n");
+    Ok(())
+}
+
+pub(crate) fn write_indirect_object(output: &mut Vec<u8>, id: lopdf::ObjectId, object: &Object)
+-> io::Result<()>
+{
+    writeln!(output, "{} {} obj", id.0, id.1)?;
+    write_object(output, object)?;
+    output.extend_from_slice(b"\nendobj\n");
+    Ok(())
+}
+
+/// Serializes an object, in the same format as lopdf
+fn write_object(output: &mut Vec<u8>, object: &Object)
+-> io::Result<()>
+{
+    match *object {
+        Object::Null => output.extend_from_slice(b"null"),
+        Object::Boolean(value) => output.extend_from_slice(if value { b"true" } else { b"false" }),
+        Object::Integer(value) => write!(output, "{}", value)?,
+        Object::Real(value) => write!(output, "{}", value)?,
+        Object::Name(ref name) => write_name(output, name)?,
+        Object::String(ref text, ref format) => write_string(output, text, format)?,
+        Object::Array(ref array) => {
+            output.push(b'[');
+            for (index, object) in array.iter().enumerate() {
+                if index > 0 {
+                    output.push(b' ');
+                }
+                write_object(output, object)?;
+            }
+            output.push(b']');
+        },
+        Object::Dictionary(ref dict) => write_dictionary(output, dict)?,
+        Object::Stream(ref stream) => {
+            let mut dict = stream.dict.clone();
+            dict.set("Length", Object::Integer(stream.content.len() as i64));
+            write_dictionary(output, &dict)?;
+            output.extend_from_slice(b"stream\n");
+            output.extend_from_slice(&stream.content);
+            output.extend_from_slice(b"\nendstream");
+        },
+        Object::Reference(id) => write!(output, "{} {} R", id.0, id.1)?,
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_dictionary(output: &mut Vec<u8>, dict: &lopdf::Dictionary)
+-> io::Result<()>
+{
+    output.extend_from_slice(b"<<");
+    for (key, value) in dict.iter() {
+        write_name(output, key)?;
+        output.push(b' ');
+        write_object(output, value)?;
+    }
+    output.extend_from_slice(b">>");
+    Ok(())
+}
+
+fn write_name(output: &mut Vec<u8>, name: &[u8])
+-> io::Result<()>
+{
+    output.push(b'/');
+    for &byte in name {
+        // white-space, delimiters and bytes outside of 33 (!) to 126 (~) are written as #XX
+        if b"()<>[]{}/%#".contains(&byte) || !(33..=126).contains(&byte) {
+            write!(output, "#{:02X}", byte)?;
+        } else {
+            output.push(byte);
+        }
+    }
+    Ok(())
+}
+
+fn write_string(output: &mut Vec<u8>, text: &[u8], format: &StringFormat)
+-> io::Result<()>
+{
+    match *format {
+        StringFormat::Literal => {
+            output.push(b'(');
+            for &byte in text {
+                match byte {
+                    // escaping all parentheses is always valid, balanced or not
+                    b'(' | b')' | b'\\' => output.extend_from_slice(&[b'\\', byte]),
+                    b'\r' => output.extend_from_slice(b"\\r"),
+                    _ => output.push(byte),
+                }
+            }
+            output.push(b')');
+        },
+        StringFormat::Hexadecimal => {
+            output.push(b'<');
+            for &byte in text {
+                write!(output, "{:02X}", byte)?;
+            }
+            output.push(b'>');
+        },
+    }
+    Ok(())
+}
+
+#[test]
+fn test_save_with_object_streams() {
+    use lopdf::Object::*;
+
+    let mut doc = lopdf::Document::with_version("1.5");
+    let content_id = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::new(), b"0 0 m 10 10 l S".to_vec()));
+    let page_id = doc.add_object(lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("Page".as_bytes().to_vec())),
+        ("Contents", Reference(content_id)),
+        ("Title", String("a (test) \\ string".as_bytes().to_vec(), StringFormat::Literal)),
+        ("Name With Spaces", Real(0.5)),
+    ]));
+    let catalog_id = doc.add_object(lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("Catalog".as_bytes().to_vec())),
+        ("Pages", Array(vec![Reference(page_id)])),
+    ]));
+    doc.trailer.set("Root", Reference(catalog_id));
+
+    let bytes = save_with_object_streams(&doc, 6).unwrap();
+    let loaded = lopdf::Document::load_mem(&bytes).unwrap();
+
+    let page = loaded.get_object(page_id).unwrap().as_dict().unwrap();
+    assert_eq!(page.get(b"Title").unwrap().as_str().unwrap(), b"a (test) \\ string");
+    assert_eq!(page.get(b"Name With Spaces").unwrap().as_f64().unwrap(), 0.5);
+    assert_eq!(loaded.get_object(content_id).unwrap().as_stream().unwrap().content, b"0 0 m 10 10 l S".to_vec());
+    assert_eq!(loaded.trailer.get(b"Root").unwrap().as_reference().unwrap(), catalog_id);
+}
+
+#[test]
+fn test_save_with_object_streams_skips_outdated_streams() {
+    use lopdf::Object::*;
+
+    let mut doc = lopdf::Document::with_version("1.5");
+    let object_stream_id = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("ObjStm".as_bytes().to_vec())),
+        ("N", Integer(0)),
+        ("First", Integer(0)),
+    ]), Vec::new()));
+    let xref_stream_id = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("XRef".as_bytes().to_vec())),
+    ]), Vec::new()));
+    let catalog_id = doc.add_object(lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("Catalog".as_bytes().to_vec())),
+    ]));
+    doc.trailer.set("Root", Reference(catalog_id));
+
+    let loaded = lopdf::Document::load_mem(&save_with_object_streams(&doc, 0).unwrap()).unwrap();
+    assert!(loaded.get_object(object_stream_id).is_err());
+    assert!(loaded.get_object(xref_stream_id).is_err());
+    assert!(loaded.get_object(catalog_id).is_ok());
+}
+
+#[test]
+fn test_save_with_xref_table_links_free_entries() {
+    use lopdf::Object::*;
+
+    let mut doc = lopdf::Document::with_version("1.4");
+    let unused_id = doc.new_object_id();
+    let content_id = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::new(), b"0 0 m 10 10 l S".to_vec()));
+    let second_unused_id = doc.new_object_id();
+    let catalog_id = doc.add_object(lopdf::Dictionary::from_iter(vec![
+        ("Type", Name("Catalog".as_bytes().to_vec())),
+        ("Contents", Reference(content_id)),
+    ]));
+    doc.trailer.set("Root", Reference(catalog_id));
+
+    let bytes = save_with_xref_table(&doc).unwrap();
+    let text = ::std::string::String::from_utf8_lossy(&bytes);
+    let xref = &text[text.rfind("\nxref\n").unwrap() + 1..];
+    let entries: Vec<&str> = xref.lines().skip(2).take(5).collect();
+    assert_eq!(entries[0], format!("{:010} 65535 f ", unused_id.0));
+    assert_eq!(entries[unused_id.0 as usize], format!("{:010} 00000 f ", second_unused_id.0));
+    assert_eq!(entries[second_unused_id.0 as usize], "0000000000 00000 f ");
+    assert!(entries[content_id.0 as usize].ends_with(" 00000 n "));
+
+    let loaded = lopdf::Document::load_mem(&bytes).unwrap();
+    assert_eq!(loaded.get_object(content_id).unwrap().as_stream().unwrap().content, b"0 0 m 10 10 l S".to_vec());
+    assert_eq!(loaded.trailer.get(b"Root").unwrap().as_reference().unwrap(), catalog_id);
+}
@@ -0,0 +1,302 @@
+//! Document outline (bookmarks / table of contents shown in the sidebar of a PDF viewer)
+
+use lopdf;
+use lopdf::{Dictionary as LoDictionary, ObjectId};
+use std::iter::FromIterator;
+use document_info::to_pdf_text_string;
+use {Destination, OutlineItemIndex, Rgb};
+
+/// An entry in the document outline, see page 584 in the PDF 1.7 reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineItem {
+    /// Text that is displayed for this entry
+    pub title: String,
+    /// Where to jump when the entry is clicked
+    pub destination: Destination,
+    /// Whether the children of this entry are shown initially. Default: false
+    pub is_open: bool,
+    /// Color of the title. Default: None (black)
+    pub color: Option<Rgb>,
+    /// Display the title in bold. Default: false
+    pub bold: bool,
+    /// Display the title in italic. Default: false
+    pub italic: bool,
+}
+
+impl OutlineItem {
+
+    /// Creates a new, closed outline item
+    pub fn new<S>(title: S, destination: Destination)
+    -> Self where S: Into<String>
+    {
+        Self {
+            title: title.into(),
+            destination,
+            is_open: false,
+            color: None,
+            bold: false,
+            italic: false,
+        }
+    }
+
+    /// Sets whether the children of this item are initially shown
+    #[inline]
+    pub fn with_open(mut self, is_open: bool)
+    -> Self
+    {
+        self.is_open = is_open;
+        self
+    }
+
+    /// Sets the color of the title
+    #[inline]
+    pub fn with_color(mut self, color: Rgb)
+    -> Self
+    {
+        self.color = Some(color);
+        self
+    }
+
+    /// Displays the title in bold
+    #[inline]
+    pub fn with_bold(mut self, bold: bool)
+    -> Self
+    {
+        self.bold = bold;
+        self
+    }
+
+    /// Displays the title in italic
+    #[inline]
+    pub fn with_italic(mut self, italic: bool)
+    -> Self
+    {
+        self.italic = italic;
+        self
+    }
+}
+
+/// Outline tree of a document. Items are stored in insertion order,
+/// each item knows its parent item (`None` for top-level items)
+#[derive(Debug, Default, Clone)]
+pub struct Outline {
+    items: Vec<(Option<OutlineItemIndex>, OutlineItem)>,
+}
+
+impl Outline {
+
+    /// Creates a new, empty outline
+    pub fn new()
+    -> Self
+    {
+        Self::default()
+    }
+
+    /// Adds an item to the outline, as the last child of `parent`
+    /// (or as a top-level item if `parent` is `None`)
+    pub fn add_item(&mut self, parent: Option<OutlineItemIndex>, item: OutlineItem)
+    -> OutlineItemIndex
+    {
+        self.items.push((parent, item));
+        OutlineItemIndex(self.items.len() - 1)
+    }
+
+    /// Returns the number of items in the outline
+    #[inline]
+    pub fn len(&self)
+    -> usize
+    {
+        self.items.len()
+    }
+
+    /// Returns if the outline is empty
+    #[inline]
+    pub fn is_empty(&self)
+    -> bool
+    {
+        self.items.is_empty()
+    }
+
+    /// Updates the destinations of the items after pages were moved. `update` returns
+    /// `false` if the page of the destination was deleted, then the item is removed
+    /// and its children move up to the parent of the item
+    pub(crate) fn update_destinations<F>(&mut self, mut update: F)
+    where F: FnMut(&mut Destination) -> bool
+    {
+        let keep: Vec<bool> = self.items.iter_mut().map(|(_, item)| update(&mut item.destination)).collect();
+
+        // the new parent of the children of each item: the new index of the item,
+        // or the new parent of the item if it is removed
+        let mut new_parents: Vec<Option<OutlineItemIndex>> = Vec::with_capacity(self.items.len());
+        let mut items = Vec::new();
+        for (idx, (parent, item)) in ::std::mem::take(&mut self.items).into_iter().enumerate() {
+            // parents are always added before their children
+            let parent = parent.and_then(|p| new_parents[p.0]);
+            if keep[idx] {
+                new_parents.push(Some(OutlineItemIndex(items.len())));
+                items.push((parent, item));
+            } else {
+                new_parents.push(parent);
+            }
+        }
+
+        self.items = items;
+    }
+
+    /// Appends the items of another outline (of an appended document)
+    pub(crate) fn append(&mut self, other: Outline)
+    {
+        let offset = self.items.len();
+        self.items.extend(other.items.into_iter().map(|(parent, item)| {
+            (parent.map(|p| OutlineItemIndex(p.0 + offset)), item)
+        }));
+    }
+
+    /// Children (indices into `self.items`) of every item, in insertion order. The first
+    /// entry contains the top-level items, the children of item `i` are at `i + 1`
+    fn children(&self) -> Vec<Vec<usize>> {
+        let mut children = vec![Vec::new(); self.items.len() + 1];
+        for (idx, (parent, _)) in self.items.iter().enumerate() {
+            children[parent.map(|p| p.0 + 1).unwrap_or(0)].push(idx);
+        }
+        children
+    }
+
+    /// Number of descendants of every item that are visible if the item is open
+    /// (the same layout as `children`, the first entry is for the whole outline)
+    fn visible_descendants(&self, children: &[Vec<usize>]) -> Vec<i64> {
+        let mut visible = vec![0; children.len()];
+        // children are always added after their parents, so they are counted first
+        for parent in (0..children.len()).rev() {
+            visible[parent] = children[parent].iter().map(|&child| {
+                1 + if self.items[child].1.is_open { visible[child + 1] } else { 0 }
+            }).sum();
+        }
+        visible
+    }
+
+    /// Adds the outline items to the document, returns the ID of the outline dictionary
+    /// (or `None` if the outline is empty). `page_ids` are the object IDs of the pages.
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, page_ids: &[ObjectId])
+    -> Option<ObjectId>
+    {
+        use lopdf::Object::*;
+
+        if self.items.is_empty() {
+            return None;
+        }
+
+        let outline_id = doc.new_object_id();
+        let item_ids: Vec<ObjectId> = self.items.iter().map(|_| doc.new_object_id()).collect();
+        let children = self.children();
+        let visible = self.visible_descendants(&children);
+
+        // previous and next sibling of every item
+        let mut siblings = vec![(None, None); self.items.len()];
+        for items in &children {
+            for pair in items.windows(2) {
+                siblings[pair[0]].1 = Some(pair[1]);
+                siblings[pair[1]].0 = Some(pair[0]);
+            }
+        }
+
+        // links First / Last of the parent to its children
+        let link_children = |dict: &mut LoDictionary, children: &[usize]| {
+            if let (Some(first), Some(last)) = (children.first(), children.last()) {
+                dict.set("First", Reference(item_ids[*first]));
+                dict.set("Last", Reference(item_ids[*last]));
+            }
+        };
+
+        for (idx, (parent, item)) in self.items.iter().enumerate() {
+            let parent = parent.map(|p| p.0);
+
+            let mut dict = LoDictionary::from_iter(vec![
+                ("Title", to_pdf_text_string(&item.title)),
+                ("Parent", Reference(parent.map(|p| item_ids[p]).unwrap_or(outline_id))),
+                ("Dest", item.destination.into_pdf_array(page_ids)),
+            ]);
+
+            let (prev, next) = siblings[idx];
+            if let Some(prev) = prev {
+                dict.set("Prev", Reference(item_ids[prev]));
+            }
+            if let Some(next) = next {
+                dict.set("Next", Reference(item_ids[next]));
+            }
+
+            if !children[idx + 1].is_empty() {
+                link_children(&mut dict, &children[idx + 1]);
+                // negative count = closed item, see page 586 in the PDF 1.7 reference
+                let count = visible[idx + 1];
+                dict.set("Count", Integer(if item.is_open { count } else { -count }));
+            }
+
+            if let Some(ref color) = item.color {
+                dict.set("C", Array(vec![Real(color.r), Real(color.g), Real(color.b)]));
+            }
+
+            let flags = if item.italic { 1 } else { 0 } | if item.bold { 2 } else { 0 };
+            if flags != 0 {
+                dict.set("F", Integer(flags));
+            }
+
+            doc.objects.insert(item_ids[idx], Dictionary(dict));
+        }
+
+        let mut outline = LoDictionary::from_iter(vec![
+            ("Type", Name("Outlines".into())),
+            ("Count", Integer(visible[0])),
+        ]);
+        link_children(&mut outline, &children[0]);
+        doc.objects.insert(outline_id, Dictionary(outline));
+
+        Some(outline_id)
+    }
+}
+
+#[test]
+fn test_outline_count() {
+    use PdfPageIndex;
+
+    let dest = Destination::page(PdfPageIndex(0));
+    let mut outline = Outline::new();
+    let chapter1 = outline.add_item(None, OutlineItem::new("Chapter 1", dest).with_open(true));
+    let section = outline.add_item(Some(chapter1), OutlineItem::new("Section 1.1", dest));
+    outline.add_item(Some(section), OutlineItem::new("Section 1.1.1", dest));
+    outline.add_item(None, OutlineItem::new("Chapter 2", dest));
+
+    // Chapter 1, Section 1.1 and Chapter 2 are visible, Section 1.1.1 is hidden
+    let visible = outline.visible_descendants(&outline.children());
+    assert_eq!(visible[0], 3);
+    assert_eq!(visible[chapter1.0 + 1], 1);
+}
+
+#[test]
+fn test_outline_titles_and_bookmarks() {
+    use lopdf::Object;
+    use pdf_document::lock_document;
+    use {Mm, PdfDocument};
+
+    let (doc, page, _) = PdfDocument::new("Outline", Mm(100.0), Mm(100.0), "Layer 1");
+    let chapter = doc.add_outline_item(None, OutlineItem::new("Größe", Destination::page(page)));
+    doc.add_outline_item(Some(chapter), OutlineItem::new("Size", Destination::page(page)));
+    #[allow(deprecated)]
+    lock_document(&doc.document).bookmarks.insert(0, "Bookmark".to_string());
+
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let outlines = saved.catalog().unwrap().get(b"Outlines").and_then(Object::as_reference).unwrap();
+    let outlines = saved.get_dictionary(outlines).unwrap();
+    assert_eq!(outlines.get(b"Count").and_then(Object::as_i64).unwrap(), 2);
+
+    let first = saved.get_dictionary(outlines.get(b"First").and_then(Object::as_reference).unwrap()).unwrap();
+    let mut title = vec![0xFE, 0xFF];
+    title.extend("Größe".encode_utf16().flat_map(|unit| unit.to_be_bytes().to_vec()));
+    assert_eq!(first.get(b"Title").and_then(Object::as_str).unwrap(), &title[..]);
+    assert_eq!(first.get(b"Count").and_then(Object::as_i64).unwrap(), -1);
+
+    // the deprecated bookmarks come after the outline items
+    let last = saved.get_dictionary(outlines.get(b"Last").and_then(Object::as_reference).unwrap()).unwrap();
+    assert_eq!(last.get(b"Title").and_then(Object::as_str).unwrap(), b"Bookmark");
+    assert_eq!(last.get(b"Prev").and_then(Object::as_reference).unwrap(), outlines.get(b"First").and_then(Object::as_reference).unwrap());
+}
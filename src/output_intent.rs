@@ -0,0 +1,341 @@
+//! Output intent of a document: the printing condition (press, paper, ...) that
+//! the colors of the document are meant for, with the ICC profile that describes it
+
+use lopdf;
+
+use glob_defines::ICC_PROFILE_ECI_V2;
+use {IccProfile, IccProfileType};
+
+/// Standard that the output intent is written for (`/S` entry of the output intent)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputIntentSubtype {
+    /// `GTS_PDFX`, for the PDF/X standards
+    PdfX,
+    /// `GTS_PDFA1`, for all parts of the PDF/A standard
+    PdfA,
+    /// `ISO_PDFE1`, for the PDF/E standard
+    PdfE,
+}
+
+impl OutputIntentSubtype {
+
+    /// Returns the name that is written to the file
+    pub fn get_name(&self)
+    -> &'static str
+    {
+        match *self {
+            OutputIntentSubtype::PdfX => "GTS_PDFX",
+            OutputIntentSubtype::PdfA => "GTS_PDFA1",
+            OutputIntentSubtype::PdfE => "ISO_PDFE1",
+        }
+    }
+}
+
+/// ICC profile of an output intent
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputIntentProfile {
+    /// The profile is embedded in the file
+    Embedded(IccProfile),
+    /// The profile is not embedded, only referenced (only allowed by PDF/X-4p, PDF/X-5pg and PDF/X-5n)
+    Referenced(ReferencedIccProfile),
+}
+
+/// ICC profile that is not embedded in the file, but identified by its name and checksum.
+/// Print shops use their own copy of the profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferencedIccProfile {
+    /// Name of the profile, for example "ISO Coated v2 (ECI)"
+    pub profile_name: String,
+    /// MD5 checksum of the profile file
+    pub check_sum: [u8; 16],
+    /// Version of the ICC specification that the profile is written in, for example "4.2"
+    pub icc_version: String,
+    /// Color space of the profile
+    pub color_space: IccProfileType,
+    /// Where the profile can be downloaded
+    pub urls: Vec<String>,
+}
+
+/// Output intent of a document, see `PdfDocumentReference::with_output_intent`.
+///
+/// The condition identifier should be the name of the printing condition in the
+/// [ICC registry](http://www.color.org/registry/), for example "FOGRA39" or "CGATS21_CRPC6"
+/// (GRACoL 2013). Printing conditions that are not registered need an `info` text
+/// that describes them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputIntent {
+    /// Standard that the output intent is for, has to match the conformance of the document
+    pub subtype: OutputIntentSubtype,
+    /// Name of the printing condition (`OutputConditionIdentifier`)
+    pub condition_identifier: String,
+    /// Description of the printing condition, for humans (`OutputCondition`)
+    pub condition: Option<String>,
+    /// Registry of the printing condition, usually "http://www.color.org" (`RegistryName`)
+    pub registry_name: Option<String>,
+    /// Further information about the printing condition (`Info`)
+    pub info: Option<String>,
+    /// ICC profile of the printing condition
+    pub profile: OutputIntentProfile,
+}
+
+impl OutputIntent {
+
+    /// Creates an output intent with an embedded profile, registered at the ICC registry
+    ///
+    /// ```rust,no_run
+    /// use printpdf::*;
+    ///
+    /// let (doc, _, _) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let profile = IccProfile::new(std::fs::read("GRACoL2013_CRPC6.icc").unwrap(), IccProfileType::Cmyk);
+    /// let doc = doc.with_output_intent(OutputIntent::new(OutputIntentSubtype::PdfX, "CGATS21_CRPC6", profile)
+    ///     .with_info("GRACoL 2013 (CGATS21_CRPC6)"));
+    /// ```
+    pub fn new<S>(subtype: OutputIntentSubtype, condition_identifier: S, profile: IccProfile)
+    -> Self where S: Into<String>
+    {
+        Self {
+            subtype,
+            condition_identifier: condition_identifier.into(),
+            condition: None,
+            registry_name: Some("http://www.color.org".into()),
+            info: None,
+            profile: OutputIntentProfile::Embedded(profile),
+        }
+    }
+
+    /// The output intent that is used if the conformance requires one and
+    /// the document has none: coated offset paper (FOGRA39)
+    pub fn fogra39(subtype: OutputIntentSubtype)
+    -> Self
+    {
+        let profile = IccProfile::new(ICC_PROFILE_ECI_V2.to_vec(), IccProfileType::Cmyk)
+            .with_alternate_profile(false)
+            .with_range(true);
+
+        Self::new(subtype, "FOGRA39", profile)
+            .with_condition("Commercial and special offset print acccording to ISO \
+                             12647-2:2004 / Amd 1, paper type 1 or 2 (matte or gloss-coated \
+                             offset paper, 115 g/m2), screen ruling 60/cm")
+            .with_info("Coated FOGRA39 (ISO 12647-2:2004)")
+    }
+
+    /// Sets the description of the printing condition
+    #[inline]
+    pub fn with_condition<S>(mut self, condition: S)
+    -> Self where S: Into<String>
+    {
+        self.condition = Some(condition.into());
+        self
+    }
+
+    /// Sets the registry of the printing condition, `None` for printing conditions that are not registered
+    #[inline]
+    pub fn with_registry_name(mut self, registry_name: Option<String>)
+    -> Self
+    {
+        self.registry_name = registry_name;
+        self
+    }
+
+    /// Sets further information about the printing condition
+    #[inline]
+    pub fn with_info<S>(mut self, info: S)
+    -> Self where S: Into<String>
+    {
+        self.info = Some(info.into());
+        self
+    }
+
+    /// References the profile instead of embedding it (PDF/X-4p, PDF/X-5pg and PDF/X-5n)
+    #[inline]
+    pub fn with_referenced_profile(mut self, profile: ReferencedIccProfile)
+    -> Self
+    {
+        self.profile = OutputIntentProfile::Referenced(profile);
+        self
+    }
+
+    /// Returns the color space of the profile: the one in the ICC header of an embedded
+    /// profile (if it can be read), otherwise the declared one
+    pub fn get_color_space(&self)
+    -> IccProfileType
+    {
+        match self.profile {
+            OutputIntentProfile::Embedded(ref profile) => profile.get_header_type().unwrap_or_else(|| profile.get_type()),
+            OutputIntentProfile::Referenced(ref profile) => profile.color_space,
+        }
+    }
+
+    /// Returns the profile, if it is embedded
+    pub fn get_embedded_profile(&self)
+    -> Option<&IccProfile>
+    {
+        match self.profile {
+            OutputIntentProfile::Embedded(ref profile) => Some(profile),
+            OutputIntentProfile::Referenced(_) => None,
+        }
+    }
+
+    /// Converts the output intent into its dictionary, the embedded profile is added to the document
+    pub(crate) fn into_obj(self, doc: &mut lopdf::Document)
+    -> lopdf::Object
+    {
+        use lopdf::Object::*;
+        use lopdf::StringFormat::{Hexadecimal, Literal};
+        use std::iter::FromIterator;
+
+        let mut dict = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("OutputIntent".into())),
+            ("S", Name(self.subtype.get_name().into())),
+            ("OutputConditionIdentifier", String(self.condition_identifier.into_bytes(), Literal)),
+        ]);
+
+        if let Some(condition) = self.condition {
+            dict.set("OutputCondition", String(condition.into_bytes(), Literal));
+        }
+        if let Some(registry_name) = self.registry_name {
+            dict.set("RegistryName", String(registry_name.into_bytes(), Literal));
+        }
+        if let Some(info) = self.info {
+            dict.set("Info", String(info.into_bytes(), Literal));
+        }
+
+        match self.profile {
+            OutputIntentProfile::Embedded(profile) => {
+                let profile: lopdf::Stream = profile.into();
+                dict.set("DestOutputProfile", Reference(doc.add_object(profile)));
+            },
+            OutputIntentProfile::Referenced(profile) => {
+                // ICC signature of the color space
+                let color_space = match profile.color_space {
+                    IccProfileType::Cmyk => "CMYK",
+                    IccProfileType::Rgb => "RGB ",
+                    IccProfileType::Greyscale => "GRAY",
+                };
+                let urls = profile.urls.into_iter().map(|url| Dictionary(lopdf::Dictionary::from_iter(vec![
+                    ("FS", Name("URL".into())),
+                    ("F", String(url.into_bytes(), Literal)),
+                ]))).collect();
+
+                dict.set("DestOutputProfileRef", lopdf::Dictionary::from_iter(vec![
+                    ("ProfileName", String(profile.profile_name.into_bytes(), Literal)),
+                    ("CheckSum", String(profile.check_sum.to_vec(), Hexadecimal)),
+                    ("ICCVersion", String(profile.icc_version.into_bytes(), Literal)),
+                    ("ProfileCS", String(color_space.into(), Literal)),
+                    ("URLs", Array(urls)),
+                ]));
+            },
+        }
+
+        Dictionary(dict)
+    }
+}
+
+#[test]
+fn test_output_intent() {
+    use lopdf::Object;
+    use {Mm, PdfConformance, PdfDocument, ViolationKind};
+
+    let cmyk_profile = || IccProfile::new(ICC_PROFILE_ECI_V2.to_vec(), IccProfileType::Cmyk);
+    let saved_output_intent = |doc: ::PdfDocumentReference| {
+        let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+        let output_intents = saved.catalog().unwrap().get(b"OutputIntents").and_then(Object::as_array).unwrap().clone();
+        saved.dereference(&output_intents[0]).unwrap().1.as_dict().unwrap().clone()
+    };
+
+    let (doc, _, _) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let doc = doc
+        .with_conformance(PdfConformance::X3_2002_PDF_1_3)
+        .with_output_intent(OutputIntent::new(OutputIntentSubtype::PdfX, "CGATS21_CRPC6", cmyk_profile())
+            .with_info("GRACoL 2013 (CGATS21_CRPC6)"));
+    // PDF/X-3 doesn't allow optional content, the layer is written as a plain content stream
+    doc.repair_errors(PdfConformance::X3_2002_PDF_1_3).unwrap();
+    assert!(doc.check_conformance().is_ok());
+    let output_intent = saved_output_intent(doc);
+    assert_eq!(output_intent.get(b"S").and_then(Object::as_name_str).unwrap(), "GTS_PDFX");
+    assert_eq!(output_intent.get(b"OutputConditionIdentifier").and_then(Object::as_str).unwrap(), b"CGATS21_CRPC6");
+    assert_eq!(output_intent.get(b"Info").and_then(Object::as_str).unwrap(), b"GRACoL 2013 (CGATS21_CRPC6)");
+    assert!(!output_intent.has(b"OutputCondition"));
+    assert!(output_intent.has(b"DestOutputProfile"));
+
+    // PDF/X-1a is only for CMYK printing conditions, PDF/A requires its own subtype
+    let rgb_profile = IccProfile::new(test_icc_profile(b"RGB "), IccProfileType::Rgb);
+    let (doc, _, _) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let doc = doc
+        .with_conformance(PdfConformance::X1A_2001_PDF_1_3)
+        .with_output_intent(OutputIntent::new(OutputIntentSubtype::PdfX, "sRGB", rgb_profile));
+    assert_eq!(doc.check_conformance().violations[0].kind,
+               ViolationKind::InvalidOutputIntent("the color space of the profile is not allowed"));
+    let doc = doc.with_conformance(PdfConformance::A2B_2011_PDF_1_7);
+    assert_eq!(doc.check_conformance().violations[0].kind,
+               ViolationKind::InvalidOutputIntent("the subtype is for another standard"));
+    doc.repair_errors(PdfConformance::A2B_2011_PDF_1_7).unwrap();
+    assert_eq!(saved_output_intent(doc).get(b"S").and_then(Object::as_name_str).unwrap(), "GTS_PDFA1");
+
+    // only PDF/X-4p and PDF/X-5 allow profiles that are not embedded
+    let referenced = ReferencedIccProfile {
+        profile_name: "Coated FOGRA39 (ISO 12647-2:2004)".into(),
+        check_sum: [0x12; 16],
+        icc_version: "2.1".into(),
+        color_space: IccProfileType::Cmyk,
+        urls: vec!["http://www.color.org/registry/".into()],
+    };
+    let (doc, _, _) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let doc = doc
+        .with_conformance(PdfConformance::X3_2003_PDF_1_4)
+        .with_output_intent(OutputIntent::fogra39(OutputIntentSubtype::PdfX).with_referenced_profile(referenced));
+    assert_eq!(doc.check_conformance().violations[0].kind,
+               ViolationKind::InvalidOutputIntent("the profile is not embedded"));
+    let doc = doc.with_conformance(PdfConformance::X4P_2010_PDF_1_6);
+    assert!(doc.check_conformance().is_ok());
+    let output_intent = saved_output_intent(doc);
+    assert!(!output_intent.has(b"DestOutputProfile"));
+    let profile_ref = output_intent.get(b"DestOutputProfileRef").and_then(Object::as_dict).unwrap();
+    assert_eq!(profile_ref.get(b"ProfileCS").and_then(Object::as_str).unwrap(), b"CMYK");
+    assert_eq!(profile_ref.get(b"CheckSum").and_then(Object::as_str).unwrap(), &[0x12; 16]);
+
+    // the declared color space has to match the one in the header of the profile
+    let mislabeled = IccProfile::new(ICC_PROFILE_ECI_V2.to_vec(), IccProfileType::Rgb);
+    let output_intent = OutputIntent::new(OutputIntentSubtype::PdfX, "FOGRA39", mislabeled);
+    assert_eq!(output_intent.get_color_space(), IccProfileType::Cmyk);
+    let (doc, _, _) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let doc = doc.with_conformance(PdfConformance::X3_2003_PDF_1_4).with_output_intent(output_intent);
+    assert_eq!(doc.check_conformance().violations[0].kind,
+               ViolationKind::InvalidOutputIntent("the color space of the profile doesn't match its ICC header"));
+}
+
+/// Header of an ICC profile with the color space `color_space` and no tags
+#[cfg(test)]
+fn test_icc_profile(color_space: &[u8; 4])
+-> Vec<u8>
+{
+    let mut icc = vec![0; 132];
+    icc[0..4].copy_from_slice(&132u32.to_be_bytes());
+    icc[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    icc[12..16].copy_from_slice(b"mntr");
+    icc[16..20].copy_from_slice(color_space);
+    icc[20..24].copy_from_slice(b"XYZ ");
+    icc[36..40].copy_from_slice(b"acsp");
+    icc
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_deprecated_target_icc_profile() {
+    use lopdf::Object;
+    use pdf_document::lock_document;
+    use {Mm, PdfConformance, PdfDocument};
+
+    let (doc, _, _) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let doc = doc.with_conformance(PdfConformance::X3_2002_PDF_1_3);
+    lock_document(&doc.document).metadata.target_icc_profile =
+        Some(IccProfile::new(test_icc_profile(b"GRAY"), IccProfileType::Greyscale));
+
+    // the profile replaces the one of the default output intent
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let output_intents = saved.catalog().unwrap().get(b"OutputIntents").and_then(Object::as_array).unwrap();
+    let output_intent = saved.dereference(&output_intents[0]).unwrap().1.as_dict().unwrap();
+    assert_eq!(output_intent.get(b"OutputConditionIdentifier").and_then(Object::as_str).unwrap(), b"FOGRA39");
+    let profile = saved.dereference(output_intent.get(b"DestOutputProfile").unwrap()).unwrap().1.as_stream().unwrap();
+    assert_eq!(profile.dict.get(b"N").and_then(Object::as_i64).unwrap(), 1);
+}
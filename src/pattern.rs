@@ -1,23 +1,318 @@
-use lopdf;
-use std::collections::HashMap;
+//! Tiling and shading patterns, which can be used instead of a fill or outline color
 
-/// __STUB__
-#[derive(Default, Debug, Copy, Clone)]
-pub struct Pattern {
+use lopdf;
+use lopdf::content::Operation;
+use lopdf::{Dictionary as LoDictionary, ObjectId};
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use {Color, CurTransMat, Point, Pt, Rect};
 
+/// A pattern, see page 173 in the PDF 1.7 reference
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Repeating cell of arbitrary content (hatches, dots, logos, ...)
+    Tiling(TilingPattern),
+    /// Smooth color transition (gradient)
+    Shading(ShadingPattern),
 }
 
 impl Pattern {
-    /// Creates a new Pattern
-    pub fn new()
+
+    /// Returns if the pattern needs a color when it is used (uncolored tiling pattern)
+    #[inline]
+    pub fn is_uncolored(&self)
+    -> bool
+    {
+        match *self {
+            Pattern::Tiling(ref tiling) => !tiling.colored,
+            Pattern::Shading(_) => false,
+        }
+    }
+
+    /// Adds the pattern to the document. `resources_id` is the resource
+    /// dictionary that the content stream of a tiling pattern uses
+    pub(crate) fn into_with_document(self, doc: &mut lopdf::Document, resources_id: ObjectId)
+    -> ObjectId
+    {
+        match self {
+            Pattern::Tiling(tiling) => tiling.into_with_document(doc, resources_id),
+            Pattern::Shading(shading) => doc.add_object(shading.into_pdf_dictionary()),
+        }
+    }
+}
+
+/// Pattern that repeats a cell horizontally and vertically
+///
+/// The cell is drawn with the regular layer operations, see
+/// `PdfPageReference::add_tiling_pattern`
+#[derive(Debug, Clone)]
+pub struct TilingPattern {
+    /// Bounding box of the pattern cell, in pattern space
+    pub bbox: Rect,
+    /// Horizontal distance between two cells
+    pub x_step: Pt,
+    /// Vertical distance between two cells
+    pub y_step: Pt,
+    /// Whether the cell specifies its own colors. Uncolored cells are
+    /// painted with the color given when the pattern is used
+    pub colored: bool,
+    /// Maps the pattern space to the default coordinate space of the page.
+    /// Default: None (identity matrix)
+    pub matrix: Option<CurTransMat>,
+    /// Content stream of the cell
+    pub(crate) operations: Vec<Operation>,
+}
+
+impl TilingPattern {
+
+    /// Creates a new colored tiling pattern, where cells are `x_step` / `y_step` apart
+    #[inline]
+    pub fn new(bbox: Rect, x_step: Pt, y_step: Pt)
     -> Self
     {
-        Self::default()
+        Self {
+            bbox,
+            x_step,
+            y_step,
+            colored: true,
+            matrix: None,
+            operations: Vec::new(),
+        }
+    }
+
+    /// Marks the pattern as uncolored (stencil). The cell must not set
+    /// any colors, the color is given when the pattern is used
+    #[inline]
+    pub fn with_uncolored(mut self)
+    -> Self
+    {
+        self.colored = false;
+        self
+    }
+
+    /// Sets the pattern matrix (for rotating or scaling the pattern)
+    #[inline]
+    pub fn with_matrix(mut self, matrix: CurTransMat)
+    -> Self
+    {
+        self.matrix = Some(matrix);
+        self
+    }
+
+    fn into_with_document(self, doc: &mut lopdf::Document, resources_id: ObjectId)
+    -> ObjectId
+    {
+        use lopdf::Object::*;
+
+        let bbox = self.bbox;
+        let mut dict = LoDictionary::from_iter(vec![
+            ("Type", Name("Pattern".into())),
+            ("PatternType", Integer(1)),
+            ("PaintType", Integer(if self.colored { 1 } else { 2 })),
+            // constant spacing
+            ("TilingType", Integer(1)),
+            ("BBox", Array(vec![bbox.ll.x.into(), bbox.ll.y.into(), bbox.ur.x.into(), bbox.ur.y.into()])),
+            ("XStep", self.x_step.into()),
+            ("YStep", self.y_step.into()),
+            ("Resources", Reference(resources_id)),
+        ]);
+
+        if let Some(matrix) = self.matrix {
+            dict.set("Matrix", matrix);
+        }
+
+        let content = lopdf::content::Content { operations: self.operations };
+        doc.add_object(lopdf::Stream::new(dict, content.encode().unwrap()))
+    }
+}
+
+/// Geometry of a gradient
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShadingType {
+    /// Linear gradient from `start` to `end`
+    Axial {
+        start: Point,
+        end: Point,
+    },
+    /// Gradient between two circles
+    Radial {
+        start: Point,
+        start_radius: Pt,
+        end: Point,
+        end_radius: Pt,
+    },
+}
+
+/// Color at a position of the gradient
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorStop {
+    /// Position between 0.0 (start of the gradient) and 1.0 (end of the gradient)
+    pub offset: f64,
+    /// Color at this position
+    pub color: Color,
+}
+
+impl ColorStop {
+
+    /// Creates a new color stop
+    #[inline]
+    pub fn new(offset: f64, color: Color)
+    -> Self
+    {
+        Self { offset, color }
+    }
+}
+
+/// Gradient pattern (axial or radial shading)
+///
+/// All color stops must use the same kind of color (RGB, CMYK or
+/// greyscale), stops that don't match the first stop are ignored
+#[derive(Debug, Clone)]
+pub struct ShadingPattern {
+    /// Axial or radial gradient
+    pub shading_type: ShadingType,
+    /// Colors of the gradient, sorted by offset
+    pub stops: Vec<ColorStop>,
+    /// Whether the colors are extended beyond the start and the end of the gradient
+    pub extend: (bool, bool),
+    /// Maps the pattern space to the default coordinate space of the page.
+    /// Default: None (identity matrix)
+    pub matrix: Option<CurTransMat>,
+}
+
+impl ShadingPattern {
+
+    /// Creates a new gradient, which is extended beyond both ends
+    pub fn new(shading_type: ShadingType, mut stops: Vec<ColorStop>)
+    -> Self
+    {
+        stops.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap_or(::std::cmp::Ordering::Equal));
+        Self {
+            shading_type,
+            stops,
+            extend: (true, true),
+            matrix: None,
+        }
+    }
+
+    /// Sets whether the colors are extended beyond the start and the end of the gradient
+    #[inline]
+    pub fn with_extend(mut self, extend_start: bool, extend_end: bool)
+    -> Self
+    {
+        self.extend = (extend_start, extend_end);
+        self
+    }
+
+    /// Sets the pattern matrix
+    #[inline]
+    pub fn with_matrix(mut self, matrix: CurTransMat)
+    -> Self
+    {
+        self.matrix = Some(matrix);
+        self
+    }
+
+    /// Returns if the gradient is written in the DeviceRGB color space (the first
+    /// stop is an RGB color, see `into_pdf_dictionary`)
+    pub(crate) fn is_rgb(&self)
+    -> bool
+    {
+        match self.stops.first() {
+            Some(stop) => matches!(stop.color, Color::Rgb(_)) && stop.color.get_cie_color_space().is_none(),
+            None => false,
+        }
+    }
+
+    /// Stitches the color stops together into one function (type 3), consisting
+    /// of one exponential interpolation (type 2) per pair of stops.
+    /// See page 167 in the PDF 1.7 reference
+    fn stops_function(&self)
+    -> lopdf::Object
+    {
+        use lopdf::Object::*;
+
+        let component_count = self.stops.first().map(|s| s.color.clone().into_vec().len()).unwrap_or(1);
+        let mut stops: Vec<(f64, Vec<f64>)> = self.stops.iter()
+            .map(|s| (s.offset.clamp(0.0, 1.0), s.color.clone().into_vec()))
+            .filter(|s| s.1.len() == component_count)
+            .collect();
+
+        // the function has to cover the whole domain from 0.0 to 1.0
+        match (stops.first().cloned(), stops.last().cloned()) {
+            (Some(first), Some(last)) => {
+                if first.0 > 0.0 { stops.insert(0, (0.0, first.1)); }
+                if last.0 < 1.0 { stops.push((1.0, last.1)); }
+            },
+            _ => stops = vec![(0.0, vec![0.0; component_count]), (1.0, vec![0.0; component_count])],
+        }
+
+        let numbers = |v: &[f64]| Array(v.iter().cloned().map(Real).collect());
+        let functions = stops.windows(2).map(|pair| Dictionary(LoDictionary::from_iter(vec![
+            ("FunctionType", Integer(2)),
+            ("Domain", Array(vec![Real(0.0), Real(1.0)])),
+            ("C0", numbers(&pair[0].1)),
+            ("C1", numbers(&pair[1].1)),
+            ("N", Real(1.0)),
+        ]))).collect::<Vec<_>>();
+
+        let bounds = stops[1..stops.len() - 1].iter().map(|s| Real(s.0)).collect();
+        let encode = functions.iter().flat_map(|_| vec![Real(0.0), Real(1.0)]).collect();
+
+        Dictionary(LoDictionary::from_iter(vec![
+            ("FunctionType", Integer(3)),
+            ("Domain", Array(vec![Real(0.0), Real(1.0)])),
+            ("Functions", Array(functions)),
+            ("Bounds", Array(bounds)),
+            ("Encode", Array(encode)),
+        ]))
+    }
+
+    fn into_pdf_dictionary(self)
+    -> LoDictionary
+    {
+        use lopdf::Object::*;
+
+        let first_color = self.stops.first().map(|s| &s.color);
+        let color_space = match first_color.and_then(Color::get_cie_color_space) {
+            Some(color_space) => color_space,
+            None => Name(match first_color {
+                Some(&Color::Rgb(_)) => "DeviceRGB",
+                Some(&Color::Cmyk(_)) | Some(&Color::SpotColor(_)) => "DeviceCMYK",
+                _ => "DeviceGray",
+            }.into()),
+        };
+
+        let (shading_type, coords) = match self.shading_type {
+            ShadingType::Axial { start, end } => (2, vec![start.x, start.y, end.x, end.y]),
+            ShadingType::Radial { start, start_radius, end, end_radius } =>
+                (3, vec![start.x, start.y, start_radius, end.x, end.y, end_radius]),
+        };
+
+        let shading = LoDictionary::from_iter(vec![
+            ("ShadingType", Integer(shading_type)),
+            ("ColorSpace", color_space),
+            ("Coords", Array(coords.into_iter().map(Into::into).collect())),
+            ("Function", self.stops_function()),
+            ("Extend", Array(vec![Boolean(self.extend.0), Boolean(self.extend.1)])),
+        ]);
+
+        let mut dict = LoDictionary::from_iter(vec![
+            ("Type", Name("Pattern".into())),
+            ("PatternType", Integer(2)),
+            ("Shading", Dictionary(shading)),
+        ]);
+
+        if let Some(matrix) = self.matrix {
+            dict.set("Matrix", matrix);
+        }
+
+        dict
     }
 }
 
 /// Named reference to a pattern
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PatternRef {
     pub(crate) name: String,
 }
@@ -32,9 +327,18 @@ impl PatternRef {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct PatternList {
-    patterns: HashMap<String, Pattern>,
+    patterns: BTreeMap<String, Pattern>,
+    /// Is the `PatternRGB` color space written for uncolored patterns? Turned off by
+    /// `repair_errors` for conformances that don't allow DeviceRGB
+    pub(crate) rgb_color_space: bool,
+}
+
+impl Default for PatternList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PatternList {
@@ -43,7 +347,8 @@ impl PatternList {
     -> Self
     {
         Self {
-            patterns: HashMap::new(),
+            patterns: BTreeMap::new(),
+            rgb_color_space: true,
         }
     }
 
@@ -56,13 +361,71 @@ impl PatternList {
         self.patterns.insert(pattern_ref.name.clone(), pattern);
         pattern_ref
     }
-}
 
-impl Into<lopdf::Dictionary> for PatternList {
-    fn into(self)
-    -> lopdf::Dictionary
+    /// The patterns of the list and their names
+    pub(crate) fn iter(&self)
+    -> impl Iterator<Item = (&String, &Pattern)>
+    {
+        self.patterns.iter()
+    }
+
+    /// The patterns of the list, for updating their content
+    pub(crate) fn patterns_mut(&mut self)
+    -> impl Iterator<Item = &mut Pattern>
+    {
+        self.patterns.values_mut()
+    }
+
+    /// Returns the color spaces, which are needed to paint uncolored
+    /// patterns (see `PATTERN_COLOR_SPACES`), if there are any uncolored patterns
+    pub(crate) fn color_spaces(&self)
+    -> LoDictionary
     {
-        // todo
-        lopdf::Dictionary::new()
+        use lopdf::Object::*;
+
+        if !self.patterns.values().any(Pattern::is_uncolored) {
+            return LoDictionary::new();
+        }
+
+        let rgb_color_space = self.rgb_color_space;
+        PATTERN_COLOR_SPACES.iter().filter(|(name, _)| rgb_color_space || *name != PATTERN_COLOR_SPACES[0].0).map(|(name, base)| {
+            (name.to_string(), Array(vec![Name("Pattern".into()), Name(base.to_string().into_bytes())]))
+        }).collect()
     }
+
+    /// Same as `XObjectList::into_with_document`, the patterns are added as references.
+    /// `resources_id` is the ID of the resource dictionary of the page
+    pub fn into_with_document(self, doc: &mut lopdf::Document, resources_id: ObjectId)
+    -> LoDictionary
+    {
+        self.patterns.into_iter().map(|(name, pattern)| {
+            (name, lopdf::Object::Reference(pattern.into_with_document(doc, resources_id)))
+        }).collect()
+    }
+}
+
+/// Names of the color spaces for uncolored patterns in the resource dictionary
+/// of a page, together with the underlying color space
+pub(crate) const PATTERN_COLOR_SPACES: [(&str, &str); 3] = [
+    ("PatternRGB", "DeviceRGB"),
+    ("PatternCMYK", "DeviceCMYK"),
+    ("PatternGray", "DeviceGray"),
+];
+
+#[test]
+fn test_shading_stops_cover_domain() {
+    use {Mm, Rgb};
+
+    let red = Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None));
+    let blue = Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None));
+    let axial = ShadingType::Axial { start: Point::new(Mm(0.0), Mm(0.0)), end: Point::new(Mm(100.0), Mm(0.0)) };
+    let pattern = ShadingPattern::new(axial, vec![ColorStop::new(0.75, blue), ColorStop::new(0.25, red)]);
+
+    // implicit stops are added at 0.0 and 1.0, so there are three segments
+    let function = pattern.stops_function();
+    let function = function.as_dict().unwrap();
+    assert_eq!(function.get(b"Functions").unwrap().as_array().unwrap().len(), 3);
+    let bounds: Vec<f64> = function.get(b"Bounds").unwrap().as_array().unwrap()
+        .iter().map(|b| b.as_f64().unwrap()).collect();
+    assert_eq!(bounds, vec![0.25, 0.75]);
 }
@@ -0,0 +1,445 @@
+//! Outline, named destinations and form fields of loaded documents. They are kept as
+//! they are in the catalog entries of the document, merged when documents are appended
+//! and filtered when pages are removed
+
+use lopdf::{self, Dictionary, Object, ObjectId, StringFormat};
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::FromIterator;
+
+use pdf_loader::{resolve_array, resolve_dict};
+
+/// Key of the name tree with the named destinations in the `Names` dictionary
+const DESTS: &[u8] = b"Dests";
+
+/// Maximum depth of a name tree, in case it contains a cycle
+const MAX_NAME_TREE_DEPTH: usize = 64;
+
+/// Target of an outline item or a link of a loaded document
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LoadedTarget {
+    /// A page object of the loaded document
+    Page(ObjectId),
+    /// A named destination
+    Name(Vec<u8>),
+    /// Another action (or no action at all)
+    Other,
+}
+
+/// Returns where a destination points to: the `Dest` of an outline item, the `D`
+/// of a `GoTo` action or the value of a named destination
+pub(crate) fn destination_target(doc: &lopdf::Document, destination: &Object)
+-> LoadedTarget
+{
+    let page = |array: Option<&Vec<Object>>| {
+        array.and_then(|array| array.first())
+            .and_then(|page| page.as_reference().ok())
+            .map(LoadedTarget::Page)
+            .unwrap_or(LoadedTarget::Other)
+    };
+
+    let destination = match doc.dereference(destination) {
+        Ok((_, destination)) => destination,
+        Err(_) => return LoadedTarget::Other,
+    };
+
+    match *destination {
+        Object::Name(ref name) | Object::String(ref name, _) => LoadedTarget::Name(name.clone()),
+        // named destinations can be a dictionary with the destination in `D`
+        Object::Dictionary(ref dict) => page(dict.get(b"D").ok().and_then(|array| resolve_array(doc, array))),
+        Object::Array(ref array) => page(Some(array)),
+        _ => LoadedTarget::Other,
+    }
+}
+
+/// Returns where an outline item or a link annotation points to
+pub(crate) fn link_target(doc: &lopdf::Document, dict: &Dictionary)
+-> LoadedTarget
+{
+    if let Ok(destination) = dict.get(b"Dest") {
+        return destination_target(doc, destination);
+    }
+
+    match dict.get(b"A").ok().and_then(|action| resolve_dict(doc, action)) {
+        Some(action) if matches!(action.get(b"S").and_then(Object::as_name), Ok(b"GoTo")) => {
+            action.get(b"D").map(|destination| destination_target(doc, destination)).unwrap_or(LoadedTarget::Other)
+        },
+        _ => LoadedTarget::Other,
+    }
+}
+
+/// Renames the named destinations that outline items and `GoTo` actions in the object point to
+pub(crate) fn rename_destinations(object: &mut Object, renames: &BTreeMap<Vec<u8>, Vec<u8>>)
+{
+    let dict = match *object {
+        Object::Dictionary(ref mut dict) => dict,
+        Object::Array(ref mut array) => {
+            for item in array.iter_mut() {
+                rename_destinations(item, renames);
+            }
+            return;
+        },
+        _ => return,
+    };
+
+    let is_goto = matches!(dict.get(b"S").and_then(Object::as_name), Ok(b"GoTo"));
+    for (key, value) in dict.iter_mut() {
+        let is_destination = key.as_slice() == b"Dest" || (is_goto && key.as_slice() == b"D");
+        match *value {
+            Object::Name(ref mut name) | Object::String(ref mut name, _) if is_destination => {
+                if let Some(new_name) = renames.get(name) {
+                    *name = new_name.clone();
+                }
+            },
+            _ => rename_destinations(value, renames),
+        }
+    }
+}
+
+/// Returns `name`, or `name-2`, `name-3`, ... if `is_taken` returns `true` for the name
+pub(crate) fn unique_name<F>(name: &[u8], mut is_taken: F)
+-> Vec<u8> where F: FnMut(&[u8]) -> bool
+{
+    let mut new_name = name.to_vec();
+    let mut number = 2;
+    while is_taken(&new_name) {
+        new_name = name.to_vec();
+        new_name.extend_from_slice(format!("-{}", number).as_bytes());
+        number += 1;
+    }
+    new_name
+}
+
+// ----- OUTLINE
+
+/// Returns the object ID of the outline dictionary of a loaded document. If the
+/// catalog entry contains the dictionary directly, it is added to the document
+pub(crate) fn outline_root(doc: &mut lopdf::Document, entries: &mut Dictionary)
+-> Option<ObjectId>
+{
+    match entries.get(b"Outlines").ok()?.clone() {
+        Object::Reference(id) => Some(id).filter(|&id| doc.get_dictionary(id).is_ok()),
+        Object::Dictionary(dict) => {
+            let id = doc.add_object(dict);
+            entries.set("Outlines", Object::Reference(id));
+            Some(id)
+        },
+        _ => None,
+    }
+}
+
+/// Returns the children of an outline item (or of the outline dictionary)
+fn outline_children(doc: &lopdf::Document, parent: ObjectId, visited: &mut BTreeSet<ObjectId>)
+-> Vec<ObjectId>
+{
+    let mut children = Vec::new();
+    let mut next = doc.get_dictionary(parent).and_then(|parent| parent.get(b"First")).and_then(Object::as_reference).ok();
+    while let Some(id) = next {
+        // the items are a linked list, stop if it contains a cycle
+        if !visited.insert(id) {
+            break;
+        }
+        let item = match doc.get_dictionary(id) {
+            Ok(item) => item,
+            Err(_) => break,
+        };
+        children.push(id);
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+    children
+}
+
+/// Links the items as the children of `parent` (`First`, `Last`, `Prev`, `Next` and `Parent`)
+/// and updates the number of visible descendants of `parent` (`Count`)
+fn link_outline_items(doc: &mut lopdf::Document, parent: ObjectId, items: &[ObjectId])
+{
+    let mut visible = 0;
+    for (index, &id) in items.iter().enumerate() {
+        let item = match doc.objects.get_mut(&id) {
+            Some(&mut Object::Dictionary(ref mut item)) => item,
+            _ => continue,
+        };
+
+        item.set("Parent", Object::Reference(parent));
+        match index.checked_sub(1).map(|prev| items[prev]) {
+            Some(prev) => item.set("Prev", Object::Reference(prev)),
+            None => { item.remove(b"Prev"); },
+        }
+        match items.get(index + 1) {
+            Some(&next) => item.set("Next", Object::Reference(next)),
+            None => { item.remove(b"Next"); },
+        }
+
+        // the descendants of open items (positive count) are visible too
+        visible += 1 + item.get(b"Count").and_then(Object::as_i64).unwrap_or(0).max(0);
+    }
+
+    let parent = match doc.objects.get_mut(&parent) {
+        Some(&mut Object::Dictionary(ref mut parent)) => parent,
+        _ => return,
+    };
+
+    if let (Some(&first), Some(&last)) = (items.first(), items.last()) {
+        parent.set("First", Object::Reference(first));
+        parent.set("Last", Object::Reference(last));
+        // the outline dictionary has no parent, closed items have a negative count
+        let is_closed = parent.has(b"Parent") && parent.get(b"Count").and_then(Object::as_i64).unwrap_or(0) <= 0;
+        parent.set("Count", Object::Integer(if is_closed { -visible } else { visible }));
+    } else {
+        parent.remove(b"First");
+        parent.remove(b"Last");
+        parent.remove(b"Count");
+    }
+}
+
+/// Appends the top-level items of the outline `other_root` to the outline `root`
+pub(crate) fn append_outline(doc: &mut lopdf::Document, root: ObjectId, other_root: ObjectId)
+{
+    let mut visited = BTreeSet::new();
+    let mut items = outline_children(doc, root, &mut visited);
+    items.extend(outline_children(doc, other_root, &mut visited));
+    link_outline_items(doc, root, &items);
+    doc.objects.remove(&other_root);
+}
+
+/// Removes the items of the loaded outline for which `keep` returns `false`, the
+/// children of a removed item move up to the parent of the item
+pub(crate) fn retain_outline_items<F>(doc: &mut lopdf::Document, entries: &mut Dictionary, mut keep: F)
+where F: FnMut(&lopdf::Document, &Dictionary) -> bool
+{
+    /// Returns the items that are kept on this level
+    fn retain_children<F>(doc: &mut lopdf::Document, parent: ObjectId, keep: &mut F, visited: &mut BTreeSet<ObjectId>)
+    -> Vec<ObjectId> where F: FnMut(&lopdf::Document, &Dictionary) -> bool
+    {
+        let mut kept = Vec::new();
+        for id in outline_children(doc, parent, visited) {
+            let children = retain_children(doc, id, keep, visited);
+            if doc.get_dictionary(id).map(|item| keep(doc, item)).unwrap_or(false) {
+                link_outline_items(doc, id, &children);
+                kept.push(id);
+            } else {
+                kept.extend(children);
+            }
+        }
+        kept
+    }
+
+    let root = match outline_root(doc, entries) {
+        Some(root) => root,
+        None => return,
+    };
+
+    let items = retain_children(doc, root, &mut keep, &mut BTreeSet::new());
+    link_outline_items(doc, root, &items);
+    if items.is_empty() {
+        entries.remove(b"Outlines");
+    }
+}
+
+// ----- ANNOTATIONS AND FORMS
+
+/// Removes the link annotations of a loaded page (`Annots` of the page entries)
+/// for which `keep` returns `false`
+pub(crate) fn retain_links<F>(doc: &lopdf::Document, page_entries: &mut Dictionary, mut keep: F)
+where F: FnMut(&lopdf::Document, &Dictionary) -> bool
+{
+    let annotations = match page_entries.get(b"Annots").ok().and_then(|annotations| resolve_array(doc, annotations)) {
+        Some(annotations) => annotations.clone(),
+        None => return,
+    };
+
+    let kept = annotations.into_iter().filter(|annotation| match resolve_dict(doc, annotation) {
+        Some(dict) if matches!(dict.get(b"Subtype").and_then(Object::as_name), Ok(b"Link")) => keep(doc, dict),
+        _ => true,
+    }).collect();
+    page_entries.set("Annots", Object::Array(kept));
+}
+
+/// Adds the form fields of another loaded document (`AcroForm` of `other_entries`).
+/// The other settings of the form of this document are kept
+pub(crate) fn append_form(doc: &lopdf::Document, entries: &mut Dictionary, other_entries: &Dictionary)
+{
+    let form = |entries: &Dictionary| entries.get(b"AcroForm").ok().and_then(|form| resolve_dict(doc, form)).cloned();
+    let fields = |form: &Dictionary| {
+        form.get(b"Fields").ok().and_then(|fields| resolve_array(doc, fields)).cloned().unwrap_or_default()
+    };
+
+    let other_form = match form(other_entries) {
+        Some(other_form) => other_form,
+        None => return,
+    };
+
+    let merged = match form(entries) {
+        Some(mut form) => {
+            let mut all_fields = fields(&form);
+            all_fields.extend(fields(&other_form));
+            form.set("Fields", Object::Array(all_fields));
+            form
+        },
+        None => other_form,
+    };
+    entries.set("AcroForm", Object::Dictionary(merged));
+}
+
+// ----- NAMED DESTINATIONS
+
+/// Name trees of the `Names` catalog entry and the older `Dests` dictionary of a loaded document
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LoadedNames {
+    /// Entries of every name tree, by the key of the tree in the `Names` dictionary
+    trees: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Object>>,
+    /// Entries of the `Dests` dictionary
+    dests: BTreeMap<Vec<u8>, Object>,
+}
+
+impl LoadedNames {
+
+    /// Reads the name trees from the catalog entries of a loaded document
+    pub(crate) fn read(doc: &lopdf::Document, entries: &Dictionary)
+    -> Self
+    {
+        let mut names = Self::default();
+
+        if let Some(trees) = entries.get(b"Names").ok().and_then(|trees| resolve_dict(doc, trees)) {
+            for (key, tree) in trees.iter() {
+                let mut tree_entries = BTreeMap::new();
+                read_name_tree(doc, tree, &mut tree_entries, 0);
+                names.trees.insert(key.clone(), tree_entries);
+            }
+        }
+
+        if let Some(dests) = entries.get(b"Dests").ok().and_then(|dests| resolve_dict(doc, dests)) {
+            names.dests = dests.iter().map(|(name, value)| (name.clone(), value.clone())).collect();
+        }
+
+        names
+    }
+
+    /// Writes the names into the catalog entries, every name tree gets a single node
+    /// (the keys are sorted, because they are stored in a `BTreeMap`)
+    pub(crate) fn write(self, entries: &mut Dictionary)
+    {
+        let mut trees = Dictionary::new();
+        for (key, tree) in self.trees.into_iter().filter(|(_, tree)| !tree.is_empty()) {
+            let names = tree.into_iter().flat_map(|(name, value)| {
+                vec![Object::String(name, StringFormat::Literal), value]
+            }).collect();
+            trees.set(key, Object::Dictionary(Dictionary::from_iter(vec![("Names", Object::Array(names))])));
+        }
+
+        if trees.is_empty() {
+            entries.remove(b"Names");
+        } else {
+            entries.set("Names", Object::Dictionary(trees));
+        }
+
+        if self.dests.is_empty() {
+            entries.remove(b"Dests");
+        } else {
+            entries.set("Dests", Object::Dictionary(Dictionary::from_iter(self.dests)));
+        }
+    }
+
+    /// Returns if there are no names (of any name tree)
+    pub(crate) fn is_empty(&self)
+    -> bool
+    {
+        self.dests.is_empty() && self.trees.values().all(BTreeMap::is_empty)
+    }
+
+    /// Returns the names of all named destinations
+    pub(crate) fn destination_names(&self)
+    -> BTreeSet<Vec<u8>>
+    {
+        let tree_names = self.trees.get(DESTS).into_iter().flat_map(|tree| tree.keys());
+        tree_names.chain(self.dests.keys()).cloned().collect()
+    }
+
+    /// Removes the named destinations for which `keep` returns `false`
+    pub(crate) fn retain_destinations<F>(&mut self, mut keep: F)
+    where F: FnMut(&Object) -> bool
+    {
+        if let Some(tree) = self.trees.get_mut(DESTS) {
+            tree.retain(|_, destination| keep(destination));
+        }
+        self.dests.retain(|_, destination| keep(destination));
+    }
+
+    /// Adds a named destination to the name tree, it replaces a loaded destination with the same name
+    pub(crate) fn insert_destination(&mut self, name: Vec<u8>, destination: Object)
+    {
+        self.dests.remove(&name);
+        self.trees.entry(DESTS.to_vec()).or_default().insert(name, destination);
+    }
+
+    /// Adds the names of another document. Named destinations are renamed with `renames`,
+    /// the keys of other name trees that are taken get a new name (see `unique_name`)
+    pub(crate) fn append(&mut self, other: LoadedNames, renames: &BTreeMap<Vec<u8>, Vec<u8>>)
+    {
+        let rename = |name: Vec<u8>| renames.get(&name).cloned().unwrap_or(name);
+
+        for (key, other_tree) in other.trees {
+            let is_dests = key.as_slice() == DESTS;
+            let tree = self.trees.entry(key).or_default();
+            for (name, value) in other_tree {
+                let new_name = if is_dests { rename(name) } else { unique_name(&name, |name| tree.contains_key(name)) };
+                tree.insert(new_name, value);
+            }
+        }
+
+        for (name, value) in other.dests {
+            self.dests.insert(rename(name), value);
+        }
+    }
+}
+
+/// Adds the entries of a name tree node and its children (`Kids`) to `entries`
+fn read_name_tree(doc: &lopdf::Document, node: &Object, entries: &mut BTreeMap<Vec<u8>, Object>, depth: usize)
+{
+    let node = match resolve_dict(doc, node) {
+        Some(node) if depth < MAX_NAME_TREE_DEPTH => node,
+        _ => return,
+    };
+
+    if let Some(names) = node.get(b"Names").ok().and_then(|names| resolve_array(doc, names)) {
+        for pair in names.chunks(2) {
+            if let (Ok(name), Some(value)) = (pair[0].as_str(), pair.get(1)) {
+                entries.insert(name.to_vec(), value.clone());
+            }
+        }
+    }
+
+    if let Some(kids) = node.get(b"Kids").ok().and_then(|kids| resolve_array(doc, kids)) {
+        for kid in kids {
+            read_name_tree(doc, kid, entries, depth + 1);
+        }
+    }
+}
+
+#[test]
+fn test_retain_outline_items() {
+    // outline: A (open, children B and C), D; C is removed, then A is removed
+    let mut doc = lopdf::Document::with_version("1.7");
+    let root = doc.new_object_id();
+    let ids: Vec<ObjectId> = (0..4).map(|_| doc.new_object_id()).collect();
+    let item = |title: &str| Dictionary::from_iter(vec![("Title", Object::string_literal(title))]);
+    for (id, title) in ids.iter().zip(&["A", "B", "C", "D"]) {
+        doc.objects.insert(*id, Object::Dictionary(item(title)));
+    }
+    doc.objects.insert(root, Object::Dictionary(Dictionary::from_iter(vec![("Type", Object::Name(b"Outlines".to_vec()))])));
+    link_outline_items(&mut doc, ids[0], &ids[1..3]);
+    link_outline_items(&mut doc, root, &[ids[0], ids[3]]);
+    assert_eq!(doc.get_dictionary(root).unwrap().get(b"Count").unwrap().as_i64().unwrap(), 4);
+
+    let mut entries = Dictionary::from_iter(vec![("Outlines", Object::Reference(root))]);
+    let title = |item: &Dictionary| item.get(b"Title").unwrap().as_str().unwrap().to_vec();
+    retain_outline_items(&mut doc, &mut entries, |_, item| title(item) != b"C");
+    assert_eq!(doc.get_dictionary(root).unwrap().get(b"Count").unwrap().as_i64().unwrap(), 3);
+    assert_eq!(doc.get_dictionary(ids[0]).unwrap().get(b"Last").unwrap().as_reference().unwrap(), ids[1]);
+
+    retain_outline_items(&mut doc, &mut entries, |_, item| title(item) != b"A");
+    assert_eq!(outline_children(&doc, root, &mut BTreeSet::new()), vec![ids[1], ids[3]]);
+    assert_eq!(doc.get_dictionary(ids[1]).unwrap().get(b"Parent").unwrap().as_reference().unwrap(), root);
+
+    retain_outline_items(&mut doc, &mut entries, |_, _| false);
+    assert!(!entries.has(b"Outlines"));
+}
@@ -6,6 +6,8 @@
 //!
 //! [PDF/A Versions](https://en.wikipedia.org/wiki/PDF/A)
 
+use {IccProfileType, OutputIntentSubtype};
+
 /// List of (relevant) PDF versions
 /// Please note the difference between **PDF/A** (archiving), **PDF/UA** (universal acessibility),
 /// **PDF/X** (printing), **PDF/E** (engineering / CAD), **PDF/VT** (large volume transactions with
@@ -108,7 +110,7 @@ pub struct CustomPdfConformance {
     /// 
     /// _(please don't enable this if you do any work that has to be printed accurately)_
     /// 
-    /// Default: __false__
+    /// Default: __true__
     pub allows_default_fonts: bool,
     /// Does this standard require an ICC profile to be embedded for color management?
     /// 
@@ -118,6 +120,14 @@ pub struct CustomPdfConformance {
     /// 
     /// Default: __true__
     pub allows_pdf_layers: bool,
+    /// Does this standard allow colors and images in the DeviceRGB color space?
+    ///
+    /// Default: __true__
+    pub allows_device_rgb: bool,
+    /// Does this standard allow transparency (alpha, soft masks, blend modes)?
+    ///
+    /// Default: __true__
+    pub allows_transparency: bool,
 }
 
 impl Default for CustomPdfConformance {
@@ -130,9 +140,11 @@ impl Default for CustomPdfConformance {
             allows_embedded_javascript: false,
             allows_jpeg_content: true,
             requires_xmp_metadata: false,
-            allows_default_fonts: false,
+            allows_default_fonts: true,
             requires_icc_profile: false,
             allows_pdf_layers: true,
+            allows_device_rgb: true,
+            allows_transparency: true,
         }
     }
 }
@@ -170,6 +182,60 @@ impl PdfConformance {
         identifier.to_string()
     }
 
+    /// The PDF version that the standard is based on, which is written in the header of the
+    /// file. `None` for custom conformances, which keep the version of the document.
+    pub fn get_pdf_version(&self)
+    -> Option<&'static str>
+    {
+        match *self {
+            PdfConformance::X1A_2001_PDF_1_3 |
+            PdfConformance::X3_2002_PDF_1_3   => Some("1.3"),
+            PdfConformance::A1B_2005_PDF_1_4 |
+            PdfConformance::A1A_2005_PDF_1_4 |
+            PdfConformance::X1A_2003_PDF_1_4 |
+            PdfConformance::X3_2003_PDF_1_4 |
+            PdfConformance::X4_2010_PDF_1_4 |
+            PdfConformance::VT_2010_PDF_1_4   => Some("1.4"),
+            PdfConformance::UA_2014_PDF_1_6 |
+            PdfConformance::X4P_2010_PDF_1_6 |
+            PdfConformance::X5G_2010_PDF_1_6 |
+            PdfConformance::X5PG_2010_PDF_1_6 |
+            PdfConformance::X5N_2010_PDF_1_6 |
+            PdfConformance::E1_2008_PDF_1_6   => Some("1.6"),
+            PdfConformance::A2_2011_PDF_1_7 |
+            PdfConformance::A2A_2011_PDF_1_7 |
+            PdfConformance::A2B_2011_PDF_1_7 |
+            PdfConformance::A2U_2011_PDF_1_7 |
+            PdfConformance::A3_2012_PDF_1_7   => Some("1.7"),
+            PdfConformance::Custom(_)         => None,
+        }
+    }
+
+    /// Part (1, 2 or 3) and conformance level ("A", "B" or "U") of the PDF/A standards,
+    /// for the `pdfaid` schema of the XMP metadata. `None` for all other conformances.
+    pub fn get_pdfa_part(&self)
+    -> Option<(u32, &'static str)>
+    {
+        match *self {
+            PdfConformance::A1B_2005_PDF_1_4  => Some((1, "B")),
+            PdfConformance::A1A_2005_PDF_1_4  => Some((1, "A")),
+            PdfConformance::A2_2011_PDF_1_7 |
+            PdfConformance::A2B_2011_PDF_1_7  => Some((2, "B")),
+            PdfConformance::A2A_2011_PDF_1_7  => Some((2, "A")),
+            PdfConformance::A2U_2011_PDF_1_7  => Some((2, "U")),
+            PdfConformance::A3_2012_PDF_1_7   => Some((3, "B")),
+            _                                 => None,
+        }
+    }
+
+    /// Is this one of the PDF/A (archiving) standards?
+    #[inline]
+    pub fn is_pdf_a(&self)
+    -> bool
+    {
+        self.get_pdfa_part().is_some()
+    }
+
     /// __STUB__: Detects if the PDF has 3D content, but the
     /// conformance to the given PDF standard does not allow it.
     pub fn is_3d_content_allowed(&self)
@@ -245,29 +311,31 @@ impl PdfConformance {
             PdfConformance::X5G_2010_PDF_1_6  => { true },
             PdfConformance::X5PG_2010_PDF_1_6 => { true },
             PdfConformance::Custom(ref c)     => { c.requires_xmp_metadata }
+            _ if self.is_pdf_a()              => { true },
             _                                 => { false },
         }
     }
 
-    /// Check if the conformance level must have an ICC Profile
+    /// Check if the conformance level must have an ICC Profile (for the output intent,
+    /// which every PDF/X standard requires)
     pub fn must_have_icc_profile(&self)
     -> bool
     {
-        // todo
         match *self {
-            PdfConformance::X1A_2001_PDF_1_3  => { false },
             PdfConformance::Custom(ref c)     => { c.requires_icc_profile }
             _                                 => { true },
         }
     }
 
-    /// __STUB__: Detects if the PDF has layering (optional content groups),
-    /// but the conformance to the given PDF standard does not allow it.
+    /// Does this conformance level allow layers (optional content groups)?
+    /// PDF/A-1, PDF/X-1a and PDF/X-3 don't, the layers are written without optional content.
     #[cfg_attr(feature = "cargo-clippy", allow(match_same_arms))]
     pub fn is_layering_allowed(&self)
     -> bool
     {
         match *self {
+            PdfConformance::A1B_2005_PDF_1_4  => { false },
+            PdfConformance::A1A_2005_PDF_1_4  => { false },
             PdfConformance::X1A_2001_PDF_1_3  => { false },
             PdfConformance::X3_2002_PDF_1_3   => { false },
             PdfConformance::X1A_2003_PDF_1_4  => { false },
@@ -276,4 +344,106 @@ impl PdfConformance {
             _                                 => { true },
         }
     }
+
+    /// Does this conformance level allow the builtin fonts (Helvetica, etc.), which are not
+    /// embedded in the file? The PDF/A, PDF/X, PDF/E and PDF/UA standards require embedded fonts.
+    pub fn is_default_font_allowed(&self)
+    -> bool
+    {
+        match *self {
+            PdfConformance::Custom(ref c)     => { c.allows_default_fonts }
+            _                                 => { false },
+        }
+    }
+
+    /// Does this conformance level allow colors and images in the DeviceRGB color space?
+    /// PDF/X-1a only allows CMYK, grey and spot colors.
+    pub fn is_device_rgb_allowed(&self)
+    -> bool
+    {
+        match *self {
+            PdfConformance::X1A_2001_PDF_1_3  => { false },
+            PdfConformance::X1A_2003_PDF_1_4  => { false },
+            PdfConformance::Custom(ref c)     => { c.allows_device_rgb }
+            _                                 => { true },
+        }
+    }
+
+    /// Does this conformance level allow colors in ICC-based and CIE-based color spaces
+    /// (colors with an ICC profile, Lab, CalRGB and CalGray)? PDF/X-1a (and custom conformances
+    /// that don't allow DeviceRGB) only allow device colors.
+    pub fn is_device_independent_color_allowed(&self)
+    -> bool
+    {
+        match *self {
+            PdfConformance::X1A_2001_PDF_1_3  => { false },
+            PdfConformance::X1A_2003_PDF_1_4  => { false },
+            PdfConformance::Custom(ref c)     => { c.allows_device_rgb }
+            _                                 => { true },
+        }
+    }
+
+    /// Does this conformance level allow transparency (constant alpha, soft masks,
+    /// blend modes and transparency groups)? PDF/A-1, PDF/X-1a and PDF/X-3 forbid it.
+    pub fn is_transparency_allowed(&self)
+    -> bool
+    {
+        match *self {
+            PdfConformance::A1B_2005_PDF_1_4  => { false },
+            PdfConformance::A1A_2005_PDF_1_4  => { false },
+            PdfConformance::X1A_2001_PDF_1_3  => { false },
+            PdfConformance::X3_2002_PDF_1_3   => { false },
+            PdfConformance::X1A_2003_PDF_1_4  => { false },
+            PdfConformance::X3_2003_PDF_1_4   => { false },
+            PdfConformance::Custom(ref c)     => { c.allows_transparency }
+            _                                 => { true },
+        }
+    }
+
+    /// Subtype of the output intent that the standard requires (`None` for
+    /// the standards without output intent and for custom conformances)
+    pub fn get_output_intent_subtype(&self)
+    -> Option<OutputIntentSubtype>
+    {
+        match *self {
+            PdfConformance::E1_2008_PDF_1_6   => Some(OutputIntentSubtype::PdfE),
+            PdfConformance::UA_2014_PDF_1_6 |
+            PdfConformance::Custom(_)         => None,
+            _ if self.is_pdf_a()              => Some(OutputIntentSubtype::PdfA),
+            _                                 => Some(OutputIntentSubtype::PdfX),
+        }
+    }
+
+    /// Does this conformance level allow an output intent with a profile in the given
+    /// color space? PDF/X-1a is only meant for CMYK printing, the other standards allow
+    /// grey, RGB and CMYK printing conditions.
+    pub fn is_output_intent_color_space_allowed(&self, color_space: IccProfileType)
+    -> bool
+    {
+        match *self {
+            PdfConformance::X1A_2001_PDF_1_3 |
+            PdfConformance::X1A_2003_PDF_1_4  => color_space == IccProfileType::Cmyk,
+            _                                 => true,
+        }
+    }
+
+    /// Does this conformance level allow an output intent whose profile is only
+    /// referenced, not embedded? Only PDF/X-4p, PDF/X-5pg and PDF/X-5n do.
+    pub fn is_referenced_output_profile_allowed(&self)
+    -> bool
+    {
+        matches!(*self,
+            PdfConformance::X4P_2010_PDF_1_6 |
+            PdfConformance::X5PG_2010_PDF_1_6 |
+            PdfConformance::X5N_2010_PDF_1_6 |
+            PdfConformance::Custom(_))
+    }
+
+    /// Does this conformance level allow images that are interpolated (smoothed) when
+    /// they are scaled up? The PDF/A standards forbid it, the image has to look the same in every viewer.
+    pub fn is_image_interpolation_allowed(&self)
+    -> bool
+    {
+        !self.is_pdf_a()
+    }
 }
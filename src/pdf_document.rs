@@ -1,19 +1,21 @@
 //! A `PDFDocument` represents the whole content of the file
 
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::BufWriter;
 use std::io::Write;
-use std::rc::Rc;
-use utils::random_character_string_32;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use utils::{random_character_string_32, ContentHash};
 
 use crate::OffsetDateTime;
 use lopdf;
 
 use indices::*;
+use object_streams::{save_with_object_streams, save_with_xref_table};
+use pdf_catalog::{append_outline, outline_root, LoadedNames};
 use {
     ExternalFont, Font, PdfPage, FontList, IccProfileList, PdfMetadata, PdfConformance, IndirectFontRef,
-    DirectFontRef, BuiltinFont, PdfPageReference, Error, Mm, FontData
+    DirectFontRef, BuiltinFont, PdfPageReference, Error, PdfError, Mm, FontData, TextMetrics,
+    Outline, OutlineItem, Destination, PdfSaveOptions, OutputIntent, IccProfile, IccProfileRef
 };
 
 /// PDF document
@@ -31,24 +33,387 @@ pub struct PdfDocument {
     pub document_id: String,
     /// Metadata for this document
     pub metadata: PdfMetadata,
-    /// The bookmarks in the document. A HashMap<Page Number, Bookmark Name>
+    /// The outline (bookmarks) of the document
+    pub(super) outline: Outline,
+    /// The bookmarks in the document. A HashMap<Page Number, Bookmark Name>. They are
+    /// added to the outline as top-level items (sorted by page) when the document is saved
+    #[deprecated(note = "bookmarks are part of the outline, use `PdfDocumentReference::add_bookmark` or `add_outline_item`")]
     pub bookmarks: HashMap<usize, String>,
+    /// Named destinations, which links can jump to by name
+    pub(super) named_destinations: BTreeMap<String, Destination>,
+    /// Entries of the catalog of a loaded document (outline, forms, ...), which
+    /// are written as they are, unless the document sets them itself
+    pub(super) original_catalog_entries: lopdf::Dictionary,
+    /// Objects copied from imported PDF files (by the hash of the file): maps the
+    /// object IDs of the file to their IDs in this document, so that resources that
+    /// several imported pages share are only copied once
+    pub(super) imported_objects: BTreeMap<String, BTreeMap<lopdf::ObjectId, lopdf::ObjectId>>,
 }
 
 /// Marker struct for a document. Used to make the API a bit nicer.
 /// It simply calls `PdfDocument` functions.
+///
+/// The document is behind a mutex, so the reference (and the page and layer
+/// references) can be shared between threads, for example to draw different
+/// pages in parallel (`thread::scope` needs Rust 1.63 or newer). A panic in one
+/// thread doesn't lock the others out of the document:
+///
+/// ```rust
+/// use printpdf::*;
+/// use std::thread;
+///
+/// let doc = PdfDocument::empty("Parallel");
+/// let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+/// let pages: Vec<_> = (0..4).map(|_| doc.add_page(Mm(210.0), Mm(297.0), "Layer 1")).collect();
+///
+/// thread::scope(|scope| {
+///     for (i, &(page, layer)) in pages.iter().enumerate() {
+///         let (doc, font) = (&doc, &font);
+///         scope.spawn(move || {
+///             let layer = doc.get_page(page).get_layer(layer);
+///             layer.use_text(format!("Page {}", i + 1), 48.0, Mm(10.0), Mm(270.0), font);
+///         });
+///     }
+/// });
+///
+/// let bytes = doc.save_to_bytes().unwrap();
+/// ```
 pub struct PdfDocumentReference {
     /// A wrapper for a document, so actions from outside this library
     /// are restricted to functions inside this crate (only functions in `lopdf`
     /// can directly manipulate the document)
-    pub(crate) document: Rc<RefCell<PdfDocument>>,
+    pub(crate) document: Arc<Mutex<PdfDocument>>,
+}
+
+/// Locks the document. If another thread panicked while it held the lock, the document
+/// stays usable (without the changes of the operation that panicked, if it didn't get far)
+pub(crate) fn lock_document(document: &Mutex<PdfDocument>)
+-> MutexGuard<'_, PdfDocument>
+{
+    document.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Objects that the pages refer to, allocated before the first page is written
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct SharedObjectIds {
+    /// The page tree (`/Pages`)
+    pages_id: lopdf::ObjectId,
+    /// The font dictionary, shared by all pages
+    pub(crate) fonts_id: lopdf::ObjectId,
+    /// Usage dictionary of the optional content groups
+    ocg_usage_id: lopdf::ObjectId,
+    /// Intent array of the optional content groups
+    ocg_intent_id: lopdf::ObjectId,
+}
+
+impl SharedObjectIds {
+
+    /// `creator` is the application that created the document, for the usage dictionary of the layers
+    pub(crate) fn new(inner_doc: &mut lopdf::Document, creator: &str)
+    -> Self
+    {
+        use lopdf::Object::*;
+        use lopdf::StringFormat::Literal;
+        use lopdf::Dictionary as LoDictionary;
+        use std::iter::FromIterator;
+
+        let pages_id = inner_doc.new_object_id();
+        let fonts_id = inner_doc.new_object_id();
+
+        let mut usage_ocg_dict = LoDictionary::new();
+        if !creator.is_empty() {
+            usage_ocg_dict.set("CreatorInfo", Dictionary(LoDictionary::from_iter(vec![
+                ("Creator", String(creator.into(), Literal)),
+                ("Subtype", Name("Artwork".into()))
+            ])));
+        }
+
+        let ocg_usage_id = inner_doc.add_object(Dictionary(usage_ocg_dict));
+
+        let intent_arr = Array(vec![
+            Name("View".into()),
+            Name("Design".into()),
+        ]);
+
+        let ocg_intent_id = inner_doc.add_object(intent_arr);
+
+        Self { pages_id, fonts_id, ocg_usage_id, ocg_intent_id }
+    }
+}
+
+/// Adds a page with its resources, contents and links to the document,
+/// under the (already allocated) `page_id`. `page_ids` are the IDs of all pages, for links.
+/// Returns the optional content groups of the layers of the page (of the layers
+/// that are optional content, see `PdfLayer::optional_content`)
+pub(crate) fn add_page_objects(inner_doc: &mut lopdf::Document, mut page: PdfPage, page_id: lopdf::ObjectId,
+                               page_ids: &[lopdf::ObjectId], shared_ids: &SharedObjectIds, has_fonts: bool)
+-> Vec<lopdf::Object>
+{
+    use lopdf::Object::*;
+    use lopdf::StringFormat::Literal;
+    use lopdf::Dictionary as LoDictionary;
+    use std::iter::FromIterator;
+
+    let annotations = ::std::mem::take(&mut page.annotations);
+    let original_entries = ::std::mem::take(&mut page.original_entries);
+
+    // optional content groups (layers) of this page, added to the /Catalog later
+    let layers: Vec<(usize, lopdf::Object)> = page.layers.iter().enumerate().filter(|&(_, layer)| layer.optional_content).map(|(layer_idx, layer)|
+        (layer_idx,
+        Reference(inner_doc.add_object(
+            Dictionary(LoDictionary::from_iter(vec![
+                ("Type", Name("OCG".into())),
+                ("Name", String(layer.name.clone().into(), Literal)),
+                ("Intent", Reference(shared_ids.ocg_intent_id)),
+                ("Usage", Reference(shared_ids.ocg_usage_id))
+            ]))
+        )))
+    ).collect();
+
+    let mut p = LoDictionary::from_iter(vec![
+        ("Type", "Page".into()),
+        ("Rotate", Integer(page.rotation)),
+        (
+            "MediaBox",
+            vec![0.into(), 0.into(), page.width.into(), page.height.into()].into(),
+        ),
+        (
+            "TrimBox",
+            vec![0.into(), 0.into(), page.width.into(), page.height.into()].into(),
+        ),
+        (
+            "CropBox",
+            vec![0.into(), 0.into(), page.width.into(), page.height.into()].into(),
+        ),
+        ("Parent", Reference(shared_ids.pages_id)),
+    ]);
+
+    // this will collect the resources needed for rendering this page
+    let resources_page_id = inner_doc.new_object_id();
+    let (mut resources_page, layer_streams) =
+        page.collect_resources_and_streams(inner_doc, resources_page_id, &layers);
+
+    if has_fonts {
+        resources_page.set("Font", Reference(shared_ids.fonts_id));
+    }
+
+    if !resources_page.is_empty() {
+        inner_doc.objects.insert(resources_page_id, Dictionary(resources_page));
+        p.set("Resources", Reference(resources_page_id));
+    }
+
+    // merge all streams of the individual layers into one big stream
+    let mut layer_streams_merged_vec = Vec::<u8>::new();
+    for mut stream in layer_streams {
+        layer_streams_merged_vec.append(&mut stream.content);
+    }
+
+    let merged_layer_stream = lopdf::Stream::new(lopdf::Dictionary::new(), layer_streams_merged_vec);
+    let page_content_id = inner_doc.add_object(merged_layer_stream);
+
+    p.set("Contents", Reference(page_content_id));
+
+    // boxes and annotations of a loaded page
+    for (key, value) in original_entries.iter() {
+        p.set(key.clone(), value.clone());
+    }
+
+    if !annotations.is_empty() {
+        let mut annotation_refs = match p.remove(b"Annots") {
+            Some(Array(existing)) => existing,
+            _ => Vec::new(),
+        };
+        for annotation in annotations {
+            let annotation_id = annotation.into_with_document(inner_doc, page_id, page_ids);
+            annotation_refs.push(Reference(annotation_id));
+        }
+        p.set("Annots", Array(annotation_refs));
+    }
+
+    inner_doc.objects.insert(page_id, Dictionary(p));
+
+    layers.into_iter().map(|(_, ocg)| ocg).collect()
 }
 
 impl PdfDocument {
 
+    /// Adds the objects on document level (catalog, page tree, fonts, outline, metadata)
+    /// after all pages were added with `add_page_objects`. `ocgs` are the optional
+    /// content groups of all pages. Returns the finished document
+    ///
+    /// `written_content` is the hash of the objects that were already written
+    /// (by a `PdfStreamWriter`), for reproducible IDs.
+    #[allow(unused_qualifications)]
+    pub(crate) fn into_lopdf_document(self, shared_ids: &SharedObjectIds, page_ids: &[lopdf::ObjectId], ocgs: Vec<lopdf::Object>,
+                                      options: &PdfSaveOptions, written_content: ContentHash)
+    -> lopdf::Document
+    {
+        use lopdf::Object::*;
+        use lopdf::StringFormat::Literal;
+        use lopdf::Dictionary as LoDictionary;
+        use std::iter::FromIterator;
+
+        let mut doc = self;
+
+        #[allow(deprecated)]
+        let bookmarks: BTreeMap<usize, ::std::string::String> = ::std::mem::take(&mut doc.bookmarks).into_iter().collect();
+        for (page, name) in bookmarks {
+            doc.outline.add_item(None, OutlineItem::new(name, Destination::page(PdfPageIndex(page))));
+        }
+
+        // extra pdf infos, the XMP metadata contains the document IDs and is added last
+        let xmp_metadata_id = if doc.metadata.conformance.must_have_xmp_metadata() {
+            Some(doc.inner_doc.new_object_id())
+        } else {
+            None
+        };
+
+        let document_info_id = doc.inner_doc.add_object(doc.metadata.document_info_obj());
+        let output_intent = doc.metadata.output_intent();
+
+        // add catalog
+        let mut catalog = LoDictionary::from_iter(vec![
+            ("Type", "Catalog".into()),
+            ("PageLayout", "OneColumn".into()),
+            (
+                "PageMode",
+                if doc.outline.is_empty() {
+                    "UseNone"
+                } else {
+                    "UseOutlines"
+                }
+                .into(),
+            ),
+            ("Pages", Reference(shared_ids.pages_id)),
+        ]);
+
+        if let Some(output_intent) = output_intent {
+            catalog.set("OutputIntents", Array(vec![output_intent.into_obj(&mut doc.inner_doc)]));
+        }
+
+        if let Some(metadata_id) = xmp_metadata_id {
+            catalog.set("Metadata", Reference(metadata_id));
+        }
+
+        // ----- OCG CONTENT
+
+        // optional content groups of a loaded document (used by the original
+        // content of its pages), the ones that were hidden stay hidden
+        let mut visible_ocgs = ocgs.clone();
+        let mut all_ocgs = ocgs;
+        let mut hidden_ocgs = Vec::new();
+        if let Some(Dictionary(original)) = doc.original_catalog_entries.remove(b"OCProperties") {
+            if let Ok(original_ocgs) = original.get(b"OCGs").and_then(lopdf::Object::as_array) {
+                all_ocgs.extend(original_ocgs.iter().cloned());
+            }
+            if let Ok(off) = original.get(b"OFF").and_then(lopdf::Object::as_array) {
+                hidden_ocgs = off.clone();
+            }
+            let hidden_ids: Vec<_> = hidden_ocgs.iter().filter_map(|ocg| ocg.as_reference().ok()).collect();
+            visible_ocgs = all_ocgs.iter()
+                .filter(|ocg| ocg.as_reference().map(|id| !hidden_ids.contains(&id)).unwrap_or(true))
+                .cloned()
+                .collect();
+        }
+
+        // without layers (or if they were removed by `repair_errors`), there is no optional content
+        if !all_ocgs.is_empty() {
+            catalog.set("OCProperties", Dictionary(LoDictionary::from_iter(vec![
+                ("OCGs", Array(all_ocgs.clone())),
+                // optional content configuration dictionary, page 376
+                ("D", Dictionary(LoDictionary::from_iter(vec![
+                    // PDF/A-2 requires a name for every configuration
+                    ("Name", String("Layers".into(), Literal)),
+                    ("Order", Array(all_ocgs)),
+                    // "radio button groups"
+                    ("RBGroups", Array(vec![])),
+                    // initially visible OCG
+                    ("ON", Array(visible_ocgs)),
+                    ("OFF", Array(hidden_ocgs)),
+                ])))
+            ])));
+        }
+
+        // ----- END OCG CONTENT (on document level)
+
+        // add all fonts / other resources shared in the whole document
+        let fonts_dict: lopdf::Dictionary = doc.fonts.into_with_document(&mut doc.inner_doc);
+
+        if !fonts_dict.is_empty() {
+            doc.inner_doc.objects.insert(shared_ids.fonts_id, Dictionary(fonts_dict));
+        }
+
+        // the items of a loaded outline come first
+        if let Some(outline_id) = doc.outline.into_with_document(&mut doc.inner_doc, page_ids) {
+            let outline_id = match outline_root(&mut doc.inner_doc, &mut doc.original_catalog_entries) {
+                Some(loaded_id) => { append_outline(&mut doc.inner_doc, loaded_id, outline_id); loaded_id },
+                None => outline_id,
+            };
+            doc.original_catalog_entries.remove(b"Outlines");
+            catalog.set("Outlines", Reference(outline_id));
+        }
+
+        // named destinations (added to the ones of a loaded document),
+        // the keys of the name tree have to be sorted (BTreeMap)
+        if !doc.named_destinations.is_empty() {
+            let mut names = LoadedNames::read(&doc.inner_doc, &doc.original_catalog_entries);
+            for (name, destination) in doc.named_destinations {
+                names.insert_destination(name.into_bytes(), destination.into_pdf_array(page_ids));
+            }
+            names.write(&mut doc.original_catalog_entries);
+        }
+
+        let pages = LoDictionary::from_iter(vec![
+            ("Type", "Pages".into()),
+            ("Count", Integer(page_ids.len() as i64)),
+            ("Kids", Array(page_ids.iter().map(|id| Reference(*id)).collect())),
+        ]);
+
+        doc.inner_doc.objects.insert(shared_ids.pages_id, Dictionary(pages));
+
+        let instance_id = match options.document_ids(written_content, &doc.inner_doc.objects) {
+            Some((document_id, instance_id)) => {
+                doc.metadata.xmp_metadata.document_id = document_id.clone();
+                doc.document_id = document_id;
+                instance_id
+            },
+            None => random_character_string_32(),
+        };
+
+        if let Some(xmp_metadata_id) = xmp_metadata_id {
+            if let Some(xmp_metadata) = doc.metadata.xmp_metadata_obj(&instance_id) {
+                doc.inner_doc.objects.insert(xmp_metadata_id, xmp_metadata);
+            }
+        }
+
+        for (key, value) in doc.original_catalog_entries.iter() {
+            if !catalog.has(key) {
+                catalog.set(key.clone(), value.clone());
+            }
+        }
+
+        // save inner document
+        let catalog_id = doc.inner_doc.add_object(catalog);
+
+        doc.inner_doc.trailer.set("Root", Reference(catalog_id));
+        doc.inner_doc
+            .trailer
+            .set("Info", Reference(document_info_id));
+        doc.inner_doc.trailer.set(
+            "ID",
+            Array(vec![
+                String(doc.document_id.as_bytes().to_vec(), Literal),
+                String(instance_id.as_bytes().to_vec(), Literal),
+            ]),
+        );
+
+        doc.inner_doc
+    }
+
     /// Creates a new PDF document
     #[inline]
     #[cfg_attr(feature = "cargo-clippy", allow(new_ret_no_self))]
+    #[allow(deprecated)]
     pub fn new<S1, S2>(
         document_title: S1,
         initial_page_width: Mm,
@@ -64,10 +429,14 @@ impl PdfDocument {
             icc_profiles: IccProfileList::new(),
             inner_doc: lopdf::Document::with_version("1.3"),
             metadata: PdfMetadata::new(document_title, 1, false, PdfConformance::default()),
+            outline: Outline::new(),
             bookmarks: HashMap::new(),
+            named_destinations: BTreeMap::new(),
+            original_catalog_entries: lopdf::Dictionary::new(),
+            imported_objects: BTreeMap::new(),
         };
 
-        let doc_ref = Rc::new(RefCell::new(doc));
+        let doc_ref = Arc::new(Mutex::new(doc));
 
         let (initial_page, layer_index) = PdfPage::new(
             initial_page_width,
@@ -75,11 +444,12 @@ impl PdfDocument {
             initial_layer_name,
             0);
 
-        { doc_ref.borrow_mut().pages.push(initial_page); }
+        { lock_document(&doc_ref).pages.push(initial_page); }
 
         (PdfDocumentReference { document: doc_ref }, PdfPageIndex(0), layer_index)
     }
 
+    #[allow(deprecated)]
     pub fn empty<S: Into<String>>(document_title: S) -> PdfDocumentReference {
         let doc = Self {
             pages: Vec::new(),
@@ -88,39 +458,32 @@ impl PdfDocument {
             icc_profiles: IccProfileList::new(),
             inner_doc: lopdf::Document::with_version("1.3"),
             metadata: PdfMetadata::new(document_title, 1, false, PdfConformance::X3_2002_PDF_1_3),
+            outline: Outline::new(),
             bookmarks: HashMap::new(),
+            named_destinations: BTreeMap::new(),
+            original_catalog_entries: lopdf::Dictionary::new(),
+            imported_objects: BTreeMap::new(),
         };
 
-        let doc_ref = Rc::new(RefCell::new(doc));
+        let doc_ref = Arc::new(Mutex::new(doc));
         PdfDocumentReference { document: doc_ref }
     }
 }
 
 macro_rules! implement_adding_fonts {
-    ($self:expr, $font_name:expr, $font:expr) => {{
-        let font_ref;
-
-        let possible_ref = {
-            let doc = $self.document.borrow();
-            font_ref = IndirectFontRef::new($font_name);
-            match doc.fonts.get_font(&font_ref) {
-                Some(f) => Some(f.clone()),
-                None => None,
-            }
-        };
+    ($doc:expr, $font_name:expr, $font:expr) => {{
+        let font_ref = IndirectFontRef::new($font_name);
 
-        if possible_ref.is_some() {
-            Ok(font_ref)
-        } else {
-            let mut doc = $self.document.borrow_mut();
+        if $doc.fonts.get_font(&font_ref).is_none() {
             let direct_ref = DirectFontRef {
-                inner_obj: doc.inner_doc.new_object_id(),
+                inner_obj: $doc.inner_doc.new_object_id(),
                 data: $font
             };
 
-            doc.fonts.add_font(font_ref.clone(), direct_ref);
-            Ok(font_ref)
+            $doc.fonts.add_font(font_ref.clone(), direct_ref);
         }
+
+        Ok(font_ref)
     }}
 }
 
@@ -133,7 +496,7 @@ impl PdfDocumentReference {
     pub fn with_title<S>(self, new_title: S)
     -> Self where S: Into<String>
     {
-        self.document.borrow_mut().metadata.document_title = new_title.into();
+        lock_document(&self.document).metadata.document_title = new_title.into();
         self
     }
     
@@ -142,7 +505,7 @@ impl PdfDocumentReference {
     pub fn with_author<S>(self, author: S)
     -> Self where S: Into<String>
     {
-        self.document.borrow_mut().metadata.author = author.into();
+        lock_document(&self.document).metadata.author = author.into();
         self
     }
     
@@ -151,7 +514,7 @@ impl PdfDocumentReference {
     pub fn with_creator<S>(self, creator: S)
     -> Self where S: Into<String>
     {
-        self.document.borrow_mut().metadata.creator = creator.into();
+        lock_document(&self.document).metadata.creator = creator.into();
         self
     }
      
@@ -160,7 +523,7 @@ impl PdfDocumentReference {
     pub fn with_producer<S>(self, producer: S)
     -> Self where S: Into<String>
     {
-        self.document.borrow_mut().metadata.producer = producer.into();
+        lock_document(&self.document).metadata.producer = producer.into();
         self
     }
     
@@ -169,7 +532,7 @@ impl PdfDocumentReference {
     pub fn with_keywords<S>(self, keywords: Vec<S>)
     -> Self where S: Into<String>
     {
-        self.document.borrow_mut().metadata.keywords = keywords.into_iter().map(|s| s.into()).collect();
+        lock_document(&self.document).metadata.keywords = keywords.into_iter().map(|s| s.into()).collect();
         self
     }
 
@@ -178,7 +541,7 @@ impl PdfDocumentReference {
     pub fn with_subject<S>(self, subject: S)
     -> Self where S: Into<String>
     {
-        self.document.borrow_mut().metadata.subject = subject.into();
+        lock_document(&self.document).metadata.subject = subject.into();
         self
     }
     
@@ -187,7 +550,7 @@ impl PdfDocumentReference {
     pub fn with_identifier<S>(self, identifier: S)
     -> Self where S: Into<String>
     {
-        self.document.borrow_mut().metadata.identifier = identifier.into();
+        lock_document(&self.document).metadata.identifier = identifier.into();
         self
     }
 
@@ -196,7 +559,7 @@ impl PdfDocumentReference {
     pub fn with_trapping(self, trapping: bool)
     -> Self
     {
-        self.document.borrow_mut().metadata.trapping = trapping;
+        lock_document(&self.document).metadata.trapping = trapping;
         self
     }
 
@@ -205,7 +568,7 @@ impl PdfDocumentReference {
     pub fn with_document_id(self, id: String)
     -> Self
     {
-        self.document.borrow_mut().metadata.xmp_metadata.document_id = id;
+        lock_document(&self.document).metadata.xmp_metadata.document_id = id;
         self
     }
 
@@ -214,7 +577,7 @@ impl PdfDocumentReference {
     pub fn with_document_version(self, version: u32)
     -> Self
     {
-        self.document.borrow_mut().metadata.document_version = version;
+        lock_document(&self.document).metadata.document_version = version;
         self
     }
 
@@ -224,7 +587,18 @@ impl PdfDocumentReference {
     pub fn with_conformance(self, conformance: PdfConformance)
     -> Self
     {
-        self.document.borrow_mut().metadata.conformance = conformance;
+        lock_document(&self.document).metadata.conformance = conformance;
+        self
+    }
+
+    /// Sets the output intent: the printing condition that the colors of the document are
+    /// meant for, see `OutputIntent`. Only written if the conformance requires an output intent
+    /// (all PDF/A, PDF/X and PDF/E standards), which is FOGRA39 (coated offset paper) by default.
+    #[inline]
+    pub fn with_output_intent(self, output_intent: OutputIntent)
+    -> Self
+    {
+        lock_document(&self.document).metadata.output_intent = Some(output_intent);
         self
     }
 
@@ -235,7 +609,7 @@ impl PdfDocumentReference {
     pub fn with_creation_date(self, creation_date: OffsetDateTime)
     -> Self
     {
-        self.document.borrow_mut().metadata.creation_date = creation_date;
+        lock_document(&self.document).metadata.creation_date = creation_date;
         self
     }
 
@@ -246,7 +620,7 @@ impl PdfDocumentReference {
     pub fn with_metadata_date(self, metadata_date: OffsetDateTime)
     -> Self
     {
-        self.document.borrow_mut().metadata.metadata_date = metadata_date;
+        lock_document(&self.document).metadata.metadata_date = metadata_date;
         self
     }
 
@@ -256,7 +630,22 @@ impl PdfDocumentReference {
     pub fn with_mod_date(self, mod_date: OffsetDateTime)
     -> Self
     {
-        self.document.borrow_mut().metadata.modification_date = mod_date;
+        lock_document(&self.document).metadata.modification_date = mod_date;
+        self
+    }
+
+    /// Sets the creation, modification and metadata date to the same date,
+    /// for reproducible output (see `PdfSaveOptions::reproducible`)
+    #[inline]
+    pub fn with_fixed_date(self, date: OffsetDateTime)
+    -> Self
+    {
+        {
+            let mut doc = lock_document(&self.document);
+            doc.metadata.creation_date = date.clone();
+            doc.metadata.modification_date = date.clone();
+            doc.metadata.metadata_date = date;
+        }
         self
     }
 
@@ -273,22 +662,57 @@ impl PdfDocumentReference {
     where
         S: Into<String>,
     {
-        let mut doc = self.document.borrow_mut();
+        let mut doc = lock_document(&self.document);
         let (pdf_page, pdf_layer_index) =
             PdfPage::new(x_mm, y_mm, inital_layer_name, doc.pages.len());
         doc.pages.push(pdf_page);
         let page_index = PdfPageIndex(doc.pages.len() - 1);
         (page_index, pdf_layer_index)
     }
-    /// Create a new pdf page and returns the index of the page.
-    /// If the page already has a bookmark, overwrites it.
+    /// Adds a top-level bookmark that jumps to the top of the page.
+    /// Shorthand for `add_outline_item(None, OutlineItem::new(name, Destination::page(page)))`
     #[inline]
     pub fn add_bookmark<S>(&self, name: S, page: PdfPageIndex)
     where
         S: Into<String>,
     {
-        let mut doc = self.document.borrow_mut();
-        doc.bookmarks.insert(page.0, name.into());
+        self.add_outline_item(None, OutlineItem::new(name, Destination::page(page)));
+    }
+
+    /// Adds an item to the document outline, as the last child of `parent`
+    /// (or as a top-level item if `parent` is `None`). Returns the index of the
+    /// new item, which can be used as the parent of other items.
+    #[inline]
+    pub fn add_outline_item(&self, parent: Option<OutlineItemIndex>, item: OutlineItem)
+    -> OutlineItemIndex
+    {
+        let mut doc = lock_document(&self.document);
+        doc.outline.add_item(parent, item)
+    }
+
+    /// Adds an ICC profile to the document, for colors in the color space of the profile:
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// # let profile_bytes = Vec::new();
+    /// let (doc, page1, layer1) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let adobe_rgb = doc.add_icc_profile(IccProfile::new(profile_bytes, IccProfileType::Rgb));
+    /// let layer = doc.get_page(page1).get_layer(layer1);
+    /// layer.set_fill_color(Color::Rgb(Rgb::new(0.8, 0.1, 0.1, Some(adobe_rgb))));
+    /// ```
+    ///
+    /// The profile is embedded once and added to the resources of the pages that use it.
+    /// Colors with a profile in another color space (for example a CMYK color with an RGB
+    /// profile) are written without the profile.
+    pub fn add_icc_profile(&self, profile: IccProfile)
+    -> IccProfileRef
+    {
+        let mut doc = lock_document(&self.document);
+        let icc_type = profile.get_type();
+        let stream: lopdf::Stream = profile.into();
+        let object_id = doc.inner_doc.add_object(stream);
+        doc.icc_profiles.add_embedded_profile(icc_type, object_id)
     }
 
     /// Add a font from a font stream
@@ -299,14 +723,14 @@ impl PdfDocumentReference {
     where
         R: ::std::io::Read,
     {
-        let last_font_index = {
-            let doc = self.document.borrow();
-            doc.fonts.len()
-        };
+        // the document stays locked until the font is added, so
+        // that fonts added from other threads get different names
+        let mut doc = lock_document(&self.document);
+        let last_font_index = doc.fonts.len();
         let external_font = ExternalFont::new(font_stream, last_font_index)?;
         let external_font_name = external_font.face_name.clone();
         let font = Font::ExternalFont(external_font);
-        implement_adding_fonts!(&self, external_font_name, font)
+        implement_adding_fonts!(doc, external_font_name, font)
     }
 
     /// Add a font from a custom font backend
@@ -315,11 +739,12 @@ impl PdfDocumentReference {
     where
         F: FontData + 'static,
     {
-        let last_font_index = { let doc = self.document.borrow(); doc.fonts.len() };
+        let mut doc = lock_document(&self.document);
+        let last_font_index = doc.fonts.len();
         let external_font = ExternalFont::with_font_data(bytes, last_font_index, Box::new(data));
         let external_font_name = external_font.face_name.clone();
         let font = Font::ExternalFont(external_font);
-        implement_adding_fonts!(&self, external_font_name, font)
+        implement_adding_fonts!(doc, external_font_name, font)
     }
 
     /// Add a built-in font to the document
@@ -332,19 +757,38 @@ impl PdfDocumentReference {
     -> ::std::result::Result<IndirectFontRef, Error>
     {
         let builtin_font_name: &'static str = builtin_font.clone().into();
-        implement_adding_fonts!(&self, builtin_font_name, Font::BuiltinFont(builtin_font))
+        let mut doc = lock_document(&self.document);
+        implement_adding_fonts!(doc, builtin_font_name, Font::BuiltinFont(builtin_font))
+    }
+
+    /// Registers a named destination, which can be the target of a `LinkAction::Named`
+    /// (or of links from other documents). Overwrites an existing destination with the same name.
+    #[inline]
+    pub fn add_named_destination<S>(&self, name: S, destination: Destination)
+    where
+        S: Into<String>,
+    {
+        let mut doc = lock_document(&self.document);
+        doc.named_destinations.insert(name.into(), destination);
     }
 
     // ----- GET FUNCTIONS
 
     /// Returns the page (for inserting content)
     #[inline]
-    #[cfg_attr(feature = "cargo-clippy", allow(unnecessary_operation))]
     pub fn get_page(&self, page: PdfPageIndex)
     -> PdfPageReference
     {
-        &self.document.borrow_mut().pages[page.0];
-        PdfPageReference { document: Rc::downgrade(&self.document).clone(), page }
+        PdfPageReference { document: Arc::downgrade(&self.document).clone(), page }
+    }
+
+    /// Returns the indices of all pages of the document
+    #[inline]
+    pub fn get_pages(&self)
+    -> Vec<PdfPageIndex>
+    {
+        let doc = lock_document(&self.document);
+        (0..doc.pages.len()).map(PdfPageIndex).collect()
     }
 
     /// Returns a direct reference (object ID) to the font from an
@@ -353,385 +797,382 @@ impl PdfDocumentReference {
     pub fn get_font(&self, font: &IndirectFontRef)
     -> Option<DirectFontRef>
     {
-        let doc = self.document.borrow();
+        let doc = lock_document(&self.document);
         doc.fonts.get_font(font)
     }
 
+    /// Measures the width, ascent, descent and line gap of a string written with
+    /// `write_text` in the given font and font size (in points).
+    ///
+    /// Returns `None` if the font was not added to this document.
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// let doc = PdfDocument::empty("Measure");
+    /// let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    /// let metrics = doc.measure_text("Hello", &font, 12.0).unwrap();
+    /// let width: Mm = metrics.width.into();
+    /// assert!(width.0 > 0.0);
+    /// ```
+    #[inline]
+    pub fn measure_text(&self, text: &str, font: &IndirectFontRef, font_size: f64)
+    -> Option<TextMetrics>
+    {
+        let doc = lock_document(&self.document);
+        doc.fonts.get_font_data(font).map(|f| f.measure_text(text, font_size))
+    }
+
     /// Drops the PDFDocument, returning the inner `lopdf::Document`.
     /// Document may be only half-written, use only in extreme cases
     #[inline]
     pub unsafe fn get_inner(self)
     -> lopdf::Document
     {
-        let doc = Rc::try_unwrap(self.document).unwrap().into_inner();
+        let doc = Arc::try_unwrap(self.document).unwrap().into_inner().unwrap_or_else(PoisonError::into_inner);
         doc.inner_doc
     }
 
+    /// Takes the document out of the reference, for saving it. Fails if another
+    /// thread still uses the document (through a page or layer reference)
+    pub(crate) fn into_document(self)
+    -> Result<PdfDocument, Error>
+    {
+        let document = Arc::try_unwrap(self.document).map_err(|_| PdfError::DocumentInUse)?;
+        Ok(document.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+
     // --- MISC FUNCTIONS
 
-    /// Checks for invalid settings in the document
+    /// Checks the document against the rules of its conformance, returns
+    /// `Error::Conformance` with all violations (see `check_conformance`)
     pub fn check_for_errors(&self)
     -> ::std::result::Result<(), Error>
     {
-        // TODO
-        #[cfg(feature = "logging")] {
-            warn!("Checking PDFs for errors is currently not supported!");
+        let report = self.check_conformance();
+        if report.is_ok() {
+            Ok(())
+        } else {
+            Err(report.into())
         }
-
-        Ok(())
     }
 
-    /// Tries to match the document to the given conformance.
-    /// Errors only on an unrecoverable error.
-    pub fn repair_errors(&self, _conformance: PdfConformance)
+    /// Tries to match the document to the given conformance, which becomes the
+    /// conformance of the document. Fixes what can be fixed automatically:
+    ///
+    /// - RGB colors, images and patterns are converted to CMYK with the ICC profile of the
+    ///   output intent, if the conformance doesn't allow DeviceRGB (PDF/X-1a, or PDF/A
+    ///   with a CMYK profile for the output intent)
+    /// - colors in ICC-based, Lab, CalRGB and CalGray color spaces are converted to CMYK
+    ///   and the color spaces are removed from the page resources, if the conformance only
+    ///   allows device colors (PDF/X-1a)
+    /// - constant alpha, soft masks, blend modes and transparency groups are removed and
+    ///   images with an alpha channel are flattened onto white, if transparency is not allowed
+    /// - the output intent is set to the default (FOGRA39), if the conformance requires
+    ///   one and the document has none, and its subtype is set to the one of the conformance
+    /// - the layers are written as plain content streams without optional content groups,
+    ///   if layers are not allowed (references to the layers can still be used, layers
+    ///   that are added afterwards are written as optional content again)
+    /// - builtin fonts that no page uses are removed
+    /// - the interpolation of images is turned off, if it is not allowed (PDF/A)
+    ///
+    /// Returns `Error::Conformance` with the violations that can't be fixed,
+    /// such as builtin fonts that are used, see `check_conformance`.
+    pub fn repair_errors(&self, conformance: PdfConformance)
     -> ::std::result::Result<(), Error>
     {
-        // TODO
-        #[cfg(feature = "logging")] {
-            warn!("Reparing PDFs is currently not supported!");
-        }
-
-        Ok(())
+        let mut doc = lock_document(&self.document);
+        doc.repair(conformance)
     }
 
-    /// Save PDF document to bytes
-    #[allow(unused_qualifications)]
+    /// Save PDF document to bytes, with the default `PdfSaveOptions`
     pub fn save_to_bytes(self) -> Result<Vec<u8>, Error> {
+        self.save_to_bytes_with_options(PdfSaveOptions::default())
+    }
 
-        use lopdf::Object::*;
-        use lopdf::StringFormat::Literal;
-        use lopdf::{Dictionary as LoDictionary, Object as LoObject};
-        use std::iter::FromIterator;
-        use std::mem;
+    /// Save PDF document to bytes, `options` control the compression of the streams
+    pub fn save_to_bytes_with_options(self, options: PdfSaveOptions) -> Result<Vec<u8>, Error> {
 
-        // todo: remove unwrap, handle error
-        let mut doc = Rc::try_unwrap(self.document).unwrap().into_inner();
-        let pages_id = doc.inner_doc.new_object_id();
-        let bookmarks_id = doc.inner_doc.new_object_id();
-        let mut bookmarks_list = LoDictionary::from_iter(vec![
-            ("Type", "Outlines".into()),
-            ("Count", Integer(doc.bookmarks.len() as i64)),
-            /* First and Last will be filled in once they are created from the pages */
-        ]);
+        use std::mem;
 
-        // extra pdf infos
-        let (xmp_metadata, document_info, icc_profile) = doc.metadata.clone().into_obj();
+        let mut doc = self.into_document()?;
+        let shared_ids = SharedObjectIds::new(&mut doc.inner_doc, &doc.metadata.creator);
 
-        let xmp_metadata_id = match xmp_metadata {
-            Some(metadata) => Some(doc.inner_doc.add_object(metadata)),
-            None => None,
+        // page IDs are allocated before the pages are written, so that
+        // outline items can refer to any page
+        let page_obj_ids: Vec<lopdf::ObjectId> = {
+            let inner_doc = &mut doc.inner_doc;
+            doc.pages.iter().map(|page| page.object_id.unwrap_or_else(|| inner_doc.new_object_id())).collect()
         };
 
-        let document_info_id = doc.inner_doc.add_object(document_info);
-
-        // add catalog
-        let icc_profile_descr = "Commercial and special offset print acccording to ISO \
-                                 12647-2:2004 / Amd 1, paper type 1 or 2 (matte or gloss-coated \
-                                 offset paper, 115 g/m2), screen ruling 60/cm";
-        let icc_profile_str = "Coated FOGRA39 (ISO 12647-2:2004)";
-        let icc_profile_short = "FOGRA39";
-
-        let mut output_intents = LoDictionary::from_iter(vec![
-            ("S", Name("GTS_PDFX".into())),
-            ("OutputCondition", String(icc_profile_descr.into(), Literal)),
-            ("Type", Name("OutputIntent".into())),
-            (
-                "OutputConditionIdentifier",
-                String(icc_profile_short.into(), Literal),
-            ),
-            (
-                "RegistryName",
-                String("http://www.color.org".into(), Literal),
-            ),
-            ("Info", String(icc_profile_str.into(), Literal)),
-        ]);
+        let has_fonts = !doc.fonts.is_empty();
+        let pdf_version = doc.metadata.conformance.get_pdf_version();
+        let mut ocgs = Vec::new();
+        for (page, page_id) in mem::take(&mut doc.pages).into_iter().zip(page_obj_ids.iter()) {
+            ocgs.append(&mut add_page_objects(&mut doc.inner_doc, page, *page_id, &page_obj_ids, &shared_ids, has_fonts));
+        }
 
-        let mut catalog = LoDictionary::from_iter(vec![
-            ("Type", "Catalog".into()),
-            ("PageLayout", "OneColumn".into()),
-            (
-                "PageMode",
-                if doc.bookmarks.len() > 0 {
-                    "UseOutlines"
-                } else {
-                    "UseNone"
-                }
-                .into(),
-            ),
-            ("Outlines", Reference(bookmarks_id)),
-            ("Pages", Reference(pages_id)),
-        ]);
+        let mut inner_doc = doc.into_lopdf_document(&shared_ids, &page_obj_ids, ocgs, &options, ContentHash::new());
+        options.apply(&mut inner_doc);
 
-        if let Some(profile) = icc_profile {
-            let icc_profile: lopdf::Stream = profile.into();
-            let icc_profile_id = doc.inner_doc.add_object(Stream(icc_profile));
-            output_intents.set("DestinationOutputProfile", Reference(icc_profile_id));
-            catalog.set("OutputIntents", Array(vec![Dictionary(output_intents)]));
+        if let Some(version) = pdf_version {
+            inner_doc.version = version.into();
         }
 
-        if let Some(metadata_id) = xmp_metadata_id {
-            catalog.set("Metadata", Reference(metadata_id));
+        // the standards that are based on an older version don't allow object streams
+        if options.object_streams && !matches!(pdf_version, Some(version) if version < "1.5") {
+            // object streams and cross-reference streams were added in PDF 1.5
+            if inner_doc.version.as_str() < "1.5" {
+                inner_doc.version = "1.5".into();
+            }
+            return Ok(save_with_object_streams(&inner_doc, options.compression_level)?);
         }
 
-        let mut pages = LoDictionary::from_iter(vec![
-                      ("Type", "Pages".into()),
-                      ("Count", Integer(doc.pages.len() as i64)),
-                      /* Kids and Resources missing */
-                      ]);
-
-        // add all pages with contents
-        let mut page_ids = Vec::<LoObject>::new();
-
-        // ----- OCG CONTENT
-
-        // page index + page names to add the OCG to the /Catalog
-        let page_layer_names: Vec<(usize, Vec<::std::string::String>)> =
-            doc.pages.iter().map(|page|
-                (page.index, page.layers.iter().map(|layer|
-                    layer.name.clone()).collect()
-            )).collect();
-
-        // add optional content groups (layers) to the /Catalog
-        let usage_ocg_dict = LoDictionary::from_iter(vec![
-            ("Type", Name("OCG".into())),
-            ("CreatorInfo", Dictionary(LoDictionary::from_iter(vec![
-                ("Creator", String("Adobe Illustrator 14.0".into(), Literal)),
-                ("Subtype", Name("Artwork".into()))
-            ]))),
-        ]);
-
-        let usage_ocg_dict_ref = doc.inner_doc.add_object(Dictionary(usage_ocg_dict));
+        Ok(save_with_xref_table(&inner_doc)?)
+    }
 
-        let intent_arr = Array(vec![
-            Name("View".into()),
-            Name("Design".into()),
-        ]);
+    /// Save PDF Document, writing the contents to the target
+    pub fn save<W: Write>(self, target: &mut BufWriter<W>) -> Result<(), Error> {
+        self.save_with_options(target, PdfSaveOptions::default())
+    }
 
-        let intent_arr_ref = doc.inner_doc.add_object(intent_arr);
-
-        // page index, layer index, reference to OCG dictionary
-        let ocg_list: Vec<(usize, Vec<(usize, lopdf::Object)>)> =
-
-        page_layer_names.into_iter().map(|(page_idx, layer_names)|
-            (page_idx,
-            layer_names.into_iter().enumerate().map(|(layer_idx, layer_name)|
-                (layer_idx,
-                Reference(doc.inner_doc.add_object(
-                    Dictionary(LoDictionary::from_iter(vec![
-                        ("Type", Name("OCG".into())),
-                        ("Name", String(layer_name.into(), Literal)),
-                        ("Intent", Reference(intent_arr_ref)),
-                        ("Usage", Reference(usage_ocg_dict_ref))
-                    ]))
-                )))
-            ).collect()))
-        .collect();
+    /// Save PDF Document with the given options, writing the contents to the target
+    pub fn save_with_options<W: Write>(self, target: &mut BufWriter<W>, options: PdfSaveOptions) -> Result<(), Error> {
+        let pdf_as_bytes = self.save_to_bytes_with_options(options)?;
+        target.write_all(&pdf_as_bytes)?;
+        Ok(())
+    }
+}
 
-        let flattened_ocg_list: Vec<lopdf::Object> =
-            ocg_list.iter().flat_map(|&(_, ref layers)|
-                layers.iter().map(|&(_, ref obj)| obj.clone())
-            ).collect();
-
-        catalog.set("OCProperties", Dictionary(LoDictionary::from_iter(vec![
-            ("OCGs", Array(flattened_ocg_list.clone())),
-            // optional content configuration dictionary, page 376
-            ("D", Dictionary(LoDictionary::from_iter(vec![
-                ("Order", Array(flattened_ocg_list.clone())),
-                // "radio button groups"
-                ("RBGroups", Array(vec![])),
-                // initially visible OCG
-                ("ON", Array(flattened_ocg_list)),
-            ])))
-        ])));
+#[test]
+fn test_document_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() { }
+    assert_send_sync::<PdfDocumentReference>();
+    assert_send_sync::<PdfPageReference>();
+    assert_send_sync::<::PdfLayerReference>();
+}
 
-        // ----- END OCG CONTENT (on document level)
+#[test]
+fn test_draw_pages_in_parallel() {
+    use std::thread;
+    use {BuiltinFont, PdfDocument, PdfLayerIndex};
+
+    let doc = PdfDocument::empty("Parallel");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let pages: Vec<_> = (0..8).map(|_| doc.add_page(Mm(210.0), Mm(297.0), "Layer 1")).collect();
+
+    thread::scope(|scope| {
+        for (i, &(page, layer)) in pages.iter().enumerate() {
+            let (doc, font) = (&doc, &font);
+            scope.spawn(move || {
+                let layer = doc.get_page(page).get_layer(layer);
+                for line in 0..20 {
+                    layer.use_text(format!("Page {} line {}", i + 1, line), 12.0, Mm(10.0), Mm(280.0 - line as f64 * 5.0), font);
+                }
+            });
+        }
 
-        // ----- PAGE CONTENT
+        // a thread that panics while it holds the lock doesn't make the document unusable
+        let page = pages[0].0;
+        let doc = &doc;
+        assert!(scope.spawn(move || { doc.get_page(page).get_layer(PdfLayerIndex(5)); }).join().is_err());
+    });
+
+    doc.get_page(pages[0].0).add_layer("Layer 2").use_text("After the panic", 12.0, Mm(10.0), Mm(10.0), &font);
+
+    let doc = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let page_ids = doc.get_pages();
+    assert_eq!(page_ids.len(), 8);
+    for (number, page_id) in page_ids {
+        let content = lopdf::content::Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+        let texts: Vec<&[u8]> = content.operations.iter()
+            .filter(|operation| operation.operator == "Tj")
+            .map(|operation| operation.operands[0].as_str().unwrap())
+            .collect();
+        assert_eq!(texts.len(), if number == 1 { 21 } else { 20 });
+        assert_eq!(texts[19], format!("Page {} line 19", number).as_bytes());
+    }
+}
 
-        // add fonts (shared resources)
-        let mut font_dict_id = None;
+#[test]
+fn test_layers_are_written_as_optional_content() {
+    use PdfDocument;
 
-        // add all fonts / other resources shared in the whole document
-        let fonts_dict: lopdf::Dictionary = doc.fonts.into_with_document(&mut doc.inner_doc);
+    // PDF/X-3 doesn't allow layers, but only `repair_errors` removes them
+    let (doc, page, _) = PdfDocument::new("Layers", Mm(210.0), Mm(297.0), "Background");
+    let doc = doc.with_conformance(PdfConformance::X3_2002_PDF_1_3);
+    doc.get_page(page).add_layer("Text");
 
-        if fonts_dict.len() > 0 {
-            font_dict_id = Some(doc.inner_doc.add_object(Dictionary(fonts_dict)));
-        }
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let catalog = saved.catalog().unwrap();
+    let properties = catalog.get(b"OCProperties").unwrap().as_dict().unwrap();
+    assert_eq!(properties.get(b"OCGs").unwrap().as_array().unwrap().len(), 2);
+}
 
-        let mut page_id_to_obj: HashMap<usize, (u32, u16)> = HashMap::new();
-
-        for (idx, page) in doc.pages.into_iter().enumerate() {
-            let mut p = LoDictionary::from_iter(vec![
-                ("Type", "Page".into()),
-                ("Rotate", Integer(0)),
-                (
-                    "MediaBox",
-                    vec![0.into(), 0.into(), page.width.into(), page.height.into()].into(),
-                ),
-                (
-                    "TrimBox",
-                    vec![0.into(), 0.into(), page.width.into(), page.height.into()].into(),
-                ),
-                (
-                    "CropBox",
-                    vec![0.into(), 0.into(), page.width.into(), page.height.into()].into(),
-                ),
-                ("Parent", Reference(pages_id)),
-            ]);
-
-            // this will collect the resources needed for rendering this page
-            let layers_temp = ocg_list.iter().find(|e| e.0 == idx).unwrap();
-            let (mut resources_page, layer_streams) =
-                page.collect_resources_and_streams(&mut doc.inner_doc, &layers_temp.1);
-
-            if let Some(f) = font_dict_id {
-                resources_page.set("Font", Reference(f));
-            }
+#[test]
+fn test_reproducible_output() {
+    use {BuiltinFont, PdfDocument};
 
-            if resources_page.len() > 0 {
-                let resources_page_id = doc.inner_doc.add_object(Dictionary(resources_page));
-                p.set("Resources", Reference(resources_page_id));
-            }
+    fn build(text: &str, options: PdfSaveOptions) -> Vec<u8> {
+        let (doc, page, layer) = PdfDocument::new("Reproducible", Mm(210.0), Mm(297.0), "Layer 1");
+        let doc = doc.with_fixed_date(OffsetDateTime::unix_epoch());
+        let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+        doc.get_page(page).get_layer(layer).use_text(text, 12.0, Mm(10.0), Mm(280.0), &font);
+        doc.save_to_bytes_with_options(options).unwrap()
+    }
 
-            // merge all streams of the individual layers into one big stream
-            let mut layer_streams_merged_vec = Vec::<u8>::new();
-            for mut stream in layer_streams {
-                layer_streams_merged_vec.append(&mut stream.content);
-            }
+    let options = PdfSaveOptions::default().with_reproducible_ids(true);
+    assert_eq!(build("Hello", options), build("Hello", options));
+    assert_ne!(build("Hello", options), build("World", options));
+    assert_ne!(build("Hello", PdfSaveOptions::default()), build("Hello", PdfSaveOptions::default()));
 
-            let merged_layer_stream = lopdf::Stream::new(lopdf::Dictionary::new(), layer_streams_merged_vec);
-            let page_content_id = doc.inner_doc.add_object(merged_layer_stream);
+    // with a seed, the IDs don't depend on the content
+    let seeded = PdfSaveOptions::default().with_id_seed(42);
+    let id = |bytes: &[u8]| format!("{:?}", lopdf::Document::load_mem(bytes).unwrap().trailer.get(b"ID").unwrap());
+    assert_eq!(id(&build("Hello", seeded)), id(&build("World", seeded)));
+}
 
-            p.set("Contents", Reference(page_content_id));
-            let page_obj = doc.inner_doc.add_object(p);
-            if doc.bookmarks.len() > 0 {
-                if let Some(_) = doc.bookmarks.get(&idx) {
-                    page_id_to_obj.insert(idx, page_obj);
-                }
-            }
-            page_ids.push(Reference(page_obj))
-        }
+#[test]
+fn test_pdfa_output() {
+    use std::io::Cursor;
+    use lopdf::Object;
+    use {Color, Rgb, ColorSpace, ColorBits, Px, Image, ImageTransform, ImageXObject};
+
+    fn build(conformance: PdfConformance) -> lopdf::Document {
+        let (doc, page, layer) = PdfDocument::new("Annual report", Mm(210.0), Mm(297.0), "Background");
+        let font = doc.add_external_font(Cursor::new(include_bytes!("../assets/fonts/RobotoMedium.ttf").to_vec())).unwrap();
+        let background = doc.get_page(page).get_layer(layer);
+        background.set_fill_color(Color::Rgb(Rgb::new(0.2, 0.4, 0.6, None)));
+        let image = ImageXObject::new(Px(1), Px(1), ColorSpace::Greyscale, ColorBits::Bit8, true, None, None, vec![128]);
+        Image::from(image).add_to_layer(background, ImageTransform::default());
+        doc.get_page(page).add_layer("Text").use_text("Straße", 12.0, Mm(10.0), Mm(280.0), &font);
+
+        doc.repair_errors(conformance).unwrap();
+        let bytes = doc.save_to_bytes().unwrap();
+        // the header is followed by a comment with 4 binary characters
+        assert!(bytes.split(|&byte| byte == b'\n').nth(1).unwrap().iter().skip(1).all(|&byte| byte > 127));
+        lopdf::Document::load_mem(&bytes).unwrap()
+    }
 
-        if doc.bookmarks.len() > 0 {
-            let len = doc.bookmarks.len();
-            if len == 1 {
-                let page_index = doc.bookmarks.iter().next().unwrap().0.to_owned();
-                let title = doc.bookmarks.iter().next().unwrap().1.to_owned();
-                let obj_ref = doc
-                    .inner_doc
-                    .add_object(Dictionary(LoDictionary::from_iter(vec![
-                        ("Parent", Reference(bookmarks_id)),
-                        ("Title", String(title.into(), Literal)),
-                        (
-                            "Dest",
-                            Array(vec![
-                                Reference(page_id_to_obj.get(&page_index).unwrap().to_owned()),
-                                "XYZ".into(),
-                                Null,
-                                Null,
-                                Null,
-                            ]),
-                        ),
-                    ])));
-                bookmarks_list.set("First", Reference(obj_ref));
-                bookmarks_list.set("Last", Reference(obj_ref));
-            } else {
-                let mut sorted_bmarks: Vec<(&usize, &std::string::String)> = doc.bookmarks.iter().collect();
-                sorted_bmarks.sort();
-                for (i, (page_index, b_name)) in sorted_bmarks.iter().enumerate() {
-                    let dest = (
-                        "Dest",
-                        Array(vec![
-                            Reference(page_id_to_obj.get(page_index).unwrap().to_owned()),
-                                "XYZ".into(),
-                                Null,
-                                Null,
-                                Null,
-                        ]),
-                    );
-                    doc.inner_doc
-                        .add_object(Dictionary(LoDictionary::from_iter(if i == 0 {
-                            bookmarks_list.set("First", Reference((doc.inner_doc.max_id + 1, 0)));
-                            vec![
-                                ("Parent", Reference(bookmarks_id)),
-                                ("Title", String(b_name.to_owned().to_owned().into(), Literal)),
-                                ("Next", Reference((doc.inner_doc.max_id + 2, 0))),
-                                dest,
-                            ]
-                        } else if i == len - 1 {
-                            bookmarks_list.set("Last", Reference((doc.inner_doc.max_id + 1, 0)));
-                            vec![
-                                ("Parent", Reference(bookmarks_id)),
-                                ("Title", String(b_name.to_owned().to_owned().into(), Literal)),
-                                ("Prev", Reference((doc.inner_doc.max_id, 0))),
-                                dest,
-                            ]
-                        } else {
-                            vec![
-                                ("Parent", Reference(bookmarks_id)),
-                                ("Title", String(b_name.to_owned().to_owned().into(), Literal)),
-                                ("Prev", Reference((doc.inner_doc.max_id, 0))),
-                                ("Next", Reference((doc.inner_doc.max_id + 2, 0))),
-                                dest,
-                            ]
-                        })));
-                }
+    let name = |object: &Object| object.as_name_str().unwrap().to_string();
+
+    for &(ref conformance, version, part) in &[
+        (PdfConformance::A1B_2005_PDF_1_4, "1.4", "1"),
+        (PdfConformance::A2B_2011_PDF_1_7, "1.7", "2"),
+        (PdfConformance::A3_2012_PDF_1_7, "1.7", "3"),
+    ] {
+        let saved = build(conformance.clone());
+        assert_eq!(saved.version, version);
+        assert!(saved.trailer.has(b"ID"));
+
+        let catalog = saved.catalog().unwrap();
+        assert_eq!(catalog.has(b"OCProperties"), part != "1");
+        let output_intents = catalog.get(b"OutputIntents").and_then(Object::as_array).unwrap();
+        let output_intent = saved.dereference(&output_intents[0]).unwrap().1.as_dict().unwrap();
+        assert_eq!(name(output_intent.get(b"S").unwrap()), "GTS_PDFA1");
+        assert!(output_intent.get(b"DestOutputProfile").and_then(Object::as_reference).is_ok());
+
+        let metadata = saved.get_object(catalog.get(b"Metadata").and_then(Object::as_reference).unwrap()).unwrap().as_stream().unwrap();
+        let metadata = String::from_utf8(metadata.content.clone()).unwrap();
+        assert!(metadata.contains(&format!("<pdfaid:part>{}</pdfaid:part>", part)));
+        assert!(metadata.contains("<pdfaid:conformance>B</pdfaid:conformance>"));
+        assert!(metadata.contains("Annual report"));
+
+        // the RGB color is converted to the CMYK color space of the output intent
+        let page_id = saved.page_iter().next().unwrap();
+        let operators: Vec<_> = saved.get_and_decode_page_content(page_id).unwrap().operations.into_iter().map(|operation| operation.operator).collect();
+        assert!(operators.contains(&"k".to_string()) && !operators.contains(&"rg".to_string()));
+
+        let info = saved.get_object(saved.trailer.get(b"Info").and_then(Object::as_reference).unwrap()).unwrap().as_dict().unwrap();
+        assert!(!info.has(b"Trapped") && !info.has(b"GTS_PDFXVersion"));
+
+        for object in saved.objects.values() {
+            let dict = match *object {
+                Object::Dictionary(ref dict) => dict,
+                Object::Stream(ref stream) => &stream.dict,
+                _ => continue,
+            };
+            match dict.get(b"Type").map(|object| name(object)).as_ref().map(String::as_str) {
+                Ok("FontDescriptor") => {
+                    assert!(dict.has(b"CIDSet"));
+                    assert_eq!(dict.get(b"Flags").and_then(Object::as_i64).unwrap(), 4);
+                    assert!(dict.get(b"Ascent").and_then(Object::as_i64).unwrap() > 0);
+                },
+                Ok("Font") if name(dict.get(b"Subtype").unwrap()) == "CIDFontType2" => {
+                    assert_eq!(name(dict.get(b"CIDToGIDMap").unwrap()), "Identity");
+                },
+                Ok("XObject") => {
+                    assert_eq!(dict.get(b"Interpolate").and_then(Object::as_bool).ok(), Some(false));
+                },
+                _ => { },
             }
         }
+    }
+}
 
-        pages.set::<_, LoObject>("Kids".to_string(), page_ids.into());
-
-        // ----- END PAGE CONTENT
-
-        doc.inner_doc.objects.insert(pages_id, Dictionary(pages));
-        doc.inner_doc
-            .objects
-            .insert(bookmarks_id, Dictionary(bookmarks_list));
-
-        // save inner document
-        let catalog_id = doc.inner_doc.add_object(catalog);
-        let instance_id = random_character_string_32();
-
-        doc.inner_doc.trailer.set("Root", Reference(catalog_id));
-        doc.inner_doc
-            .trailer
-            .set("Info", Reference(document_info_id));
-        doc.inner_doc.trailer.set(
-            "ID",
-            Array(vec![
-                String(doc.document_id.as_bytes().to_vec(), Literal),
-                String(instance_id.as_bytes().to_vec(), Literal),
-            ]),
-        );
-
-        // does nothing in debug mode, optimized in release mode
-        Self::optimize(&mut doc.inner_doc);
-        let mut bytes = Vec::new();
-        let mut writer = BufWriter::new(&mut bytes);
-        doc.inner_doc.save_to(&mut writer)?;
-        mem::drop(writer);
-
-        Ok(bytes)
+#[test]
+fn test_icc_and_lab_colors() {
+    use lopdf::Object;
+    use glob_defines::ICC_PROFILE_ECI_V2;
+    use {Color, Cmyk, IccProfileType, Lab, Rgb, WhitePoint};
+
+    let (doc, page, layer) = PdfDocument::new("Colors", Mm(210.0), Mm(297.0), "Layer 1");
+    let profile = doc.add_icc_profile(IccProfile::new(ICC_PROFILE_ECI_V2.to_vec(), IccProfileType::Cmyk));
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.set_fill_color(Color::Cmyk(Cmyk::new(0.1, 0.2, 0.3, 0.4, Some(profile.clone()))));
+    layer.set_outline_color(Color::Lab(Lab::new(50.0, 20.0, -30.0, WhitePoint::D50)));
+    // the same color space is only added once
+    layer.set_fill_color(Color::Cmyk(Cmyk::new(0.5, 0.5, 0.5, 0.5, Some(profile.clone()))));
+    // a CMYK profile doesn't apply to RGB colors
+    layer.set_fill_color(Color::Rgb(Rgb::new(1.0, 0.0, 0.0, Some(profile))));
+
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let page_id = saved.page_iter().next().unwrap();
+    let operations: Vec<_> = saved.get_and_decode_page_content(page_id).unwrap().operations.into_iter()
+        .map(|operation| (operation.operator, operation.operands.first().and_then(|operand| operand.as_name_str().ok().map(str::to_string))))
+        .collect();
+    let expected = [("cs", Some("CS0")), ("scn", None), ("CS", Some("CS1")), ("SCN", None), ("cs", Some("CS0")), ("scn", None), ("rg", None)];
+    let color_operations: Vec<_> = operations.iter().filter(|operation| expected.iter().any(|&(operator, _)| operation.0 == operator)).collect();
+    assert_eq!(color_operations.len(), expected.len());
+    for (operation, &(operator, operand)) in color_operations.into_iter().zip(expected.iter()) {
+        assert_eq!(operation.0, operator);
+        assert_eq!(operation.1.as_ref().map(String::as_str), operand);
     }
 
+    let page = saved.get_dictionary(page_id).unwrap();
+    let resources = saved.dereference(page.get(b"Resources").unwrap()).unwrap().1.as_dict().unwrap();
+    let color_spaces = resources.get(b"ColorSpace").and_then(Object::as_dict).unwrap();
+    assert_eq!(color_spaces.len(), 2);
 
-    /// Save PDF Document, writing the contents to the target
-    pub fn save<W: Write>(self, target: &mut BufWriter<W>) -> Result<(), Error> {
-        let pdf_as_bytes = self.save_to_bytes()?;
-        target.write(&pdf_as_bytes)?;
-        Ok(())
-    }
+    let icc_based = color_spaces.get(b"CS0").and_then(Object::as_array).unwrap();
+    assert_eq!(icc_based[0].as_name_str().unwrap(), "ICCBased");
+    let icc_stream = saved.get_object(icc_based[1].as_reference().unwrap()).unwrap().as_stream().unwrap();
+    assert_eq!(icc_stream.dict.get(b"N").and_then(Object::as_i64).unwrap(), 4);
 
-    #[cfg(any(debug_assertions, feature="less-optimization"))]
-    #[inline]
-    fn optimize(_: &mut lopdf::Document) { }
+    let lab = color_spaces.get(b"CS1").and_then(Object::as_array).unwrap();
+    assert_eq!(lab[0].as_name_str().unwrap(), "Lab");
+    let white_point = lab[1].as_dict().unwrap().get(b"WhitePoint").and_then(Object::as_array).unwrap();
+    assert_eq!(white_point[2].as_float().unwrap(), 0.8249);
+}
 
-    #[cfg(all(not(debug_assertions), not(feature="less-optimization")))]
-    #[inline]
-    fn optimize(doc: &mut lopdf::Document)
-    {
-        doc.prune_objects();
-        doc.delete_zero_length_streams();
-        doc.compress();
-    }
+#[test]
+fn test_save_without_pdfa_conformance() {
+    use {BuiltinFont, PdfDocument};
+
+    let (doc, page, layer) = PdfDocument::new("Round trip", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    doc.get_page(page).get_layer(layer).use_text("Hello", 12.0, Mm(10.0), Mm(280.0), &font);
+    let (second_page, second_layer) = doc.add_page(Mm(100.0), Mm(100.0), "Layer 1");
+    doc.get_page(second_page).get_layer(second_layer).use_text("World", 12.0, Mm(10.0), Mm(50.0), &font);
+
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let texts: Vec<Vec<u8>> = saved.get_pages().values().map(|&page_id| {
+        let content = lopdf::content::Content::decode(&saved.get_page_content(page_id).unwrap()).unwrap();
+        content.operations.iter()
+            .find(|operation| operation.operator == "Tj")
+            .map(|operation| operation.operands[0].as_str().unwrap().to_vec())
+            .unwrap()
+    }).collect();
+    assert_eq!(texts, vec![b"Hello".to_vec(), b"World".to_vec()]);
+    assert!(saved.catalog().unwrap().get(b"OutputIntents").is_err());
 }
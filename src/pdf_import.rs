@@ -0,0 +1,278 @@
+//! Imports pages of other PDF files as form XObjects, which can be placed
+//! (scaled, rotated, many times) on the pages of a document
+
+use lopdf::{self, Object, ObjectId};
+use std::collections::BTreeMap;
+
+use pdf_document::lock_document;
+use pdf_loader::{inherited, page_content, read_box, resolve_dict};
+use utils::ContentHash;
+use {
+    Error, PdfError, PdfDocument, PdfDocumentReference, PdfLayerReference, Pt, Point, Rect,
+    XObject, FormXObject, CurTransMat
+};
+
+/// Page of another PDF file, imported with `PdfDocumentReference::import_page`.
+///
+/// The page is stored once in the document and can be placed on any page of the
+/// document that imported it, as often as needed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ImportedPageRef {
+    /// The form XObject with the content of the page
+    object_id: ObjectId,
+    /// Width of the page (after its rotation)
+    pub width: Pt,
+    /// Height of the page (after its rotation)
+    pub height: Pt,
+}
+
+impl ImportedPageRef {
+
+    /// Places the page on the layer. Without transformations, the lower left corner
+    /// of the page is at the lower left corner of the layer and the page has its original size.
+    ///
+    /// The transformations are applied in order, for example to place the page at
+    /// half its size, 20 mm from the left and bottom border:
+    /// `&[CurTransMat::Scale(0.5, 0.5), CurTransMat::Translate(Mm(20.0).into(), Mm(20.0).into())]`
+    pub fn add_to_layer(&self, layer: &PdfLayerReference, transformations: &[CurTransMat])
+    {
+        let xobject_ref = layer.add_xobject(XObject::Reference(self.object_id));
+        layer.use_xobject(xobject_ref, transformations);
+    }
+}
+
+impl PdfDocumentReference {
+
+    /// Imports a page (starting at 1) of a PDF file, see `import_pages`
+    pub fn import_page(&self, bytes: &[u8], page_number: usize)
+    -> Result<ImportedPageRef, Error>
+    {
+        let mut pages = self.import_pages(bytes, &[page_number])?;
+        Ok(pages.remove(0))
+    }
+
+    /// Imports pages (starting at 1) of a PDF file, so that they can be placed on the
+    /// pages of this document, for example a letterhead or a datasheet of a supplier.
+    ///
+    /// The content of each page and all resources it uses (fonts, images, ...) are copied
+    /// into this document. Resources that are shared by the pages are only copied once,
+    /// also when pages of the same file are imported with several calls.
+    /// Annotations and form fields of the pages are not imported.
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// # let (letterhead, _, _) = PdfDocument::new("Letterhead", Mm(210.0), Mm(297.0), "Layer 1");
+    /// # let letterhead_bytes = letterhead.save_to_bytes().unwrap();
+    /// let (doc, page1, layer1) = PdfDocument::new("Letter", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let letterhead = doc.import_page(&letterhead_bytes, 1).unwrap();
+    ///
+    /// let layer = doc.get_page(page1).get_layer(layer1);
+    /// letterhead.add_to_layer(&layer, &[]);
+    ///
+    /// // a small preview of the same page
+    /// letterhead.add_to_layer(&layer, &[CurTransMat::Scale(0.25, 0.25), CurTransMat::Translate(Mm(10.0).into(), Mm(10.0).into())]);
+    /// ```
+    pub fn import_pages(&self, bytes: &[u8], page_numbers: &[usize])
+    -> Result<Vec<ImportedPageRef>, Error>
+    {
+        let source = lopdf::Document::load_mem(bytes)?;
+
+        if source.trailer.has(b"Encrypt") {
+            return Err(PdfError::InvalidPdf("encrypted PDF files are not supported").into());
+        }
+
+        let page_ids: Vec<ObjectId> = source.page_iter().collect();
+        let mut source_hash = ContentHash::new();
+        source_hash.write(bytes);
+
+        let mut doc = lock_document(&self.document);
+        let PdfDocument { ref mut inner_doc, ref mut imported_objects, .. } = *doc;
+        let copied_ids = imported_objects.entry(source_hash.to_hex_string()).or_insert_with(BTreeMap::new);
+
+        page_numbers.iter().map(|&page_number| {
+            let page_id = match page_number.checked_sub(1).and_then(|index| page_ids.get(index)) {
+                Some(page_id) => *page_id,
+                None => return Err(PdfError::InvalidPdf("the page does not exist").into()),
+            };
+            import_page(&source, page_id, inner_doc, copied_ids)
+        }).collect()
+    }
+}
+
+/// Copies the page into `target` as a form XObject. `copied_ids` maps the
+/// objects of `source` that are already copied to their ID in `target`
+fn import_page(source: &lopdf::Document, page_id: ObjectId, target: &mut lopdf::Document,
+               copied_ids: &mut BTreeMap<ObjectId, ObjectId>)
+-> Result<ImportedPageRef, Error>
+{
+    let page = source.get_dictionary(page_id)?;
+
+    // the visible area of the page is the crop box, which is the media box by default
+    let media_box = inherited(source, page, b"MediaBox").and_then(|media_box| read_box(source, media_box));
+    let visible_box = inherited(source, page, b"CropBox").and_then(|crop_box| read_box(source, crop_box))
+        .or(media_box)
+        .ok_or(PdfError::InvalidPdf("the page has no media box"))?;
+    let (x, y) = (visible_box[0], visible_box[1]);
+    let (width, height) = (visible_box[2] - x, visible_box[3] - y);
+
+    let rotation = inherited(source, page, b"Rotate")
+        .and_then(|rotation| rotation.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+
+    // moves the lower left corner of the visible area to 0, 0 and rotates the page
+    // clockwise (like a viewer shows it), so that it is above and right of 0, 0 again
+    let (matrix, size) = match rotation {
+        90 => ([0.0, -1.0, 1.0, 0.0, -y, x + width], (height, width)),
+        180 => ([-1.0, 0.0, 0.0, -1.0, x + width, y + height], (width, height)),
+        270 => ([0.0, 1.0, -1.0, 0.0, y + height, -x], (height, width)),
+        _ => ([1.0, 0.0, 0.0, 1.0, -x, -y], (width, height)),
+    };
+
+    let resources = match inherited(source, page, b"Resources").and_then(|resources| resolve_dict(source, resources)) {
+        Some(resources) => copy_object(source, &Object::Dictionary(resources.clone()), target, copied_ids),
+        None => Object::Dictionary(lopdf::Dictionary::new()),
+    };
+
+    let bbox = Rect {
+        ll: Point { x: Pt(visible_box[0]), y: Pt(visible_box[1]) },
+        ur: Point { x: Pt(visible_box[2]), y: Pt(visible_box[3]) },
+    };
+    let form = FormXObject {
+        matrix: Some(CurTransMat::Raw(matrix)),
+        .. FormXObject::new(page_content(source, page_id)?, bbox)
+    };
+
+    let mut stream: lopdf::Stream = form.into();
+    stream.dict.set("Resources", resources);
+    // pages with transparency are blended as a group, the form has to be a group as well
+    if let Ok(group) = page.get(b"Group") {
+        stream.dict.set("Group", copy_object(source, group, target, copied_ids));
+    }
+
+    Ok(ImportedPageRef {
+        object_id: target.add_object(stream),
+        width: Pt(size.0),
+        height: Pt(size.1),
+    })
+}
+
+/// Copies the object and all objects it refers to from `source` into `target`
+fn copy_object(source: &lopdf::Document, object: &Object, target: &mut lopdf::Document,
+               copied_ids: &mut BTreeMap<ObjectId, ObjectId>)
+-> Object
+{
+    match *object {
+        Object::Reference(id) => {
+            if let Some(copied_id) = copied_ids.get(&id) {
+                return Object::Reference(*copied_id);
+            }
+
+            let referenced = match source.get_object(id) {
+                Ok(referenced) => referenced,
+                Err(_) => return Object::Null,
+            };
+
+            // a reference to a page (for example the /P entry of an annotation)
+            // would copy the whole document
+            let object_type = match *referenced {
+                Object::Dictionary(ref dict) => dict.get(b"Type").and_then(Object::as_name_str).ok(),
+                _ => None,
+            };
+            if object_type == Some("Page") || object_type == Some("Pages") {
+                return Object::Null;
+            }
+
+            // the ID is reserved first, in case the object refers to itself
+            let copied_id = target.new_object_id();
+            copied_ids.insert(id, copied_id);
+            let copied = copy_object(source, referenced, target, copied_ids);
+            target.objects.insert(copied_id, copied);
+            Object::Reference(copied_id)
+        },
+        Object::Array(ref array) => {
+            Object::Array(array.iter().map(|item| copy_object(source, item, target, copied_ids)).collect())
+        },
+        Object::Dictionary(ref dict) => {
+            Object::Dictionary(copy_dictionary(source, dict, target, copied_ids))
+        },
+        Object::Stream(ref stream) => {
+            let dict = copy_dictionary(source, &stream.dict, target, copied_ids);
+            Object::Stream(lopdf::Stream::new(dict, stream.content.clone()))
+        },
+        ref other => other.clone(),
+    }
+}
+
+fn copy_dictionary(source: &lopdf::Document, dict: &lopdf::Dictionary, target: &mut lopdf::Document,
+                   copied_ids: &mut BTreeMap<ObjectId, ObjectId>)
+-> lopdf::Dictionary
+{
+    let mut copied = lopdf::Dictionary::new();
+    for (key, value) in dict.iter() {
+        copied.set(key.clone(), copy_object(source, value, target, copied_ids));
+    }
+    copied
+}
+
+#[test]
+fn test_import_page() {
+    use {BuiltinFont, Mm, PdfDocument};
+
+    let (supplier, page, layer) = PdfDocument::new("Datasheet", Mm(100.0), Mm(50.0), "Layer 1");
+    let font = supplier.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    supplier.get_page(page).get_layer(layer).use_text("Datasheet", 12.0, Mm(10.0), Mm(10.0), &font);
+    supplier.get_page(page).set_rotation(90);
+    let supplier_bytes = supplier.save_to_bytes().unwrap();
+
+    let (doc, page, layer) = PdfDocument::new("Catalog", Mm(210.0), Mm(297.0), "Layer 1");
+    let datasheet = doc.import_page(&supplier_bytes, 1).unwrap();
+    assert!((Mm::from(datasheet.width).0 - 50.0).abs() < 0.01);
+    assert!((Mm::from(datasheet.height).0 - 100.0).abs() < 0.01);
+    assert!(doc.import_page(&supplier_bytes, 2).is_err());
+
+    let layer = doc.get_page(page).get_layer(layer);
+    datasheet.add_to_layer(&layer, &[]);
+    datasheet.add_to_layer(&layer, &[CurTransMat::Translate(Mm(100.0).into(), Mm(0.0).into())]);
+    let (page2, layer2) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    datasheet.add_to_layer(&doc.get_page(page2).get_layer(layer2), &[]);
+    let bytes = doc.save_to_bytes().unwrap();
+
+    // both pages use the same form XObject, with the font of the supplier
+    let saved = lopdf::Document::load_mem(&bytes).unwrap();
+    let forms: Vec<_> = saved.objects.values().filter_map(|object| object.as_stream().ok())
+        .filter(|stream| stream.dict.get(b"Subtype").and_then(Object::as_name_str).ok() == Some("Form"))
+        .collect();
+    assert_eq!(forms.len(), 1);
+    let resources = forms[0].dict.get(b"Resources").and_then(Object::as_dict).unwrap();
+    assert!(resources.has(b"Font"));
+}
+
+#[test]
+fn test_import_pages_of_a_file_separately() {
+    use {BuiltinFont, Mm, PdfDocument};
+
+    let (supplier, page1, layer1) = PdfDocument::new("Datasheets", Mm(100.0), Mm(50.0), "Layer 1");
+    let font = supplier.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    supplier.get_page(page1).get_layer(layer1).use_text("Datasheet 1", 12.0, Mm(10.0), Mm(10.0), &font);
+    let (page2, layer2) = supplier.add_page(Mm(100.0), Mm(50.0), "Layer 1");
+    supplier.get_page(page2).get_layer(layer2).use_text("Datasheet 2", 12.0, Mm(10.0), Mm(10.0), &font);
+    let supplier_bytes = supplier.save_to_bytes().unwrap();
+
+    let (doc, page, layer) = PdfDocument::new("Catalog", Mm(210.0), Mm(297.0), "Layer 1");
+    let layer = doc.get_page(page).get_layer(layer);
+    doc.import_page(&supplier_bytes, 1).unwrap().add_to_layer(&layer, &[]);
+    doc.import_page(&supplier_bytes, 2).unwrap().add_to_layer(&layer, &[CurTransMat::Translate(Mm(100.0).into(), Mm(0.0).into())]);
+
+    // the font that both pages use is copied once
+    let saved = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let fonts = saved.objects.values()
+        .filter(|object| object.as_dict().ok().and_then(|dict| dict.get(b"Type").and_then(Object::as_name_str).ok()) == Some("Font"))
+        .count();
+    assert_eq!(fonts, 1);
+    let forms = saved.objects.values().filter_map(|object| object.as_stream().ok())
+        .filter(|stream| stream.dict.get(b"Subtype").and_then(Object::as_name_str).ok() == Some("Form"))
+        .count();
+    assert_eq!(forms, 2);
+}
@@ -1,14 +1,20 @@
 //! PDF layer management. Layers can contain referenced or real content.
 
 use indices::{PdfPageIndex, PdfLayerIndex};
-use std::rc::Weak;
-use std::cell::RefCell;
+use std::sync::{Mutex, Weak};
 use lopdf::content::Operation;
-use glob_defines::OP_PATH_STATE_SET_LINE_WIDTH;
+use pdf_document::lock_document;
+use glob_defines::{
+    OP_PATH_STATE_SET_LINE_WIDTH, OP_COLOR_SET_FILL_CS, OP_COLOR_SET_STROKE_CS,
+    OP_COLOR_SET_FILL_COLOR_ICC, OP_COLOR_SET_STROKE_COLOR_ICC,
+};
 use {
-    Font, XObject, PdfColor,  PdfDocument, ExtendedGraphicsStateBuilder, Line, ImageXObject, XObjectRef, Color, IndirectFontRef, BlendMode,
-    LineJoinStyle, LineCapStyle, LineDashPattern, CurTransMat, TextMatrix, TextRenderingMode, Mm, Pt
+    Font, XObject, PdfColor,  PdfDocument, ExtendedGraphicsStateBuilder, Line, ImageXObject, XObjectRef, Color, ColorSpaceKey, IndirectFontRef, DirectFontRef, BlendMode, FontData, ShapedText,
+    LineJoinStyle, LineCapStyle, LineDashPattern, CurTransMat, TextMatrix, TextRenderingMode, Mm, Pt, Rect, TextAlignment,
+    PatternRef, SoftMask, IccProfileType
 };
+use text_layout::break_lines;
+use pattern::PATTERN_COLOR_SPACES;
 
 /// One layer of PDF data
 #[derive(Debug, Clone)]
@@ -17,14 +23,20 @@ pub struct PdfLayer {
     pub(crate) name: String,
     /// Stream objects in this layer. Usually, one layer == one stream
     pub(super) operations: Vec<Operation>,
+    /// Font that was last selected with `set_font`, used for tracking
+    /// which glyphs of a font are written by `write_codepoints`
+    pub(crate) current_font: Option<IndirectFontRef>,
+    /// Is the layer written as an optional content group? Turned off by
+    /// `repair_errors` for conformances that don't allow layers
+    pub(crate) optional_content: bool,
 }
 
-/// A "reference" to the current layer, allows for inner mutability
+/// A "reference" to the current layer, allows for inner mutability (and can be sent to other threads)
 /// but only inside this library
 #[derive(Debug, Clone)]
 pub struct PdfLayerReference {
     /// A weak reference to the document, for inner mutability
-    pub document: Weak<RefCell<PdfDocument>>,
+    pub document: Weak<Mutex<PdfDocument>>,
     /// The index of the page this layer is on
     pub page: PdfPageIndex,
     /// The index of the layer this layer has (inside the page)
@@ -41,6 +53,8 @@ impl PdfLayer {
         Self {
             name: name.into(),
             operations: Vec::new(),
+            current_font: None,
+            optional_content: true,
         }
     }
 }
@@ -84,7 +98,7 @@ impl PdfLayerReference {
     -> XObjectRef where T: Into<XObject>
     {
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
         let page_mut = &mut doc.pages[self.page.0];
 
         page_mut.add_xobject(xobject.into())
@@ -108,12 +122,17 @@ impl PdfLayerReference {
         self.add_operation(Operation::new("ET", vec![] ));
     }
 
-    /// Set the current fill color for the layer
+    /// Set the current fill color for the layer. Colors with an ICC profile (see
+    /// `PdfDocumentReference::add_icc_profile`) and Lab, CalRGB and CalGray colors are
+    /// set in their color space, which is added to the resources of the page
     #[inline]
     pub fn set_fill_color(&self, fill_color: Color)
     -> ()
     {
-        self.add_operation(PdfColor::FillColor(fill_color));
+        match self.get_color_space(&fill_color) {
+            Some((key, color_space)) => self.set_color(key, color_space, fill_color, OP_COLOR_SET_FILL_CS, OP_COLOR_SET_FILL_COLOR_ICC),
+            None => self.add_operation(PdfColor::FillColor(fill_color)),
+        }
     }
 
     /// Set the current font, only valid in a `begin_text_section` to
@@ -122,17 +141,40 @@ impl PdfLayerReference {
     pub fn set_font(&self, font: &IndirectFontRef, font_size: f64)
     -> ()
     {
-        self.add_operation(Operation::new("Tf",
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        let layer = doc.pages[self.page.0].layer_mut(self.layer);
+        layer.current_font = Some(font.clone());
+        layer.operations.push(Operation::new("Tf",
             vec![font.name.clone().into(), (font_size).into()]
         ));
     }
 
-    /// Set the current line / outline color for the layer
+    /// Set the current line / outline color for the layer, see `set_fill_color`
     #[inline]
     pub fn set_outline_color(&self, color: Color)
     {
-        self.add_operation(PdfColor::OutlineColor(color));
+        match self.get_color_space(&color) {
+            Some((key, color_space)) => self.set_color(key, color_space, color, OP_COLOR_SET_STROKE_CS, OP_COLOR_SET_STROKE_COLOR_ICC),
+            None => self.add_operation(PdfColor::OutlineColor(color)),
+        }
+    }
+    /// Fill the following shapes with a pattern (gradient or tiling pattern) instead of a color.
+    /// The pattern must have been added to the same page. Uncolored tiling patterns
+    /// are painted in `color`, all other patterns must be used with `None`
+    #[inline]
+    pub fn set_fill_pattern(&self, pattern: &PatternRef, color: Option<Color>)
+    {
+        self.set_pattern(pattern, color, OP_COLOR_SET_FILL_CS, OP_COLOR_SET_FILL_COLOR_ICC);
     }
+
+    /// Stroke the following lines with a pattern, see `set_fill_pattern`
+    #[inline]
+    pub fn set_outline_pattern(&self, pattern: &PatternRef, color: Option<Color>)
+    {
+        self.set_pattern(pattern, color, OP_COLOR_SET_STROKE_CS, OP_COLOR_SET_STROKE_COLOR_ICC);
+    }
+
     /// Instantiate layers, forms and postscript items on the page
     /// __WARNING__: Object must be added to the same page, since the XObjectRef is just a
     /// String, essentially, it can't be checked that this is the case. The caller is
@@ -169,14 +211,14 @@ impl PdfLayerReference {
                                       .build();
 
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
         let page_mut = &mut doc.pages[self.page.0];
 
         let new_ref = page_mut.add_graphics_state(new_overprint_state);
 
         // add gs operator to stream
-        page_mut.layers[self.layer.0]
-            .operations.push(lopdf::content::Operation::new(
+        page_mut.layer_mut(self.layer)
+            .operations.push(Operation::new(
                 "gs", vec![lopdf::Object::Name(new_ref.gs_name.as_bytes().to_vec())]
         ));
     }
@@ -191,12 +233,12 @@ impl PdfLayerReference {
                                       .build();
 
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
         let page_mut = &mut doc.pages[self.page.0];
 
         let new_ref = page_mut.add_graphics_state(new_overprint_state);
-        page_mut.layers[self.layer.0]
-            .operations.push(lopdf::content::Operation::new(
+        page_mut.layer_mut(self.layer)
+            .operations.push(Operation::new(
                 "gs", vec![lopdf::Object::Name(new_ref.gs_name.as_bytes().to_vec())]
         ));
     }
@@ -211,17 +253,47 @@ impl PdfLayerReference {
                                       .build();
 
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
         let page_mut = &mut doc.pages[self.page.0];
 
         let new_ref = page_mut.add_graphics_state(new_blend_mode_state);
 
-        page_mut.layers[self.layer.0]
-            .operations.push(lopdf::content::Operation::new(
+        page_mut.layer_mut(self.layer)
+            .operations.push(Operation::new(
+                "gs", vec![lopdf::Object::Name(new_ref.gs_name.as_bytes().to_vec())]
+        ));
+    }
+
+    /// Sets the soft mask for the following content, `None` removes the current soft mask.
+    /// The soft mask stays active until the graphics state is restored.
+    /// The group of the mask has to be added to the same page, otherwise the mask is ignored.
+    /// This changes the graphics state of the current page, don't do it too often or you'll bloat the file size
+    pub fn set_soft_mask(&self, soft_mask: Option<SoftMask>)
+    {
+        let new_soft_mask_state = ExtendedGraphicsStateBuilder::new()
+                                      .with_soft_mask(soft_mask)
+                                      .build();
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        let page_mut = &mut doc.pages[self.page.0];
+
+        let new_ref = page_mut.add_graphics_state(new_soft_mask_state);
+
+        page_mut.layer_mut(self.layer)
+            .operations.push(Operation::new(
                 "gs", vec![lopdf::Object::Name(new_ref.gs_name.as_bytes().to_vec())]
         ));
     }
 
+    /// Paints a transparency group that was added to the same page with
+    /// `PdfPageReference::add_transparency_group`
+    #[inline]
+    pub fn use_transparency_group(&self, group: &XObjectRef)
+    {
+        self.use_xobject(group.clone(), &[]);
+    }
+
     /// Set the current line thickness, in points
     ///
     /// __NOTE__: 0.0 is a special value, it does not make the line disappear, but rather
@@ -354,14 +426,19 @@ impl PdfLayerReference {
 
     /// Add text to the file at the current position by specifying font codepoints for an
     /// ExternalFont
+    ///
+    /// The codepoints are registered as used glyphs of the font that was last selected
+    /// via `set_font` on this layer, so that they are included when the font is subset.
     pub fn write_codepoints<I>(&self, codepoints: I)
     where I: IntoIterator<Item = u16>
     {
         use lopdf::Object::*;
         use lopdf::StringFormat::Hexadecimal;
 
+        let codepoints = codepoints.into_iter().collect::<Vec<u16>>();
+
         let bytes = codepoints
-            .into_iter()
+            .iter()
             .flat_map(|x| {
                 let [b0, b1] = x.to_be_bytes();
                 std::iter::once(b0).chain(std::iter::once(b1))
@@ -369,9 +446,12 @@ impl PdfLayerReference {
             .collect::<Vec<u8>>();
 
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
+        if let Some(font) = doc.pages[self.page.0].layer(self.layer).current_font.clone() {
+            Self::mark_glyphs_used(&mut doc, &font, codepoints.iter().map(|gid| (*gid, &[][..])));
+        }
         doc.pages[self.page.0]
-            .layers[self.layer.0]
+            .layer_mut(self.layer)
                 .operations.push(Operation::new("Tj",
                     vec![String(bytes, Hexadecimal)]
             ));
@@ -379,13 +459,29 @@ impl PdfLayerReference {
 
     /// Add text to the file at the current position by specifying
     /// font codepoints with additional kerning offset
+    ///
+    /// Like `write_codepoints`, the codepoints are registered as used glyphs
+    /// of the font that was last selected via `set_font` on this layer.
     pub fn write_positioned_codepoints<I>(&self, codepoints: I)
     where I: IntoIterator<Item = (i64, u16)>
+    {
+        let current_font = {
+            let doc = self.document.upgrade().unwrap();
+            let doc = lock_document(&doc);
+            doc.pages[self.page.0].layer(self.layer).current_font.clone()
+        };
+        self.add_positioned_codepoints(codepoints, current_font.as_ref());
+    }
+
+    // internal function to add positioned codepoints, which are registered as used glyphs of `font`
+    fn add_positioned_codepoints<I>(&self, codepoints: I, font: Option<&IndirectFontRef>)
+    where I: IntoIterator<Item = (i64, u16)>
     {
         use lopdf::Object::*;
         use lopdf::StringFormat::Hexadecimal;
 
         let mut list = Vec::new();
+        let mut glyphs = Vec::new();
 
         for (pos, codepoint) in codepoints {
             if pos != 0 {
@@ -393,12 +489,16 @@ impl PdfLayerReference {
             }
             let bytes = codepoint.to_be_bytes().to_vec();
             list.push(String(bytes, Hexadecimal));
+            glyphs.push(codepoint);
         }
 
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
+        if let Some(font) = font {
+            Self::mark_glyphs_used(&mut doc, font, glyphs.iter().map(|gid| (*gid, &[][..])));
+        }
         doc.pages[self.page.0]
-            .layers[self.layer.0]
+            .layer_mut(self.layer)
                 .operations.push(Operation::new("TJ", vec![Array(list)]));
     }
 
@@ -422,7 +522,7 @@ impl PdfLayerReference {
 
         // we need to transform the characters into glyph ids and then add them to the layer
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
 
         // glyph IDs that make up this string
 
@@ -431,14 +531,15 @@ impl PdfLayerReference {
         // let mut kerning_data = Vec::<freetype::Vector>::new();
 
         let bytes: Vec<u8> = {
-            if let Font::ExternalFont(face_direct_ref) = doc.fonts.get_font(font).unwrap().data {
+            if let Font::ExternalFont(ref mut face_direct_ref) = doc.fonts.get_font_mut(font).unwrap().data {
 
                 let mut list_gid = Vec::<u16>::new();
-                let font = &face_direct_ref.font_data;
 
                 for ch in text.chars() {
-                    if let Some(glyph_id) = font.glyph_id(ch) {
+                    if let Some(glyph_id) = face_direct_ref.font_data.glyph_id(ch) {
                         list_gid.push(glyph_id);
+                        // remember the glyph, so that it ends up in the font subset
+                        face_direct_ref.mark_glyph_used(glyph_id, &[ch]);
                     }
                 }
 
@@ -453,12 +554,56 @@ impl PdfLayerReference {
         };
 
         doc.pages[self.page.0]
-            .layers[self.layer.0]
+            .layer_mut(self.layer)
                 .operations.push(Operation::new("Tj",
                     vec![String(bytes, Hexadecimal)]
         ));
     }
 
+    /// Add shaped text to the file at the current position
+    ///
+    /// The text is shaped into positioned glyphs (see `ExternalFont::shape_text`) and
+    /// written with `TJ` adjustments, so that kerning, ligatures and - with the
+    /// `text_shaping` feature enabled - complex scripts are rendered correctly.
+    /// Vertical glyph offsets (`y_offset`) can't be expressed with `TJ` and are ignored.
+    ///
+    /// For built-in fonts, this is the same as `write_text`.
+    pub fn write_shaped_text<S>(&self, text: S, font: &IndirectFontRef)
+    where S: Into<String>
+    {
+        let text = text.into();
+
+        let positioned_glyphs = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = lock_document(&doc);
+
+            let external_font = match doc.fonts.get_font_mut(font) {
+                Some(DirectFontRef { data: Font::ExternalFont(ref mut f), .. }) => Some(f),
+                _ => None,
+            };
+
+            match external_font {
+                Some(f) => {
+                    let shaped = f.shape_text(&text);
+
+                    // remember which text the glyphs represent, for the ToUnicode map
+                    for (i, glyph) in shaped.glyphs.iter().enumerate() {
+                        let glyph_text = shaped.glyph_text(i).chars().collect::<Vec<char>>();
+                        f.mark_glyph_used(glyph.glyph_id, &glyph_text);
+                    }
+
+                    Some(positioned_glyphs(&shaped, &*f.font_data))
+                },
+                None => None,
+            }
+        };
+
+        match positioned_glyphs {
+            Some(glyphs) => self.add_positioned_codepoints(glyphs, Some(font)),
+            None => self.write_text(text, font),
+        }
+    }
+
     /// Saves the current graphic state
     #[inline]
     pub fn save_graphics_state(&self) {
@@ -489,6 +634,109 @@ impl PdfLayerReference {
             self.end_text_section();
     }
 
+    /// Lays out text in a rectangle, breaking it into lines at Unicode line break opportunities
+    ///
+    /// The first baseline is placed at the top of the box minus the ascent of the font, every following
+    /// line is moved down by `line_height` (in points). Lines are added as long as their descent
+    /// still fits into the box. Text that did not fit is returned, so that it can be continued in
+    /// another box or on another page (an empty string means that all text was written).
+    ///
+    /// Justified text is stretched with the word spacing (built-in fonts) or the character spacing
+    /// (external fonts, because PDF only applies word spacing to single-byte encodings).
+    ///
+    /// Nothing is written if the width of the box or `line_height` is 0 or negative.
+    pub fn add_text_box<S>(&self, text: S, font: &IndirectFontRef, font_size: f64,
+                           line_height: f64, rect: Rect, alignment: TextAlignment)
+    -> String where S: Into<String>
+    {
+        let text = text.into();
+        let box_width = rect.width().0;
+
+        if box_width <= 0.0 || line_height <= 0.0 {
+            return text;
+        }
+
+        // lines with their x offset inside the box and their word / character spacing
+        let (lines, rest, ascent, is_builtin_font) = {
+            let doc = self.document.upgrade().unwrap();
+            let doc = lock_document(&doc);
+            let font_data = match doc.fonts.get_font_data(font) {
+                Some(f) => f,
+                None => return text,
+            };
+
+            let measure = |s: &str| font_data.measure_text(s, font_size).width.0;
+            let metrics = font_data.measure_text("", font_size);
+
+            let usable_height = rect.height().0 - metrics.ascent.0 + metrics.descent.0;
+            let max_lines = if usable_height < 0.0 {
+                0
+            } else {
+                (usable_height / line_height).floor() as usize + 1
+            };
+
+            let (lines, rest) = break_lines(&text, box_width, max_lines, measure);
+            let text_ends = rest == text.len();
+            let line_count = lines.len();
+
+            let lines: Vec<(String, f64, f64)> = lines.into_iter().enumerate().map(|(i, line)| {
+                let line_text = &text[line.start..line.end];
+                let free_space = box_width - measure(line_text);
+                let is_last_line = line.ends_paragraph || (i + 1 == line_count && text_ends);
+
+                let (offset, spacing) = match alignment {
+                    TextAlignment::Left => (0.0, 0.0),
+                    TextAlignment::Right => (free_space, 0.0),
+                    TextAlignment::Center => (free_space / 2.0, 0.0),
+                    TextAlignment::Justify if is_last_line => (0.0, 0.0),
+                    TextAlignment::Justify => {
+                        let gaps = match font_data {
+                            Font::BuiltinFont(_) => line_text.chars().filter(|c| *c == ' ').count(),
+                            Font::ExternalFont(f) => line_text.chars()
+                                .filter(|c| f.font_data.glyph_id(*c).is_some())
+                                .count()
+                                .saturating_sub(1),
+                        };
+                        (0.0, if gaps > 0 { free_space / gaps as f64 } else { 0.0 })
+                    },
+                };
+
+                (line_text.to_string(), offset, spacing)
+            }).collect();
+
+            let is_builtin_font = match font_data {
+                Font::BuiltinFont(_) => true,
+                Font::ExternalFont(_) => false,
+            };
+
+            (lines, text[rest..].to_string(), metrics.ascent.0, is_builtin_font)
+        };
+
+        // scope the word / character spacing to this text box
+        self.save_graphics_state();
+        self.begin_text_section();
+        self.set_font(font, font_size);
+
+        let mut baseline = rect.ur.y.0 - ascent;
+        for (line_text, offset, spacing) in lines {
+            if alignment == TextAlignment::Justify {
+                if is_builtin_font {
+                    self.set_word_spacing(spacing);
+                } else {
+                    self.set_character_spacing(spacing);
+                }
+            }
+            self.set_text_matrix(TextMatrix::Translate(Pt(rect.ll.x.0 + offset), Pt(baseline)));
+            self.write_text(line_text, font);
+            baseline -= line_height;
+        }
+
+        self.end_text_section();
+        self.restore_graphics_state();
+
+        rest
+    }
+
     /// Add an operation
     ///
     /// This is the low level function used by other function in this struct.
@@ -498,8 +746,8 @@ impl PdfLayerReference {
     -> () where T: Into<Operation>
     {
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
-        let layer = &mut doc.pages[self.page.0].layers[self.layer.0];
+        let mut doc = lock_document(&doc);
+        let layer = doc.pages[self.page.0].layer_mut(self.layer);
         layer.operations.push(op.into());
     }
 
@@ -511,13 +759,13 @@ impl PdfLayerReference {
     {
         let svg_element_ref = {
             let doc = self.document.upgrade().unwrap();
-            let doc = doc.borrow_mut();
+            let doc = lock_document(&doc);
             let element = doc.contents.get((svg_data_index.0).0).expect("invalid svg reference");
             (*element).clone()
         };
 
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
 
         // todo: what about width / height?
         doc.pages.get_mut(self.page.0).unwrap()
@@ -526,16 +774,212 @@ impl PdfLayerReference {
     }
 */
 
+    // internal function to register glyphs as used on a font, so that they end up in the font subset
+    fn mark_glyphs_used<'a, I>(doc: &mut PdfDocument, font: &IndirectFontRef, glyphs: I)
+    where I: IntoIterator<Item = (u16, &'a [char])>
+    {
+        if let Some(DirectFontRef { data: Font::ExternalFont(ref mut font), .. }) = doc.fonts.get_font_mut(font) {
+            for (glyph_id, text) in glyphs {
+                font.mark_glyph_used(glyph_id, text);
+            }
+        }
+    }
+
+    // internal function to select a pattern as the current color, `cs_op` is either `cs` or `CS`
+    fn set_pattern(&self, pattern: &PatternRef, color: Option<Color>, cs_op: &str, color_op: &str)
+    {
+        use lopdf::Object::*;
+
+        // uncolored patterns use a [/Pattern /DeviceXXX] color space, which is added to the page
+        // resources (see PatternList::color_spaces), colored patterns the /Pattern color space.
+        // Colors in other color spaces use a [/Pattern base] color space of the page resources
+        let (color_space, mut operands) = match color {
+            Some(color) => match self.get_color_space(&color) {
+                Some((key, base)) => {
+                    let color_space = self.add_color_space(ColorSpaceKey::Pattern(Box::new(key)),
+                                                           Array(vec![Name("Pattern".into()), base]));
+                    (color_space, color.into_vec().into_iter().map(Real).collect())
+                },
+                None => {
+                    let color_space = match color {
+                        Color::Cmyk(_) | Color::SpotColor(_) => PATTERN_COLOR_SPACES[1].0,
+                        Color::Greyscale(_) | Color::CalGray(_) => PATTERN_COLOR_SPACES[2].0,
+                        _ => PATTERN_COLOR_SPACES[0].0,
+                    };
+                    (color_space.to_string(), color.into_device_vec().into_iter().map(Real).collect())
+                },
+            },
+            None => ("Pattern".to_string(), Vec::new()),
+        };
+
+        operands.push(Name(pattern.name.as_bytes().to_vec()));
+        self.add_operation(Operation::new(cs_op, vec![Name(color_space.as_bytes().to_vec())]));
+        self.add_operation(Operation::new(color_op, operands));
+    }
+
+    // internal function to get the color space of a color with an ICC profile or of a
+    // Lab, CalRGB or CalGray color (`None` for colors in a device color space)
+    fn get_color_space(&self, color: &Color)
+    -> Option<(ColorSpaceKey, lopdf::Object)>
+    {
+        let key = color.get_color_space_key()?;
+        if let Some(color_space) = color.get_cie_color_space() {
+            return Some((key, color_space));
+        }
+
+        let profile = match color.get_icc_profile() {
+            Some(&Some(ref profile)) => profile,
+            _ => return None,
+        };
+        let icc_type = match *color {
+            Color::Rgb(_) => IccProfileType::Rgb,
+            Color::Cmyk(_) => IccProfileType::Cmyk,
+            _ => IccProfileType::Greyscale,
+        };
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        let doc = &mut *doc;
+        doc.icc_profiles.get_color_space(profile, icc_type, &mut doc.inner_doc).map(|color_space| (key, color_space))
+    }
+
+    // internal function to add a color space to the page resources, returns its name
+    fn add_color_space(&self, key: ColorSpaceKey, color_space: lopdf::Object)
+    -> String
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        doc.pages[self.page.0].resources.color_spaces.add_color_space(key, color_space)
+    }
+
+    // internal function to set a color in its color space, `cs_op` is either `cs` or `CS`
+    fn set_color(&self, key: ColorSpaceKey, color_space: lopdf::Object, color: Color, cs_op: &str, color_op: &str)
+    {
+        use lopdf::Object::*;
+
+        let name = self.add_color_space(key, color_space);
+        self.add_operation(Operation::new(cs_op, vec![Name(name.into_bytes())]));
+        self.add_operation(Operation::new(color_op, color.into_vec().into_iter().map(Real).collect()));
+    }
+
     // internal function to invoke an xobject
     fn internal_invoke_xobject(&self, name: String)
     {
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
         let page_mut = &mut doc.pages[self.page.0];
 
-        page_mut.layers[self.layer.0]
-          .operations.push(lopdf::content::Operation::new(
+        page_mut.layer_mut(self.layer)
+          .operations.push(Operation::new(
               "Do", vec![lopdf::Object::Name(name.as_bytes().to_vec())]
         ));
     }
 }
+
+/// Converts shaped glyphs into `TJ` offsets (in thousandths of a text space unit)
+///
+/// A PDF viewer advances by the glyph width in the `W` array after each glyph,
+/// the `TJ` offsets correct this to the position calculated by the shaper.
+fn positioned_glyphs(shaped: &ShapedText, font_data: &dyn FontData) -> Vec<(i64, u16)> {
+    // same scaling that is used for the W array of the font
+    let scale = 1000.0 / f64::from(shaped.units_per_em);
+
+    let mut glyphs = Vec::with_capacity(shaped.glyphs.len());
+    // position of the pen according to the shaper
+    let mut shaper_pos = 0.0;
+    // position of the pen in the PDF viewer
+    let mut pdf_pos = 0.0;
+
+    for glyph in &shaped.glyphs {
+        let target = shaper_pos + f64::from(glyph.x_offset) * scale;
+        let adjust = (target - pdf_pos).round();
+        pdf_pos += adjust;
+
+        // positive numbers in a TJ array move the next glyph to the left
+        glyphs.push((-adjust as i64, glyph.glyph_id));
+
+        let pdf_width = font_data.glyph_metrics(glyph.glyph_id)
+            .map(|m| (f64::from(m.width) * scale) as i64)
+            .unwrap_or(1000);
+        pdf_pos += pdf_width as f64;
+        // undo the offset, it should not affect the following glyphs
+        shaper_pos += f64::from(glyph.x_advance) * scale;
+    }
+
+    glyphs
+}
+
+#[test]
+fn test_positioned_glyphs() {
+    use std::collections::HashMap;
+    use {FontMetrics, GlyphMetrics, ShapedGlyph};
+
+    // every glyph is 1000 units wide, 500 / 1000 of the font size
+    #[derive(Debug, Clone)]
+    struct FixedWidth;
+
+    impl FontData for FixedWidth {
+        fn font_metrics(&self) -> FontMetrics { FontMetrics::new(1600, -400, 2000) }
+        fn glyph_id(&self, _: char) -> Option<u16> { None }
+        fn glyph_ids(&self) -> HashMap<u16, char> { HashMap::new() }
+        fn glyph_metrics(&self, _: u16) -> Option<GlyphMetrics> { Some(GlyphMetrics { width: 1000, height: 2000 }) }
+    }
+
+    let glyph = |glyph_id, x_advance, x_offset| ShapedGlyph { glyph_id, cluster: 0, x_advance, x_offset, y_offset: 0 };
+    let shaped = ShapedText {
+        text: String::new(),
+        glyphs: vec![
+            glyph(1, 1000, 0),
+            // kerned: the next glyph starts 100 / 1000 further left
+            glyph(2, 800, 0),
+            glyph(3, 1000, 0),
+            // moved 30 / 1000 to the right, without affecting the next glyph
+            glyph(4, 1000, 60),
+            glyph(5, 1000, 0),
+        ],
+        units_per_em: 2000,
+    };
+
+    assert_eq!(positioned_glyphs(&shaped, &FixedWidth), vec![(0, 1), (0, 2), (100, 3), (-30, 4), (30, 5)]);
+}
+
+#[test]
+fn test_shaped_text_marks_glyphs_of_its_font() {
+    use std::io::Cursor;
+    use {PdfDocument, Mm};
+
+    let font_bytes = include_bytes!("../assets/fonts/RobotoMedium.ttf");
+    let (doc, page, layer) = PdfDocument::new("Fonts", Mm(100.0), Mm(100.0), "Layer 1");
+    let current_font = doc.add_external_font(Cursor::new(font_bytes.to_vec())).unwrap();
+    let shaped_font = doc.add_external_font(Cursor::new(font_bytes.to_vec())).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    layer.set_font(&current_font, 12.0);
+    layer.write_shaped_text("Hi", &shaped_font);
+
+    let document = lock_document(&doc.document);
+    let used_glyphs = |font: &IndirectFontRef| match document.fonts.get_font(font) {
+        Some(DirectFontRef { data: Font::ExternalFont(ref font), .. }) => font.used_glyphs.len(),
+        _ => 0,
+    };
+    assert_eq!(used_glyphs(&current_font), 0);
+    assert_eq!(used_glyphs(&shaped_font), 2);
+}
+
+#[test]
+fn test_text_box_edge_cases() {
+    use {PdfDocument, BuiltinFont};
+
+    let (doc, page, layer) = PdfDocument::new("Text box", Mm(100.0), Mm(100.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page).get_layer(layer);
+    let rect = |width: f64| Rect::new(Mm(10.0), Mm(10.0), Mm(width), Mm(80.0));
+
+    assert_eq!(layer.add_text_box("", &font, 12.0, 14.0, rect(50.0), TextAlignment::Left), "");
+    assert_eq!(layer.add_text_box("  \n ", &font, 12.0, 14.0, rect(50.0), TextAlignment::Justify), "");
+    assert_eq!(layer.add_text_box("Incomprehensibilities", &font, 12.0, 14.0, rect(5.0), TextAlignment::Left), "");
+
+    // nothing fits into a box without width or lines without height
+    assert_eq!(layer.add_text_box("Hello", &font, 12.0, 14.0, rect(0.0), TextAlignment::Left), "Hello");
+    assert_eq!(layer.add_text_box("Hello", &font, 12.0, 14.0, rect(-5.0), TextAlignment::Left), "Hello");
+    assert_eq!(layer.add_text_box("Hello", &font, 12.0, 0.0, rect(50.0), TextAlignment::Left), "Hello");
+}
@@ -0,0 +1,423 @@
+//! Loads existing PDF files, so that new content (page numbers, stamps, ...)
+//! can be added to their pages before they are saved again
+
+use lopdf::{self, Object, ObjectId};
+use lopdf::content::Operation;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::iter::FromIterator;
+use std::sync::{Arc, Mutex};
+
+use utils::random_character_string_32;
+use {
+    Error, PdfError, PdfDocument, PdfDocumentReference, PdfPage, PdfMetadata, PdfConformance,
+    FontList, IccProfileList, Outline, Mm, Pt, Point, Rect, XObject, FormXObject, CurTransMat
+};
+
+/// Name of the layer with the content of a loaded page
+const ORIGINAL_CONTENT_LAYER: &str = "Original content";
+
+/// Size of pages without a media box (US Letter, like most viewers assume)
+const DEFAULT_MEDIA_BOX: [f64; 4] = [0.0, 0.0, 612.0, 792.0];
+
+/// Page boxes that are kept, they are moved if the media box doesn't start at 0, 0
+const PAGE_BOXES: [&str; 4] = ["CropBox", "BleedBox", "TrimBox", "ArtBox"];
+
+/// Other entries of the page dictionary that are kept
+const PAGE_ENTRIES: [&str; 5] = ["Annots", "Group", "UserUnit", "Tabs", "AA"];
+
+/// Entries of the catalog that are kept (unless the document sets them itself)
+const CATALOG_ENTRIES: [&str; 9] = [
+    "Outlines", "Names", "Dests", "AcroForm", "PageLabels",
+    "ViewerPreferences", "OutputIntents", "OpenAction", "Lang",
+];
+
+impl PdfDocument {
+
+    /// Loads a PDF file, see `load_from_bytes`
+    pub fn load<R: Read>(mut reader: R)
+    -> Result<PdfDocumentReference, Error>
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::load_from_bytes(&bytes)
+    }
+
+    /// Loads a PDF file, so that new layers, text and images can be added to its pages.
+    ///
+    /// The pages keep their size and rotation. The existing content of each page is
+    /// the first layer of the page ("Original content"), it is embedded as it is, with
+    /// its fonts, images and other resources. These resources can't be used by new
+    /// content (fonts have to be added with `add_external_font` or `add_builtin_font`).
+    /// Annotations, form fields, the outline and named destinations of the file are kept.
+    ///
+    /// ```rust,no_run
+    /// use printpdf::*;
+    /// use std::fs::File;
+    /// use std::io::BufWriter;
+    ///
+    /// let doc = PdfDocument::load(File::open("supplier.pdf").unwrap()).unwrap();
+    /// let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    ///
+    /// let pages = doc.get_pages();
+    /// for (number, page) in pages.iter().enumerate() {
+    ///     let page = doc.get_page(*page);
+    ///     let (width, _) = page.get_size();
+    ///     let layer = page.add_layer("Page numbers");
+    ///     let text = format!("{} / {}", number + 1, pages.len());
+    ///     layer.use_text(text, 10.0, Mm(width.0 - 30.0), Mm(10.0), &font);
+    /// }
+    ///
+    /// doc.save(&mut BufWriter::new(File::create("stamped.pdf").unwrap())).unwrap();
+    /// ```
+    #[allow(deprecated)]
+    pub fn load_from_bytes(bytes: &[u8])
+    -> Result<PdfDocumentReference, Error>
+    {
+        let mut inner_doc = lopdf::Document::load_mem(bytes)?;
+
+        if inner_doc.trailer.has(b"Encrypt") {
+            return Err(PdfError::InvalidPdf("encrypted PDF files are not supported").into());
+        }
+
+        let page_ids: Vec<ObjectId> = inner_doc.page_iter().collect();
+        if page_ids.is_empty() {
+            return Err(PdfError::InvalidPdf("the document has no pages").into());
+        }
+
+        let mut pages = Vec::with_capacity(page_ids.len());
+        for (index, page_id) in page_ids.iter().enumerate() {
+            pages.push(load_page(&inner_doc, *page_id, index)?);
+        }
+
+        let mut original_catalog_entries = lopdf::Dictionary::new();
+        if let Ok(catalog) = inner_doc.catalog() {
+            for key in CATALOG_ENTRIES.iter() {
+                if let Ok(value) = catalog.get(key.as_bytes()) {
+                    original_catalog_entries.set(*key, value.clone());
+                }
+            }
+
+            // the optional content groups are merged with the layers of the
+            // document when it is saved, only the groups and the hidden groups are kept
+            let optional_content = catalog.get(b"OCProperties").ok().and_then(|properties| resolve_dict(&inner_doc, properties));
+            if let Some(optional_content) = optional_content {
+                let array = |object: Option<&Object>| {
+                    object.and_then(|object| resolve_array(&inner_doc, object)).cloned().unwrap_or_default()
+                };
+                let default_config = optional_content.get(b"D").ok().and_then(|config| resolve_dict(&inner_doc, config));
+                original_catalog_entries.set("OCProperties", lopdf::Dictionary::from_iter(vec![
+                    ("OCGs", Object::Array(array(optional_content.get(b"OCGs").ok()))),
+                    ("OFF", Object::Array(array(default_config.and_then(|config| config.get(b"OFF").ok())))),
+                ]));
+            }
+        }
+
+        let mut metadata = PdfMetadata::new("", 1, false, PdfConformance::default());
+        if let Some(info) = inner_doc.trailer.get(b"Info").ok().and_then(|info| resolve_dict(&inner_doc, info)) {
+            let text = |key: &[u8]| info.get(key).and_then(Object::as_str).map(decode_text_string).ok();
+            metadata.document_title = text(b"Title").unwrap_or_default();
+            metadata.author = text(b"Author").unwrap_or_default();
+            metadata.creator = text(b"Creator").unwrap_or_default();
+            metadata.producer = text(b"Producer").unwrap_or_default();
+            metadata.subject = text(b"Subject").unwrap_or_default();
+            metadata.keywords = text(b"Keywords").map(|keywords| {
+                keywords.split(',').map(|keyword| keyword.trim().to_string()).filter(|k| !k.is_empty()).collect()
+            }).unwrap_or_default();
+        }
+
+        // the catalog, the page tree, the document info and the cross-reference
+        // streams are created again when the document is saved
+        let mut removed_ids = page_ids;
+        removed_ids.extend(inner_doc.trailer.get(b"Root").and_then(Object::as_reference).ok());
+        removed_ids.extend(inner_doc.trailer.get(b"Info").and_then(Object::as_reference).ok());
+        removed_ids.extend(inner_doc.objects.iter().filter_map(|(id, object)| {
+            let object_type = match *object {
+                Object::Dictionary(ref dict) => dict.get(b"Type"),
+                Object::Stream(ref stream) => stream.dict.get(b"Type"),
+                _ => return None,
+            };
+            match object_type.and_then(Object::as_name_str) {
+                Ok("Pages") | Ok("ObjStm") | Ok("XRef") => Some(*id),
+                _ => None,
+            }
+        }).collect::<Vec<_>>());
+
+        for id in removed_ids {
+            inner_doc.objects.remove(&id);
+        }
+        inner_doc.trailer = lopdf::Dictionary::new();
+
+        let doc = PdfDocument {
+            pages,
+            document_id: random_character_string_32(),
+            fonts: FontList::new(),
+            icc_profiles: IccProfileList::new(),
+            inner_doc,
+            metadata,
+            outline: Outline::new(),
+            bookmarks: HashMap::new(),
+            named_destinations: BTreeMap::new(),
+            original_catalog_entries,
+            imported_objects: BTreeMap::new(),
+        };
+
+        Ok(PdfDocumentReference { document: Arc::new(Mutex::new(doc)) })
+    }
+}
+
+/// Creates a page with the size, rotation and content of a page of the loaded document
+fn load_page(doc: &lopdf::Document, page_id: ObjectId, index: usize)
+-> Result<PdfPage, Error>
+{
+    let page = doc.get_dictionary(page_id)?;
+
+    let media_box = inherited(doc, page, b"MediaBox")
+        .and_then(|media_box| read_box(doc, media_box))
+        .unwrap_or(DEFAULT_MEDIA_BOX);
+    let (x, y) = (media_box[0], media_box[1]);
+    let (width, height) = (media_box[2] - x, media_box[3] - y);
+
+    let (mut pdf_page, layer_index) = PdfPage::new(Mm::from(Pt(width)), Mm::from(Pt(height)), ORIGINAL_CONTENT_LAYER, index);
+    pdf_page.width = Pt(width);
+    pdf_page.height = Pt(height);
+    pdf_page.object_id = Some(page_id);
+    pdf_page.rotation = inherited(doc, page, b"Rotate")
+        .and_then(|rotation| rotation.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+
+    // the new page starts at 0, 0, so the boxes (and the content) are moved
+    for key in PAGE_BOXES.iter() {
+        if let Some(page_box) = inherited(doc, page, key.as_bytes()).and_then(|page_box| read_box(doc, page_box)) {
+            let moved_box = vec![page_box[0] - x, page_box[1] - y, page_box[2] - x, page_box[3] - y];
+            pdf_page.original_entries.set(*key, Object::Array(moved_box.into_iter().map(Object::Real).collect()));
+        }
+    }
+
+    for key in PAGE_ENTRIES.iter() {
+        if let Ok(value) = page.get(key.as_bytes()) {
+            pdf_page.original_entries.set(*key, value.clone());
+        }
+    }
+
+    let content = page_content(doc, page_id)?;
+    if content.is_empty() {
+        return Ok(pdf_page);
+    }
+
+    let resources = inherited(doc, page, b"Resources")
+        .and_then(|resources| resolve_dict(doc, resources))
+        .cloned()
+        .unwrap_or_default();
+
+    // the content is wrapped in a form XObject with the resources of the page,
+    // so that the names of its resources can't collide with new resources
+    let bbox = Rect {
+        ll: Point { x: Pt(media_box[0]), y: Pt(media_box[1]) },
+        ur: Point { x: Pt(media_box[2]), y: Pt(media_box[3]) },
+    };
+    let form = FormXObject {
+        matrix: if x != 0.0 || y != 0.0 { Some(CurTransMat::Translate(Pt(-x), Pt(-y))) } else { None },
+        resources: Some(resources),
+        .. FormXObject::new(content, bbox)
+    };
+
+    let form_ref = pdf_page.add_xobject(XObject::Form(Box::new(form)));
+    pdf_page.layers[layer_index.0].operations.push(Operation::new("Do", vec![Object::Name(form_ref.name.into_bytes())]));
+
+    Ok(pdf_page)
+}
+
+/// Returns the entry of the page, or of the nearest parent in the page tree that has
+/// this entry (`Resources`, `MediaBox`, `CropBox` and `Rotate` are inherited)
+pub(crate) fn inherited<'a>(doc: &'a lopdf::Document, page: &'a lopdf::Dictionary, key: &[u8])
+-> Option<&'a Object>
+{
+    let mut node = page;
+    // the depth is limited, in case the page tree contains a cycle
+    for _ in 0..64 {
+        if let Ok(value) = node.get(key) {
+            return Some(value);
+        }
+        node = node.get(b"Parent").and_then(Object::as_reference).and_then(|id| doc.get_dictionary(id)).ok()?;
+    }
+    None
+}
+
+/// Returns the dictionary, or the dictionary the reference points to
+pub(crate) fn resolve_dict<'a>(doc: &'a lopdf::Document, object: &'a Object)
+-> Option<&'a lopdf::Dictionary>
+{
+    match *object {
+        Object::Dictionary(ref dict) => Some(dict),
+        Object::Reference(id) => doc.get_dictionary(id).ok(),
+        _ => None,
+    }
+}
+
+/// Returns the array, or the array the reference points to
+pub(crate) fn resolve_array<'a>(doc: &'a lopdf::Document, object: &'a Object)
+-> Option<&'a Vec<Object>>
+{
+    match *object {
+        Object::Array(ref array) => Some(array),
+        Object::Reference(id) => doc.get_object(id).and_then(Object::as_array).ok(),
+        _ => None,
+    }
+}
+
+/// Reads a rectangle (`[llx lly urx ury]`), the corners are normalized so that
+/// the lower left corner comes first
+pub(crate) fn read_box(doc: &lopdf::Document, object: &Object)
+-> Option<[f64; 4]>
+{
+    let array = match *object {
+        Object::Array(ref array) => array,
+        Object::Reference(id) => doc.get_object(id).and_then(Object::as_array).ok()?,
+        _ => return None,
+    };
+
+    if array.len() != 4 {
+        return None;
+    }
+
+    let mut values = [0.0; 4];
+    for (value, object) in values.iter_mut().zip(array.iter()) {
+        *value = match *object {
+            Object::Integer(i) => i as f64,
+            Object::Real(r) => r,
+            _ => return None,
+        };
+    }
+
+    Some([values[0].min(values[2]), values[1].min(values[3]), values[0].max(values[2]), values[1].max(values[3])])
+}
+
+/// Returns the decompressed content streams of the page, one after another
+pub(crate) fn page_content(doc: &lopdf::Document, page_id: ObjectId)
+-> Result<Vec<u8>, Error>
+{
+    let mut content = Vec::new();
+    for stream_id in doc.get_page_contents(page_id) {
+        let stream = match doc.get_object(stream_id).and_then(Object::as_stream) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        if stream.dict.has(b"Filter") {
+            let decompressed = stream.decompressed_content()
+                .map_err(|_| PdfError::InvalidPdf("content stream with an unsupported filter"))?;
+            content.extend_from_slice(&decompressed);
+        } else {
+            content.extend_from_slice(&stream.content);
+        }
+
+        // the streams are split at token boundaries, but a token may end at the end of a stream
+        content.push(b'\n');
+    }
+    Ok(content)
+}
+
+/// Decodes a text string from the document info (UTF-16 with a byte order mark or PDFDocEncoding,
+/// which is the same as Latin-1 for the characters that are commonly used)
+fn decode_text_string(bytes: &[u8])
+-> String
+{
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = bytes[2..].chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        bytes.iter().map(|&byte| char::from(byte)).collect()
+    }
+}
+
+#[test]
+fn test_load_document() {
+    use {BuiltinFont, Mm};
+
+    let (doc, page, _) = PdfDocument::new("Original", Mm(210.0), Mm(297.0), "Layer 1");
+    doc.get_page(page).set_rotation(90);
+    doc.add_page(Mm(100.0), Mm(50.0), "Layer 1");
+    let bytes = doc.save_to_bytes().unwrap();
+
+    let doc = PdfDocument::load_from_bytes(&bytes).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 2);
+    assert_eq!(doc.get_page(pages[0]).get_rotation(), 90);
+    let (width, height) = doc.get_page(pages[1]).get_size();
+    assert!((width.0 - 100.0).abs() < 0.01 && (height.0 - 50.0).abs() < 0.01);
+
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(pages[0]).add_layer("Stamp");
+    layer.use_text("Page 1", 12.0, Mm(10.0), Mm(10.0), &font);
+    let bytes = doc.save_to_bytes().unwrap();
+
+    let reloaded = lopdf::Document::load_mem(&bytes).unwrap();
+    assert_eq!(reloaded.get_pages().len(), 2);
+    assert!(PdfDocument::load_from_bytes(b"not a pdf").is_err());
+}
+
+#[test]
+fn test_load_fixture_with_text_and_fonts() {
+    use {BuiltinFont, Mm};
+
+    // a real document with text in embedded fonts. The first page inherits its resources
+    // from the page tree and its media box doesn't start at 0, 0
+    let mut source = lopdf::Document::load_mem(include_bytes!("../assets/Color Profile Bundling License_10.15.08.pdf")).unwrap();
+    let first_page_id = source.get_pages()[&1];
+    let original_content = source.get_page_content(first_page_id).unwrap();
+    let (resources, parent_id) = {
+        let page = source.get_object_mut(first_page_id).unwrap().as_dict_mut().unwrap();
+        let offset_box = || Object::Array(vec![Object::Integer(50), Object::Integer(100), Object::Integer(662), Object::Integer(892)]);
+        page.set("MediaBox", offset_box());
+        page.set("CropBox", offset_box());
+        (page.remove(b"Resources").unwrap(), page.get(b"Parent").unwrap().as_reference().unwrap())
+    };
+    source.get_object_mut(parent_id).unwrap().as_dict_mut().unwrap().set("Resources", resources);
+    let mut bytes = Vec::new();
+    source.save_to(&mut bytes).unwrap();
+
+    let doc = PdfDocument::load_from_bytes(&bytes).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 7);
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    doc.get_page(pages[0]).add_layer("Stamp").use_text("Copy", 12.0, Mm(10.0), Mm(10.0), &font);
+
+    let reloaded = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    assert_eq!(reloaded.get_pages().len(), 7);
+    let page = reloaded.get_dictionary(reloaded.get_pages()[&1]).unwrap();
+    let numbers = |object: &Object| -> Vec<f64> {
+        object.as_array().unwrap().iter().map(|value| value.as_f64().or_else(|_| value.as_i64().map(|value| value as f64)).unwrap()).collect()
+    };
+    assert_eq!(numbers(page.get(b"MediaBox").unwrap()), vec![0.0, 0.0, 612.0, 792.0]);
+    assert_eq!(numbers(page.get(b"CropBox").unwrap()), vec![0.0, 0.0, 612.0, 792.0]);
+
+    // the original content is wrapped in a form, with the inherited resources and moved by the offset
+    let resources = match *page.get(b"Resources").unwrap() {
+        Object::Reference(id) => reloaded.get_dictionary(id).unwrap(),
+        ref object => object.as_dict().unwrap(),
+    };
+    let xobjects = resources.get(b"XObject").unwrap().as_dict().unwrap();
+    assert_eq!(xobjects.len(), 1);
+    let (_, form_id) = xobjects.iter().next().unwrap();
+    let form = reloaded.get_object(form_id.as_reference().unwrap()).unwrap().as_stream().unwrap();
+    let form_content = form.decompressed_content().unwrap_or_else(|_| form.content.clone());
+    assert!(form_content.starts_with(&original_content));
+    assert!(form_content[original_content.len()..].iter().all(u8::is_ascii_whitespace));
+    assert_eq!(numbers(form.dict.get(b"BBox").unwrap()), vec![50.0, 100.0, 662.0, 892.0]);
+    assert_eq!(numbers(form.dict.get(b"Matrix").unwrap()), vec![1.0, 0.0, 0.0, 1.0, -50.0, -100.0]);
+
+    let form_resources = match *form.dict.get(b"Resources").unwrap() {
+        Object::Reference(id) => reloaded.get_dictionary(id).unwrap(),
+        ref object => object.as_dict().unwrap(),
+    };
+    let form_fonts = form_resources.get(b"Font").unwrap().as_dict().unwrap();
+    assert!(form_fonts.has(b"F1") && form_fonts.has(b"TT2"));
+    let embedded_font = reloaded.get_dictionary(form_fonts.get(b"TT2").unwrap().as_reference().unwrap()).unwrap();
+    assert_eq!(embedded_font.get(b"BaseFont").unwrap().as_name_str().unwrap(), "CFFBKJ+TimesNewRomanPSMT");
+
+    // the new text uses the fonts of the new document
+    let page_fonts = reloaded.get_dictionary(resources.get(b"Font").unwrap().as_reference().unwrap()).unwrap();
+    assert!(page_fonts.has(font.name.as_bytes()));
+}
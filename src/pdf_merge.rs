@@ -0,0 +1,685 @@
+//! Document-level page operations: appending other documents, moving, deleting
+//! and duplicating pages and extracting pages into a new document
+
+use lopdf::{Object, ObjectId};
+use lopdf::content::{Content, Operation};
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use pdf_catalog::{
+    append_form, append_outline, destination_target, link_target, outline_root, rename_destinations,
+    retain_links, retain_outline_items, unique_name, LoadedNames, LoadedTarget
+};
+use pdf_document::lock_document;
+use utils::random_character_string_32;
+use {
+    Destination, Error, IndexError, IndirectFontRef, LinkAction, PdfDocument, PdfDocumentReference,
+    PdfPage, PdfPageIndex, Pattern, XObject
+};
+
+impl PdfDocumentReference {
+
+    /// Appends all pages of `other` to this document and returns their new indices.
+    ///
+    /// The outline items and named destinations of `other` are added to this document
+    /// (named destinations whose name is taken are renamed, together with the links to them).
+    /// This includes the outline, named destinations and form fields of a loaded document,
+    /// the items of a loaded outline are shown before the ones added with `add_outline_item`.
+    /// Fonts that both documents use are only embedded once. The font references
+    /// of `other` can't be used anymore, the fonts have to be added to this document again.
+    /// The metadata of this document is kept.
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// let (letter, _, _) = PdfDocument::new("Cover letter", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let (statement, _, _) = PdfDocument::new("Statement", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let (terms, _, _) = PdfDocument::new("Terms", Mm(210.0), Mm(297.0), "Layer 1");
+    ///
+    /// letter.append_document(statement);
+    /// letter.append_document(terms);
+    /// assert_eq!(letter.get_pages().len(), 3);
+    /// ```
+    pub fn append_document(&self, other: PdfDocumentReference)
+    -> Vec<PdfPageIndex>
+    {
+        // the pages and layers of `other` only hold weak references to it
+        let other = match Arc::try_unwrap(other.document) {
+            Ok(other) => other.into_inner().unwrap_or_else(PoisonError::into_inner),
+            Err(other) => lock_document(&other).clone(),
+        };
+
+        let mut doc = lock_document(&self.document);
+        doc.append(other)
+    }
+
+    /// Moves the page to `new_index`, the pages in between move up or down by one.
+    ///
+    /// Links, outline items and named destinations keep pointing to the moved pages.
+    /// Previously returned page indices (and page and layer references) refer to the
+    /// position of the page, not to the page itself.
+    pub fn move_page(&self, page: PdfPageIndex, new_index: PdfPageIndex)
+    -> Result<(), Error>
+    {
+        let mut doc = lock_document(&self.document);
+        let page_count = doc.pages.len();
+        if page.0 >= page_count || new_index.0 >= page_count {
+            return Err(IndexError::PdfPageIndexError.into());
+        }
+
+        let mut new_order: Vec<usize> = (0..page_count).filter(|&index| index != page.0).collect();
+        new_order.insert(new_index.0, page.0);
+        doc.reorder_pages(&new_order);
+        Ok(())
+    }
+
+    /// Deletes the page. Outline items and links that point to the page are removed,
+    /// the following pages move up by one (see `move_page`).
+    pub fn delete_page(&self, page: PdfPageIndex)
+    -> Result<(), Error>
+    {
+        let mut doc = lock_document(&self.document);
+        let page_count = doc.pages.len();
+        if page.0 >= page_count {
+            return Err(IndexError::PdfPageIndexError.into());
+        }
+
+        let new_order: Vec<usize> = (0..page_count).filter(|&index| index != page.0).collect();
+        doc.reorder_pages(&new_order);
+        Ok(())
+    }
+
+    /// Inserts a copy of the page (with all its layers) after the page,
+    /// returns the index of the copy. The following pages move down by one (see `move_page`).
+    pub fn duplicate_page(&self, page: PdfPageIndex)
+    -> Result<PdfPageIndex, Error>
+    {
+        let mut doc = lock_document(&self.document);
+        let page_count = doc.pages.len();
+        let mut copy = doc.pages.get(page.0).cloned().ok_or(IndexError::PdfPageIndexError)?;
+
+        // the copy is a new page object, annotations of a loaded
+        // page belong to the original page and are not copied
+        copy.object_id = None;
+        copy.original_entries.remove(b"Annots");
+        doc.pages.push(copy);
+
+        let mut new_order: Vec<usize> = (0..page_count).collect();
+        new_order.insert(page.0 + 1, page_count);
+        doc.reorder_pages(&new_order);
+        Ok(PdfPageIndex(page.0 + 1))
+    }
+
+    /// Creates a new document with a copy of the pages in `pages` (for example `0..10`),
+    /// with the outline items, named destinations and fonts that these pages use.
+    /// The metadata is copied, the new document gets a new document ID.
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// let doc = PdfDocument::empty("Batch");
+    /// for _ in 0..6 {
+    ///     doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    /// }
+    ///
+    /// // three pages per customer
+    /// let customers: Vec<_> = (0..2).map(|customer| doc.extract_pages(customer * 3..(customer + 1) * 3).unwrap()).collect();
+    /// assert_eq!(customers[1].get_pages().len(), 3);
+    /// ```
+    pub fn extract_pages<R>(&self, pages: R)
+    -> Result<PdfDocumentReference, Error> where R: RangeBounds<usize>
+    {
+        let mut extracted = lock_document(&self.document).clone();
+        let page_count = extracted.pages.len();
+
+        let start = match pages.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match pages.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => page_count,
+        };
+        if start > end || end > page_count {
+            return Err(IndexError::PdfPageIndexError.into());
+        }
+
+        let new_order: Vec<usize> = (start..end).collect();
+        extracted.reorder_pages(&new_order);
+        extracted.remove_unused_fonts();
+        extracted.document_id = random_character_string_32();
+
+        Ok(PdfDocumentReference { document: Arc::new(Mutex::new(extracted)) })
+    }
+}
+
+impl PdfDocument {
+
+    /// Puts the pages in a new order. `new_order` contains the old index of each
+    /// page, pages that are not in `new_order` are removed. Links, outline items
+    /// and named destinations are updated, the ones that point to removed pages are removed.
+    fn reorder_pages(&mut self, new_order: &[usize])
+    {
+        let mut new_indices = vec![None; self.pages.len()];
+        for (new_index, &old_index) in new_order.iter().enumerate() {
+            new_indices[old_index] = Some(new_index);
+        }
+
+        // page objects of a loaded document that are removed
+        let mut removed_ids: BTreeSet<ObjectId> = self.pages.iter().filter_map(|page| page.object_id).collect();
+        for &old_index in new_order {
+            if let Some(id) = self.pages[old_index].object_id {
+                removed_ids.remove(&id);
+            }
+        }
+
+        let mut old_pages: Vec<Option<PdfPage>> = mem::take(&mut self.pages).into_iter().map(Some).collect();
+        self.pages = new_order.iter().filter_map(|&old_index| old_pages[old_index].take()).collect();
+        for (index, page) in self.pages.iter_mut().enumerate() {
+            page.index = index;
+        }
+
+        let update = |destination: &mut Destination| {
+            match new_indices.get(destination.get_page().0).cloned().and_then(|new_index| new_index) {
+                Some(new_index) => { destination.set_page(PdfPageIndex(new_index)); true },
+                None => false,
+            }
+        };
+
+        self.outline.update_destinations(update);
+
+        // the outline, named destinations and links of a loaded document point to the page objects
+        let mut loaded_names = LoadedNames::read(&self.inner_doc, &self.original_catalog_entries);
+        let mut removed_names = loaded_names.destination_names();
+        removed_names.extend(self.named_destinations.keys().map(|name| name.clone().into_bytes()));
+
+        self.named_destinations.retain(|_, destination| update(destination));
+        if !removed_ids.is_empty() {
+            loaded_names.retain_destinations(|destination| {
+                !matches!(destination_target(&self.inner_doc, destination), LoadedTarget::Page(id) if removed_ids.contains(&id))
+            });
+        }
+        for name in loaded_names.destination_names().into_iter().chain(self.named_destinations.keys().map(|name| name.clone().into_bytes())) {
+            removed_names.remove(&name);
+        }
+
+        let is_dangling = |target: LoadedTarget| match target {
+            LoadedTarget::Page(id) => removed_ids.contains(&id),
+            LoadedTarget::Name(name) => removed_names.contains(&name),
+            LoadedTarget::Other => false,
+        };
+
+        for page in &mut self.pages {
+            page.annotations.retain(|annotation| match annotation.action {
+                LinkAction::GoTo(destination) => matches!(new_indices.get(destination.get_page().0), Some(Some(_))),
+                LinkAction::Named(ref name) => !is_dangling(LoadedTarget::Name(name.clone().into_bytes())),
+                LinkAction::Uri(_) => true,
+            });
+            for annotation in &mut page.annotations {
+                if let LinkAction::GoTo(ref mut destination) = annotation.action {
+                    update(destination);
+                }
+            }
+        }
+
+        if !removed_ids.is_empty() || !removed_names.is_empty() {
+            for page in &mut self.pages {
+                retain_links(&self.inner_doc, &mut page.original_entries, |doc, link| !is_dangling(link_target(doc, link)));
+            }
+            retain_outline_items(&mut self.inner_doc, &mut self.original_catalog_entries, |doc, item| !is_dangling(link_target(doc, item)));
+            loaded_names.write(&mut self.original_catalog_entries);
+        }
+    }
+
+    /// Appends the pages, outline, named destinations, form fields and fonts of `other`
+    /// (see `PdfDocumentReference::append_document`)
+    fn append(&mut self, mut other: PdfDocument)
+    -> Vec<PdfPageIndex>
+    {
+        let page_offset = self.pages.len();
+        let move_destination = |destination: &mut Destination| {
+            destination.set_page(PdfPageIndex(destination.get_page().0 + page_offset));
+            true
+        };
+
+        // the objects of a loaded (or imported) document get IDs after the IDs of this document
+        let id_offset = self.inner_doc.max_id;
+        let mut other_object_ids = Vec::with_capacity(other.inner_doc.objects.len());
+        for (id, mut object) in mem::take(&mut other.inner_doc.objects) {
+            offset_object_ids(&mut object, id_offset);
+            other_object_ids.push((id.0 + id_offset, id.1));
+            self.inner_doc.objects.insert((id.0 + id_offset, id.1), object);
+        }
+        self.inner_doc.max_id += other.inner_doc.max_id;
+        for (_, value) in other.original_catalog_entries.iter_mut() {
+            offset_object_ids(value, id_offset);
+        }
+
+        // named destinations that are taken (by generated or loaded destinations) get a new name
+        let mut loaded_names = LoadedNames::read(&self.inner_doc, &self.original_catalog_entries);
+        let other_loaded_names = LoadedNames::read(&self.inner_doc, &other.original_catalog_entries);
+        let mut taken_names = loaded_names.destination_names();
+        let mut renamed_destinations = BTreeMap::new();
+        for (name, mut destination) in mem::take(&mut other.named_destinations) {
+            move_destination(&mut destination);
+            let new_name = unique_name(name.as_bytes(), |new_name| {
+                let existing = String::from_utf8_lossy(new_name);
+                matches!(self.named_destinations.get(existing.as_ref()), Some(existing) if *existing != destination)
+                || taken_names.contains(new_name)
+            });
+            let new_name = String::from_utf8_lossy(&new_name).into_owned();
+            if new_name != name {
+                renamed_destinations.insert(name.into_bytes(), new_name.clone().into_bytes());
+            }
+            self.named_destinations.insert(new_name, destination);
+        }
+
+        taken_names.extend(self.named_destinations.keys().map(|name| name.clone().into_bytes()));
+        let other_names = other_loaded_names.destination_names();
+        for name in &other_names {
+            let new_name = unique_name(name, |new_name| taken_names.contains(new_name) || (new_name != &name[..] && other_names.contains(new_name)));
+            if new_name != *name {
+                renamed_destinations.insert(name.clone(), new_name.clone());
+            }
+            taken_names.insert(new_name);
+        }
+
+        if !renamed_destinations.is_empty() {
+            for id in &other_object_ids {
+                if let Some(object) = self.inner_doc.objects.get_mut(id) {
+                    rename_destinations(object, &renamed_destinations);
+                }
+            }
+        }
+
+        other.outline.update_destinations(move_destination);
+        self.outline.append(mem::take(&mut other.outline));
+
+        // the outline, named destinations and form fields of a loaded document
+        if let Some(other_root) = outline_root(&mut self.inner_doc, &mut other.original_catalog_entries) {
+            match outline_root(&mut self.inner_doc, &mut self.original_catalog_entries) {
+                Some(root) => append_outline(&mut self.inner_doc, root, other_root),
+                None => self.original_catalog_entries.set("Outlines", Object::Reference(other_root)),
+            }
+        }
+        if !other_loaded_names.is_empty() {
+            loaded_names.append(other_loaded_names, &renamed_destinations);
+            loaded_names.write(&mut self.original_catalog_entries);
+        }
+        append_form(&self.inner_doc, &mut self.original_catalog_entries, &other.original_catalog_entries);
+
+        // the optional content groups of the original content of loaded pages
+        if let Some(Object::Dictionary(mut other_groups)) = other.original_catalog_entries.remove(b"OCProperties") {
+            match self.original_catalog_entries.get_mut(b"OCProperties") {
+                Ok(&mut Object::Dictionary(ref mut groups)) => {
+                    for key in [&b"OCGs"[..], &b"OFF"[..]].iter() {
+                        if let (Ok(&mut Object::Array(ref mut existing)), Some(Object::Array(added))) = (groups.get_mut(key), other_groups.remove(key)) {
+                            existing.extend(added);
+                        }
+                    }
+                },
+                _ => self.original_catalog_entries.set("OCProperties", Object::Dictionary(other_groups)),
+            }
+        }
+
+        let inner_doc = &mut self.inner_doc;
+        let renamed_fonts = self.fonts.merge(mem::take(&mut other.fonts), || inner_doc.new_object_id());
+
+        for (index, mut page) in other.pages.into_iter().enumerate() {
+            page.index = page_offset + index;
+            page.object_id = page.object_id.map(|id| (id.0 + id_offset, id.1));
+            for (_, value) in page.original_entries.iter_mut() {
+                offset_object_ids(value, id_offset);
+                rename_destinations(value, &renamed_destinations);
+            }
+            for color_space in page.resources.color_spaces.color_spaces_mut() {
+                offset_object_ids(color_space, id_offset);
+            }
+            for xobject in page.resources.xobjects.objects_mut() {
+                match *xobject {
+                    XObject::Form(ref mut form) => {
+                        if let Some(ref mut resources) = form.resources {
+                            for (_, value) in resources.iter_mut() {
+                                offset_object_ids(value, id_offset);
+                            }
+                        }
+                    },
+                    XObject::External(ref mut stream) => {
+                        for (_, value) in stream.dict.iter_mut() {
+                            offset_object_ids(value, id_offset);
+                        }
+                    },
+                    XObject::Reference(ref mut id) => id.0 += id_offset,
+                    _ => { },
+                }
+            }
+
+            for annotation in &mut page.annotations {
+                match annotation.action {
+                    LinkAction::GoTo(ref mut destination) => { move_destination(destination); },
+                    LinkAction::Named(ref mut name) => {
+                        if let Some(new_name) = renamed_destinations.get(name.as_bytes()) {
+                            *name = String::from_utf8_lossy(new_name).into_owned();
+                        }
+                    },
+                    LinkAction::Uri(_) => { },
+                }
+            }
+
+            visit_font_names(&mut page, |font_name| {
+                let new_name = String::from_utf8(font_name.clone()).ok().and_then(|name| renamed_fonts.get(&name));
+                if let Some(new_name) = new_name {
+                    *font_name = new_name.clone().into_bytes();
+                }
+            });
+            for layer in &mut page.layers {
+                let new_name = layer.current_font.as_ref().and_then(|font| renamed_fonts.get(&font.name));
+                if let Some(new_name) = new_name {
+                    layer.current_font = Some(IndirectFontRef::new(new_name.as_str()));
+                }
+            }
+
+            self.pages.push(page);
+        }
+
+        (page_offset..self.pages.len()).map(PdfPageIndex).collect()
+    }
+
+    /// Removes the fonts that no page uses (in its layers, patterns or transparency groups)
+    pub(crate) fn remove_unused_fonts(&mut self)
+    {
+        let mut used_fonts = BTreeSet::new();
+        for page in &mut self.pages {
+            visit_font_names(page, |font_name| {
+                if let Ok(name) = String::from_utf8(font_name.clone()) {
+                    used_fonts.insert(name);
+                }
+            });
+        }
+
+        self.fonts.retain_used(&used_fonts);
+    }
+}
+
+/// Calls `visit` with the name of each font that is selected (`Tf`) on the page: in the
+/// layers, in tiling patterns and in forms that use the resources of the page (transparency
+/// groups). Forms with their own resources (loaded pages) don't use the fonts of the document
+fn visit_font_names<F>(page: &mut PdfPage, mut visit: F) where F: FnMut(&mut Vec<u8>)
+{
+    /// Returns if a name was changed
+    fn visit_operations<F>(operations: &mut [Operation], visit: &mut F)
+    -> bool where F: FnMut(&mut Vec<u8>)
+    {
+        let mut changed = false;
+        for operation in operations.iter_mut().filter(|operation| operation.operator == "Tf") {
+            if let Some(&mut Object::Name(ref mut font_name)) = operation.operands.first_mut() {
+                let old_name = font_name.clone();
+                visit(font_name);
+                changed |= *font_name != old_name;
+            }
+        }
+        changed
+    }
+
+    for layer in &mut page.layers {
+        visit_operations(&mut layer.operations, &mut visit);
+    }
+
+    for pattern in page.resources.patterns.patterns_mut() {
+        if let Pattern::Tiling(ref mut pattern) = *pattern {
+            visit_operations(&mut pattern.operations, &mut visit);
+        }
+    }
+
+    for xobject in page.resources.xobjects.objects_mut() {
+        let form = match *xobject {
+            XObject::Form(ref mut form) if form.resources.is_none() => form,
+            _ => continue,
+        };
+        if let Ok(mut content) = Content::decode(&form.bytes) {
+            if visit_operations(&mut content.operations, &mut visit) {
+                if let Ok(bytes) = content.encode() {
+                    form.bytes = bytes;
+                }
+            }
+        }
+    }
+}
+
+/// Adds `offset` to the object number of all references in the object
+fn offset_object_ids(object: &mut Object, offset: u32)
+{
+    match *object {
+        Object::Reference(ref mut id) => id.0 += offset,
+        Object::Array(ref mut array) => {
+            for item in array.iter_mut() {
+                offset_object_ids(item, offset);
+            }
+        },
+        Object::Dictionary(ref mut dict) => {
+            for (_, value) in dict.iter_mut() {
+                offset_object_ids(value, offset);
+            }
+        },
+        Object::Stream(ref mut stream) => {
+            for (_, value) in stream.dict.iter_mut() {
+                offset_object_ids(value, offset);
+            }
+        },
+        _ => { },
+    }
+}
+
+#[test]
+fn test_merge_and_reorder() {
+    use {BuiltinFont, Mm, OutlineItem, LinkAnnotation, Rect, Point, Pt};
+
+    let build = |title: &str, pages: usize| {
+        let doc = PdfDocument::empty(title);
+        let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+        for number in 0..pages {
+            let (page, layer) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+            doc.get_page(page).get_layer(layer).use_text(format!("{} {}", title, number), 12.0, Mm(10.0), Mm(10.0), &font);
+            doc.add_bookmark(format!("{} {}", title, number), page);
+        }
+        doc.add_named_destination("start", Destination::page(PdfPageIndex(0)));
+        doc
+    };
+
+    let doc = build("Letter", 2);
+    let appended = doc.append_document(build("Terms", 3));
+    assert_eq!(appended, vec![PdfPageIndex(2), PdfPageIndex(3), PdfPageIndex(4)]);
+    {
+        let inner = lock_document(&doc.document);
+        assert_eq!(inner.fonts.len(), 1);
+        assert_eq!(inner.outline.len(), 5);
+        assert_eq!(inner.named_destinations.get("start-2"), Some(&Destination::page(PdfPageIndex(2))));
+    }
+
+    // a link on the last page to the first page of the terms
+    let rect = Rect { ll: Point { x: Pt(0.0), y: Pt(0.0) }, ur: Point { x: Pt(10.0), y: Pt(10.0) } };
+    doc.get_page(PdfPageIndex(4)).add_link_annotation(LinkAnnotation::new(rect, LinkAction::GoTo(Destination::page(PdfPageIndex(2)))));
+    doc.add_outline_item(None, OutlineItem::new("Terms", Destination::page(PdfPageIndex(2))));
+
+    doc.move_page(PdfPageIndex(4), PdfPageIndex(0)).unwrap();
+    doc.delete_page(PdfPageIndex(3)).unwrap();
+    let copy = doc.duplicate_page(PdfPageIndex(0)).unwrap();
+    assert_eq!(copy, PdfPageIndex(1));
+    assert!(doc.delete_page(PdfPageIndex(10)).is_err());
+
+    {
+        let inner = lock_document(&doc.document);
+        assert_eq!(inner.pages.len(), 5);
+        // the terms page was deleted, its outline items and the link to it are gone
+        assert_eq!(inner.outline.len(), 4);
+        assert!(inner.pages[0].annotations.is_empty());
+        assert!(inner.pages.iter().enumerate().all(|(index, page)| page.index == index));
+    }
+
+    let first_two = doc.extract_pages(..2).unwrap();
+    assert_eq!(first_two.get_pages().len(), 2);
+    assert!(doc.extract_pages(3..9).is_err());
+    assert!(first_two.save_to_bytes().is_ok());
+    assert!(doc.save_to_bytes().is_ok());
+}
+
+#[test]
+fn test_merge_renames_fonts_and_keeps_used_fonts() {
+    use std::io::Cursor;
+    use {BuiltinFont, ExternalFont, Font, GroupXObject, Mm, Rect, TilingPattern};
+
+    let font_bytes = include_bytes!("../assets/fonts/RobotoMedium.ttf").to_vec();
+
+    let doc = PdfDocument::empty("Letter");
+    let (page, layer) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_external_font(Cursor::new(font_bytes.clone())).unwrap();
+    doc.get_page(page).get_layer(layer).use_text("Letter", 12.0, Mm(10.0), Mm(10.0), &font);
+
+    // a different font with the same name, and fonts that are only used by a pattern and a group
+    let other = PdfDocument::empty("Terms");
+    let (other_page, other_layer) = other.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    let mut other_bytes = font_bytes.clone();
+    other_bytes.extend_from_slice(&[0; 4]);
+    let other_font = other.add_external_font(Cursor::new(other_bytes)).unwrap();
+    assert_eq!(other_font.name, font.name);
+    let courier = other.add_builtin_font(BuiltinFont::Courier).unwrap();
+    let times = other.add_builtin_font(BuiltinFont::TimesRoman).unwrap();
+    let page_ref = other.get_page(other_page);
+    let area = Rect::new(Mm(0.0), Mm(0.0), Mm(10.0), Mm(10.0));
+    page_ref.add_tiling_pattern(TilingPattern::new(area, Mm(10.0).into(), Mm(10.0).into()), |cell| {
+        cell.use_text("x", 8.0, Mm(1.0), Mm(1.0), &courier);
+    });
+    page_ref.add_transparency_group(GroupXObject::new(), area, |group| {
+        group.use_text("y", 8.0, Mm(1.0), Mm(1.0), &times);
+        group.use_text("z", 8.0, Mm(1.0), Mm(5.0), &other_font);
+    });
+    let layer_ref = page_ref.get_layer(other_layer);
+    layer_ref.begin_text_section();
+    layer_ref.set_font(&other_font, 12.0);
+
+    doc.append_document(other);
+    let renamed = {
+        let inner = lock_document(&doc.document);
+        assert_eq!(inner.fonts.len(), 4);
+
+        // only the name in the resources changes, not the name of the font itself
+        let (renamed, renamed_font) = inner.fonts.iter()
+            .find(|&(_, direct)| match direct.data { Font::ExternalFont(ref font) => font.font_bytes.len() > font_bytes.len(), _ => false })
+            .unwrap();
+        assert_ne!(renamed.name, font.name);
+        if let Font::ExternalFont(ExternalFont { ref face_name, .. }) = renamed_font.data {
+            assert_eq!(face_name, &font.name);
+        }
+
+        let page = &inner.pages[1];
+        assert_eq!(page.layers[0].current_font.as_ref().map(|font| font.name.as_str()), Some(renamed.name.as_str()));
+        let group = page.resources.xobjects.iter().filter_map(|(_, xobject)| match *xobject {
+            XObject::Form(ref form) => Some(Content::decode(&form.bytes).unwrap()),
+            _ => None,
+        }).next().unwrap();
+        let group_fonts: Vec<&str> = group.operations.iter()
+            .filter(|operation| operation.operator == "Tf")
+            .map(|operation| operation.operands[0].as_name_str().unwrap())
+            .collect();
+        assert_eq!(group_fonts, vec![times.name.as_str(), renamed.name.as_str()]);
+        renamed.clone()
+    };
+
+    // fonts that are only used in patterns or groups are kept
+    let terms = doc.extract_pages(1..).unwrap();
+    {
+        let inner = lock_document(&terms.document);
+        let mut names: Vec<&str> = inner.fonts.iter().map(|(font_ref, _)| font_ref.name.as_str()).collect();
+        names.sort();
+        let mut expected = vec![courier.name.as_str(), times.name.as_str(), renamed.name.as_str()];
+        expected.sort();
+        assert_eq!(names, expected);
+    }
+    assert_eq!(lock_document(&doc.extract_pages(..1).unwrap().document).fonts.len(), 1);
+    assert!(lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).is_ok());
+}
+
+#[test]
+fn test_merge_and_extract_loaded_documents() {
+    use std::iter::FromIterator;
+    use {LinkAnnotation, Mm, OutlineItem, Point, Pt, Rect};
+
+    let rect = Rect { ll: Point { x: Pt(0.0), y: Pt(0.0) }, ur: Point { x: Pt(10.0), y: Pt(10.0) } };
+
+    // a loaded document with an outline item and a named destination for every page, the
+    // first page has links to all named destinations and the form has one field
+    let load = |title: &str, pages: usize| {
+        let doc = PdfDocument::empty(title);
+        for number in 0..pages {
+            let (page, _) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+            doc.add_outline_item(None, OutlineItem::new(format!("{} {}", title, number), Destination::page(page)));
+            doc.add_named_destination(format!("page-{}", number), Destination::page(page));
+            doc.get_page(PdfPageIndex(0)).add_link_annotation(LinkAnnotation::new(rect, LinkAction::Named(format!("page-{}", number))));
+        }
+        let loaded = PdfDocument::load_from_bytes(&doc.save_to_bytes().unwrap()).unwrap();
+        {
+            let mut inner = lock_document(&loaded.document);
+            let field = inner.inner_doc.add_object(lopdf::Dictionary::from_iter(vec![("T", Object::string_literal(title))]));
+            let form = lopdf::Dictionary::from_iter(vec![("Fields", Object::Array(vec![Object::Reference(field)]))]);
+            inner.original_catalog_entries.set("AcroForm", Object::Dictionary(form));
+        }
+        loaded
+    };
+
+    let names = |doc: &PdfDocumentReference| {
+        let inner = lock_document(&doc.document);
+        let names = LoadedNames::read(&inner.inner_doc, &inner.original_catalog_entries).destination_names();
+        names.into_iter().map(|name| String::from_utf8(name).unwrap()).collect::<Vec<_>>()
+    };
+    let link_targets = |doc: &PdfDocumentReference, page: usize| {
+        let inner = lock_document(&doc.document);
+        let annotations = inner.pages[page].original_entries.get(b"Annots").unwrap().as_array().unwrap();
+        annotations.iter().map(|annotation| {
+            link_target(&inner.inner_doc, inner.inner_doc.get_dictionary(annotation.as_reference().unwrap()).unwrap())
+        }).collect::<Vec<_>>()
+    };
+    let outline_count = |bytes: &[u8]| {
+        let saved = lopdf::Document::load_mem(bytes).unwrap();
+        let outline_id = saved.catalog().unwrap().get(b"Outlines").unwrap().as_reference().unwrap();
+        saved.get_dictionary(outline_id).unwrap().get(b"Count").unwrap().as_i64().unwrap()
+    };
+    let name = |name: &str| LoadedTarget::Name(name.as_bytes().to_vec());
+
+    // the named destinations of the terms that are taken are renamed, together with the links to them
+    let doc = load("Letter", 2);
+    doc.append_document(load("Terms", 3));
+    assert_eq!(names(&doc), vec!["page-0", "page-0-2", "page-1", "page-1-2", "page-2"]);
+    assert_eq!(link_targets(&doc, 2), vec![name("page-0-2"), name("page-1-2"), name("page-2")]);
+    {
+        let inner = lock_document(&doc.document);
+        let form = inner.original_catalog_entries.get(b"AcroForm").unwrap().as_dict().unwrap();
+        assert_eq!(form.get(b"Fields").unwrap().as_array().unwrap().len(), 2);
+    }
+
+    doc.add_outline_item(None, OutlineItem::new("Appendix", Destination::page(PdfPageIndex(4))));
+    doc.add_named_destination("appendix", Destination::page(PdfPageIndex(4)));
+    doc.get_page(PdfPageIndex(0)).add_link_annotation(LinkAnnotation::new(rect, LinkAction::Named("page-2".into())));
+    doc.get_page(PdfPageIndex(0)).add_link_annotation(LinkAnnotation::new(rect, LinkAction::Named("page-1".into())));
+
+    // the outline items, named destinations and links of the removed pages are removed
+    let extracted = doc.extract_pages(..3).unwrap();
+    assert_eq!(names(&extracted), vec!["page-0", "page-0-2", "page-1"]);
+    assert_eq!(link_targets(&extracted, 2), vec![name("page-0-2")]);
+    {
+        let inner = lock_document(&extracted.document);
+        assert!(inner.named_destinations.is_empty());
+        assert!(inner.outline.is_empty());
+        assert_eq!(inner.pages[0].annotations.len(), 1);
+    }
+    assert_eq!(outline_count(&extracted.save_to_bytes().unwrap()), 3);
+
+    // the loaded outline items come before the added outline item
+    let saved = doc.save_to_bytes().unwrap();
+    assert_eq!(outline_count(&saved), 6);
+    let reloaded = PdfDocument::load_from_bytes(&saved).unwrap();
+    assert_eq!(names(&reloaded), vec!["appendix", "page-0", "page-0-2", "page-1", "page-1-2", "page-2"]);
+}
@@ -3,10 +3,10 @@
 use lopdf;
 use crate::OffsetDateTime;
 use {
-	IccProfileType, PdfConformance, XmpMetadata, DocumentInfo, IccProfile
+	PdfConformance, XmpMetadata, DocumentInfo, IccProfile, OutputIntent, OutputIntentProfile, OutputIntentSubtype
 };
 
-use glob_defines::ICC_PROFILE_ECI_V2;
+use utils::random_character_string_32;
 
 /// This is a wrapper in order to keep shared data between the documents XMP metadata and
 /// the "Info" dictionary in sync
@@ -42,13 +42,19 @@ pub struct PdfMetadata {
 	pub xmp_metadata: XmpMetadata,
 	/// PDF Info dictionary. Contains metadata for this document
 	pub document_info: DocumentInfo,
-	/// Target color profile
+	/// Output intent (the printing condition and its ICC profile). If the conformance
+	/// requires one and it is `None`, FOGRA39 (coated offset paper) is used
+	pub output_intent: Option<OutputIntent>,
+	/// Target color profile. Only used if `output_intent` is `None`, the profile replaces
+	/// the one of the FOGRA39 output intent
+	#[deprecated(note = "use `output_intent` (`PdfDocumentReference::with_output_intent`), which also sets the printing condition")]
 	pub target_icc_profile: Option<IccProfile>,
 }
 
 impl PdfMetadata {
 
 	/// Creates a new metadata object
+	#[allow(deprecated)]
 	pub fn new<S>(title: S, document_version: u32, trapping: bool, conformance: PdfConformance)
 	-> Self where S: Into<String>
 	{
@@ -70,38 +76,58 @@ impl PdfMetadata {
 			conformance: conformance,
 			xmp_metadata: XmpMetadata::new(Some("default".into()), 1),
 			document_info: DocumentInfo::new(),
+			output_intent: None,
 			target_icc_profile: None,
 		}
 	}
 
-	/// Consumes the metadata, returning the (Option<xmp_metadata>, document_info, icc_profile_stream).
+	/// Consumes the metadata, returning the (Option<xmp_metadata>, document_info, output_intent).
 	pub fn into_obj(self)
-	-> (Option<lopdf::Object>, lopdf::Object, Option<IccProfile>)
+	-> (Option<lopdf::Object>, lopdf::Object, Option<OutputIntent>)
 	{
-		let metadata = self.clone();
-		let xmp_obj = {
-			if self.conformance.must_have_xmp_metadata() {
-				Some(self.xmp_metadata.into_obj(&metadata))
-			} else {
-				None
-			}
-		};
+		let xmp_obj = self.xmp_metadata_obj(&random_character_string_32());
+		let doc_info_obj = self.document_info_obj();
+		let output_intent = self.output_intent();
+		(xmp_obj, doc_info_obj, output_intent)
+	}
 
-		let doc_info_obj = self.document_info.into_obj(&metadata);
-		// add icc profile if necessary
-		let icc_profile = {
-		    if self.conformance.must_have_icc_profile() {
-		        match self.target_icc_profile {
-		            Some(icc) => Some(icc),
-		            None =>      Some(IccProfile::new(ICC_PROFILE_ECI_V2.to_vec(), IccProfileType::Cmyk)
-		            			      .with_alternate_profile(false)
-		            			      .with_range(true)),
-		        }
-		    } else {
-		        None
-		    }
-		};
+	/// The XMP metadata stream, if the conformance requires it
+	pub(crate) fn xmp_metadata_obj(&self, instance_id: &str)
+	-> Option<lopdf::Object>
+	{
+		if self.conformance.must_have_xmp_metadata() {
+			Some(self.xmp_metadata.clone().into_obj(self, instance_id))
+		} else {
+			None
+		}
+	}
 
-		(xmp_obj, doc_info_obj, icc_profile)
+	/// The "Info" dictionary
+	pub(crate) fn document_info_obj(&self)
+	-> lopdf::Object
+	{
+		self.document_info.into_obj(self)
+	}
+
+	/// The output intent, if the conformance requires it
+	#[allow(deprecated)]
+	pub(crate) fn output_intent(&self)
+	-> Option<OutputIntent>
+	{
+		if self.conformance.must_have_icc_profile() {
+			match self.output_intent {
+				Some(ref output_intent) => Some(output_intent.clone()),
+				None => {
+					let subtype = self.conformance.get_output_intent_subtype().unwrap_or(OutputIntentSubtype::PdfX);
+					let mut output_intent = OutputIntent::fogra39(subtype);
+					if let Some(ref profile) = self.target_icc_profile {
+						output_intent.profile = OutputIntentProfile::Embedded(profile.clone());
+					}
+					Some(output_intent)
+				},
+			}
+		} else {
+			None
+		}
 	}
 }
@@ -1,13 +1,15 @@
 //! PDF page management
 
 use lopdf;
-use std::rc::Weak;
-use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, Weak};
 
 use indices::{PdfPageIndex, PdfLayerIndex};
+use pdf_document::lock_document;
 use {
     PdfResources, PdfLayer, PdfDocument, ExtendedGraphicsState, ExtendedGraphicsStateRef, Pattern, XObject, XObjectRef,
-    PdfLayerReference, PatternRef, Mm, Pt
+    PdfLayerReference, PatternRef, Mm, Pt, LinkAnnotation, TilingPattern, ShadingPattern,
+    GroupXObject, FormXObject, Rect
 };
 
 /// PDF page
@@ -23,13 +25,26 @@ pub struct PdfPage {
     pub layers: Vec<PdfLayer>,
     /// Resources used in this page
     pub(crate) resources: PdfResources,
+    /// Links on this page
+    pub(crate) annotations: Vec<LinkAnnotation>,
+    /// Clockwise rotation of the page when it is displayed, in degrees (multiple of 90)
+    pub(crate) rotation: i64,
+    /// Object ID of the page in a loaded document. The page keeps its ID, so
+    /// that existing links and outline items still point to it
+    pub(crate) object_id: Option<lopdf::ObjectId>,
+    /// Entries of the page dictionary of a loaded page (page boxes, annotations, ...),
+    /// which are written as they are
+    pub(crate) original_entries: lopdf::Dictionary,
+    /// Layers that patterns and transparency groups are drawn on (see `draw_on_temporary_layer`),
+    /// by the index of their reference. They are not part of `layers` and are never written
+    pub(crate) temporary_layers: BTreeMap<usize, PdfLayer>,
 }
 
-/// A "reference" to the current page, allows for inner mutability
+/// A "reference" to the current page, allows for inner mutability (and can be sent to other threads)
 /// but only inside this library
 pub struct PdfPageReference {
     /// A weak reference to the document, for inner mutability
-    pub document: Weak<RefCell<PdfDocument>>,
+    pub document: Weak<Mutex<PdfDocument>>,
     /// The index of the page this layer is on
     pub page: PdfPageIndex,
 }
@@ -51,6 +66,11 @@ impl PdfPage {
             height: height.into(),
             layers: Vec::new(),
             resources: PdfResources::new(),
+            annotations: Vec::new(),
+            rotation: 0,
+            object_id: None,
+            original_entries: lopdf::Dictionary::new(),
+            temporary_layers: BTreeMap::new(),
         };
 
         let initial_layer = PdfLayer::new(layer_name);
@@ -61,6 +81,27 @@ impl PdfPage {
         (page, PdfLayerIndex(layer_index))
     }
 
+    /// Returns the layer that a `PdfLayerReference` with the index draws on (a layer
+    /// of the page or a temporary layer), panics if there is no such layer
+    pub(crate) fn layer(&self, layer: PdfLayerIndex)
+    -> &PdfLayer
+    {
+        match self.temporary_layers.get(&layer.0) {
+            Some(temporary_layer) => temporary_layer,
+            None => &self.layers[layer.0],
+        }
+    }
+
+    /// See `layer`
+    pub(crate) fn layer_mut(&mut self, layer: PdfLayerIndex)
+    -> &mut PdfLayer
+    {
+        match self.temporary_layers.get_mut(&layer.0) {
+            Some(temporary_layer) => temporary_layer,
+            None => &mut self.layers[layer.0],
+        }
+    }
+
     /// Iterates through the layers attached to this page and gathers all resources,
     /// which the layers need. Then returns a dictonary with all the resources
     /// (fonts, image XObjects, etc.)
@@ -71,13 +112,14 @@ impl PdfPage {
     ///
     /// `layers` should be a Vec with all layers (optional content groups) that were added
     /// to the document on a document level, it should contain the indices of the layers
-    /// (they will be ignored, todo) and references to the actual OCG dictionaries
+    /// (they will be ignored, todo) and references to the actual OCG dictionaries.
+    /// `resources_id` is the ID the resource dictionary will be stored under
     #[inline]
-    pub(crate) fn collect_resources_and_streams(self, doc: &mut lopdf::Document, layers: &[(usize, lopdf::Object)])
+    pub(crate) fn collect_resources_and_streams(self, doc: &mut lopdf::Document, resources_id: lopdf::ObjectId, layers: &[(usize, lopdf::Object)])
     -> (lopdf::Dictionary, Vec<lopdf::Stream>)
     {
         let cur_layers = layers.iter().map(|l| l.1.clone()).collect();
-        let (resource_dictionary, ocg_refs) = self.resources.into_with_document_and_layers(doc, cur_layers);
+        let (resource_dictionary, ocg_refs) = self.resources.into_with_document_and_layers(doc, resources_id, cur_layers);
 
         // set contents
         let mut layer_streams = Vec::<lopdf::Stream>::new();
@@ -87,15 +129,21 @@ impl PdfPage {
         for (idx, mut layer) in self.layers.into_iter().enumerate() {
 
             // push OCG and q to the beginning of the layer
+            // (without OCG if the layer is not optional content)
+            let ocg_ref = layers.iter().position(|l| l.0 == idx).and_then(|position| ocg_refs.get(position));
             layer.operations.insert(0, Operation::new("q".into(), vec![]));
-            layer.operations.insert(0, Operation::new("BDC".into(), vec![
-                Name("OC".into()),
-                Name(ocg_refs[idx].name.clone().into())
-            ]));
+            if let Some(ocg_ref) = ocg_ref {
+                layer.operations.insert(0, Operation::new("BDC".into(), vec![
+                    Name("OC".into()),
+                    Name(ocg_ref.name.clone().into())
+                ]));
+            }
 
             // push OCG END and Q to the end of the layer stream
             layer.operations.push(Operation::new("Q".into(), vec![]));
-            layer.operations.push(Operation::new("EMC".into(), vec![]));
+            if ocg_ref.is_some() {
+                layer.operations.push(Operation::new("EMC".into(), vec![]));
+            }
 
             // should end up looking like this:
 
@@ -125,7 +173,7 @@ impl PdfPage {
         self.resources.add_graphics_state(added_state)
     }
 
-    /// __STUB__: Adds a pattern to the pages resources
+    /// Adds a pattern to the pages resources
     #[inline]
     pub fn add_pattern(&mut self, pattern: Pattern)
     -> PatternRef
@@ -151,7 +199,7 @@ impl PdfPageReference {
     -> PdfLayerReference where S: Into<String>
     {
         let doc = self.document.upgrade().unwrap();
-        let mut doc = doc.borrow_mut();
+        let mut doc = lock_document(&doc);
         let page = &mut doc.pages[self.page.0];
 
         let current_page_index = page.layers.len(); /* order is important */
@@ -166,6 +214,156 @@ impl PdfPageReference {
         }
     }
 
+    /// Adds a tiling pattern to the page. The cell of the pattern is drawn by `draw_cell`
+    /// on a temporary layer, which is not one of the layers of the page.
+    /// The cell can use all fonts of the document and all resources of the page
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// let (doc, page1, layer1) = PdfDocument::new("Hatches", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let page = doc.get_page(page1);
+    /// let hatch = page.add_tiling_pattern(TilingPattern::new(Rect::new(Mm(0.0), Mm(0.0), Mm(2.0), Mm(2.0)), Mm(2.0).into(), Mm(2.0).into()), |cell| {
+    ///     cell.set_outline_thickness(0.5);
+    ///     cell.add_shape(Line {
+    ///         points: vec![(Point::new(Mm(0.0), Mm(0.0)), false), (Point::new(Mm(2.0), Mm(2.0)), false)],
+    ///         is_closed: false, has_fill: false, has_stroke: true, is_clipping_path: false,
+    ///     });
+    /// });
+    /// page.get_layer(layer1).set_fill_pattern(&hatch, None);
+    /// ```
+    pub fn add_tiling_pattern<F>(&self, mut pattern: TilingPattern, draw_cell: F)
+    -> PatternRef where F: FnOnce(&PdfLayerReference)
+    {
+        pattern.operations = self.draw_on_temporary_layer(draw_cell);
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        doc.pages[self.page.0].add_pattern(Pattern::Tiling(pattern))
+    }
+
+    /// Adds a transparency group (a form XObject with group attributes) to the page.
+    /// Like with `add_tiling_pattern`, the contents of the group are drawn by `draw_group`.
+    /// `bbox` is the area of the page that the group covers.
+    ///
+    /// The group can be painted with `PdfLayerReference::use_transparency_group`
+    /// or be used as a `SoftMask`
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// let (doc, page1, layer1) = PdfDocument::new("Fade", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let page = doc.get_page(page1);
+    /// let area = Rect::new(Mm(10.0), Mm(10.0), Mm(100.0), Mm(100.0));
+    /// let fade = page.add_shading_pattern(ShadingPattern::new(
+    ///     ShadingType::Axial { start: area.ll, end: area.ur },
+    ///     vec![ColorStop::new(0.0, Color::Greyscale(Greyscale::new(1.0, None))),
+    ///          ColorStop::new(1.0, Color::Greyscale(Greyscale::new(0.0, None)))],
+    /// ));
+    /// let mask = page.add_transparency_group(GroupXObject::new().with_color_space(ColorSpace::Greyscale), area, |group| {
+    ///     group.set_fill_pattern(&fade, None);
+    ///     group.add_shape(Line {
+    ///         points: vec![(area.ll, false), (Point { x: area.ur.x, y: area.ll.y }, false),
+    ///                      (area.ur, false), (Point { x: area.ll.x, y: area.ur.y }, false)],
+    ///         is_closed: true, has_fill: true, has_stroke: false, is_clipping_path: false,
+    ///     });
+    /// });
+    /// page.get_layer(layer1).set_soft_mask(Some(SoftMask::new(SoftMaskFunction::GroupLuminosity, mask)));
+    /// ```
+    pub fn add_transparency_group<F>(&self, group: GroupXObject, bbox: Rect, draw_group: F)
+    -> XObjectRef where F: FnOnce(&PdfLayerReference)
+    {
+        let operations = self.draw_on_temporary_layer(draw_group);
+        let content = lopdf::content::Content { operations };
+
+        let form = FormXObject {
+            group: Some(group),
+            .. FormXObject::new(content.encode().unwrap(), bbox)
+        };
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        doc.pages[self.page.0].add_xobject(XObject::Form(Box::new(form)))
+    }
+
+    /// Adds a gradient to the page
+    #[inline]
+    pub fn add_shading_pattern(&self, pattern: ShadingPattern)
+    -> PatternRef
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        doc.pages[self.page.0].add_pattern(Pattern::Shading(pattern))
+    }
+
+    /// Adds a link (clickable rectangle) to the page
+    #[inline]
+    pub fn add_link_annotation(&self, annotation: LinkAnnotation)
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        doc.pages[self.page.0].annotations.push(annotation);
+    }
+
+    /// Lets `draw` paint on a new layer, then returns the operations that were added to it.
+    /// The layer is kept outside of `layers`, so that the indices of the layers that are
+    /// added in the meantime (by other threads) don't change when it is removed
+    fn draw_on_temporary_layer<F>(&self, draw: F)
+    -> Vec<lopdf::content::Operation> where F: FnOnce(&PdfLayerReference)
+    {
+        let temporary_layer = {
+            let doc = self.document.upgrade().unwrap();
+            let mut doc = lock_document(&doc);
+            let page = &mut doc.pages[self.page.0];
+
+            // counted down from the end, so that the index is never the one of a layer in `layers`
+            let index = page.temporary_layers.keys().next().map(|first| first - 1).unwrap_or(usize::MAX);
+            page.temporary_layers.insert(index, PdfLayer::new("Temporary"));
+
+            PdfLayerReference {
+                document: self.document.clone(),
+                page: self.page,
+                layer: PdfLayerIndex(index),
+            }
+        };
+        draw(&temporary_layer);
+
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        doc.pages[self.page.0].temporary_layers.remove(&temporary_layer.layer.0).unwrap().operations
+    }
+
+    /// Returns the width and height of the page
+    #[inline]
+    pub fn get_size(&self)
+    -> (Mm, Mm)
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = lock_document(&doc);
+        let page = &doc.pages[self.page.0];
+        (page.width.into(), page.height.into())
+    }
+
+    /// Returns the clockwise rotation of the page in degrees (0, 90, 180 or 270)
+    #[inline]
+    pub fn get_rotation(&self)
+    -> i64
+    {
+        let doc = self.document.upgrade().unwrap();
+        let doc = lock_document(&doc);
+        doc.pages[self.page.0].rotation
+    }
+
+    /// Rotates the page clockwise when it is displayed or printed, `degrees`
+    /// must be a multiple of 90. The content of the page is not changed
+    #[inline]
+    pub fn set_rotation(&self, degrees: i64)
+    {
+        let doc = self.document.upgrade().unwrap();
+        let mut doc = lock_document(&doc);
+        doc.pages[self.page.0].rotation = degrees.rem_euclid(360);
+    }
+
     /// Validates that a layer is present and returns a reference to it
     #[inline]
     #[cfg_attr(feature = "cargo-clippy", allow(no_effect))]
@@ -173,7 +371,7 @@ impl PdfPageReference {
     -> PdfLayerReference
     {
         let doc = self.document.upgrade().unwrap();
-        let doc = doc.borrow();
+        let doc = lock_document(&doc);
 
         let _ = &doc.pages[self.page.0].layers[layer.0];
 
@@ -184,3 +382,83 @@ impl PdfPageReference {
         }
     }
 }
+
+#[test]
+fn test_transparency_group_and_soft_mask() {
+    use lopdf::Object;
+    use {PdfDocument, Mm, Color, Greyscale, ColorSpace, SoftMask, SoftMaskFunction};
+
+    let (doc, page1, layer1) = PdfDocument::new("Soft mask", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page1);
+    let area = Rect::new(Mm(10.0), Mm(10.0), Mm(100.0), Mm(100.0));
+    let group = GroupXObject::new().with_color_space(ColorSpace::Greyscale).with_isolated(true);
+    let mask = page.add_transparency_group(group, area, |group| {
+        group.set_fill_color(Color::Greyscale(Greyscale::new(0.5, None)));
+    });
+    let layer = page.get_layer(layer1);
+    layer.set_soft_mask(Some(SoftMask::new(SoftMaskFunction::GroupLuminosity, mask.clone())
+        .with_backdrop(Color::Greyscale(Greyscale::new(1.0, None)))));
+    layer.use_transparency_group(&mask);
+    layer.set_soft_mask(None);
+
+    let doc = lopdf::Document::load_mem(&doc.save_to_bytes().unwrap()).unwrap();
+    let page_id = doc.get_pages()[&1];
+    let resources = doc.get_dictionary(page_id).unwrap().get(b"Resources").unwrap();
+    let resources = match *resources {
+        Object::Reference(id) => doc.get_dictionary(id).unwrap(),
+        ref object => object.as_dict().unwrap(),
+    };
+
+    // the form has a /Group dictionary with the attributes of the group
+    let xobject_id = resources.get(b"XObject").unwrap().as_dict().unwrap()
+        .get(mask.name.as_bytes()).unwrap().as_reference().unwrap();
+    let form = doc.get_object(xobject_id).unwrap().as_stream().unwrap();
+    let bbox: Vec<f64> = form.dict.get(b"BBox").unwrap().as_array().unwrap().iter()
+        .map(|value| value.as_f64().unwrap()).collect();
+    assert_eq!(bbox.len(), 4);
+    assert!((bbox[2] - bbox[0] - Pt::from(Mm(100.0)).0).abs() < 0.01);
+    let group = form.dict.get(b"Group").unwrap().as_dict().unwrap();
+    assert_eq!(group.get(b"S").unwrap().as_name_str().unwrap(), "Transparency");
+    assert_eq!(group.get(b"CS").unwrap().as_name_str().unwrap(), "DeviceGray");
+    assert!(group.get(b"I").unwrap().as_bool().unwrap());
+    assert!(!group.get(b"K").unwrap().as_bool().unwrap());
+
+    // one graphics state sets the mask, the other one removes it
+    let graphics_states = resources.get(b"ExtGState").unwrap().as_dict().unwrap();
+    let soft_masks: Vec<&Object> = graphics_states.iter()
+        .map(|(_, state)| state.as_dict().unwrap().get(b"SMask").unwrap())
+        .collect();
+    assert_eq!(soft_masks.len(), 2);
+    assert!(soft_masks.iter().any(|soft_mask| soft_mask.as_name_str().ok() == Some("None")));
+    let soft_mask = soft_masks.iter().filter_map(|soft_mask| soft_mask.as_dict().ok()).next().unwrap();
+    assert_eq!(soft_mask.get(b"Type").unwrap().as_name_str().unwrap(), "Mask");
+    assert_eq!(soft_mask.get(b"S").unwrap().as_name_str().unwrap(), "Luminosity");
+    assert_eq!(soft_mask.get(b"G").unwrap().as_reference().unwrap(), xobject_id);
+    assert_eq!(soft_mask.get(b"BC").unwrap().as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_layers_added_while_drawing_a_group() {
+    use pdf_document::lock_document;
+    use {PdfDocument, Mm, Color, Greyscale};
+
+    let (doc, page1, _) = PdfDocument::new("Groups", Mm(210.0), Mm(297.0), "Layer 1");
+    let page = doc.get_page(page1);
+    let area = Rect::new(Mm(10.0), Mm(10.0), Mm(100.0), Mm(100.0));
+
+    // a layer that is added while the group is drawn (as by another thread) keeps its index
+    let mut added = None;
+    page.add_transparency_group(GroupXObject::new(), area, |group| {
+        added = Some(page.add_layer("Layer 2"));
+        group.set_fill_color(Color::Greyscale(Greyscale::new(0.5, None)));
+    });
+    let added = added.unwrap();
+    added.set_fill_color(Color::Greyscale(Greyscale::new(0.0, None)));
+
+    let document = lock_document(&doc.document);
+    let page = &document.pages[page1.0];
+    assert!(page.temporary_layers.is_empty());
+    let names: Vec<&str> = page.layers.iter().map(|layer| layer.name.as_str()).collect();
+    assert_eq!(names, vec!["Layer 1", "Layer 2"]);
+    assert_eq!(page.layers[added.layer.0].operations.len(), 1);
+}
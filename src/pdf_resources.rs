@@ -2,7 +2,7 @@ use lopdf;
 use {
     XObject, Pattern, ExtendedGraphicsState, ExtendedGraphicsStateList, 
     PatternRef, OCGRef, XObjectList, XObjectRef, ExtendedGraphicsStateRef,
-    OCGList, PatternList
+    OCGList, PatternList, ColorSpaceList
 };
 
 /// Struct for storing the PDF Resources, to be used on a PDF page
@@ -10,12 +10,14 @@ use {
 pub struct PdfResources {
     /// External graphics objects
     pub xobjects: XObjectList,
-    /// Patterns used on this page. Do not yet, use, placeholder.
+    /// Patterns used on this page
     pub patterns: PatternList,
     /// Graphics states used on this page
     pub graphics_states: ExtendedGraphicsStateList,
     /// Layers / optional content ("Properties") in the resource dictionary
     pub layers: OCGList,
+    /// ICC-based and CIE-based color spaces used on this page
+    pub color_spaces: ColorSpaceList,
 }
 
 impl PdfResources {
@@ -43,7 +45,7 @@ impl PdfResources {
         self.xobjects.add_xobject(xobj)
     }
 
-    /// __STUB__: Adds a pattern to the resources, to be used like a color
+    /// Adds a pattern to the resources, to be used like a color
     #[inline]
     pub fn add_pattern(&mut self, pattern: Pattern)
     -> PatternRef
@@ -53,9 +55,10 @@ impl PdfResources {
 
     /// See `XObject::Into_with_document`.
     /// The resources also need access to the layers (the optional content groups), this should be a
-    /// `Vec<lopdf::Object::Reference>` (to the actual OCG groups, which are added on the document level)
+    /// `Vec<lopdf::Object::Reference>` (to the actual OCG groups, which are added on the document level).
+    /// `resources_id` is the ID that the returned dictionary will be stored under, tiling patterns refer to it
     #[cfg_attr(feature = "cargo-clippy", allow(needless_return))]
-    pub fn into_with_document_and_layers(self, doc: &mut lopdf::Document, layers: Vec<lopdf::Object>)
+    pub fn into_with_document_and_layers(self, doc: &mut lopdf::Document, resources_id: lopdf::ObjectId, layers: Vec<lopdf::Object>)
     -> (lopdf::Dictionary, Vec<OCGRef>)
     {
             let mut dict = lopdf::Dictionary::new();
@@ -63,9 +66,13 @@ impl PdfResources {
             let mut ocg_dict = self.layers;
             let mut ocg_references = Vec::<OCGRef>::new();
 
-            let xobjects_dict: lopdf::Dictionary = self.xobjects.into_with_document(doc);
-            let patterns_dict: lopdf::Dictionary = self.patterns.into();
-            let graphics_state_dict: lopdf::Dictionary = self.graphics_states.into();
+            let xobjects_dict: lopdf::Dictionary = self.xobjects.into_with_document(doc, resources_id);
+            let mut color_spaces_dict = self.patterns.color_spaces();
+            for (name, color_space) in self.color_spaces.color_spaces {
+                color_spaces_dict.set(name, color_space);
+            }
+            let patterns_dict: lopdf::Dictionary = self.patterns.into_with_document(doc, resources_id);
+            let graphics_state_dict = self.graphics_states.into_with_xobjects(&xobjects_dict);
 
             if !layers.is_empty() {
 
@@ -88,6 +95,10 @@ impl PdfResources {
                 dict.set("Pattern", lopdf::Object::Dictionary(patterns_dict));
             }
 
+            if color_spaces_dict.len() > 0 {
+                dict.set("ColorSpace", lopdf::Object::Dictionary(color_spaces_dict));
+            }
+
             if graphics_state_dict.len() > 0 {
                 dict.set("ExtGState", lopdf::Object::Dictionary(graphics_state_dict));
             }
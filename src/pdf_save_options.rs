@@ -0,0 +1,292 @@
+//! Options for saving a PDF document (compression and clean-up of unused objects)
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use lopdf;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use object_streams::write_indirect_object;
+use utils::ContentHash;
+
+/// Options for `PdfDocumentReference::save_with_options`.
+///
+/// The default options compress all streams except fonts and the XMP metadata and remove
+/// unused objects. With the `less-optimization` feature, the defaults are the same as
+/// `PdfSaveOptions::uncompressed()`, which is easier to read when debugging a PDF file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PdfSaveOptions {
+    /// Flate compression level from 0 (no compression) to 9 (smallest file). Default: 6
+    pub compression_level: u32,
+    /// Compress page contents, form XObjects, patterns and other content streams. Default: true
+    pub compress_content: bool,
+    /// Compress images that aren't compressed yet (JPEG and PNG data is
+    /// embedded as it is, see `ImageXObject::from_jpeg`). Default: true
+    pub compress_images: bool,
+    /// Compress embedded font files. Default: false
+    pub compress_fonts: bool,
+    /// Compress embedded ICC profiles. Default: true
+    pub compress_icc_profiles: bool,
+    /// Remove objects that aren't referenced from the document. Default: true
+    pub prune_objects: bool,
+    /// Remove streams without content. Default: true
+    pub delete_zero_length_streams: bool,
+    /// Pack all objects except streams into compressed object streams and write a
+    /// cross-reference stream instead of the cross-reference table. Raises the version
+    /// of the document to PDF 1.5, ignored if the conformance of the document is based on
+    /// an older version (PDF/A-1, PDF/X-1a, PDF/X-3, ...). Default: false
+    pub object_streams: bool,
+    /// Derive the document ID (trailer `/ID`, XMP document and instance ID) from a hash
+    /// of the document content instead of creating random IDs, so that saving the same
+    /// document twice produces the same bytes. The dates of the document have to be
+    /// fixed as well, see `PdfDocumentReference::with_fixed_date`. Default: false
+    pub reproducible: bool,
+    /// Derive the reproducible IDs from this seed instead of the content. Default: None
+    pub id_seed: Option<u64>,
+}
+
+impl Default for PdfSaveOptions {
+    #[cfg(not(feature = "less-optimization"))]
+    fn default()
+    -> Self
+    {
+        Self {
+            compression_level: 6,
+            compress_content: true,
+            compress_images: true,
+            compress_fonts: false,
+            compress_icc_profiles: true,
+            prune_objects: true,
+            delete_zero_length_streams: true,
+            object_streams: false,
+            reproducible: false,
+            id_seed: None,
+        }
+    }
+
+    #[cfg(feature = "less-optimization")]
+    fn default()
+    -> Self
+    {
+        Self::uncompressed()
+    }
+}
+
+/// Kind of a stream in the document, to decide if it should be compressed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StreamKind {
+    Content,
+    Image,
+    Font,
+    IccProfile,
+    Metadata,
+}
+
+impl PdfSaveOptions {
+
+    /// Creates the default options
+    #[inline]
+    pub fn new()
+    -> Self
+    {
+        Self::default()
+    }
+
+    /// Options that leave the document as it is: no compression, no removal of objects
+    pub fn uncompressed()
+    -> Self
+    {
+        Self {
+            compression_level: 0,
+            compress_content: false,
+            compress_images: false,
+            compress_fonts: false,
+            compress_icc_profiles: false,
+            prune_objects: false,
+            delete_zero_length_streams: false,
+            object_streams: false,
+            reproducible: false,
+            id_seed: None,
+        }
+    }
+
+    /// Sets the Flate compression level (0 - 9)
+    #[inline]
+    pub fn with_compression_level(mut self, compression_level: u32)
+    -> Self
+    {
+        self.compression_level = compression_level.min(9);
+        self
+    }
+
+    /// Writes the document with object streams and a cross-reference stream (PDF 1.5)
+    #[inline]
+    pub fn with_object_streams(mut self, object_streams: bool)
+    -> Self
+    {
+        self.object_streams = object_streams;
+        self
+    }
+
+    /// Derives the document IDs from the content of the document (see `reproducible`)
+    #[inline]
+    pub fn with_reproducible_ids(mut self, reproducible: bool)
+    -> Self
+    {
+        self.reproducible = reproducible;
+        self
+    }
+
+    /// Derives the document IDs from `seed` (turns on `reproducible`)
+    #[inline]
+    pub fn with_id_seed(mut self, seed: u64)
+    -> Self
+    {
+        self.reproducible = true;
+        self.id_seed = Some(seed);
+        self
+    }
+
+    /// Returns the document ID and the instance ID if the IDs are reproducible.
+    /// Without a seed, they are derived from `written_content` (the hash of the objects
+    /// that were already written) and the remaining `objects` of the document.
+    pub(crate) fn document_ids(&self, written_content: ContentHash, objects: &BTreeMap<lopdf::ObjectId, lopdf::Object>)
+    -> Option<(String, String)>
+    {
+        if !self.reproducible {
+            return None;
+        }
+
+        let mut hash = match self.id_seed {
+            Some(seed) => {
+                let mut hash = ContentHash::new();
+                hash.write(&seed.to_be_bytes());
+                hash
+            },
+            None => {
+                let mut hash = written_content;
+                let mut buffer = Vec::new();
+                for (&id, object) in objects {
+                    buffer.clear();
+                    // writing to a Vec<u8> can't fail
+                    let _ = write_indirect_object(&mut buffer, id, object);
+                    hash.write(&buffer);
+                }
+                hash
+            },
+        };
+
+        let document_id = hash.to_hex_string();
+        hash.write(b"instance");
+        Some((document_id, hash.to_hex_string()))
+    }
+
+    /// Removes unused objects and compresses the streams of the document
+    pub(crate) fn apply(&self, doc: &mut lopdf::Document)
+    {
+        if self.prune_objects {
+            doc.prune_objects();
+        }
+
+        if self.delete_zero_length_streams {
+            doc.delete_zero_length_streams();
+        }
+
+        self.compress_objects(doc.objects.values_mut());
+    }
+
+    /// Compresses the streams in `objects`, depending on the kind of the stream
+    pub(crate) fn compress_objects<'a, I>(&self, objects: I)
+    where I: Iterator<Item = &'a mut lopdf::Object>
+    {
+        if self.compression_level == 0 {
+            return;
+        }
+
+        for object in objects {
+            if let lopdf::Object::Stream(ref mut stream) = *object {
+                let compress = match stream_kind(stream) {
+                    StreamKind::Content => self.compress_content,
+                    StreamKind::Image => self.compress_images,
+                    StreamKind::Font => self.compress_fonts,
+                    StreamKind::IccProfile => self.compress_icc_profiles,
+                    // XMP metadata should stay readable for tools that don't parse PDF
+                    StreamKind::Metadata => false,
+                };
+
+                if compress {
+                    compress_stream(stream, self.compression_level);
+                }
+            }
+        }
+    }
+}
+
+/// Guesses the kind of the stream from its dictionary
+fn stream_kind(stream: &lopdf::Stream)
+-> StreamKind
+{
+    let name = |key: &[u8]| stream.dict.get(key).and_then(|name| name.as_name_str()).ok();
+
+    match (name(b"Type"), name(b"Subtype")) {
+        (Some("Metadata"), _) => StreamKind::Metadata,
+        (_, Some("Image")) => StreamKind::Image,
+        (_, Some("Type1C")) | (_, Some("CIDFontType0C")) | (_, Some("OpenType")) => StreamKind::Font,
+        _ if stream.dict.has(b"Length1") => StreamKind::Font,
+        // ICC profile streams are the only streams with the number of color components
+        _ if stream.dict.has(b"N") => StreamKind::IccProfile,
+        _ => StreamKind::Content,
+    }
+}
+
+/// Compresses a stream with the Flate filter, if it isn't compressed yet
+/// and if the compressed content is smaller than the original content
+pub(crate) fn compress_stream(stream: &mut lopdf::Stream, compression_level: u32)
+{
+    if stream.dict.has(b"Filter") {
+        return;
+    }
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(compression_level));
+    let compressed = match encoder.write_all(&stream.content).and_then(|_| encoder.finish()) {
+        Ok(compressed) => compressed,
+        Err(_) => return,
+    };
+
+    // "/Filter /FlateDecode" makes the dictionary larger
+    if compressed.len() + 19 < stream.content.len() {
+        stream.dict.set("Filter", lopdf::Object::Name("FlateDecode".as_bytes().to_vec()));
+        stream.set_content(compressed);
+    }
+}
+
+#[test]
+fn test_compress_by_stream_kind() {
+    use std::iter::FromIterator;
+    use lopdf::Object::*;
+
+    let content = vec![b'0'; 1000];
+    let mut doc = lopdf::Document::with_version("1.3");
+    let page_content = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::new(), content.clone()));
+    let font = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::from_iter(vec![
+        ("Length1", Integer(1000)),
+    ]), content.clone()));
+    let image = doc.add_object(lopdf::Stream::new(lopdf::Dictionary::from_iter(vec![
+        ("Subtype", Name("Image".as_bytes().to_vec())),
+    ]), content));
+
+    // a compression level alone doesn't compress anything
+    PdfSaveOptions::uncompressed().with_compression_level(9).apply(&mut doc);
+    assert!(!doc.get_object(page_content).unwrap().as_stream().unwrap().dict.has(b"Filter"));
+
+    let options = PdfSaveOptions {
+        compression_level: 6,
+        compress_images: true,
+        compress_content: true,
+        .. PdfSaveOptions::uncompressed()
+    };
+    options.apply(&mut doc);
+    assert!(doc.get_object(page_content).unwrap().as_stream().unwrap().dict.has(b"Filter"));
+    assert!(doc.get_object(image).unwrap().as_stream().unwrap().dict.has(b"Filter"));
+    assert!(!doc.get_object(font).unwrap().as_stream().unwrap().dict.has(b"Filter"));
+}
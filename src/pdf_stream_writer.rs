@@ -0,0 +1,271 @@
+//! Writes a document page by page, so that finished pages (with their images
+//! and content streams) don't have to be kept in memory until the document is saved
+
+use lopdf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::mem;
+
+use object_streams::{write_dictionary, write_indirect_object, write_xref_table};
+use pdf_document::{add_page_objects, lock_document, SharedObjectIds};
+use utils::ContentHash;
+use {Error, IndexError, PdfDocumentReference, PdfLayer, PdfPage, PdfPageIndex, PdfResources, PdfSaveOptions};
+
+/// Writes the pages of a document to `target` as soon as they are finished.
+/// Only the objects that are shared by all pages (fonts, outline, page tree,
+/// catalog) are written at the end, by `finish`.
+///
+/// ```rust
+/// use printpdf::*;
+///
+/// let (doc, page1, layer1) = PdfDocument::new("Streaming", Mm(210.0), Mm(297.0), "Layer 1");
+/// let mut writer = PdfStreamWriter::new(&doc, Vec::new(), PdfSaveOptions::default()).unwrap();
+///
+/// let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+/// doc.get_page(page1).get_layer(layer1).use_text("Page 1", 12.0, Mm(10.0), Mm(280.0), &font);
+/// writer.write_page(&doc, page1).unwrap();
+///
+/// let (page2, layer2) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+/// doc.get_page(page2).get_layer(layer2).use_text("Page 2", 12.0, Mm(10.0), Mm(280.0), &font);
+///
+/// // writes page 2 and everything else
+/// let bytes = writer.finish(doc).unwrap();
+/// assert!(bytes.starts_with(b"%PDF"));
+/// ```
+pub struct PdfStreamWriter<W: Write> {
+    target: W,
+    bytes_written: usize,
+    /// Offset and generation of the written objects, for the cross-reference table
+    offsets: BTreeMap<u32, (usize, u16)>,
+    shared_ids: SharedObjectIds,
+    /// Object IDs of the pages, by page index
+    page_ids: Vec<lopdf::ObjectId>,
+    /// Indices of the pages that are already written
+    written_pages: BTreeSet<usize>,
+    /// Optional content groups of the written pages
+    ocgs: Vec<lopdf::Object>,
+    options: PdfSaveOptions,
+    /// Hash of the written bytes, for reproducible IDs
+    content_hash: ContentHash,
+}
+
+impl<W: Write> PdfStreamWriter<W> {
+
+    /// Starts writing the document to `target` (writes the header of the file).
+    ///
+    /// The streams are compressed according to `options`. Since objects are written
+    /// before the document is finished, unused objects are not removed and object
+    /// streams are not used (`prune_objects`, `delete_zero_length_streams` and
+    /// `object_streams` are ignored).
+    pub fn new(doc: &PdfDocumentReference, target: W, options: PdfSaveOptions)
+    -> Result<Self, Error>
+    {
+        let mut document = lock_document(&doc.document);
+        let document = &mut *document;
+        let shared_ids = SharedObjectIds::new(&mut document.inner_doc, &document.metadata.creator);
+
+        if let Some(version) = document.metadata.conformance.get_pdf_version() {
+            document.inner_doc.version = version.into();
+        }
+
+        let mut writer = Self {
+            target,
+            bytes_written: 0,
+            offsets: BTreeMap::new(),
+            shared_ids,
+            page_ids: Vec::new(),
+            written_pages: BTreeSet::new(),
+            ocgs: Vec::new(),
+            options,
+            content_hash: ContentHash::new(),
+        };
+
+        let header = format!("%PDF-{}\n", document.inner_doc.version);
+        writer.write_bytes(header.as_bytes())?;
+        // binary comment, so that tools detect the file as binary
+        writer.write_bytes(b"%\xE2\xE3\xCF\xD3\n")?;
+
+        // objects that were already added to the document
+        writer.write_objects(&mut document.inner_doc)?;
+
+        Ok(writer)
+    }
+
+    /// Writes a finished page, with its contents, images and other resources, and
+    /// removes them from the document. Content that is added to the page afterwards
+    /// is ignored. Links on the page can only point to pages that already exist.
+    /// Writing a page a second time does nothing.
+    pub fn write_page(&mut self, doc: &PdfDocumentReference, page: PdfPageIndex)
+    -> Result<(), Error>
+    {
+        if self.written_pages.contains(&page.0) {
+            return Ok(());
+        }
+
+        let mut document = lock_document(&doc.document);
+        let document = &mut *document;
+
+        let finished_page = {
+            let current_page = document.pages.get_mut(page.0).ok_or(IndexError::PdfPageIndexError)?;
+            // the (empty) layers stay, so that `get_layer` still works on this page
+            let empty_page = PdfPage {
+                index: current_page.index,
+                width: current_page.width,
+                height: current_page.height,
+                layers: current_page.layers.iter().map(|layer| PdfLayer::new(layer.name.clone())).collect(),
+                resources: PdfResources::new(),
+                annotations: Vec::new(),
+                rotation: current_page.rotation,
+                object_id: current_page.object_id,
+                original_entries: lopdf::Dictionary::new(),
+                temporary_layers: mem::take(&mut current_page.temporary_layers),
+            };
+            mem::replace(current_page, empty_page)
+        };
+
+        self.allocate_page_ids(&mut document.inner_doc, &document.pages);
+        // fonts can still be added after the page is written, so the page always
+        // refers to the font dictionary (`finish` makes sure that it exists)
+        let mut ocgs = add_page_objects(&mut document.inner_doc, finished_page, self.page_ids[page.0],
+                                        &self.page_ids, &self.shared_ids, true);
+        self.ocgs.append(&mut ocgs);
+        self.written_pages.insert(page.0);
+
+        self.write_objects(&mut document.inner_doc)
+    }
+
+    /// Writes the pages that weren't written yet, the objects that are shared by all
+    /// pages and the cross-reference table. Returns the target, or
+    /// `PdfError::DocumentInUse` if another thread still uses the document.
+    pub fn finish(mut self, doc: PdfDocumentReference)
+    -> Result<W, Error>
+    {
+        let mut document = doc.into_document()?;
+        self.allocate_page_ids(&mut document.inner_doc, &document.pages);
+
+        let has_fonts = !document.fonts.is_empty();
+        for (index, page) in mem::take(&mut document.pages).into_iter().enumerate() {
+            if !self.written_pages.contains(&index) {
+                let mut ocgs = add_page_objects(&mut document.inner_doc, page, self.page_ids[index],
+                                                &self.page_ids, &self.shared_ids, has_fonts);
+                self.ocgs.append(&mut ocgs);
+            }
+        }
+
+        let ocgs = mem::take(&mut self.ocgs);
+        let mut inner_doc = document.into_lopdf_document(&self.shared_ids, &self.page_ids, ocgs,
+                                                     &self.options, self.content_hash);
+        if !self.written_pages.is_empty() {
+            inner_doc.objects.entry(self.shared_ids.fonts_id)
+                .or_insert_with(|| lopdf::Object::Dictionary(lopdf::Dictionary::new()));
+        }
+        self.write_objects(&mut inner_doc)?;
+
+        // cross-reference table and trailer
+        let xref_offset = self.bytes_written;
+        let size = inner_doc.max_id + 1;
+        let mut xref = Vec::new();
+        write_xref_table(&mut xref, &self.offsets, size)?;
+
+        inner_doc.trailer.set("Size", i64::from(size));
+        xref.extend_from_slice(b"trailer\n");
+        write_dictionary(&mut xref, &inner_doc.trailer)?;
+        write!(xref, "\nstartxref\n{}\n%%EOF", xref_offset)?;
+        self.write_bytes(&xref)?;
+
+        self.target.flush()?;
+        Ok(self.target)
+    }
+
+    /// Allocates the object IDs of new pages, so that links and
+    /// outline items can refer to all pages that exist so far
+    fn allocate_page_ids(&mut self, inner_doc: &mut lopdf::Document, pages: &[PdfPage])
+    {
+        for page in &pages[self.page_ids.len()..] {
+            self.page_ids.push(page.object_id.unwrap_or_else(|| inner_doc.new_object_id()));
+        }
+    }
+
+    /// Writes all objects of `inner_doc` and removes them from the document
+    fn write_objects(&mut self, inner_doc: &mut lopdf::Document)
+    -> Result<(), Error>
+    {
+        let mut objects = mem::take(&mut inner_doc.objects);
+        self.options.compress_objects(objects.values_mut());
+
+        let mut buffer = Vec::new();
+        for (id, object) in objects {
+            buffer.clear();
+            write_indirect_object(&mut buffer, id, &object)?;
+            self.offsets.insert(id.0, (self.bytes_written, id.1));
+            self.write_bytes(&buffer)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8])
+    -> Result<(), Error>
+    {
+        self.target.write_all(bytes)?;
+        self.bytes_written += bytes.len();
+        if self.options.reproducible && self.options.id_seed.is_none() {
+            self.content_hash.write(bytes);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_stream_pages() {
+    use lopdf::Object;
+    use {BuiltinFont, Color, Greyscale, Mm, PdfDocument};
+
+    let (doc, page1, layer1) = PdfDocument::new("Streaming", Mm(210.0), Mm(297.0), "Layer 1");
+    let mut writer = PdfStreamWriter::new(&doc, Vec::new(), PdfSaveOptions::default()).unwrap();
+
+    // the first page is written before the document has any fonts
+    doc.get_page(page1).get_layer(layer1).set_fill_color(Color::Greyscale(Greyscale::new(0.5, None)));
+    writer.write_page(&doc, page1).unwrap();
+
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    for text in &["Page 2", "Page 3"] {
+        let (page, layer) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+        doc.get_page(page).get_layer(layer).use_text(*text, 12.0, Mm(10.0), Mm(280.0), &font);
+        writer.write_page(&doc, page).unwrap();
+    }
+    // the last page is written by `finish`
+    let (page4, layer4) = doc.add_page(Mm(210.0), Mm(297.0), "Layer 1");
+    doc.get_page(page4).get_layer(layer4).use_text("Page 4", 12.0, Mm(10.0), Mm(280.0), &font);
+
+    let bytes = writer.finish(doc).unwrap();
+    let doc = lopdf::Document::load_mem(&bytes).unwrap();
+    let pages = doc.get_pages();
+    assert_eq!(pages.len(), 4);
+
+    for (number, page_id) in pages {
+        let resources = match *doc.get_dictionary(page_id).unwrap().get(b"Resources").unwrap() {
+            Object::Reference(id) => doc.get_dictionary(id).unwrap(),
+            ref object => object.as_dict().unwrap(),
+        };
+        let fonts = doc.get_dictionary(resources.get(b"Font").unwrap().as_reference().unwrap()).unwrap();
+        assert!(fonts.has(font.name.as_bytes()));
+
+        let content = lopdf::content::Content::decode(&doc.get_page_content(page_id).unwrap()).unwrap();
+        let texts: Vec<&[u8]> = content.operations.iter()
+            .filter(|operation| operation.operator == "Tj")
+            .map(|operation| operation.operands[0].as_str().unwrap())
+            .collect();
+        if number == 1 {
+            assert!(texts.is_empty());
+        } else {
+            assert_eq!(texts, vec![format!("Page {}", number).as_bytes()]);
+        }
+    }
+
+    // a document that is still in use by another thread can't be finished
+    let (doc, _, _) = PdfDocument::new("In use", Mm(210.0), Mm(297.0), "Layer 1");
+    let writer = PdfStreamWriter::new(&doc, Vec::new(), PdfSaveOptions::default()).unwrap();
+    let _in_use = doc.document.clone();
+    assert!(writer.finish(doc).is_err());
+}
@@ -0,0 +1,1014 @@
+//! Checks a document against the rules of its `PdfConformance` (embedded fonts,
+//! color spaces, transparency, ...), before it is sent to a print shop or an archive
+
+use lopdf::content::{Content, Operation};
+use lopdf::Object;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::mem;
+
+use icc_profile::RgbToCmyk;
+use pattern::PATTERN_COLOR_SPACES;
+use pdf_document::lock_document;
+use pdf_loader::{resolve_array, resolve_dict};
+use {
+    AlphaHandling, Cmyk, Color, ColorSpaceList, Error, Font, Greyscale, IccProfileType, ImageFilter, Lab, OutputIntent,
+    Pattern, PdfConformance, PdfDocument, PdfDocumentReference, PdfLayerIndex, PdfPage, PdfPageIndex, WhitePoint, XObject
+};
+
+/// What is wrong, see `ConformanceViolation`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationKind {
+    /// A builtin font (Helvetica, etc.) is used, these fonts are not embedded in the file
+    BuiltinFont(String),
+    /// A fill or stroke color is set in the DeviceRGB color space
+    DeviceRgbColor,
+    /// An image in the DeviceRGB color space (name of the image XObject)
+    DeviceRgbImage(String),
+    /// A pattern in the DeviceRGB color space (name of the pattern, or `PatternRGB` for
+    /// the color space of uncolored patterns, which is written if the page has any)
+    DeviceRgbPattern(String),
+    /// A fill or stroke color is set in a color space of the page resources (ICC-based, Lab,
+    /// CalRGB or CalGray; name of the color space), but the conformance only allows device colors
+    DeviceIndependentColor(String),
+    /// A JPEG compressed image (name of the image XObject)
+    JpegImage(String),
+    /// Transparency: what kind of transparency and the name of the graphics
+    /// state or XObject that uses it
+    Transparency { kind: &'static str, name: String },
+    /// Layers that are written as optional content groups, but the conformance doesn't
+    /// allow optional content (`repair_errors` writes them as plain content streams)
+    Layers(Vec<String>),
+    /// The conformance requires an output intent with an ICC profile (`PdfMetadata::output_intent`)
+    MissingIccProfile,
+    /// The output intent doesn't match the conformance (what is wrong)
+    InvalidOutputIntent(&'static str),
+    /// An image that is interpolated when it is scaled up (name of the image XObject)
+    InterpolatedImage(String),
+}
+
+/// A violation of the rules of the conformance of a document, with the page and
+/// layer where it happens (`None` for violations that concern the whole document or page)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceViolation {
+    /// What is wrong
+    pub kind: ViolationKind,
+    /// Page that the violation is on
+    pub page: Option<PdfPageIndex>,
+    /// Layer that the violation is on
+    pub layer: Option<PdfLayerIndex>,
+}
+
+/// Result of `PdfDocumentReference::check_conformance`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConformanceReport {
+    /// The conformance the document was checked against (`PdfConformance::get_identifier_string`)
+    pub conformance: String,
+    /// All violations, ordered by page and layer (violations that concern the whole document first)
+    pub violations: Vec<ConformanceViolation>,
+}
+
+impl ConformanceReport {
+
+    /// Returns if the document conforms to its conformance (has no violations)
+    #[inline]
+    pub fn is_ok(&self)
+    -> bool
+    {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ViolationKind::*;
+        match *self {
+            BuiltinFont(ref name) => write!(f, "builtin font {} is not embedded", name),
+            DeviceRgbColor => write!(f, "color in the DeviceRGB color space"),
+            DeviceRgbImage(ref name) => write!(f, "image {} is in the DeviceRGB color space", name),
+            DeviceRgbPattern(ref name) => write!(f, "pattern {} is in the DeviceRGB color space", name),
+            DeviceIndependentColor(ref name) => write!(f, "color in the device independent color space {}", name),
+            JpegImage(ref name) => write!(f, "image {} is JPEG compressed", name),
+            Transparency { kind, ref name } => write!(f, "{} uses transparency ({})", name, kind),
+            Layers(ref names) => write!(f, "layers are not allowed ({})", names.join(", ")),
+            MissingIccProfile => write!(f, "no ICC profile for the output intent"),
+            InvalidOutputIntent(reason) => write!(f, "invalid output intent ({})", reason),
+            InterpolatedImage(ref name) => write!(f, "image {} is interpolated", name),
+        }
+    }
+}
+
+impl fmt::Display for ConformanceViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(page) = self.page {
+            write!(f, "page {}", page.0 + 1)?;
+            if let Some(layer) = self.layer {
+                write!(f, ", layer {}", layer.0 + 1)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} violation(s)", self.violations.len())?;
+        if !self.conformance.is_empty() {
+            write!(f, " of {}", self.conformance)?;
+        }
+        for violation in &self.violations {
+            write!(f, "\n  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl PdfDocumentReference {
+
+    /// Checks the document against the rules of its conformance (see `with_conformance`)
+    /// and returns all violations, with the page and layer they are on:
+    ///
+    /// - builtin fonts, if the conformance requires embedded fonts (PDF/A, PDF/X, ...)
+    /// - DeviceRGB colors, images and patterns, if they are not allowed (PDF/X-1a, or PDF/A
+    ///   with a CMYK profile for the output intent)
+    /// - colors in ICC-based, Lab, CalRGB and CalGray color spaces, if the conformance only
+    ///   allows device colors (PDF/X-1a)
+    /// - JPEG images, if they are not allowed
+    /// - transparency (alpha, soft masks, blend modes, transparency groups and images
+    ///   with an alpha channel), if it is not allowed (PDF/A-1, PDF/X-1a, PDF/X-3)
+    /// - layers that are written as optional content groups, if layers are not allowed
+    /// - an output intent for another standard, with a profile in a color space the standard
+    ///   doesn't allow, with a profile that is not embedded or with a profile whose ICC header
+    ///   has another color space than the declared `IccProfileType` (documents without an
+    ///   output intent are checked with the FOGRA39 output intent that is written for them)
+    /// - interpolated images, if they are not allowed (PDF/A)
+    ///
+    /// The content of loaded and imported pages is not checked.
+    ///
+    /// ```rust
+    /// use printpdf::*;
+    ///
+    /// let (doc, page1, layer1) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    /// let doc = doc.with_conformance(PdfConformance::X1A_2001_PDF_1_3);
+    /// let layer = doc.get_page(page1).get_layer(layer1);
+    /// layer.set_fill_color(Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None)));
+    ///
+    /// let report = doc.check_conformance();
+    /// let violation = report.violations.iter().find(|violation| violation.kind == ViolationKind::DeviceRgbColor).unwrap();
+    /// assert_eq!(violation.page, Some(page1));
+    /// ```
+    pub fn check_conformance(&self)
+    -> ConformanceReport
+    {
+        let doc = lock_document(&self.document);
+        doc.check_conformance()
+    }
+}
+
+impl PdfDocument {
+
+    /// See `PdfDocumentReference::check_conformance`
+    pub(crate) fn check_conformance(&self)
+    -> ConformanceReport
+    {
+        let conformance = &self.metadata.conformance;
+        let mut violations = Vec::new();
+
+        // the output intent that is written when the document is saved
+        match self.metadata.output_intent() {
+            Some(ref output_intent) => {
+                for reason in output_intent_errors(conformance, output_intent) {
+                    violations.push(ConformanceViolation { kind: ViolationKind::InvalidOutputIntent(reason), page: None, layer: None });
+                }
+            },
+            None if conformance.must_have_icc_profile() => {
+                violations.push(ConformanceViolation { kind: ViolationKind::MissingIccProfile, page: None, layer: None });
+            },
+            None => { },
+        }
+
+        // builtin fonts that no page uses are still written to the file
+        if !conformance.is_default_font_allowed() {
+            let used_fonts: BTreeSet<&str> = self.pages.iter()
+                .flat_map(|page| page.layers.iter())
+                .flat_map(|layer| operand_names(&layer.operations, "Tf"))
+                .collect();
+            for (font_ref, font) in self.fonts.iter() {
+                if let Font::BuiltinFont(_) = font.data {
+                    if !used_fonts.contains(font_ref.name.as_str()) {
+                        let kind = ViolationKind::BuiltinFont(font_ref.name.clone());
+                        violations.push(ConformanceViolation { kind, page: None, layer: None });
+                    }
+                }
+            }
+        }
+
+        let device_rgb_allowed = self.is_device_rgb_allowed();
+        for (page_index, page) in self.pages.iter().enumerate() {
+            self.check_page(conformance, device_rgb_allowed, PdfPageIndex(page_index), page, &mut violations);
+        }
+
+        ConformanceReport {
+            conformance: conformance.get_identifier_string(),
+            violations,
+        }
+    }
+
+    fn check_page(&self, conformance: &PdfConformance, device_rgb_allowed: bool, page_index: PdfPageIndex,
+                  page: &PdfPage, violations: &mut Vec<ConformanceViolation>)
+    {
+        let mut add = |kind, layer| violations.push(ConformanceViolation { kind, page: Some(page_index), layer });
+
+        // plain layers are only separate content streams, optional content groups are not allowed
+        let optional_layers: Vec<String> = page.layers.iter().filter(|layer| layer.optional_content).map(|layer| layer.name.clone()).collect();
+        if !conformance.is_layering_allowed() && !optional_layers.is_empty() {
+            add(ViolationKind::Layers(optional_layers), None);
+        }
+
+        // layers that use an XObject (Do), a graphics state (gs) or a pattern (scn), by name
+        let mut users: BTreeMap<&str, Vec<PdfLayerIndex>> = BTreeMap::new();
+        for (layer_index, layer) in page.layers.iter().enumerate() {
+            let names = operand_names(&layer.operations, "Do")
+                .chain(operand_names(&layer.operations, "gs"))
+                .chain(pattern_names(&layer.operations));
+            for name in names {
+                let layers = users.entry(name).or_default();
+                if !layers.contains(&PdfLayerIndex(layer_index)) {
+                    layers.push(PdfLayerIndex(layer_index));
+                }
+            }
+        }
+        let layers_using = |name: &str| -> Vec<Option<PdfLayerIndex>> {
+            match users.get(name) {
+                Some(layers) => layers.iter().cloned().map(Some).collect(),
+                None => vec![None],
+            }
+        };
+
+        for (layer_index, layer) in page.layers.iter().enumerate() {
+            let layer_index = Some(PdfLayerIndex(layer_index));
+
+            if !conformance.is_default_font_allowed() {
+                let fonts: BTreeSet<&str> = operand_names(&layer.operations, "Tf").collect();
+                for name in fonts {
+                    let is_builtin = self.fonts.iter().any(|(font_ref, font)| {
+                        font_ref.name == name && matches!(font.data, Font::BuiltinFont(_))
+                    });
+                    if is_builtin {
+                        add(ViolationKind::BuiltinFont(name.to_string()), layer_index);
+                    }
+                }
+            }
+
+            if !device_rgb_allowed && layer.operations.iter().any(is_device_rgb_operation) {
+                add(ViolationKind::DeviceRgbColor, layer_index);
+            }
+        }
+
+        // color spaces of the page resources, in the layers and in the tiling patterns
+        // and transparency groups that the layers paint with
+        if !conformance.is_device_independent_color_allowed() {
+            let color_spaces = &page.resources.color_spaces;
+            let mut used = Vec::new();
+            for (layer_index, layer) in page.layers.iter().enumerate() {
+                for name in resource_color_space_names(&layer.operations, color_spaces) {
+                    used.push((Some(PdfLayerIndex(layer_index)), name));
+                }
+            }
+            for (name, pattern) in page.resources.patterns.iter() {
+                if let Pattern::Tiling(ref tiling) = *pattern {
+                    for color_space in resource_color_space_names(&tiling.operations, color_spaces) {
+                        used.extend(layers_using(name).into_iter().map(|layer| (layer, color_space.clone())));
+                    }
+                }
+            }
+            for (name, xobject) in page.resources.xobjects.iter() {
+                let content = match *xobject {
+                    XObject::Form(ref form) if form.resources.is_none() => Content::decode(&form.bytes).ok(),
+                    _ => None,
+                };
+                for color_space in content.iter().flat_map(|content| resource_color_space_names(&content.operations, color_spaces)) {
+                    used.extend(layers_using(name).into_iter().map(|layer| (layer, color_space.clone())));
+                }
+            }
+
+            let mut added = BTreeSet::new();
+            for (layer, name) in used {
+                if added.insert((layer.map(|layer| layer.0), name.clone())) {
+                    add(ViolationKind::DeviceIndependentColor(name), layer);
+                }
+            }
+        }
+
+        for (name, xobject) in page.resources.xobjects.iter() {
+            let mut kinds = Vec::new();
+            match *xobject {
+                XObject::Image(ref image) => {
+                    if !device_rgb_allowed && image.is_rgb() {
+                        kinds.push(ViolationKind::DeviceRgbImage(name.clone()));
+                    }
+                    if !conformance.is_jpeg_content_allowed() && matches!(image.image_filter, Some(ImageFilter::DCT)) {
+                        kinds.push(ViolationKind::JpegImage(name.clone()));
+                    }
+                    if !conformance.is_transparency_allowed() && image.has_soft_mask() {
+                        kinds.push(ViolationKind::Transparency { kind: "alpha channel", name: name.clone() });
+                    }
+                    if !conformance.is_image_interpolation_allowed() && image.interpolate {
+                        kinds.push(ViolationKind::InterpolatedImage(name.clone()));
+                    }
+                },
+                XObject::Form(ref form) if !conformance.is_transparency_allowed() && form.group.is_some() => {
+                    kinds.push(ViolationKind::Transparency { kind: "transparency group", name: name.clone() });
+                },
+                _ => { },
+            }
+
+            for kind in kinds {
+                for layer in layers_using(name) {
+                    add(kind.clone(), layer);
+                }
+            }
+        }
+
+        if !device_rgb_allowed {
+            for (name, pattern) in page.resources.patterns.iter() {
+                let is_rgb = match *pattern {
+                    Pattern::Tiling(ref tiling) => tiling.operations.iter().any(is_device_rgb_operation),
+                    Pattern::Shading(ref shading) => shading.is_rgb(),
+                };
+                if is_rgb {
+                    for layer in layers_using(name) {
+                        add(ViolationKind::DeviceRgbPattern(name.clone()), layer);
+                    }
+                }
+            }
+            if page.resources.patterns.rgb_color_space && page.resources.patterns.iter().any(|(_, pattern)| pattern.is_uncolored()) {
+                add(ViolationKind::DeviceRgbPattern(PATTERN_COLOR_SPACES[0].0.to_string()), None);
+            }
+        }
+
+        if !conformance.is_transparency_allowed() {
+            for (name, (_, graphics_state)) in &page.resources.graphics_states.all_graphics_states {
+                if let Some(kind) = graphics_state.transparency() {
+                    for layer in layers_using(name) {
+                        add(ViolationKind::Transparency { kind, name: name.clone() }, layer);
+                    }
+                }
+            }
+        }
+
+        // page-level violations first, then by layer
+        let first_of_page = violations.iter().position(|violation| violation.page == Some(page_index)).unwrap_or(violations.len());
+        violations[first_of_page..].sort_by_key(|violation| violation.layer.map(|layer| layer.0 + 1).unwrap_or(0));
+    }
+
+    /// PDF/A only allows the device color spaces that match the profile of the output
+    /// intent, DeviceRGB isn't allowed with a CMYK profile (which is the default profile)
+    fn is_device_rgb_allowed(&self)
+    -> bool
+    {
+        let conformance = &self.metadata.conformance;
+        let cmyk_output_intent = matches!(self.metadata.output_intent(), Some(ref output_intent) if output_intent.get_color_space() == IccProfileType::Cmyk);
+        conformance.is_device_rgb_allowed() && !(conformance.is_pdf_a() && cmyk_output_intent)
+    }
+
+    /// See `PdfDocumentReference::repair_errors`
+    pub(crate) fn repair(&mut self, conformance: PdfConformance)
+    -> Result<(), Error>
+    {
+        self.metadata.conformance = conformance;
+        let conformance = self.metadata.conformance.clone();
+
+        // the default output intent that is otherwise added when saving, the RGB colors are converted with its profile
+        if conformance.must_have_icc_profile() {
+            self.metadata.output_intent = self.metadata.output_intent();
+        }
+        if let (Some(output_intent), Some(subtype)) = (self.metadata.output_intent.as_mut(), conformance.get_output_intent_subtype()) {
+            output_intent.subtype = subtype;
+        }
+
+        if !conformance.is_default_font_allowed() {
+            self.remove_unused_fonts();
+        }
+
+        // the layers stay, so that references to them can still be used
+        if !conformance.is_layering_allowed() {
+            for page in &mut self.pages {
+                for layer in &mut page.layers {
+                    layer.optional_content = false;
+                }
+            }
+        }
+
+        // before the colors are converted, images without an alpha channel can be converted
+        if !conformance.is_transparency_allowed() {
+            for page in &mut self.pages {
+                for (_, graphics_state) in page.resources.graphics_states.all_graphics_states.values_mut() {
+                    graphics_state.remove_transparency();
+                }
+                for xobject in page.resources.xobjects.objects_mut() {
+                    match *xobject {
+                        XObject::Image(ref mut image) if image.has_soft_mask() => {
+                            image.alpha_handling = AlphaHandling::Flatten(Color::Greyscale(Greyscale::new(1.0, None)));
+                        },
+                        XObject::Form(ref mut form) => form.group = None,
+                        _ => { },
+                    }
+                }
+            }
+        }
+
+        if !conformance.is_image_interpolation_allowed() {
+            for page in &mut self.pages {
+                for xobject in page.resources.xobjects.objects_mut() {
+                    if let XObject::Image(ref mut image) = *xobject {
+                        image.interpolate = false;
+                    }
+                }
+            }
+        }
+
+        // the colors are converted to DeviceCMYK, so that the color spaces can be removed from the page
+        // resources. If a color can't be converted, the color spaces stay and the violation is reported
+        if !conformance.is_device_independent_color_allowed() {
+            let conversion = RgbToCmyk::new(self.metadata.output_intent.as_ref().and_then(OutputIntent::get_embedded_profile));
+            let inner_doc = &self.inner_doc;
+            for page in &mut self.pages {
+                let color_spaces = mem::take(&mut page.resources.color_spaces);
+                if color_spaces.color_spaces.is_empty() {
+                    continue;
+                }
+
+                let mut converted = true;
+                let mut convert = |operations: &mut [Operation]| {
+                    converted &= convert_resource_color_operations(operations, &color_spaces.color_spaces, inner_doc, &conversion);
+                };
+                for layer in &mut page.layers {
+                    convert(&mut layer.operations);
+                }
+                for pattern in page.resources.patterns.patterns_mut() {
+                    if let Pattern::Tiling(ref mut tiling) = *pattern {
+                        convert(&mut tiling.operations);
+                    }
+                }
+                for xobject in page.resources.xobjects.objects_mut() {
+                    let form = match *xobject {
+                        XObject::Form(ref mut form) if form.resources.is_none() => form,
+                        _ => continue,
+                    };
+                    if let Ok(mut content) = Content::decode(&form.bytes) {
+                        convert(&mut content.operations);
+                        if let Ok(bytes) = content.encode() {
+                            form.bytes = bytes;
+                        }
+                    }
+                }
+
+                if !converted {
+                    page.resources.color_spaces = color_spaces;
+                }
+            }
+        }
+
+        if !self.is_device_rgb_allowed() {
+            let conversion = RgbToCmyk::new(self.metadata.output_intent.as_ref().and_then(OutputIntent::get_embedded_profile));
+            for page in &mut self.pages {
+                for layer in &mut page.layers {
+                    convert_rgb_operations(&mut layer.operations, &conversion);
+                }
+                for pattern in page.resources.patterns.patterns_mut() {
+                    match *pattern {
+                        Pattern::Tiling(ref mut tiling) => convert_rgb_operations(&mut tiling.operations, &conversion),
+                        Pattern::Shading(ref mut shading) if shading.is_rgb() => {
+                            for stop in &mut shading.stops {
+                                if let Color::Rgb(ref rgb) = stop.color {
+                                    let cmyk = conversion.convert(rgb.r, rgb.g, rgb.b);
+                                    stop.color = Color::Cmyk(Cmyk::new(cmyk[0], cmyk[1], cmyk[2], cmyk[3], None));
+                                }
+                            }
+                        },
+                        Pattern::Shading(_) => { },
+                    }
+                }
+                // uncolored patterns are painted in PatternCMYK instead (see `convert_rgb_operations`)
+                page.resources.patterns.rgb_color_space = false;
+                for xobject in page.resources.xobjects.objects_mut() {
+                    if let XObject::Image(ref mut image) = *xobject {
+                        if image.is_rgb() {
+                            image.convert_to_cmyk(&conversion);
+                        }
+                    }
+                }
+            }
+        }
+
+        let report = self.check_conformance();
+        if report.is_ok() {
+            return Ok(());
+        }
+
+        #[cfg(feature = "logging")] {
+            for violation in &report.violations {
+                warn!("Can't repair the document: {}", violation);
+            }
+        }
+
+        Err(report.into())
+    }
+}
+
+/// What is wrong with the output intent of a document with the conformance
+fn output_intent_errors(conformance: &PdfConformance, output_intent: &OutputIntent)
+-> Vec<&'static str>
+{
+    let mut errors = Vec::new();
+    if matches!(conformance.get_output_intent_subtype(), Some(subtype) if subtype != output_intent.subtype) {
+        errors.push("the subtype is for another standard");
+    }
+    if !conformance.is_output_intent_color_space_allowed(output_intent.get_color_space()) {
+        errors.push("the color space of the profile is not allowed");
+    }
+    if output_intent.get_embedded_profile().is_none() && !conformance.is_referenced_output_profile_allowed() {
+        errors.push("the profile is not embedded");
+    }
+    if let Some(profile) = output_intent.get_embedded_profile() {
+        if matches!(profile.get_header_type(), Some(header_type) if header_type != profile.get_type()) {
+            errors.push("the color space of the profile doesn't match its ICC header");
+        }
+    }
+    errors
+}
+
+/// Color space of the fill or stroke color, as far as `convert_rgb_operations` is concerned
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RgbColorSpace {
+    /// DeviceRGB, set by `cs` / `CS` or `rg` / `RG`
+    Device,
+    /// The color space of uncolored patterns based on DeviceRGB (`PatternRGB`)
+    Pattern,
+    /// Any other color space
+    None,
+}
+
+/// Replaces the DeviceRGB colors that the operations set with CMYK colors
+fn convert_rgb_operations(operations: &mut [Operation], conversion: &RgbToCmyk)
+{
+    // color spaces of the fill and stroke color, which `q` saves and `Q` restores
+    let mut color_spaces = (RgbColorSpace::None, RgbColorSpace::None);
+    let mut saved_color_spaces = Vec::new();
+
+    for operation in operations.iter_mut() {
+        let (is_fill, cmyk_operator) = match operation.operator.as_str() {
+            "q" => {
+                saved_color_spaces.push(color_spaces);
+                continue;
+            },
+            "Q" => {
+                if let Some(saved) = saved_color_spaces.pop() {
+                    color_spaces = saved;
+                }
+                continue;
+            },
+            "cs" | "CS" => {
+                let color_space = match operation.operands.first().and_then(|name| name.as_name_str().ok()) {
+                    Some("DeviceRGB") => RgbColorSpace::Device,
+                    Some(name) if name == PATTERN_COLOR_SPACES[0].0 => RgbColorSpace::Pattern,
+                    _ => RgbColorSpace::None,
+                };
+                match color_space {
+                    RgbColorSpace::Device => operation.operands = vec![Object::Name("DeviceCMYK".into())],
+                    RgbColorSpace::Pattern => operation.operands = vec![Object::Name(PATTERN_COLOR_SPACES[1].0.into())],
+                    RgbColorSpace::None => { },
+                }
+                if operation.operator == "cs" { color_spaces.0 = color_space; } else { color_spaces.1 = color_space; }
+                continue;
+            },
+            // these operators set the color space as well
+            "k" | "g" => { color_spaces.0 = RgbColorSpace::None; continue; },
+            "K" | "G" => { color_spaces.1 = RgbColorSpace::None; continue; },
+            "rg" => (true, Some("k")),
+            "RG" => (false, Some("K")),
+            "sc" | "scn" => (true, None),
+            "SC" | "SCN" => (false, None),
+            _ => continue,
+        };
+
+        let color_space = if is_fill { &mut color_spaces.0 } else { &mut color_spaces.1 };
+        if cmyk_operator.is_some() {
+            *color_space = RgbColorSpace::Device;
+        }
+
+        // only colors with 3 numbers are converted, followed by the name of the pattern in PatternRGB
+        let pattern_name = match *color_space {
+            RgbColorSpace::Device => None,
+            RgbColorSpace::Pattern => match operation.operands.last() {
+                Some(name @ &Object::Name(_)) => Some(name.clone()),
+                _ => continue,
+            },
+            RgbColorSpace::None => continue,
+        };
+        let color_count = operation.operands.len() - pattern_name.iter().count();
+        let rgb: Vec<f64> = operation.operands[..color_count].iter().filter_map(|operand| operand.as_float().ok()).collect();
+        if rgb.len() != 3 || color_count != 3 {
+            continue;
+        }
+        operation.operands = conversion.convert(rgb[0], rgb[1], rgb[2]).iter().map(|component| Object::Real(*component)).collect();
+        operation.operands.extend(pattern_name);
+        if let Some(cmyk_operator) = cmyk_operator {
+            operation.operator = cmyk_operator.into();
+            // the color is in DeviceCMYK now
+            *color_space = RgbColorSpace::None;
+        }
+    }
+}
+
+/// Replaces the colors that the operations set in the color spaces of the page resources
+/// (ICC-based, Lab, CalRGB, CalGray and the `[/Pattern base]` color spaces of uncolored
+/// patterns) with DeviceCMYK colors, returns `false` if a color can't be converted
+fn convert_resource_color_operations(operations: &mut [Operation], color_spaces: &BTreeMap<String, Object>,
+                                     doc: &lopdf::Document, conversion: &RgbToCmyk)
+-> bool
+{
+    // color spaces of the fill and stroke color, which `q` saves and `Q` restores
+    let mut current: (Option<&Object>, Option<&Object>) = (None, None);
+    let mut saved = Vec::new();
+    let mut converted = true;
+
+    for operation in operations.iter_mut() {
+        let is_fill = match operation.operator.as_str() {
+            "q" => {
+                saved.push(current);
+                continue;
+            },
+            "Q" => {
+                if let Some(saved) = saved.pop() {
+                    current = saved;
+                }
+                continue;
+            },
+            "cs" | "CS" => {
+                let color_space = operation.operands.first()
+                    .and_then(|name| name.as_name_str().ok())
+                    .and_then(|name| color_spaces.get(name));
+                if let Some(color_space) = color_space {
+                    let name = if pattern_base(color_space).is_some() { PATTERN_COLOR_SPACES[1].0 } else { "DeviceCMYK" };
+                    operation.operands = vec![Object::Name(name.into())];
+                }
+                if operation.operator == "cs" { current.0 = color_space; } else { current.1 = color_space; }
+                continue;
+            },
+            // these operators set a device color space
+            "k" | "g" | "rg" => { current.0 = None; continue; },
+            "K" | "G" | "RG" => { current.1 = None; continue; },
+            "sc" | "scn" => true,
+            "SC" | "SCN" => false,
+            _ => continue,
+        };
+
+        let color_space = match if is_fill { current.0 } else { current.1 } {
+            Some(color_space) => color_space,
+            None => continue,
+        };
+        // colors of uncolored patterns are followed by the name of the pattern
+        let (color_space, pattern_name) = match pattern_base(color_space) {
+            Some(base) => match operation.operands.last() {
+                Some(name @ &Object::Name(_)) => (base, Some(name.clone())),
+                _ => {
+                    converted = false;
+                    continue;
+                },
+            },
+            None => (color_space, None),
+        };
+
+        let color_count = operation.operands.len() - pattern_name.iter().count();
+        let components: Vec<f64> = operation.operands[..color_count].iter().filter_map(|operand| operand.as_float().ok()).collect();
+        match resource_color_to_cmyk(color_space, &components, doc, conversion) {
+            Some(cmyk) if components.len() == color_count => {
+                operation.operands = cmyk.iter().map(|component| Object::Real(*component)).collect();
+                operation.operands.extend(pattern_name);
+            },
+            _ => converted = false,
+        }
+    }
+
+    converted
+}
+
+/// Base color space of a `[/Pattern base]` color space, `None` for other color spaces
+fn pattern_base(color_space: &Object)
+-> Option<&Object>
+{
+    match color_space.as_array().ok()?.as_slice() {
+        [Object::Name(ref family), base] if family == b"Pattern" => Some(base),
+        _ => None,
+    }
+}
+
+/// Converts a color in an ICC-based, Lab, CalRGB or CalGray color space to CMYK, `None`
+/// for other color spaces or if the number of components doesn't match the color space
+fn resource_color_to_cmyk(color_space: &Object, components: &[f64], doc: &lopdf::Document, conversion: &RgbToCmyk)
+-> Option<[f64; 4]>
+{
+    let color_space = resolve_array(doc, color_space)?;
+    let parameters = color_space.get(1);
+    let device = match (color_space.first()?.as_name_str().ok()?, components) {
+        ("Lab", &[l, a, b]) => {
+            let white_point = parameters
+                .and_then(|parameters| resolve_dict(doc, parameters))
+                .and_then(|parameters| parameters.get(b"WhitePoint").ok())
+                .and_then(|white_point| white_point.as_array().ok())
+                .and_then(|white_point| match white_point.iter().map(Object::as_float).collect::<Result<Vec<_>, _>>() {
+                    Ok(ref xyz) if xyz.len() == 3 => Some(WhitePoint { x: xyz[0], y: xyz[1], z: xyz[2] }),
+                    _ => None,
+                })
+                .unwrap_or(WhitePoint::D50);
+            Color::Lab(Lab::new(l, a, b, white_point)).into_device_vec()
+        },
+        ("CalRGB", &[_, _, _]) | ("CalGray", &[_]) => components.to_vec(),
+        ("ICCBased", _) => {
+            let stream = match *parameters? {
+                Object::Reference(id) => doc.get_object(id).and_then(Object::as_stream).ok()?,
+                _ => return None,
+            };
+            let component_count = stream.dict.get(b"N").and_then(Object::as_i64).ok()?;
+            if component_count != components.len() as i64 {
+                return None;
+            }
+            components.to_vec()
+        },
+        _ => return None,
+    };
+
+    match *device.as_slice() {
+        [grey] => Some([0.0, 0.0, 0.0, 1.0 - grey]),
+        [r, g, b] => Some(conversion.convert(r, g, b)),
+        [c, m, y, k] => Some([c, m, y, k]),
+        _ => None,
+    }
+}
+
+/// Names of the color spaces of the page resources that the operations select (`cs` / `CS`)
+fn resource_color_space_names(operations: &[Operation], color_spaces: &ColorSpaceList)
+-> BTreeSet<String>
+{
+    operand_names(operations, "cs")
+        .chain(operand_names(operations, "CS"))
+        .filter(|name| color_spaces.color_spaces.contains_key(*name))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Names that are the first operand of the operations with the given operator
+/// (fonts for `Tf`, XObjects for `Do`, graphics states for `gs`)
+fn operand_names<'a>(operations: &'a [Operation], operator: &'a str)
+-> impl Iterator<Item = &'a str>
+{
+    operations.iter()
+        .filter(move |operation| operation.operator == operator)
+        .filter_map(|operation| operation.operands.first().and_then(|name| name.as_name_str().ok()))
+}
+
+/// Names of the patterns that the operations paint with (last operand of `scn` / `SCN`)
+fn pattern_names(operations: &[Operation])
+-> impl Iterator<Item = &str>
+{
+    operations.iter()
+        .filter(|operation| operation.operator == "scn" || operation.operator == "SCN")
+        .filter_map(|operation| operation.operands.last().and_then(|name| name.as_name_str().ok()))
+}
+
+/// Returns if the operation sets a color in the DeviceRGB color space
+/// (or in the color space of uncolored patterns that is based on it)
+fn is_device_rgb_operation(operation: &Operation)
+-> bool
+{
+    match operation.operator.as_str() {
+        "rg" | "RG" => true,
+        "cs" | "CS" => match operation.operands.first().and_then(|name| name.as_name_str().ok()) {
+            Some(name) => name == "DeviceRGB" || name == PATTERN_COLOR_SPACES[0].0,
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+#[test]
+fn test_check_conformance() {
+    use {BuiltinFont, Mm, Color, Cmyk, Rgb, CustomPdfConformance, BlendMode, SeperableBlendMode};
+    use {Point, Rect, ColorStop, ShadingPattern, ShadingType, TilingPattern};
+
+    let (doc, page1, layer1) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    let layer = doc.get_page(page1).get_layer(layer1);
+    layer.set_fill_color(Color::Cmyk(Cmyk::new(0.0, 1.0, 1.0, 0.0, None)));
+    layer.use_text("CMYK text", 12.0, Mm(10.0), Mm(10.0), &font);
+
+    let (page2, layer2) = doc.add_page(Mm(210.0), Mm(297.0), "Background");
+    let background = doc.get_page(page2).get_layer(layer2);
+    background.set_fill_color(Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None)));
+    let overlay = doc.get_page(page2).add_layer("Overlay");
+    overlay.set_blend_mode(BlendMode::Seperable(SeperableBlendMode::Multiply));
+    let gradient = doc.get_page(page2).add_shading_pattern(ShadingPattern::new(
+        ShadingType::Axial { start: Point::new(Mm(0.0), Mm(0.0)), end: Point::new(Mm(100.0), Mm(0.0)) },
+        vec![ColorStop::new(0.0, Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None))), ColorStop::new(1.0, Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None)))],
+    ));
+    overlay.set_fill_pattern(&gradient, None);
+    let area = Rect::new(Mm(0.0), Mm(0.0), Mm(10.0), Mm(10.0));
+    doc.get_page(page2).add_tiling_pattern(TilingPattern::new(area, Mm(10.0).into(), Mm(10.0).into()).with_uncolored(), |_| { });
+
+    // the default conformance allows everything that the document uses
+    assert!(doc.check_conformance().is_ok());
+    assert!(doc.check_for_errors().is_ok());
+
+    let doc = doc.with_conformance(PdfConformance::X1A_2001_PDF_1_3);
+    let report = doc.check_conformance();
+    assert_eq!(report.conformance, "PDF/X-1a:2001");
+    let kinds: Vec<_> = report.violations.iter().map(|violation| (violation.page.map(|page| page.0), violation.layer.map(|layer| layer.0), violation.kind.clone())).collect();
+    // the FOGRA39 output intent that is written for the document is valid
+    assert_eq!(kinds, vec![
+        (Some(0), None, ViolationKind::Layers(vec!["Layer 1".into()])),
+        (Some(0), Some(0), ViolationKind::BuiltinFont("Helvetica".into())),
+        (Some(1), None, ViolationKind::Layers(vec!["Background".into(), "Overlay".into()])),
+        (Some(1), None, ViolationKind::DeviceRgbPattern("PatternRGB".into())),
+        (Some(1), Some(0), ViolationKind::DeviceRgbColor),
+        (Some(1), Some(1), ViolationKind::DeviceRgbPattern("PT0".into())),
+        (Some(1), Some(1), ViolationKind::Transparency { kind: "blend mode", name: "GS0".into() }),
+    ]);
+    assert!(doc.check_for_errors().is_err());
+
+    // custom conformance that only forbids transparency
+    let doc = doc.with_conformance(PdfConformance::Custom(CustomPdfConformance {
+        allows_transparency: false,
+        .. Default::default()
+    }));
+    let report = doc.check_conformance();
+    assert_eq!(report.violations.len(), 1);
+    assert_eq!(report.to_string(), "1 violation(s)\n  page 2, layer 2: GS0 uses transparency (blend mode)");
+}
+
+#[test]
+fn test_repair_errors() {
+    use std::io::Cursor;
+    use {BuiltinFont, Mm, Rgb, ColorSpace, ColorBits, Px, Image, ImageTransform, ImageXObject, BlendMode, SeperableBlendMode};
+    use {Point, Rect, ColorStop, ShadingPattern, ShadingType, TilingPattern};
+
+    let (doc, page1, layer1) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Background");
+    let font = doc.add_external_font(Cursor::new(include_bytes!("../assets/fonts/RobotoMedium.ttf").to_vec())).unwrap();
+    let background = doc.get_page(page1).get_layer(layer1);
+    background.set_fill_color(Color::Rgb(Rgb::new(1.0, 1.0, 1.0, None)));
+    let image = ImageXObject::new(Px(2), Px(1), ColorSpace::Rgba, ColorBits::Bit8, false, None, None, vec![255, 0, 0, 255, 0, 0, 0, 0]);
+    Image::from(image).add_to_layer(background.clone(), ImageTransform::default());
+
+    let text = doc.get_page(page1).add_layer("Text");
+    text.set_blend_mode(BlendMode::Seperable(SeperableBlendMode::Multiply));
+    text.set_outline_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+    text.use_text("RGB text", 12.0, Mm(10.0), Mm(10.0), &font);
+
+    let page = doc.get_page(page1);
+    let gradient = page.add_shading_pattern(ShadingPattern::new(
+        ShadingType::Axial { start: Point::new(Mm(0.0), Mm(0.0)), end: Point::new(Mm(100.0), Mm(0.0)) },
+        vec![ColorStop::new(0.0, Color::Rgb(Rgb::new(1.0, 0.0, 0.0, None))), ColorStop::new(1.0, Color::Rgb(Rgb::new(0.0, 0.0, 1.0, None)))],
+    ));
+    let area = Rect::new(Mm(0.0), Mm(0.0), Mm(10.0), Mm(10.0));
+    let dots = page.add_tiling_pattern(TilingPattern::new(area, Mm(10.0).into(), Mm(10.0).into()).with_uncolored(), |_| { });
+    background.set_fill_pattern(&gradient, None);
+    text.set_fill_pattern(&dots, Some(Color::Rgb(Rgb::new(0.0, 1.0, 0.0, None))));
+
+    doc.repair_errors(PdfConformance::X1A_2001_PDF_1_3).unwrap();
+    assert!(doc.check_conformance().is_ok());
+
+    // the layers stay, references to them can still be used
+    text.set_fill_color(Color::Cmyk(Cmyk::new(0.0, 0.0, 0.0, 1.0, None)));
+
+    {
+        let document = lock_document(&doc.document);
+        let page = &document.pages[0];
+        assert_eq!(page.layers.len(), 2);
+        assert!(page.layers.iter().all(|layer| !layer.optional_content));
+        assert_eq!(page.layers[1].operations.last().unwrap().operator, "k");
+        let operators: Vec<_> = page.layers.iter().flat_map(|layer| layer.operations.iter()).map(|operation| operation.operator.as_str()).collect();
+        assert!(operators.contains(&"k") && operators.contains(&"K"));
+        assert!(!operators.contains(&"rg") && !operators.contains(&"RG"));
+
+        // white is printed without ink
+        let white = page.layers[0].operations.iter().find(|operation| operation.operator == "k").unwrap();
+        assert!(white.operands.iter().all(|component| component.as_float().unwrap() < 0.01));
+
+        // the uncolored pattern is painted in CMYK, the gradient is CMYK
+        let pattern_color = page.layers[1].operations.iter().find(|operation| operation.operator == "scn").unwrap();
+        assert_eq!(pattern_color.operands.len(), 5);
+        assert!(!page.resources.patterns.color_spaces().has(PATTERN_COLOR_SPACES[0].0.as_bytes()));
+        assert!(page.resources.patterns.iter().all(|(_, pattern)| match *pattern {
+            Pattern::Shading(ref shading) => shading.stops.iter().all(|stop| matches!(stop.color, Color::Cmyk(_))),
+            Pattern::Tiling(_) => true,
+        }));
+
+        let image = page.resources.xobjects.iter().find_map(|(_, xobject)| match *xobject {
+            XObject::Image(ref image) => Some(image),
+            _ => None,
+        }).unwrap();
+        assert_eq!(image.color_space, ColorSpace::Cmyk);
+        assert_eq!(image.image_data.len(), 2 * 4);
+        // the transparent pixel became white
+        assert_eq!(image.image_data[5..], [0, 0, 0]);
+    }
+    doc.save_to_bytes().unwrap();
+
+    // builtin fonts can't be embedded
+    let (doc, page1, layer1) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
+    doc.get_page(page1).get_layer(layer1).use_text("Builtin font", 12.0, Mm(10.0), Mm(10.0), &font);
+    doc.add_builtin_font(BuiltinFont::Courier).unwrap();
+
+    match doc.repair_errors(PdfConformance::X3_2003_PDF_1_4) {
+        Err(Error::Conformance(report)) => {
+            assert_eq!(report.violations.len(), 1);
+            assert_eq!(report.violations[0].kind, ViolationKind::BuiltinFont("Helvetica".into()));
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_repair_device_independent_colors() {
+    use glob_defines::ICC_PROFILE_ECI_V2;
+    use {IccProfile, Mm, Rect, TilingPattern, WhitePoint};
+
+    let (doc, page1, layer1) = PdfDocument::new("Flyer", Mm(210.0), Mm(297.0), "Layer 1");
+    let doc = doc.with_conformance(PdfConformance::X1A_2001_PDF_1_3);
+    let profile = doc.add_icc_profile(IccProfile::new(ICC_PROFILE_ECI_V2.to_vec(), IccProfileType::Cmyk));
+    let layer = doc.get_page(page1).get_layer(layer1);
+    layer.set_fill_color(Color::Lab(Lab::new(50.0, 60.0, 40.0, WhitePoint::D50)));
+    layer.set_outline_color(Color::Cmyk(Cmyk::new(0.0, 1.0, 1.0, 0.0, Some(profile))));
+
+    let area = Rect::new(Mm(0.0), Mm(0.0), Mm(10.0), Mm(10.0));
+    let dots = doc.get_page(page1).add_tiling_pattern(TilingPattern::new(area, Mm(10.0).into(), Mm(10.0).into()).with_uncolored(), |_| { });
+    layer.set_fill_pattern(&dots, Some(Color::Lab(Lab::new(80.0, 0.0, 0.0, WhitePoint::D65))));
+
+    let report = doc.check_conformance();
+    let colors: Vec<_> = report.violations.iter()
+        .filter(|violation| matches!(violation.kind, ViolationKind::DeviceIndependentColor(_)))
+        .collect();
+    assert_eq!(colors.iter().map(|violation| violation.kind.clone()).collect::<Vec<_>>(),
+               ["CS0", "CS1", "CS2"].iter().map(|name| ViolationKind::DeviceIndependentColor(name.to_string())).collect::<Vec<_>>());
+    assert!(colors.iter().all(|violation| violation.layer == Some(layer1)));
+
+    doc.repair_errors(PdfConformance::X1A_2001_PDF_1_3).unwrap();
+    {
+        let document = lock_document(&doc.document);
+        let page = &document.pages[0];
+        assert!(page.resources.color_spaces.color_spaces.is_empty());
+
+        let operations = &page.layers[0].operations;
+        let color_spaces: Vec<_> = operations.iter()
+            .filter(|operation| operation.operator == "cs" || operation.operator == "CS")
+            .map(|operation| operation.operands[0].as_name_str().unwrap())
+            .collect();
+        assert_eq!(color_spaces, ["DeviceCMYK", "DeviceCMYK", PATTERN_COLOR_SPACES[1].0]);
+
+        let colors: Vec<_> = operations.iter().filter(|operation| operation.operator.starts_with("sc") || operation.operator.starts_with("SC")).collect();
+        assert_eq!(colors.iter().map(|operation| operation.operands.len()).collect::<Vec<_>>(), [4, 4, 5]);
+        // the CMYK color of the profile stays as it is
+        let outline: Vec<f64> = colors[1].operands.iter().map(|component| component.as_float().unwrap()).collect();
+        assert_eq!(outline, [0.0, 1.0, 1.0, 0.0]);
+    }
+    doc.save_to_bytes().unwrap();
+}
+
+#[test]
+fn test_convert_rgb_operations() {
+    let conversion = RgbToCmyk::new(None);
+    let mut operations = vec![
+        Operation::new("rg", vec![Object::Real(1.0), Object::Real(0.0), Object::Real(0.0)]),
+        Operation::new("RG", vec![Object::Integer(0), Object::Integer(0), Object::Integer(1)]),
+        // malformed operations are left as they are
+        Operation::new("rg", vec![Object::Name("Red".into())]),
+        Operation::new("RG", vec![Object::Real(1.0), Object::Real(0.0)]),
+        Operation::new("cs", vec![Object::Name("DeviceRGB".into())]),
+        Operation::new("sc", vec![Object::Real(0.0), Object::Real(1.0), Object::Real(0.0)]),
+        Operation::new("scn", vec![Object::Name("P0".into())]),
+        // the color space is restored by `Q` and set by `g`
+        Operation::new("q", vec![]),
+        Operation::new("g", vec![Object::Real(0.5)]),
+        Operation::new("Q", vec![]),
+        Operation::new("sc", vec![Object::Real(0.0), Object::Real(0.0), Object::Real(1.0)]),
+        Operation::new("g", vec![Object::Real(0.5)]),
+        Operation::new("sc", vec![Object::Real(0.0), Object::Real(0.0), Object::Real(1.0)]),
+        // uncolored patterns
+        Operation::new("CS", vec![Object::Name("PatternRGB".into())]),
+        Operation::new("SCN", vec![Object::Real(0.0), Object::Real(1.0), Object::Real(0.0), Object::Name("P1".into())]),
+    ];
+    convert_rgb_operations(&mut operations, &conversion);
+
+    let converted: Vec<(&str, usize)> = operations.iter().map(|operation| (operation.operator.as_str(), operation.operands.len())).collect();
+    assert_eq!(converted, vec![
+        ("k", 4), ("K", 4), ("rg", 1), ("RG", 2), ("cs", 1), ("sc", 4), ("scn", 1),
+        ("q", 0), ("g", 1), ("Q", 0), ("sc", 4), ("g", 1), ("sc", 3),
+        ("CS", 1), ("SCN", 5),
+    ]);
+    assert_eq!(operations[4].operands[0].as_name_str().unwrap(), "DeviceCMYK");
+    assert_eq!(operations[13].operands[0].as_name_str().unwrap(), "PatternCMYK");
+    assert_eq!(operations[14].operands[4].as_name_str().unwrap(), "P1");
+}
@@ -0,0 +1,258 @@
+//! Reads the chunks of a PNG file, so that the compressed image data can be
+//! embedded without decoding it (the PDF viewer decodes it with `FlateDecode`
+//! and the PNG predictors)
+
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+use PdfError;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Color types from the IHDR chunk
+pub(crate) const COLOR_TYPE_GREYSCALE: u8 = 0;
+pub(crate) const COLOR_TYPE_RGB: u8 = 2;
+pub(crate) const COLOR_TYPE_PALETTE: u8 = 3;
+pub(crate) const COLOR_TYPE_GREYSCALE_ALPHA: u8 = 4;
+pub(crate) const COLOR_TYPE_RGBA: u8 = 6;
+
+/// The chunks of a PNG file that are needed to embed it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PngInfo {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// Bits per sample (or per palette index): 1, 2, 4, 8 or 16
+    pub(crate) bit_depth: u8,
+    pub(crate) color_type: u8,
+    /// PLTE chunk: RGB colors, 3 bytes per color
+    pub(crate) palette: Option<Vec<u8>>,
+    /// tRNS chunk: alpha per palette color, or the transparent
+    /// color (16-bit big endian samples) of greyscale and RGB images
+    pub(crate) transparency: Option<Vec<u8>>,
+    /// The concatenated IDAT chunks (zlib stream of the filtered rows)
+    pub(crate) data: Vec<u8>,
+}
+
+impl PngInfo {
+
+    /// Number of samples per pixel
+    pub(crate) fn channels(&self)
+    -> usize
+    {
+        match self.color_type {
+            COLOR_TYPE_RGB => 3,
+            COLOR_TYPE_GREYSCALE_ALPHA => 2,
+            COLOR_TYPE_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    /// Size of a row in bytes, without the filter type byte,
+    /// `None` if the dimensions overflow
+    fn row_size(&self)
+    -> Option<usize>
+    {
+        let bits = self.width.checked_mul(self.channels())?.checked_mul(usize::from(self.bit_depth))?;
+        Some(bits / 8 + if bits % 8 == 0 { 0 } else { 1 })
+    }
+
+    /// Decompresses the image data and reverses the PNG filters. The returned rows are
+    /// padded to full bytes, 16-bit samples are big endian (as PDF expects them)
+    pub(crate) fn decode(&self)
+    -> Result<Vec<u8>, PdfError>
+    {
+        let row_size = self.row_size()
+            .ok_or(PdfError::InvalidImage("PNG image is too large"))?;
+        let filtered_size = (row_size + 1).checked_mul(self.height)
+            .ok_or(PdfError::InvalidImage("PNG image is too large"))?;
+
+        // the size in the header isn't trusted, the buffer only grows with the data that is actually there
+        let mut filtered = Vec::new();
+        ZlibDecoder::new(&self.data[..]).take(filtered_size as u64).read_to_end(&mut filtered)
+            .map_err(|_| PdfError::InvalidImage("corrupt PNG image data"))?;
+
+        if filtered.len() < filtered_size {
+            return Err(PdfError::InvalidImage("PNG image data is too short"));
+        }
+
+        let bytes_per_pixel = ((self.channels() * usize::from(self.bit_depth)) / 8).max(1);
+        let mut rows = vec![0; row_size * self.height];
+        let mut previous = vec![0; row_size];
+
+        for (filtered_row, row) in filtered.chunks_exact(row_size + 1).zip(rows.chunks_exact_mut(row_size)) {
+            let filter_type = filtered_row[0];
+            row.copy_from_slice(&filtered_row[1..]);
+            unfilter_row(filter_type, row, &previous, bytes_per_pixel)?;
+            previous.copy_from_slice(row);
+        }
+
+        Ok(rows)
+    }
+}
+
+/// Reverses the PNG filter of one row, `previous` is the already unfiltered previous row
+fn unfilter_row(filter_type: u8, row: &mut [u8], previous: &[u8], bytes_per_pixel: usize)
+-> Result<(), PdfError>
+{
+    for i in 0..row.len() {
+        let left = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+
+        let prediction = match filter_type {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => return Err(PdfError::InvalidImage("unknown PNG filter type")),
+        };
+
+        row[i] = row[i].wrapping_add(prediction);
+    }
+
+    Ok(())
+}
+
+/// Paeth predictor (see the PNG specification, section 9.4)
+fn paeth(left: u8, up: u8, up_left: u8)
+-> u8
+{
+    let estimate = i16::from(left) + i16::from(up) - i16::from(up_left);
+    let distance_left = (estimate - i16::from(left)).abs();
+    let distance_up = (estimate - i16::from(up)).abs();
+    let distance_up_left = (estimate - i16::from(up_left)).abs();
+
+    if distance_left <= distance_up && distance_left <= distance_up_left {
+        left
+    } else if distance_up <= distance_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Reads the chunks of the PNG file. Interlaced PNGs are rejected, because their
+/// image data can't be decoded with the predictors of the `FlateDecode` filter
+pub(crate) fn read_png_info(data: &[u8])
+-> Result<PngInfo, PdfError>
+{
+    if !data.starts_with(&PNG_SIGNATURE) {
+        return Err(PdfError::InvalidImage("not a PNG file"));
+    }
+
+    let mut info: Option<PngInfo> = None;
+    let mut pos = PNG_SIGNATURE.len();
+
+    loop {
+        let header = data.get(pos..pos + 8)
+            .ok_or(PdfError::InvalidImage("PNG file ends before the IEND chunk"))?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk_type = &header[4..8];
+        let chunk = data.get(pos + 8..pos + 8 + length)
+            .ok_or(PdfError::InvalidImage("corrupt PNG chunk"))?;
+        // 4 bytes length, 4 bytes type, data, 4 bytes CRC
+        pos += 12 + length;
+
+        if chunk_type == b"IHDR" {
+            if chunk.len() < 13 {
+                return Err(PdfError::InvalidImage("corrupt PNG header"));
+            }
+
+            let header = PngInfo {
+                width: u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize,
+                height: u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize,
+                bit_depth: chunk[8],
+                color_type: chunk[9],
+                palette: None,
+                transparency: None,
+                data: Vec::new(),
+            };
+
+            let valid_bit_depth = match header.color_type {
+                COLOR_TYPE_GREYSCALE => [1, 2, 4, 8, 16].contains(&header.bit_depth),
+                COLOR_TYPE_PALETTE => [1, 2, 4, 8].contains(&header.bit_depth),
+                COLOR_TYPE_RGB | COLOR_TYPE_GREYSCALE_ALPHA | COLOR_TYPE_RGBA => [8, 16].contains(&header.bit_depth),
+                _ => return Err(PdfError::InvalidImage("unknown PNG color type")),
+            };
+
+            if !valid_bit_depth {
+                return Err(PdfError::InvalidImage("invalid PNG bit depth"));
+            }
+            if header.width == 0 || header.height == 0 {
+                return Err(PdfError::InvalidImage("PNG has no size in the header"));
+            }
+            if chunk[10] != 0 || chunk[11] != 0 {
+                return Err(PdfError::InvalidImage("unknown PNG compression or filter method"));
+            }
+            if chunk[12] != 0 {
+                return Err(PdfError::InvalidImage("interlaced PNGs are not supported"));
+            }
+
+            info = Some(header);
+            continue;
+        }
+
+        let info = info.as_mut().ok_or(PdfError::InvalidImage("PNG file doesn't start with the IHDR chunk"))?;
+
+        match chunk_type {
+            b"PLTE" => info.palette = Some(chunk.to_vec()),
+            b"tRNS" => info.transparency = Some(chunk.to_vec()),
+            b"IDAT" => info.data.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => { },
+        }
+    }
+
+    let info = info.ok_or(PdfError::InvalidImage("PNG file has no header"))?;
+
+    if info.data.is_empty() {
+        return Err(PdfError::InvalidImage("PNG file has no image data"));
+    }
+    if info.color_type == COLOR_TYPE_PALETTE && info.palette.is_none() {
+        return Err(PdfError::InvalidImage("PNG file has no palette"));
+    }
+
+    Ok(info)
+}
+
+#[test]
+fn test_read_png_info() {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn chunk(png: &mut Vec<u8>, chunk_type: &[u8], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        png.extend_from_slice(chunk_type);
+        png.extend_from_slice(data);
+        // the CRC isn't checked
+        png.extend_from_slice(&[0; 4]);
+    }
+
+    // 2 x 2 pixels, greyscale + alpha, 8 bit: first row "sub", second row "up" filtered
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&[1, 10, 255, 5, 0, 2, 1, 0, 2, 255]).unwrap();
+    let idat = encoder.finish().unwrap();
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 2, 8, COLOR_TYPE_GREYSCALE_ALPHA, 0, 0, 0]);
+    chunk(&mut png, b"IDAT", &idat[..4]);
+    chunk(&mut png, b"IDAT", &idat[4..]);
+    chunk(&mut png, b"IEND", &[]);
+
+    let info = read_png_info(&png).unwrap();
+    assert_eq!((info.width, info.height, info.bit_depth, info.channels()), (2, 2, 8, 2));
+    assert_eq!(info.data, idat);
+    assert_eq!(info.decode().unwrap(), vec![10, 255, 15, 255, 11, 255, 17, 254]);
+
+    // the header claims far more data than there is, or a size that doesn't fit into memory
+    let huge = PngInfo { width: 60_000, height: 60_000, .. info.clone() };
+    assert!(huge.decode().is_err());
+    let overflowing = PngInfo { width: usize::MAX / 2, height: 2, .. info.clone() };
+    assert!(overflowing.decode().is_err());
+
+    // interlaced
+    png[8 + 8 + 12] = 1;
+    assert!(read_png_info(&png).is_err());
+    assert!(read_png_info(b"GIF89a").is_err());
+}
@@ -0,0 +1,39 @@
+//! Axis-aligned rectangles on a page
+
+use {Mm, Point, Pt};
+
+/// A rectangle on the page, given by its lower left and upper right corner
+/// **WARNING: The reference point is the bottom left corner of the page, not the top left**
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rect {
+    /// Lower left corner of the rectangle
+    pub ll: Point,
+    /// Upper right corner of the rectangle
+    pub ur: Point,
+}
+
+impl Rect {
+
+    /// Creates a new rectangle from the position of its lower left corner, its width and its height
+    #[inline]
+    pub fn new(x: Mm, y: Mm, width: Mm, height: Mm)
+    -> Self
+    {
+        Self {
+            ll: Point::new(x, y),
+            ur: Point::new(x + width, y + height),
+        }
+    }
+
+    /// Width of the rectangle
+    #[inline]
+    pub fn width(&self) -> Pt {
+        Pt(self.ur.x.0 - self.ll.x.0)
+    }
+
+    /// Height of the rectangle
+    #[inline]
+    pub fn height(&self) -> Pt {
+        Pt(self.ur.y.0 - self.ll.y.0)
+    }
+}
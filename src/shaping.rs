@@ -0,0 +1,135 @@
+//! Text shaping: turns a string into positioned glyphs of an `ExternalFont`
+//!
+//! With the `text_shaping` feature enabled, the text is shaped with [`rustybuzz`][],
+//! which applies kerning, ligatures and the GSUB / GPOS rules needed for complex
+//! scripts (Arabic, Devanagari, Thai, ...). Without the feature, every character
+//! is mapped to its glyph one at a time and positioned by its advance width.
+//!
+//! [`rustybuzz`]: https://docs.rs/rustybuzz/latest/rustybuzz/
+
+#[cfg(feature = "text_shaping")]
+use rustybuzz;
+use ExternalFont;
+
+/// A single glyph, positioned by the shaper. All values are in unscaled font units
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShapedGlyph {
+    /// Glyph ID in the font
+    pub glyph_id: u16,
+    /// Byte offset of the first character in the source text this glyph belongs to
+    pub cluster: usize,
+    /// How much the line advances after drawing this glyph
+    pub x_advance: i32,
+    /// Horizontal offset of the glyph, does not affect the advance
+    pub x_offset: i32,
+    /// Vertical offset of the glyph, does not affect the advance
+    pub y_offset: i32,
+}
+
+/// A string that was shaped into glyphs, in visual (left-to-right) order
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapedText {
+    /// The source text
+    pub text: String,
+    /// Positioned glyphs
+    pub glyphs: Vec<ShapedGlyph>,
+    /// The units per em of the font, for scaling the glyph positions
+    pub units_per_em: u16,
+}
+
+impl ShapedText {
+
+    /// Returns the total advance width of the text, in unscaled font units
+    pub fn advance_width(&self) -> i64 {
+        self.glyphs.iter().map(|g| i64::from(g.x_advance)).sum()
+    }
+
+    /// Returns the part of the source text that the glyph at `index` represents.
+    ///
+    /// A ligature glyph returns all of the characters it replaces. If several glyphs
+    /// belong to the same cluster (e.g. a base character and a combining mark that
+    /// were decomposed), only the first of them returns the text, the others return `""`.
+    pub fn glyph_text(&self, index: usize) -> &str {
+        let cluster = match self.glyphs.get(index) {
+            Some(g) => g.cluster,
+            None => return "",
+        };
+
+        if self.glyphs[..index].iter().any(|g| g.cluster == cluster) {
+            return "";
+        }
+
+        let end = self.glyphs.iter()
+            .map(|g| g.cluster)
+            .filter(|c| *c > cluster)
+            .min()
+            .unwrap_or(self.text.len());
+
+        self.text.get(cluster..end).unwrap_or("")
+    }
+}
+
+impl ExternalFont {
+
+    /// Shapes the text into positioned glyphs of this font
+    ///
+    /// Characters that are not present in the font are skipped
+    pub fn shape_text(&self, text: &str) -> ShapedText {
+        let units_per_em = self.font_data.font_metrics().units_per_em;
+
+        #[cfg(feature = "text_shaping")] {
+            if let Some(glyphs) = shape_text_rustybuzz(&self.font_bytes, text) {
+                return ShapedText { text: text.to_string(), glyphs, units_per_em };
+            }
+        }
+
+        let glyphs = text.char_indices().filter_map(|(cluster, ch)| {
+            let glyph_id = self.font_data.glyph_id(ch)?;
+            let x_advance = self.font_data.glyph_metrics(glyph_id).map(|m| m.width as i32).unwrap_or(0);
+            Some(ShapedGlyph { glyph_id, cluster, x_advance, x_offset: 0, y_offset: 0 })
+        }).collect();
+
+        ShapedText { text: text.to_string(), glyphs, units_per_em }
+    }
+}
+
+/// Shapes the text with rustybuzz, returns `None` if the font can't be parsed
+#[cfg(feature = "text_shaping")]
+fn shape_text_rustybuzz(font_bytes: &[u8], text: &str) -> Option<Vec<ShapedGlyph>> {
+    let face = rustybuzz::Face::from_slice(font_bytes, 0)?;
+
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    buffer.push_str(text);
+
+    let glyph_buffer = rustybuzz::shape(&face, &[], buffer);
+
+    let glyphs = glyph_buffer.glyph_infos().iter()
+        .zip(glyph_buffer.glyph_positions().iter())
+        .map(|(info, pos)| ShapedGlyph {
+            glyph_id: info.glyph_id as u16,
+            cluster: info.cluster as usize,
+            x_advance: pos.x_advance,
+            x_offset: pos.x_offset,
+            y_offset: pos.y_offset,
+        })
+        .collect();
+
+    Some(glyphs)
+}
+
+#[test]
+fn test_glyph_text_ligature_and_marks() {
+    let glyph = |glyph_id, cluster| ShapedGlyph { glyph_id, cluster, x_advance: 500, x_offset: 0, y_offset: 0 };
+
+    // "ffia": "ffi" is a ligature, "a" is decomposed into two glyphs
+    let shaped = ShapedText {
+        text: "ffia".to_string(),
+        glyphs: vec![glyph(1, 0), glyph(2, 3), glyph(3, 3)],
+        units_per_em: 1000,
+    };
+
+    assert_eq!(shaped.glyph_text(0), "ffi");
+    assert_eq!(shaped.glyph_text(1), "a");
+    assert_eq!(shaped.glyph_text(2), "");
+    assert_eq!(shaped.advance_width(), 1500);
+}
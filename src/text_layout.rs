@@ -0,0 +1,129 @@
+//! Breaking text into lines, for laying out paragraphs in a text box
+
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+
+/// Horizontal alignment of the lines in a text box
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TextAlignment {
+    /// Lines start at the left edge of the box
+    #[default]
+    Left,
+    /// Lines end at the right edge of the box
+    Right,
+    /// Lines are centered in the box
+    Center,
+    /// Lines fill the whole width of the box, except the last line of a paragraph,
+    /// which is aligned to the left
+    Justify,
+}
+
+/// One line of a paragraph, as a byte range into the text
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct TextLine {
+    /// Start of the line in the text
+    pub(crate) start: usize,
+    /// End of the line in the text, trailing whitespace and line breaks are excluded
+    pub(crate) end: usize,
+    /// Whether the line ends a paragraph (mandatory break or end of the text)
+    pub(crate) ends_paragraph: bool,
+}
+
+/// Breaks the text into at most `max_lines` lines at Unicode line break opportunities
+/// (UAX #14), so that every line is at most `max_width` wide according to `measure`.
+///
+/// Words that are too long for a line on their own are broken between characters, every
+/// line contains at least one character (even if `max_width` is 0 or negative).
+/// Returns the lines and the byte offset of the text that did not fit.
+pub(crate) fn break_lines<F>(text: &str, max_width: f64, max_lines: usize, measure: F)
+-> (Vec<TextLine>, usize) where F: Fn(&str) -> f64
+{
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    // last break opportunity at which the current line still fits
+    let mut last_fit: Option<usize> = None;
+
+    let trimmed_end = |start: usize, end: usize| start + text[start..end].trim_end().len();
+
+    let mut breaks = linebreaks(text).peekable();
+
+    while let Some(&(idx, opportunity)) = breaks.peek() {
+
+        if lines.len() >= max_lines {
+            return (lines, line_start);
+        }
+
+        let end = trimmed_end(line_start, idx);
+
+        // whitespace always fits, so that every iteration makes progress
+        if end == line_start || measure(&text[line_start..end]) <= max_width {
+            breaks.next();
+            if opportunity == BreakOpportunity::Mandatory {
+                lines.push(TextLine { start: line_start, end, ends_paragraph: true });
+                line_start = idx;
+                last_fit = None;
+            } else {
+                last_fit = Some(idx);
+            }
+        } else if let Some(fit) = last_fit.take() {
+            // break at the last opportunity that fit, then retry the current one
+            lines.push(TextLine { start: line_start, end: trimmed_end(line_start, fit), ends_paragraph: false });
+            line_start = fit;
+        } else {
+            // a single word is too wide, break it between characters (at least one character per line)
+            let word = &text[line_start..end];
+            let split = word.char_indices()
+                .skip(1)
+                .map(|(i, _)| i)
+                .take_while(|i| measure(&word[..*i]) <= max_width)
+                .last()
+                .unwrap_or_else(|| word.chars().next().map(|c| c.len_utf8()).unwrap_or(word.len()));
+
+            if split == word.len() {
+                // the word is a single character, the line ends at the break opportunity
+                breaks.next();
+                lines.push(TextLine { start: line_start, end, ends_paragraph: opportunity == BreakOpportunity::Mandatory });
+                line_start = idx;
+            } else {
+                lines.push(TextLine { start: line_start, end: line_start + split, ends_paragraph: false });
+                line_start += split;
+            }
+        }
+    }
+
+    (lines, text.len())
+}
+
+#[test]
+fn test_break_lines() {
+    // every character is 1 unit wide
+    let measure = |s: &str| s.chars().count() as f64;
+
+    let text = "aaa bbb ccc\ndddddddd";
+    let (lines, rest) = break_lines(text, 7.0, 10, measure);
+    let lines: Vec<&str> = lines.iter().map(|l| &text[l.start..l.end]).collect();
+    assert_eq!(lines, vec!["aaa bbb", "ccc", "ddddddd", "d"]);
+    assert_eq!(rest, text.len());
+
+    let (lines, rest) = break_lines(text, 7.0, 1, measure);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(&text[rest..], "ccc\ndddddddd");
+}
+
+#[test]
+fn test_break_lines_edge_cases() {
+    let measure = |s: &str| s.chars().count() as f64;
+    let break_text = |text: &str, max_width: f64| {
+        let (lines, rest) = break_lines(text, max_width, usize::MAX, measure);
+        assert_eq!(rest, text.len());
+        lines.iter().map(|l| text[l.start..l.end].to_string()).collect::<Vec<String>>()
+    };
+
+    assert!(break_text("", 5.0).iter().all(String::is_empty));
+    assert!(break_text("   \n  ", 5.0).iter().all(String::is_empty));
+    assert!(break_text("   ", -1.0).iter().all(String::is_empty));
+
+    // a word that is longer than the line is broken, at least one character per line
+    assert_eq!(break_text("abcdefg hi", 3.0), vec!["abc", "def", "g", "hi"]);
+    assert_eq!(break_text("ab c", 0.0), vec!["a", "b", "c"]);
+    assert_eq!(break_text("ab", -1.0), vec!["a", "b"]);
+}
@@ -131,3 +131,54 @@ pub(crate) fn random_character_string_32() -> String {
 fn u8_to_char(input: u8) -> char {
     ('A' as u8 + input) as char
 }
+
+/// Number of bytes of a row of `width` samples with `bits` bits each, rows are padded to full bytes
+#[inline]
+pub(crate) fn row_bytes(width: usize, bits: usize) -> usize {
+    // the bits of the row, rounded up to full bytes
+    (width * bits + 7) >> 3
+}
+
+/// 128-bit FNV-1a hash, for document IDs that only depend on the content of the
+/// document. Unlike the hashers of the standard library, the result is the same
+/// on every platform and with every Rust version.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct ContentHash(u128);
+
+impl ContentHash {
+
+    const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+    pub(crate) fn new() -> Self {
+        ContentHash(Self::OFFSET_BASIS)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u128::from(byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Returns the hash as a string with 32 hexadecimal characters
+    pub(crate) fn to_hex_string(self) -> String {
+        format!("{:032X}", self.0)
+    }
+}
+
+#[test]
+fn test_content_hash() {
+    let mut hash = ContentHash::new();
+    assert_eq!(hash.to_hex_string(), "6C62272E07BB014262B821756295C58D");
+    hash.write(b"a");
+    assert_eq!(hash.to_hex_string(), "D228CB696F1A8CAF78912B704E4A8964");
+}
+
+#[test]
+fn test_row_bytes() {
+    assert_eq!(row_bytes(0, 1), 0);
+    assert_eq!(row_bytes(3, 2), 1);
+    assert_eq!(row_bytes(5, 4), 3);
+    assert_eq!(row_bytes(2, 8), 2);
+}
@@ -34,7 +34,7 @@ impl XmpMetadata {
 
     /// Consumes the XmpMetadata and turns it into a PDF Object.
     /// This is similar to the
-    pub(in crate) fn into_obj(self, m: &PdfMetadata)
+    pub(in crate) fn into_obj(self, m: &PdfMetadata, instance_id: &str)
     -> lopdf::Object
     {
         use lopdf::{Stream as LoStream, Dictionary as LoDictionary};
@@ -44,8 +44,6 @@ impl XmpMetadata {
         // Shared between XmpMetadata and DocumentInfo
         let trapping = if m.trapping { "True" } else { "False" };
 
-        // let xmp_instance_id = "2898d852-f86f-4479-955b-804d81046b19";
-        let instance_id = random_character_string_32();
         let create_date = to_pdf_xmp_date(&m.creation_date);
         let modification_date = to_pdf_xmp_date(&m.modification_date);
         let metadata_date = to_pdf_xmp_date(&m.metadata_date);
@@ -59,7 +57,23 @@ impl XmpMetadata {
             None => "".to_string(),
         };
 
-        let xmp_metadata = format!(include_str!("../assets/catalog_xmp_metadata.txt"),
+        let xmp_metadata = match m.conformance.get_pdfa_part() {
+            // PDF/A only allows the predefined XMP schemas (no PDF/X schemas) and requires
+            // the entries of the document info dictionary to be the same as in the XMP metadata
+            Some((part, conformance)) => format!(include_str!("../assets/catalog_xmp_metadata_pdfa.txt"),
+                           part = part,
+                           conformance = conformance,
+                           create = create_date,
+                           modify = modification_date,
+                           mdate = metadata_date,
+                           title = escape_xml(&m.document_title),
+                           author = escape_xml(&m.author),
+                           subject = escape_xml(&m.subject),
+                           creator = escape_xml(&m.creator),
+                           producer = escape_xml(&m.producer),
+                           keywords = escape_xml(&m.keywords.join(",")),
+                           id = document_id),
+            None => format!(include_str!("../assets/catalog_xmp_metadata.txt"),
                            create = create_date, 
                            modify = modification_date, 
                            mdate = metadata_date, 
@@ -74,10 +88,8 @@ impl XmpMetadata {
                            subject = m.subject,
                            keywords = m.keywords.join(","),
                            identifier = m.identifier,
-                           producer = m.producer);
-
-
-
+                           producer = m.producer),
+        };
 
         Stream(LoStream::new(LoDictionary::from_iter(vec![
             ("Type", "Metadata".into()),
@@ -86,13 +98,20 @@ impl XmpMetadata {
     }
 }
 
-// D:2018-09-19T10:05:05+00'00'
+/// Escapes the characters that have a special meaning in XML
+fn escape_xml(text: &str)
+-> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// 2018-09-19T10:05:05+00:00
 fn to_pdf_xmp_date(date: &OffsetDateTime)
 -> String
 {
     // Since the time is in UTC, we know that the time zone
-    // difference to UTC is 0 min, 0 sec, hence the 00'00
-    format!("D:{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00'00'",
+    // difference to UTC is 0 min, 0 sec, hence the 00:00
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
         date.year(),
         date.month(),
         date.day(),
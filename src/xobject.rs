@@ -6,8 +6,12 @@ use crate::OffsetDateTime;
 use image_crate::{DynamicImage, GenericImageView, ImageDecoder, ImageError};
 use lopdf;
 use lopdf::Stream as LoPdfStream;
-use std::collections::HashMap;
-use {ColorBits, ColorSpace, CurTransMat, Px};
+use std::collections::BTreeMap;
+use {Color, ColorBits, ColorSpace, CurTransMat, Px, Rect, Error};
+use jpeg::read_jpeg_info;
+use png::{read_png_info, PngInfo, COLOR_TYPE_GREYSCALE, COLOR_TYPE_PALETTE, COLOR_TYPE_RGB, COLOR_TYPE_RGBA};
+use icc_profile::RgbToCmyk;
+use utils::row_bytes;
 
 /* Parent: Resources dictionary of the page */
 /// External object that gets reference outside the PDF content stream
@@ -36,6 +40,10 @@ pub enum XObject {
     /// by `add_xobject()` is the unique name that can be used to invoke
     /// the `/Do` operator (by the `use_xobject`)
     External(LoPdfStream),
+    /// XObject that is already part of the document (for example an imported page,
+    /// see `PdfDocumentReference::import_page`), which can be used on any page.
+    /// Only its name is added to the /Resources dictionary of the page.
+    Reference(lopdf::ObjectId),
 }
 
 impl From<ImageXObject> for XObject {
@@ -44,35 +52,17 @@ impl From<ImageXObject> for XObject {
     }
 }
 
-impl XObject {
-
-    #[cfg(any(debug_assertions, feature="less-optimization"))]
-    #[inline]
-    fn compress_stream(stream: lopdf::Stream)
-    -> lopdf::Stream
-    {
-        stream
-    }
-
-    #[cfg(all(not(debug_assertions), not(feature="less-optimization")))]
-    #[inline]
-    fn compress_stream(mut stream: lopdf::Stream)
-    -> lopdf::Stream
-    {
-        let _ = stream.compress();
-        stream
-    }
-}
-
 impl Into<lopdf::Object> for XObject {
     fn into(self)
     -> lopdf::Object
     {
         match self {
-            XObject::Image(image) => { lopdf::Object::Stream(Self::compress_stream(image.into())) },
-            XObject::Form(form) => { let cur_form: FormXObject = *form; lopdf::Object::Stream(Self::compress_stream(cur_form.into())) },
-            XObject::PostScript(ps) => { lopdf::Object::Stream(Self::compress_stream(ps.into())) },
-            XObject::External(stream) => { lopdf::Object::Stream(Self::compress_stream(stream)) },
+            // the streams are compressed when the document is saved (see `PdfSaveOptions`)
+            XObject::Image(image) => { lopdf::Object::Stream(image.into()) },
+            XObject::Form(form) => { let cur_form: FormXObject = *form; lopdf::Object::Stream(cur_form.into()) },
+            XObject::PostScript(ps) => { lopdf::Object::Stream(ps.into()) },
+            XObject::External(stream) => { lopdf::Object::Stream(stream) },
+            XObject::Reference(id) => { lopdf::Object::Reference(id) },
         }
     }
 }
@@ -80,7 +70,7 @@ impl Into<lopdf::Object> for XObject {
 /// List of `XObjects`
 #[derive(Debug, Default, Clone)]
 pub struct XObjectList {
-    objects: HashMap<String, XObject>,
+    objects: BTreeMap<String, XObject>,
 }
 
 impl XObjectList {
@@ -96,23 +86,78 @@ impl XObjectList {
     pub fn add_xobject(&mut self, xobj: XObject)
     -> XObjectRef
     {
+        // objects of the document only need one name per page
+        if let XObject::Reference(id) = xobj {
+            let existing = self.objects.iter().find(|&(_, object)| match *object {
+                XObject::Reference(existing_id) => existing_id == id,
+                _ => false,
+            });
+            if let Some((name, _)) = existing {
+                return XObjectRef { name: name.clone() };
+            }
+        }
+
         let len = self.objects.len();
         let xobj_ref = XObjectRef::new(len);
         self.objects.insert(xobj_ref.name.clone(), xobj);
         xobj_ref
     }
 
+    /// The XObjects of the list and their names
+    pub(crate) fn iter(&self)
+    -> impl Iterator<Item = (&String, &XObject)>
+    {
+        self.objects.iter()
+    }
+
+    /// The XObjects of the list, for updating references to other objects
+    pub(crate) fn objects_mut(&mut self)
+    -> impl Iterator<Item = &mut XObject>
+    {
+        self.objects.values_mut()
+    }
+
     /// Same as `Into<lopdf::Dictionary>`, but since the dictionary
     /// items in an XObject dictionary are streams and must be added to
     /// the document as __references__, this function needs an additional
     /// access to the PDF document so that we can add the streams first and
     /// then track the references to them.
+    ///
+    /// Form XObjects without their own resources use the resource dictionary
+    /// of the page, `resources_id` is the ID of this dictionary
     #[cfg_attr(feature = "cargo-clippy", allow(needless_return))]
-    pub fn into_with_document(self, doc: &mut lopdf::Document)
+    pub fn into_with_document(self, doc: &mut lopdf::Document, resources_id: lopdf::ObjectId)
     -> lopdf::Dictionary
     {
         self.objects.into_iter().map(|(name, object)| {
-            let obj: lopdf::Object = object.into();
+            if let XObject::Reference(id) = object {
+                return (name, lopdf::Object::Reference(id));
+            }
+
+            let uses_page_resources = match object {
+                XObject::Form(ref form) => form.resources.is_none(),
+                _ => false,
+            };
+
+            // the alpha channel of an image is added as a separate image
+            let (object, soft_mask) = match object {
+                XObject::Image(image) => {
+                    let (image, soft_mask) = image.split_alpha();
+                    (XObject::Image(image), soft_mask)
+                },
+                other => (other, None),
+            };
+
+            let mut obj: lopdf::Object = object.into();
+            if let lopdf::Object::Stream(ref mut stream) = obj {
+                if uses_page_resources {
+                    stream.dict.set("Resources", lopdf::Object::Reference(resources_id));
+                }
+                if let Some(soft_mask) = soft_mask {
+                    let soft_mask_id = doc.add_object(XObject::Image(soft_mask));
+                    stream.dict.set("SMask", lopdf::Object::Reference(soft_mask_id));
+                }
+            }
             let obj_ref =  doc.add_object(obj);
             (name.to_string(), lopdf::Object::Reference(obj_ref))
         }).collect()
@@ -146,7 +191,7 @@ pub struct ImageXObject {
     pub width: Px,
     /// Height of the image (original height, not scaled height)
     pub height: Px,
-    /// Color space (Greyscale, RGB, CMYK)
+    /// Color space (Greyscale, RGB, CMYK, or Palette together with `palette`)
     pub color_space: ColorSpace,
     /// Bits per color component (1, 2, 4, 8, 16) - 1 for black/white, 8 Greyscale / RGB, etc.
     /// If using a JPXDecode filter (for JPEG images), this can be inferred from the image data
@@ -162,6 +207,35 @@ pub struct ImageXObject {
     /// Required bounds to clip the image, in unit space
     /// Default value: Identity matrix (`[1 0 0 1 0 0]`) - used when value is `None`
     pub clipping_bbox: Option<CurTransMat>,
+    /// What to do with the alpha channel of `Rgba` and `GreyscaleAlpha` images
+    pub alpha_handling: AlphaHandling,
+    /* /Decode [Real; 2 * number of components] */
+    /// Maps the sample values to the range of the color space, two numbers (min, max) per
+    /// color component. Default value: `None` (`[0 1]` for each component)
+    pub decode: Option<Vec<f64>>,
+    /// Color lookup table, required for `ColorSpace::Palette` images
+    pub palette: Option<ImagePalette>,
+    /* /Mask [Integer; 2 * number of components] */
+    /// Color key masking: two numbers (min, max) per color component. Pixels with all
+    /// components inside these ranges are not painted. Default value: `None`
+    pub color_key_mask: Option<Vec<u16>>,
+    /* /ImageMask true */
+    /// Stencil mask: the image is a 1-bit mask that is painted in the current fill color,
+    /// `color_space` and `bits_per_component` are ignored. Default value: false
+    pub image_mask: bool,
+}
+
+/// PDF has no color spaces with an alpha channel, the alpha channel
+/// of an image has to be removed before it is written to the PDF
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum AlphaHandling {
+    /// Write the alpha channel as a separate greyscale image (`/SMask`)
+    #[default]
+    SoftMask,
+    /// Remove the alpha channel, the image will be opaque
+    Drop,
+    /// Blend the image onto a background color, the image will be opaque
+    Flatten(Color),
 }
 
 impl<'a> ImageXObject {
@@ -183,9 +257,140 @@ impl<'a> ImageXObject {
             image_data: data,
             image_filter,
             clipping_bbox: bbox,
+            alpha_handling: AlphaHandling::default(),
+            decode: None,
+            palette: None,
+            color_key_mask: None,
+            image_mask: false,
         }
     }
 
+    /// Embeds a JPEG file as it is (with the `DCTDecode` filter), without decoding
+    /// and re-compressing it. The size, color space and bit depth are read from the
+    /// header of the file. Baseline and progressive JPEGs with 1 (greyscale),
+    /// 3 (RGB) or 4 (CMYK) components are supported.
+    pub fn from_jpeg(data: Vec<u8>)
+    -> Result<Self, Error>
+    {
+        let info = read_jpeg_info(&data)?;
+
+        let color_space = match info.components {
+            1 => ColorSpace::Greyscale,
+            3 => ColorSpace::Rgb,
+            _ => ColorSpace::Cmyk,
+        };
+
+        // Adobe applications store CMYK JPEGs inverted
+        let decode = if info.components == 4 && info.adobe {
+            Some(vec![1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0])
+        } else {
+            None
+        };
+
+        Ok(Self {
+            width: Px(info.width),
+            height: Px(info.height),
+            color_space,
+            bits_per_component: ColorBits::Bit8,
+            interpolate: true,
+            image_data: data,
+            image_filter: Some(ImageFilter::DCT),
+            clipping_bbox: None,
+            alpha_handling: AlphaHandling::default(),
+            decode,
+            palette: None,
+            color_key_mask: None,
+            image_mask: false,
+        })
+    }
+
+    /// Embeds a PNG file without decoding it: the compressed image data is copied as
+    /// it is and decoded by the PDF viewer (`FlateDecode` with PNG predictors). Palette
+    /// PNGs become indexed images, the transparent color of the tRNS chunk becomes a
+    /// color key mask. Only PNGs with an alpha channel (or with partially transparent
+    /// palette colors) are decompressed, to split off the alpha channel (see
+    /// `AlphaHandling`). Interlaced PNGs are not supported.
+    pub fn from_png(data: &[u8])
+    -> Result<Self, Error>
+    {
+        let png = read_png_info(data)?;
+
+        let bits_per_component = match png.bit_depth {
+            1 => ColorBits::Bit1,
+            2 => ColorBits::Bit2,
+            4 => ColorBits::Bit4,
+            8 => ColorBits::Bit8,
+            _ => ColorBits::Bit16,
+        };
+
+        let mut color_key_mask = None;
+        let mut palette = None;
+        let mut needs_decoding = false;
+
+        let color_space = match png.color_type {
+            COLOR_TYPE_GREYSCALE | COLOR_TYPE_RGB => {
+                // the transparent color, one 16-bit sample per component
+                color_key_mask = png.transparency.as_ref().map(|color| {
+                    color.chunks_exact(2).flat_map(|sample| {
+                        let sample = u16::from_be_bytes([sample[0], sample[1]]);
+                        vec![sample, sample]
+                    }).collect()
+                });
+
+                if png.color_type == COLOR_TYPE_RGB { ColorSpace::Rgb } else { ColorSpace::Greyscale }
+            },
+            COLOR_TYPE_PALETTE => {
+                let colors = png.palette.clone().unwrap_or_default();
+                let color_count = colors.len() / 3;
+                let mut image_palette = ImagePalette::new(ColorSpace::Rgb, colors);
+
+                if let Some(ref transparency) = png.transparency {
+                    // colors without an entry in the tRNS chunk are opaque
+                    let mut alpha: Vec<u8> = transparency.iter().cloned().take(color_count).collect();
+                    alpha.resize(color_count, 255);
+
+                    match transparent_index_range(&alpha) {
+                        Some((first, last)) => color_key_mask = Some(vec![first, last]),
+                        None if alpha.iter().all(|alpha| *alpha == 255) => { },
+                        None => {
+                            image_palette.alpha = Some(alpha);
+                            needs_decoding = true;
+                        },
+                    }
+                }
+
+                palette = Some(image_palette);
+                ColorSpace::Palette
+            },
+            color_type => {
+                needs_decoding = true;
+                if color_type == COLOR_TYPE_RGBA { ColorSpace::Rgba } else { ColorSpace::GreyscaleAlpha }
+            },
+        };
+
+        let (image_data, image_filter) = if needs_decoding {
+            (png.decode()?, None)
+        } else {
+            (png.data, Some(ImageFilter::PngFlate))
+        };
+
+        Ok(Self {
+            width: Px(png.width),
+            height: Px(png.height),
+            color_space,
+            bits_per_component,
+            interpolate: true,
+            image_data,
+            image_filter,
+            clipping_bbox: None,
+            alpha_handling: AlphaHandling::default(),
+            decode: None,
+            palette,
+            color_key_mask,
+            image_mask: false,
+        })
+    }
+
     #[cfg(feature = "embedded_images")]
     pub fn try_from<T: ImageDecoder<'a>>(image: T)
     -> Result<Self, ImageError>
@@ -205,6 +410,9 @@ impl<'a> ImageXObject {
         image.read_image(&mut image_data)?;
 
         let color_bits = ColorBits::from(color_type);
+        if let ColorBits::Bit16 = color_bits {
+            native_to_big_endian(&mut image_data);
+        }
         let color_space = ColorSpace::from(color_type);
 
         Ok(Self {
@@ -216,6 +424,11 @@ impl<'a> ImageXObject {
             interpolate: true,
             image_filter: None,
             clipping_bbox: None,
+            alpha_handling: AlphaHandling::default(),
+            decode: None,
+            palette: None,
+            color_key_mask: None,
+            image_mask: false,
         })
     }
 
@@ -225,8 +438,11 @@ impl<'a> ImageXObject {
     {
         let dim = image.dimensions();
         let color_type = image.color();
-        let data = image.to_bytes();
+        let mut data = image.to_bytes();
         let color_bits = ColorBits::from(color_type);
+        if let ColorBits::Bit16 = color_bits {
+            native_to_big_endian(&mut data);
+        }
         let color_space = ColorSpace::from(color_type);
 
         Self {
@@ -238,8 +454,466 @@ impl<'a> ImageXObject {
             interpolate: true,
             image_filter: None,
             clipping_bbox: None,
+            alpha_handling: AlphaHandling::default(),
+            decode: None,
+            palette: None,
+            color_key_mask: None,
+            image_mask: false,
+        }
+    }
+
+    /// Creates a stencil mask (`/ImageMask true`), a 1-bit image that is painted in the
+    /// current fill color. `data` contains the rows of the mask, each row padded to full
+    /// bytes (see `pack_samples`). Pixels with the bit 0 are painted, pixels with the bit 1
+    /// are left unchanged, set `decode` to `Some(vec![1.0, 0.0])` to invert this.
+    pub fn stencil_mask(width: Px, height: Px, data: Vec<u8>)
+    -> Self
+    {
+        Self {
+            image_mask: true,
+            .. Self::new(width, height, ColorSpace::Greyscale, ColorBits::Bit1, false, None, None, data)
+        }
+    }
+
+    /// Makes the image an indexed image, the image data contains one index into the
+    /// color lookup table per pixel
+    #[inline]
+    pub fn with_palette(mut self, palette: ImagePalette)
+    -> Self
+    {
+        self.color_space = ColorSpace::Palette;
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Sets the color key mask, two numbers (min, max) per color component
+    /// (one range of indices for indexed images)
+    #[inline]
+    pub fn with_color_key_mask(mut self, color_key_mask: Vec<u16>)
+    -> Self
+    {
+        self.color_key_mask = Some(color_key_mask);
+        self
+    }
+
+    /// Packs samples with less than 8 bits (one sample per byte, for example the palette
+    /// indices of an image) into the image data format of PDF: each row of `width`
+    /// samples is packed into full bytes, the first sample in the highest bits.
+    /// 8-bit samples are returned unchanged.
+    pub fn pack_samples(samples: &[u8], width: usize, bits: ColorBits)
+    -> Vec<u8>
+    {
+        let bits: i64 = bits.into();
+        let bits = bits as usize;
+        if bits >= 8 || width == 0 {
+            return samples.to_vec();
+        }
+
+        let row_size = row_bytes(width, bits);
+        let index_mask = ((1_u16 << bits) - 1) as u8;
+        let mut data = Vec::with_capacity(row_size * (samples.len() + width - 1) / width);
+
+        for row in samples.chunks(width) {
+            let mut packed = vec![0; row_size];
+            for (x, sample) in row.iter().enumerate() {
+                let bit = x * bits;
+                packed[bit / 8] |= (sample & index_mask) << (8 - bits - bit % 8);
+            }
+            data.extend_from_slice(&packed);
+        }
+
+        data
+    }
+
+    /// Sets what happens with the alpha channel of the image
+    #[inline]
+    pub fn with_alpha_handling(mut self, alpha_handling: AlphaHandling)
+    -> Self
+    {
+        self.alpha_handling = alpha_handling;
+        self
+    }
+
+    /// Returns if the image is written with a soft mask (the alpha channel, see `split_alpha`)
+    pub(crate) fn has_soft_mask(&self)
+    -> bool
+    {
+        let has_alpha = match self.color_space {
+            ColorSpace::Rgba | ColorSpace::GreyscaleAlpha => {
+                let bits: i64 = self.bits_per_component.into();
+                self.image_filter.is_none() && bits >= 8
+            },
+            ColorSpace::Palette => {
+                let bits: i64 = self.bits_per_component.into();
+                self.image_filter.is_none() && bits <= 8 && matches!(self.palette, Some(ref palette) if palette.alpha.is_some())
+            },
+            _ => false,
+        };
+
+        has_alpha && self.alpha_handling == AlphaHandling::SoftMask
+    }
+
+    /// Returns if the colors of the image are in the DeviceRGB color space
+    pub(crate) fn is_rgb(&self)
+    -> bool
+    {
+        match self.color_space {
+            ColorSpace::Rgb | ColorSpace::Rgba => true,
+            ColorSpace::Palette => matches!(self.palette, Some(ref palette) if palette.base == ColorSpace::Rgb),
+            _ => false,
+        }
+    }
+
+    /// Converts the colors of an RGB image (or the palette of an indexed image) to CMYK.
+    /// The alpha channel of an `Rgba` image is removed first, see `AlphaHandling`.
+    /// Images that can't be converted are left as they are (`check_conformance` reports
+    /// them): JPEG images, images with less than 8 bits per component, a decode array or
+    /// a color key mask and `Rgba` images that keep their alpha channel as a soft mask.
+    pub(crate) fn convert_to_cmyk(&mut self, conversion: &RgbToCmyk)
+    {
+        let to_byte = |value: f64| (value * 255.0).round() as u8;
+
+        if self.color_space == ColorSpace::Palette {
+            if let Some(ref mut palette) = self.palette {
+                if palette.base == ColorSpace::Rgb {
+                    palette.colors = palette.colors.chunks_exact(3).flat_map(|color| {
+                        let [r, g, b] = [color[0], color[1], color[2]].map(|component| f64::from(component) / 255.0);
+                        conversion.convert(r, g, b).map(to_byte)
+                    }).collect();
+                    palette.base = ColorSpace::Cmyk;
+                }
+            }
+            return;
+        }
+
+        if self.decode.is_some() || self.color_key_mask.is_some() {
+            return;
+        }
+
+        if self.color_space == ColorSpace::Rgba && self.alpha_handling != AlphaHandling::SoftMask {
+            let image = ::std::mem::replace(self, Self::new(Px(0), Px(0), ColorSpace::Rgb, ColorBits::Bit8, false, None, None, Vec::new()));
+            *self = image.split_alpha().0;
+        }
+
+        let bytes_per_component = match self.bits_per_component {
+            ColorBits::Bit8 => 1,
+            ColorBits::Bit16 => 2,
+            ColorBits::Bit1 | ColorBits::Bit2 | ColorBits::Bit4 => return,
+        };
+
+        if self.color_space != ColorSpace::Rgb {
+            return;
+        }
+
+        match self.image_filter {
+            None => { },
+            Some(ImageFilter::PngFlate) => {
+                let png = PngInfo {
+                    width: self.width.0,
+                    height: self.height.0,
+                    bit_depth: 8 * bytes_per_component as u8,
+                    color_type: COLOR_TYPE_RGB,
+                    palette: None,
+                    transparency: None,
+                    data: self.image_data.clone(),
+                };
+                match png.decode() {
+                    Ok(data) => self.image_data = data,
+                    Err(_) => return,
+                }
+                self.image_filter = None;
+            },
+            Some(_) => return,
+        }
+
+        let mut cmyk_data = Vec::with_capacity(self.image_data.len() / 3 * 4);
+        for pixel in self.image_data.chunks_exact(3 * bytes_per_component) {
+            let mut components = pixel.chunks_exact(bytes_per_component).map(read_component);
+            let (r, g, b) = (components.next().unwrap_or(0.0), components.next().unwrap_or(0.0), components.next().unwrap_or(0.0));
+            for component in &conversion.convert(r, g, b) {
+                write_component(&mut cmyk_data, *component, bytes_per_component);
+            }
+        }
+
+        self.color_space = ColorSpace::Cmyk;
+        self.image_data = cmyk_data;
+    }
+
+    /// Removes the alpha channel from the image data (see `AlphaHandling`).
+    /// Returns the image without alpha and, for `AlphaHandling::SoftMask`, the
+    /// alpha channel as a greyscale image. Images without alpha, compressed
+    /// images or images with less than 8 bits per component are returned unchanged.
+    pub fn split_alpha(mut self)
+    -> (Self, Option<Self>)
+    {
+        let (color_space, color_components) = match self.color_space {
+            ColorSpace::Rgba => (ColorSpace::Rgb, 3),
+            ColorSpace::GreyscaleAlpha => (ColorSpace::Greyscale, 1),
+            ColorSpace::Palette => return self.split_palette_alpha(),
+            _ => return (self, None),
+        };
+
+        let bytes_per_component = match self.bits_per_component {
+            ColorBits::Bit8 => 1,
+            ColorBits::Bit16 => 2,
+            ColorBits::Bit1 | ColorBits::Bit2 | ColorBits::Bit4 => return (self, None),
+        };
+
+        if self.image_filter.is_some() {
+            return (self, None);
+        }
+
+        let color_size = color_components * bytes_per_component;
+        let pixel_size = color_size + bytes_per_component;
+        let pixel_count = self.image_data.len() / pixel_size;
+        let background = match self.alpha_handling {
+            AlphaHandling::Flatten(ref color) => Some(background_components(color, color_components)),
+            _ => None,
+        };
+
+        let mut color_data = Vec::with_capacity(pixel_count * color_size);
+        let mut alpha_data = Vec::with_capacity(pixel_count * bytes_per_component);
+
+        for pixel in self.image_data.chunks_exact(pixel_size) {
+            let (color, alpha) = pixel.split_at(color_size);
+            match background {
+                Some(ref background) => {
+                    let alpha = read_component(alpha);
+                    for (component, background) in color.chunks(bytes_per_component).zip(background.iter()) {
+                        let blended = read_component(component) * alpha + background * (1.0 - alpha);
+                        write_component(&mut color_data, blended, bytes_per_component);
+                    }
+                },
+                None => color_data.extend_from_slice(color),
+            }
+            alpha_data.extend_from_slice(alpha);
+        }
+
+        let soft_mask = match self.alpha_handling {
+            AlphaHandling::SoftMask => Some(self.soft_mask(self.bits_per_component, alpha_data)),
+            _ => None,
+        };
+
+        self.color_space = color_space;
+        self.image_data = color_data;
+        (self, soft_mask)
+    }
+
+    /// Same as `split_alpha`, for the alpha values of the palette colors
+    fn split_palette_alpha(mut self)
+    -> (Self, Option<Self>)
+    {
+        let bits: i64 = self.bits_per_component.into();
+        let bits = bits as usize;
+        if self.image_filter.is_some() || bits > 8 {
+            return (self, None);
+        }
+
+        let alpha = match self.palette.as_mut().and_then(|palette| palette.alpha.take()) {
+            Some(alpha) => alpha,
+            None => return (self, None),
+        };
+
+        match self.alpha_handling {
+            AlphaHandling::SoftMask => { },
+            AlphaHandling::Drop => return (self, None),
+            AlphaHandling::Flatten(ref background) => {
+                // blending the palette colors is enough
+                if let Some(ref mut palette) = self.palette {
+                    let components = color_components(palette.base);
+                    if components == 1 || components == 3 {
+                        let background = background_components(background, components);
+                        for (color, alpha) in palette.colors.chunks_mut(components).zip(alpha.iter()) {
+                            let alpha = f64::from(*alpha) / 255.0;
+                            for (component, background) in color.iter_mut().zip(background.iter()) {
+                                let blended = f64::from(*component) / 255.0 * alpha + background * (1.0 - alpha);
+                                *component = (blended.clamp(0.0, 1.0) * 255.0).round() as u8;
+                            }
+                        }
+                    }
+                }
+                return (self, None);
+            },
+        }
+
+        // rows of the image data are padded to full bytes
+        let width = self.width.0;
+        let row_size = row_bytes(width, bits);
+        let index_mask = ((1_u16 << bits) - 1) as u8;
+        let mut alpha_data = Vec::with_capacity(width * self.height.0);
+
+        for row in self.image_data.chunks(row_size.max(1)).take(self.height.0) {
+            for x in 0..width {
+                let bit = x * bits;
+                let byte = row.get(bit / 8).cloned().unwrap_or(0);
+                let index = (byte >> (8 - bits - bit % 8)) & index_mask;
+                alpha_data.push(alpha.get(usize::from(index)).cloned().unwrap_or(255));
+            }
+        }
+
+        let soft_mask = self.soft_mask(ColorBits::Bit8, alpha_data);
+        (self, Some(soft_mask))
+    }
+
+    /// Creates the greyscale soft mask image for this image
+    fn soft_mask(&self, bits_per_component: ColorBits, alpha_data: Vec<u8>)
+    -> Self
+    {
+        Self {
+            width: self.width,
+            height: self.height,
+            color_space: ColorSpace::Greyscale,
+            bits_per_component,
+            interpolate: self.interpolate,
+            image_data: alpha_data,
+            image_filter: None,
+            clipping_bbox: None,
+            alpha_handling: AlphaHandling::default(),
+            decode: None,
+            palette: None,
+            color_key_mask: None,
+            image_mask: false,
+        }
+    }
+}
+
+/// Index range of the fully transparent colors of a palette, if the palette has no other
+/// transparent colors (a color key mask of an indexed image can only mask one range)
+fn transparent_index_range(alpha: &[u8])
+-> Option<(u16, u16)>
+{
+    let first = alpha.iter().position(|alpha| *alpha != 255)?;
+    let last = alpha.iter().rposition(|alpha| *alpha != 255)?;
+    if alpha[first..=last].iter().all(|alpha| *alpha == 0) {
+        Some((first as u16, last as u16))
+    } else {
+        None
+    }
+}
+
+/// Number of color components of a color space (including the alpha channel)
+fn color_components(color_space: ColorSpace)
+-> usize
+{
+    match color_space {
+        ColorSpace::Rgb => 3,
+        ColorSpace::Rgba | ColorSpace::Cmyk => 4,
+        ColorSpace::GreyscaleAlpha => 2,
+        ColorSpace::Greyscale | ColorSpace::Palette => 1,
+    }
+}
+
+/// Color lookup table of an indexed image (`ColorSpace::Palette`),
+/// the image data contains the index of the color of each pixel
+#[derive(Debug, Clone)]
+pub struct ImagePalette {
+    /// Color space of the colors in the table (Rgb, Cmyk or Greyscale)
+    pub base: ColorSpace,
+    /// The colors, one byte per component (for example 3 bytes per color for `Rgb`)
+    pub colors: Vec<u8>,
+    /// Alpha value of each color, for example from the tRNS chunk of a PNG file.
+    /// It is removed from the image like an alpha channel, see `AlphaHandling`
+    pub alpha: Option<Vec<u8>>,
+}
+
+impl ImagePalette {
+
+    /// Creates a new color lookup table
+    #[inline]
+    pub fn new(base: ColorSpace, colors: Vec<u8>)
+    -> Self
+    {
+        Self {
+            base,
+            colors,
+            alpha: None,
         }
     }
+
+    /// Sets the alpha value of each color
+    #[inline]
+    pub fn with_alpha(mut self, alpha: Vec<u8>)
+    -> Self
+    {
+        self.alpha = Some(alpha);
+        self
+    }
+}
+
+impl Into<lopdf::Object> for ImagePalette {
+    fn into(self)
+    -> lopdf::Object
+    {
+        use lopdf::Object::*;
+
+        let base: &'static str = self.base.into();
+        let color_count = self.colors.len() / color_components(self.base);
+
+        // [/Indexed /DeviceRGB hival <lookup>]
+        Array(vec![
+            Name("Indexed".as_bytes().to_vec()),
+            Name(base.as_bytes().to_vec()),
+            Integer(color_count as i64 - 1),
+            String(self.colors, lopdf::StringFormat::Hexadecimal),
+        ])
+    }
+}
+
+/// The image crate returns 16-bit images in native byte order, PDF expects big endian
+#[cfg(feature = "embedded_images")]
+fn native_to_big_endian(data: &mut [u8])
+{
+    for component in data.chunks_exact_mut(2) {
+        let value = u16::from_ne_bytes([component[0], component[1]]);
+        component.copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Reads an 8-bit or 16-bit (big endian) component as a value from 0.0 to 1.0
+fn read_component(bytes: &[u8])
+-> f64
+{
+    match *bytes {
+        [value] => f64::from(value) / 255.0,
+        [high, low] => f64::from(u16::from_be_bytes([high, low])) / 65535.0,
+        _ => 0.0,
+    }
+}
+
+/// Writes a value from 0.0 to 1.0 as an 8-bit or 16-bit (big endian) component
+fn write_component(data: &mut Vec<u8>, value: f64, bytes_per_component: usize)
+{
+    let value = value.clamp(0.0, 1.0);
+    if bytes_per_component == 2 {
+        data.extend_from_slice(&((value * 65535.0).round() as u16).to_be_bytes());
+    } else {
+        data.push((value * 255.0).round() as u8);
+    }
+}
+
+/// Converts the background color of a flattened image to the color space of the image
+fn background_components(color: &Color, color_components: usize)
+-> Vec<f64>
+{
+    let rgb = match *color {
+        Color::Rgb(ref rgb) => [rgb.r, rgb.g, rgb.b],
+        Color::CalRgb(ref rgb) => [rgb.r, rgb.g, rgb.b],
+        Color::Lab(ref lab) => lab.to_rgb(),
+        Color::Greyscale(ref gs) => [gs.percent; 3],
+        Color::CalGray(ref gs) => [gs.percent; 3],
+        Color::Cmyk(ref cmyk) => [
+            (1.0 - cmyk.c) * (1.0 - cmyk.k), (1.0 - cmyk.m) * (1.0 - cmyk.k), (1.0 - cmyk.y) * (1.0 - cmyk.k)
+        ],
+        Color::SpotColor(ref spot) => [
+            (1.0 - spot.c) * (1.0 - spot.k), (1.0 - spot.m) * (1.0 - spot.k), (1.0 - spot.y) * (1.0 - spot.k)
+        ],
+    };
+
+    if color_components == 1 {
+        vec![0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2]]
+    } else {
+        rgb.to_vec()
+    }
 }
 
 impl Into<lopdf::Stream> for ImageXObject {
@@ -249,31 +923,66 @@ impl Into<lopdf::Stream> for ImageXObject {
         use lopdf::Object::*;
         use std::iter::FromIterator;
 
-        let cs: &'static str = self.color_space.into();
-        let bbox: lopdf::Object = self.clipping_bbox
+        // the soft mask can only be added with access to the document
+        // (see `XObjectList::into_with_document`), here the alpha channel is dropped
+        let (image, _) = self.split_alpha();
+        let colors = color_components(image.color_space) as i64;
+        let cs: lopdf::Object = match image.palette {
+            Some(palette) => palette.into(),
+            None => {
+                let cs: &'static str = image.color_space.into();
+                Name(cs.as_bytes().to_vec())
+            },
+        };
+        let bbox: lopdf::Object = image.clipping_bbox
             .unwrap_or(CurTransMat::Identity)
             .into();
 
         let mut dict = lopdf::Dictionary::from_iter(vec![
             ("Type", Name("XObject".as_bytes().to_vec())),
             ("Subtype", Name("Image".as_bytes().to_vec())),
-            ("Width", Integer(self.width.0 as i64)),
-            ("Height", Integer(self.height.0 as i64)),
-            ("Interpolate", self.interpolate.into()),
-            ("BitsPerComponent", Integer(self.bits_per_component.into())),
-            ("ColorSpace", Name(cs.as_bytes().to_vec())),
+            ("Width", Integer(image.width.0 as i64)),
+            ("Height", Integer(image.height.0 as i64)),
+            ("Interpolate", image.interpolate.into()),
+            ("BitsPerComponent", Integer(image.bits_per_component.into())),
+            ("ColorSpace", cs),
             ("BBox", bbox),
         ]);
 
-        if let Some(filter) = self.image_filter {
+        if let Some(decode) = image.decode {
+            dict.set("Decode", Array(decode.into_iter().map(Real).collect()));
+        }
+
+        if image.image_mask {
+            // stencil masks have no color space and no mask
+            dict.remove(b"ColorSpace");
+            dict.set("ImageMask", Boolean(true));
+            dict.set("BitsPerComponent", Integer(1));
+        } else if let Some(color_key_mask) = image.color_key_mask {
+            dict.set("Mask", Array(color_key_mask.into_iter().map(|value| Integer(value.into())).collect()));
+        }
+
+        if let Some(filter) = image.image_filter {
             let params = match filter {
                 // TODO technically we could use multiple filters,
                 // DCT as an exception!
                 ImageFilter::DCT => {
+                    // the color transform (YCbCr -> RGB) is read from the JPEG header,
+                    // so no /DecodeParms are needed
                     vec![
                         ("Filter", Array(vec![Name("DCTDecode".as_bytes().to_vec())])),
-                        // not necessary, unless missing in the jpeg header
-                        ("DecodeParams", Dictionary(lopdf::dictionary!("ColorTransform" => Integer(0)))),
+                    ]
+                },
+                ImageFilter::PngFlate => {
+                    let decode_params = lopdf::Dictionary::from_iter(vec![
+                        ("Predictor", Integer(15)),
+                        ("Colors", Integer(colors)),
+                        ("BitsPerComponent", Integer(image.bits_per_component.into())),
+                        ("Columns", Integer(image.width.0 as i64)),
+                    ]);
+                    vec![
+                        ("Filter", Array(vec![Name("FlateDecode".as_bytes().to_vec())])),
+                        ("DecodeParms", Array(vec![Dictionary(decode_params)])),
                     ]
                 },
                 _ => unimplemented!("Encountered filter type is not supported"),
@@ -282,7 +991,7 @@ impl Into<lopdf::Stream> for ImageXObject {
             params.into_iter().for_each(|param| dict.set(param.0, param.1));
         }
 
-        lopdf::Stream::new(dict, self.image_data)
+        lopdf::Stream::new(dict, image.image_data)
     }
 }
 
@@ -303,6 +1012,9 @@ pub enum ImageFilter {
     DCT,
     /// JPEG2000 aka JPX wavelet based compression.
     JPX,
+    /// zlib compressed rows with a PNG filter type in front of each row,
+    /// the format of the IDAT chunks of PNG files
+    PngFlate,
 }
 
 /// __THIS IS NOT A PDF FORM!__ A form `XObject` can be nearly everything.
@@ -320,6 +1032,9 @@ pub struct FormXObject {
     pub form_type: FormType,
     /// The actual content of this FormXObject
     pub bytes: Vec<u8>,
+    /* /BBox [Integer; 4] */
+    /// (Required) Bounding box of the form in form space, content outside of it is clipped
+    pub bbox: Rect,
     /* /Matrix [Integer , 6] */
     /// Optional matrix, maps the form into user space
     pub matrix: Option<CurTransMat>,
@@ -403,6 +1118,33 @@ pub struct FormXObject {
     pub name: Option<String>,
 }
 
+impl FormXObject {
+
+    /// Creates a new Type 1 form from its encoded content stream and its bounding box,
+    /// all optional entries are left empty
+    pub fn new(bytes: Vec<u8>, bbox: Rect)
+    -> Self
+    {
+        Self {
+            form_type: FormType::Type1,
+            bytes,
+            bbox,
+            matrix: None,
+            resources: None,
+            group: None,
+            ref_dict: None,
+            metadata: None,
+            piece_info: None,
+            last_modified: None,
+            struct_parent: None,
+            struct_parents: None,
+            opi: None,
+            oc: None,
+            name: None,
+        }
+    }
+}
+
 impl Into<lopdf::Stream> for FormXObject {
     fn into(self)
     -> lopdf::Stream
@@ -410,12 +1152,27 @@ impl Into<lopdf::Stream> for FormXObject {
         use std::iter::FromIterator;
         use lopdf::Object::*;
 
-        let dict = lopdf::Dictionary::from_iter(vec![
+        let bbox = self.bbox;
+        let mut dict = lopdf::Dictionary::from_iter(vec![
             ("Type", Name("XObject".as_bytes().to_vec())),
             ("Subtype", Name("Form".as_bytes().to_vec())),
             ("FormType", Integer(self.form_type.into())),
+            ("BBox", Array(vec![bbox.ll.x.into(), bbox.ll.y.into(), bbox.ur.x.into(), bbox.ur.y.into()])),
         ]);
 
+        if let Some(matrix) = self.matrix {
+            dict.set("Matrix", matrix);
+        }
+
+        if let Some(resources) = self.resources {
+            dict.set("Resources", Dictionary(resources));
+        }
+
+        if let Some(group) = self.group {
+            let group: lopdf::Dictionary = group.into();
+            dict.set("Group", Dictionary(group));
+        }
+
         lopdf::Stream::new(dict, self.bytes)
     }
 }
@@ -533,10 +1290,86 @@ pub struct SMask {
     Q                                           % Restore graphics state
 */
 
-#[derive(Debug, Copy, Clone)]
+/// Group attributes of a form XObject. The contents of the form are composited
+/// together into one object before being painted onto the page, see page 524
+/// in the PDF 1.7 reference. Used for soft masks and for groups that fade as a whole
+#[derive(Debug, Default, Copy, Clone)]
 pub struct GroupXObject {
     /* /Type /Group */
     /* /S /Transparency */ /* currently the only valid GroupXObject */
+    /* /CS /DeviceRGB */
+    /// Color space that the group is composited in.
+    /// Required for groups that are used as a luminosity soft mask. Default: None
+    pub color_space: Option<ColorSpace>,
+    /* /I true */
+    /// Isolated groups are composited onto a fully transparent backdrop
+    /// instead of onto the content below the group. Default: false
+    pub isolated: bool,
+    /* /K true */
+    /// In a knockout group, each object is composited with the backdrop of
+    /// the group, not with the objects painted earlier in the group. Default: false
+    pub knockout: bool,
+}
+
+impl GroupXObject {
+
+    /// Creates a new transparency group (non-isolated, non-knockout)
+    #[inline]
+    pub fn new()
+    -> Self
+    {
+        Self::default()
+    }
+
+    /// Sets the color space that the group is composited in
+    #[inline]
+    pub fn with_color_space(mut self, color_space: ColorSpace)
+    -> Self
+    {
+        self.color_space = Some(color_space);
+        self
+    }
+
+    /// Makes the group isolated
+    #[inline]
+    pub fn with_isolated(mut self, isolated: bool)
+    -> Self
+    {
+        self.isolated = isolated;
+        self
+    }
+
+    /// Makes the group a knockout group
+    #[inline]
+    pub fn with_knockout(mut self, knockout: bool)
+    -> Self
+    {
+        self.knockout = knockout;
+        self
+    }
+}
+
+impl Into<lopdf::Dictionary> for GroupXObject {
+    fn into(self)
+    -> lopdf::Dictionary
+    {
+        use std::iter::FromIterator;
+        use lopdf::Object::*;
+
+        let mut dict = lopdf::Dictionary::from_iter(vec![
+            ("Type", Name("Group".as_bytes().to_vec())),
+            ("S", Name("Transparency".as_bytes().to_vec())),
+            ("I", Boolean(self.isolated)),
+            ("K", Boolean(self.knockout)),
+        ]);
+
+        if let Some(color_space) = self.color_space {
+            let cs: &'static str = color_space.into();
+            dict.set("CS", Name(cs.as_bytes().to_vec()));
+        }
+
+        dict
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -608,3 +1441,50 @@ impl Into<lopdf::Stream> for PostScriptXObject {
         lopdf::Stream::new(lopdf::Dictionary::new(), Vec::new())
     }
 }
+
+#[test]
+fn test_split_alpha() {
+    // 2 pixels RGBA, 8 bit
+    let image = ImageXObject::new(Px(2), Px(1), ColorSpace::Rgba, ColorBits::Bit8, false, None, None,
+                                  vec![255, 0, 0, 255, 0, 0, 255, 0]);
+
+    let (color, soft_mask) = image.clone().split_alpha();
+    assert_eq!(color.image_data, vec![255, 0, 0, 0, 0, 255]);
+    assert_eq!(soft_mask.unwrap().image_data, vec![255, 0]);
+
+    // flattening onto white: the transparent blue pixel becomes white
+    let white = Color::Greyscale(::Greyscale::new(1.0, None));
+    let (flattened, soft_mask) = image.with_alpha_handling(AlphaHandling::Flatten(white)).split_alpha();
+    assert!(soft_mask.is_none());
+    assert_eq!(flattened.image_data, vec![255, 0, 0, 255, 255, 255]);
+
+    // 16 bit greyscale + alpha, half transparent black on white
+    let image = ImageXObject::new(Px(1), Px(1), ColorSpace::GreyscaleAlpha, ColorBits::Bit16, false, None, None,
+                                  vec![0, 0, 0x80, 0x00])
+        .with_alpha_handling(AlphaHandling::Flatten(Color::Greyscale(::Greyscale::new(1.0, None))));
+    let (flattened, _) = image.split_alpha();
+    assert_eq!(flattened.image_data, vec![0x7F, 0xFF]);
+}
+
+#[test]
+fn test_indexed_images_and_stencil_masks() {
+    // 3 x 2 pixels with 2-bit indices, rows are padded to full bytes
+    let data = ImageXObject::pack_samples(&[0, 1, 2, 3, 2, 1], 3, ColorBits::Bit2);
+    assert_eq!(data, vec![0b0001_1000, 0b1110_0100]);
+    assert_eq!(ImageXObject::pack_samples(&[1, 0, 1, 1], 4, ColorBits::Bit1), vec![0b1011_0000]);
+
+    let palette = ImagePalette::new(ColorSpace::Rgb, vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0]);
+    let image = ImageXObject::new(Px(3), Px(2), ColorSpace::Rgb, ColorBits::Bit2, false, None, None, data)
+        .with_palette(palette)
+        .with_color_key_mask(vec![3, 3]);
+    let stream: lopdf::Stream = image.into();
+    let color_space = stream.dict.get(b"ColorSpace").unwrap().as_array().unwrap();
+    assert_eq!(color_space[0].as_name_str().unwrap(), "Indexed");
+    assert_eq!(color_space[2].as_i64().unwrap(), 3);
+    assert_eq!(stream.dict.get(b"BitsPerComponent").unwrap().as_i64().unwrap(), 2);
+    assert!(stream.dict.has(b"Mask"));
+
+    let stream: lopdf::Stream = ImageXObject::stencil_mask(Px(8), Px(1), vec![0b1010_1010]).into();
+    assert!(stream.dict.get(b"ImageMask").unwrap().as_bool().unwrap());
+    assert!(!stream.dict.has(b"ColorSpace"));
+}